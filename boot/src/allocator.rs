@@ -0,0 +1,46 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+
+/// The size of the heap, which starts where the bootloader's binary ends.
+/// Images are staged on it, and reading one from the SD card caches its
+/// sectors on it too.
+const HEAP_SIZE: usize = 256 << 20;
+
+extern "C" {
+    static __text_end: u8;
+}
+
+/// A bump allocator over the heap: memory is never freed. The bootloader only
+/// allocates to read a few files before it jumps to a kernel.
+pub struct Allocator(UnsafeCell<usize>);
+
+// The bootloader runs on a single core, without interrupts.
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    pub const fn new() -> Allocator {
+        Allocator(UnsafeCell::new(0))
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let heap = &__text_end as *const u8 as usize;
+        let next = &mut *self.0.get();
+        if *next == 0 {
+            *next = heap;
+        }
+
+        let start = (*next + layout.align() - 1) & !(layout.align() - 1);
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= heap + HEAP_SIZE => {
+                *next = end;
+                start as *mut u8
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
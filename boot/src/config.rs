@@ -0,0 +1,76 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The file on the SD card that configures the bootloader.
+pub const CONFIG_PATH: &str = "/boot.cfg";
+
+/// The most images the boot menu offers, one per digit key.
+pub const MAX_IMAGES: usize = 9;
+
+/// What the bootloader does when no image is uploaded.
+///
+/// It is read from `CONFIG_PATH`, with one `key = value` setting per line and
+/// comments starting with `#`:
+///
+/// ```text
+/// # Seconds to wait for an upload before booting from the SD card.
+/// timeout = 5
+/// # Images on the SD card, in the order of the boot menu. The first one is
+/// # booted when the wait is over.
+/// image = kernel8.img.next
+/// image = kernel8.img.old
+/// ```
+#[derive(Debug)]
+pub struct Config {
+    /// How long to wait for an upload or a choice before booting the first
+    /// image.
+    pub timeout: Duration,
+    /// The paths of the images on the SD card.
+    pub images: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let mut images = Vec::new();
+        images.push("kernel8.img.next".to_string());
+        Config { timeout: Duration::from_secs(5), images }
+    }
+}
+
+impl Config {
+    /// Parses the configuration in `text`. Settings that are not given keep
+    /// their default.
+    ///
+    /// # Errors
+    ///
+    /// Returns the number of the first line, from 1, that is not a valid
+    /// setting.
+    pub fn parse(text: &str) -> Result<Config, usize> {
+        let mut config = Config::default();
+        let mut images = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut setting = line.splitn(2, '=').map(str::trim);
+            match (setting.next(), setting.next()) {
+                (Some("timeout"), Some(secs)) => match secs.parse() {
+                    Ok(secs) => config.timeout = Duration::from_secs(secs),
+                    Err(_) => return Err(i + 1),
+                },
+                (Some("image"), Some(path)) if !path.is_empty() && images.len() < MAX_IMAGES => {
+                    images.push(path.to_string())
+                }
+                _ => return Err(i + 1),
+            }
+        }
+
+        if !images.is_empty() {
+            config.images = images;
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,103 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::{self, Debug};
+use core::time::Duration;
+
+use fat32::traits::BlockDevice;
+use fat32::vfat::{self, VFat, VFatHandle};
+use pi::timer::spin_sleep;
+use shim::io;
+use shim::ioerr;
+
+extern "C" {
+    /// A global representing the last SD controller error that occured.
+    static sd_err: i64;
+
+    /// Initializes the SD card controller.
+    ///
+    /// Returns 0 if initialization is successful. If initialization fails,
+    /// returns -1 if a timeout occured, or -2 if an error sending commands to
+    /// the SD controller occured.
+    fn sd_init() -> i32;
+
+    /// Reads sector `n` (512 bytes) from the SD card and writes it to `buffer`,
+    /// which must point to at least 512 bytes and be 4-byte aligned.
+    ///
+    /// On success, returns the number of bytes read: a positive number. On
+    /// error, returns 0 and sets `sd_err`.
+    fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
+}
+
+/// Waits for `micros` microseconds, for `libsd`. The delay is stretched the
+/// same way as in the kernel, which `libsd` needs on the board.
+#[no_mangle]
+fn wait_micros(micros: u32) {
+    spin_sleep(Duration::from_micros(micros as u64 * 200));
+}
+
+/// A handle to the SD card controller.
+#[derive(Debug)]
+pub struct Sd;
+
+impl Sd {
+    /// Initializes the SD card controller and returns a handle to it. The
+    /// caller must ensure that there is only one handle.
+    pub unsafe fn new() -> io::Result<Sd> {
+        match sd_init() {
+            0 => Ok(Sd),
+            -1 => ioerr!(TimedOut, "sd init timed out"),
+            -2 => ioerr!(Other, "failed to send command to SD controller"),
+            _ => ioerr!(Other, "sd init failed"),
+        }
+    }
+}
+
+impl BlockDevice for Sd {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.len() < 512 || buf.as_ptr().align_offset(4) != 0 || n > i32::MAX as u64 {
+            return ioerr!(InvalidInput, "bad sector read");
+        }
+
+        match unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) } {
+            n if n > 0 => Ok(n as usize),
+            _ => match unsafe { sd_err } {
+                -1 => ioerr!(TimedOut, "sd_readsector() timed out"),
+                -2 => ioerr!(Other, "failed to send command to SD controller"),
+                _ => ioerr!(Other, "sd_readsector() failed"),
+            },
+        }
+    }
+
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied, "the bootloader does not write to the SD card")
+    }
+}
+
+#[derive(Clone)]
+pub struct BootVFatHandle(Rc<RefCell<VFat<Self>>>);
+
+// The bootloader runs on a single core, without interrupts.
+unsafe impl Send for BootVFatHandle {}
+unsafe impl Sync for BootVFatHandle {}
+
+impl Debug for BootVFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BootVFatHandle")
+    }
+}
+
+impl VFatHandle for BootVFatHandle {
+    fn new(val: VFat<BootVFatHandle>) -> Self {
+        BootVFatHandle(Rc::new(RefCell::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<BootVFatHandle>) -> R) -> R {
+        f(&mut self.0.borrow_mut())
+    }
+}
+
+/// Mounts the FAT partition of the SD card. The caller must ensure that it
+/// is mounted only once.
+pub unsafe fn mount() -> Result<BootVFatHandle, vfat::Error> {
+    VFat::<BootVFatHandle>::from(Sd::new()?)
+}
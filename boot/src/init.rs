@@ -1,6 +1,7 @@
 use core::mem::zeroed;
 use core::ptr::write_volatile;
 
+mod oom;
 mod panic;
 
 use crate::kmain;
@@ -0,0 +1,238 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+use bootimg::elf::{Elf, ELF_MAGIC, PF_X};
+use bootimg::symbols::{table_addr, SymbolTable, SYMBOLS_MAGIC};
+use bootimg::{Header, HEADER_SIZE, MAGIC};
+use fat32::traits::File;
+use shim::io;
+
+use crate::{BINARY_START_ADDR, MAX_BINARY_SIZE};
+
+/// Why an image could not be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the image failed.
+    Io(io::Error),
+    /// The image was rejected.
+    Image(bootimg::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<bootimg::Error> for Error {
+    fn from(error: bootimg::Error) -> Error {
+        Error::Image(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An image loaded in memory, ready to be entered.
+#[derive(Debug)]
+pub struct Image {
+    pub version: String,
+    /// The memory the image was loaded to.
+    pub start: u64,
+    pub end: u64,
+    pub entry: u64,
+    /// The number of symbols in the symbol table left after the image.
+    pub symbols: usize,
+}
+
+/// The destination of a received image: its header, checked as soon as it is
+/// complete, then its payload, staged on the heap until the transfer is
+/// complete and the payload checked.
+///
+/// The heap is never freed, so one loader is `reset()` for every transfer and
+/// its staging buffer, large enough for any image, is allocated only once.
+pub struct Loader {
+    header: [u8; HEADER_SIZE],
+    pub received: usize,
+    image: Option<Header>,
+    payload: Vec<u8>,
+    pub error: Option<bootimg::Error>,
+}
+
+impl Loader {
+    pub fn new() -> Loader {
+        Loader { header: [0; HEADER_SIZE], received: 0, image: None, payload: Vec::new(), error: None }
+    }
+
+    /// Prepares the loader for another transfer, keeping its staging buffer.
+    pub fn reset(&mut self) {
+        self.received = 0;
+        self.image = None;
+        self.payload.clear();
+        self.error = None;
+    }
+
+    /// Checks the header once it is complete.
+    fn check_header(&mut self) -> Result<Header, bootimg::Error> {
+        let header = Header::parse(&self.header)?;
+        if header.length as usize > MAX_BINARY_SIZE {
+            return Err(bootimg::Error::BadPlacement);
+        }
+        Ok(header)
+    }
+
+    /// Checks the payload, once the transfer is complete, and loads it.
+    ///
+    /// The caller must ensure that the memory images may be loaded to is
+    /// unused.
+    pub unsafe fn finish(&self) -> Result<Image, bootimg::Error> {
+        let header = match self.image {
+            Some(header) => header,
+            None => return Err(bootimg::Error::Truncated { length: 0, received: self.received as u64 }),
+        };
+        header.check_payload(&self.payload)?;
+        install(&self.payload, &header)
+    }
+}
+
+impl io::Write for Loader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "image rejected"));
+        }
+
+        let mut data = buf;
+        if self.received < HEADER_SIZE {
+            let n = data.len().min(HEADER_SIZE - self.received);
+            self.header[self.received..self.received + n].copy_from_slice(&data[..n]);
+            self.received += n;
+            data = &data[n..];
+            if self.received == HEADER_SIZE {
+                match self.check_header() {
+                    Ok(header) => {
+                        if self.payload.capacity() < MAX_BINARY_SIZE {
+                            self.payload.reserve_exact(MAX_BINARY_SIZE);
+                        }
+                        self.image = Some(header);
+                    }
+                    Err(e) => {
+                        self.error = Some(e);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "image rejected"));
+                    }
+                }
+            }
+        }
+
+        if let Some(header) = self.image {
+            // XMODEM's padding after the payload is dropped.
+            let n = data.len().min(header.length as usize - self.payload.len());
+            self.payload.extend_from_slice(&data[..n]);
+            self.received += data.len();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns whether the memory from `start` to `end` is memory that images
+/// may be loaded to.
+fn loadable(start: u64, end: u64) -> bool {
+    start >= BINARY_START_ADDR as u64 && end <= (BINARY_START_ADDR + MAX_BINARY_SIZE) as u64
+}
+
+/// Loads the checked payload `payload` of the image with header `header`. An
+/// ELF file is loaded by its program headers, and its symbols are left after
+/// it; a raw binary is loaded at the load address of the header.
+///
+/// The caller must ensure that the memory images may be loaded to is unused.
+pub unsafe fn install(payload: &[u8], header: &Header) -> Result<Image, bootimg::Error> {
+    let version = header.version().to_string();
+    if !payload.starts_with(&ELF_MAGIC) {
+        let start = BINARY_START_ADDR as u64;
+        header.check_placement(start, start + MAX_BINARY_SIZE as u64)?;
+        let dest = slice::from_raw_parts_mut(header.load_addr as *mut u8, header.length as usize);
+        dest.copy_from_slice(&payload[..header.length as usize]);
+        let end = header.load_addr + header.length as u64;
+        return Ok(Image { version, start: header.load_addr, end, entry: header.entry, symbols: 0 });
+    }
+
+    let elf = Elf::parse(&payload[..header.length as usize])?;
+    let (mut start, mut end) = (u64::MAX, 0);
+    let mut entry = None;
+    for segment in elf.segments() {
+        let segment_end = segment.paddr.checked_add(segment.mem_size).ok_or(bootimg::Error::BadPlacement)?;
+        if !loadable(segment.paddr, segment_end) {
+            return Err(bootimg::Error::BadPlacement);
+        }
+        start = start.min(segment.paddr);
+        end = end.max(segment_end);
+
+        // The entry point is virtual; the bootloader runs without the MMU.
+        let offset = elf.entry().wrapping_sub(segment.vaddr);
+        if segment.flags & PF_X != 0 && offset < segment.mem_size {
+            entry = Some(segment.paddr + offset);
+        }
+    }
+    let entry = entry.ok_or(bootimg::Error::BadPlacement)?;
+
+    for segment in elf.segments() {
+        let dest = slice::from_raw_parts_mut(segment.paddr as *mut u8, segment.mem_size as usize);
+        let (data, bss) = dest.split_at_mut(segment.data.len());
+        data.copy_from_slice(segment.data);
+        for byte in bss.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    // The table goes right after the image, as far as there is room. With no
+    // table, one left by an earlier image is wiped.
+    let limit = (BINARY_START_ADDR + MAX_BINARY_SIZE) as u64;
+    let table = table_addr(end).min(limit);
+    let dest = slice::from_raw_parts_mut(table as *mut u8, (limit - table) as usize);
+    let symbols = match SymbolTable::write(&elf, end, dest) {
+        Some(_) => SymbolTable::parse(dest).map_or(0, |table| table.len()),
+        None => {
+            if let Some(magic) = dest.get_mut(..SYMBOLS_MAGIC.len()) {
+                magic.copy_from_slice(&[0; 4]);
+            }
+            0
+        }
+    };
+    Ok(Image { version, start, end, entry, symbols })
+}
+
+/// Loads the image in `file`, named `name`.
+///
+/// An image with a header is checked like an uploaded one. A file without one
+/// is an ELF file or a raw binary, loaded and entered at `BINARY_START_ADDR`,
+/// with the name of the file as its version.
+///
+/// The caller must ensure that the memory images may be loaded to is unused.
+pub unsafe fn load_file<F: File>(mut file: F, name: &str) -> Result<Image, Error> {
+    let size = file.size() as usize;
+    if size > HEADER_SIZE + MAX_BINARY_SIZE {
+        return Err(bootimg::Error::BadPlacement.into());
+    }
+    let mut data = Vec::new();
+    data.resize(size, 0);
+    file.read_exact(&mut data)?;
+
+    if data.starts_with(&MAGIC) {
+        let header = Header::parse(&data)?;
+        header.check_payload(&data[HEADER_SIZE..])?;
+        return Ok(install(&data[HEADER_SIZE..], &header)?);
+    }
+    let header = Header::new(&data, BINARY_START_ADDR as u64, BINARY_START_ADDR as u64, name);
+    Ok(install(&data, &header)?)
+}
@@ -1,3 +1,4 @@
+#![feature(alloc_error_handler)]
 #![feature(asm)]
 #![feature(global_asm)]
 
@@ -7,19 +8,49 @@
 #[cfg(not(test))]
 mod init;
 
-use xmodem::Xmodem;
+extern crate alloc;
+
+mod allocator;
+mod config;
+mod fs;
+mod loader;
+
+use alloc::vec::Vec;
+use xmodem::{Mode, Xmodem};
+use zmodem::Zmodem;
+use core::fmt::Write;
 use core::time::Duration;
+use fat32::traits::FileSystem;
 use pi;
+use pi::timer::current_time;
+use pi::uart::MiniUart;
+use shim::io::{self, Read};
+
+use allocator::Allocator;
+use config::{Config, CONFIG_PATH};
+use fs::BootVFatHandle;
+use loader::{Image, Loader};
+
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: Allocator = Allocator::new();
 
 /// Start address of the binary to load and of the bootloader.
 const BINARY_START_ADDR: usize = 0x80000;
 const BOOTLOADER_START_ADDR: usize = 0x4000000;
 
-/// Pointer to where the loaded binary expects to be laoded.
-const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
+/// Room left below the bootloader for its stack, which grows down from the
+/// bootloader's start address.
+const STACK_SIZE: usize = 0x100000;
+
+/// Free space between the loaded binary's start address and the bootloader's
+/// stack. Images must be loaded in it.
+const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - STACK_SIZE - BINARY_START_ADDR;
+
+/// Cancels an XMODEM transfer.
+const CAN: u8 = 0x18;
 
-/// Free space between the bootloader and the loaded binary's start address.
-const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
+/// The first byte of a ZMODEM header, which a ZMODEM sender starts with.
+const ZPAD: u8 = b'*';
 
 /// Branches to the address `addr` unconditionally.
 unsafe fn jump_to(addr: *mut u8) -> ! {
@@ -29,23 +60,196 @@ unsafe fn jump_to(addr: *mut u8) -> ! {
     }
 }
 
+/// The UART, as seen by the XMODEM receiver. The first byte it reads is kept:
+/// when it is not the start of an upload, it is a key of the boot menu.
+struct Console<'a> {
+    uart: &'a mut MiniUart,
+    first: Option<u8>,
+}
+
+impl<'a> io::Read for Console<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.uart.read(buf)?;
+        if self.first.is_none() && n > 0 {
+            self.first = Some(buf[0]);
+        }
+        Ok(n)
+    }
+}
+
+impl<'a> io::Write for Console<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(&mut *self.uart, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut *self.uart)
+    }
+}
+
+/// Stops the sender of a rejected image, then waits for it to go quiet so
+/// that the next transfer starts cleanly.
+fn cancel(uart: &mut MiniUart) {
+    let _ = io::Write::write_all(uart, &[CAN, CAN]);
+    let mut buf = [0u8; 64];
+    while uart.read(&mut buf).is_ok() {}
+}
+
+/// Receives an image uploaded with ZMODEM into `loader` and boots it. The
+/// batch is ended first, so that the sender does not wait for the receiver.
+unsafe fn receive_zmodem(uart: &mut MiniUart, loader: &mut Loader) {
+    loader.reset();
+    let received = {
+        let mut zmodem = Zmodem::new(&mut *uart);
+        let received = zmodem.receive(&mut *loader)
+            .and_then(|info| zmodem.receive(&mut Vec::new()).map(|_| info));
+        if received.is_err() {
+            let _ = zmodem.cancel();
+        }
+        received
+    };
+    match received {
+        Ok(Some(_)) => match loader.finish() {
+            Ok(image) => boot(uart, &image),
+            Err(e) => {
+                let _ = writeln!(uart, "boot: rejected image: {}", e);
+            }
+        },
+        Ok(None) => {}
+        Err(e) => match loader.error {
+            Some(e) => {
+                let _ = writeln!(uart, "boot: rejected image: {}", e);
+            }
+            None => {
+                let _ = writeln!(uart, "boot: ZMODEM upload failed: {}", e);
+            }
+        },
+    }
+}
+
+/// Reads the configuration from the SD card. A missing configuration file
+/// leaves the default one; a broken one is reported too.
+fn read_config(fs: &BootVFatHandle, uart: &mut MiniUart) -> Config {
+    let mut file = match fs.open_file(CONFIG_PATH) {
+        Ok(file) => file,
+        Err(_) => return Config::default(),
+    };
+    let mut text = Vec::new();
+    let config = file.read_to_end(&mut text).ok()
+        .and_then(|_| core::str::from_utf8(&text).ok())
+        .map(|text| Config::parse(text));
+    match config {
+        Some(Ok(config)) => config,
+        Some(Err(line)) => {
+            let _ = writeln!(uart, "boot: {}:{}: bad setting; using the defaults", CONFIG_PATH, line);
+            Config::default()
+        }
+        None => {
+            let _ = writeln!(uart, "boot: {} is unreadable; using the defaults", CONFIG_PATH);
+            Config::default()
+        }
+    }
+}
+
+/// Prints the boot menu. With a `timeout`, the first image is booted once it
+/// is over.
+fn print_menu(uart: &mut MiniUart, config: &Config, timeout: Option<Duration>) {
+    let _ = match timeout {
+        Some(timeout) => writeln!(uart, "boot: send an image, or choose one; booting 1 in {}s", timeout.as_secs()),
+        None => writeln!(uart, "boot: send an image, or choose one"),
+    };
+    for (i, image) in config.images.iter().enumerate() {
+        let _ = writeln!(uart, "  {}) {}", i + 1, image);
+    }
+    if timeout.is_some() {
+        let _ = writeln!(uart, "  u) wait for an upload");
+    }
+}
+
+/// Reports the image about to start and jumps to it.
+unsafe fn boot(uart: &mut MiniUart, image: &Image) -> ! {
+    let _ = writeln!(uart, "boot: loaded {:?} at {:#x}..{:#x} with {} symbols, starting at {:#x}",
+                     image.version, image.start, image.end, image.symbols, image.entry);
+    jump_to(image.entry as *mut u8)
+}
+
 unsafe fn kmain() -> ! {
+    let mut uart = MiniUart::new();
+    // Short, so that keys of the boot menu are seen soon.
+    uart.set_read_timeout(Duration::from_millis(250));
+
+    let sd = match fs::mount() {
+        Ok(sd) => Some(sd),
+        Err(e) => {
+            let _ = writeln!(uart, "boot: no FAT partition on the SD card: {:?}", e);
+            None
+        }
+    };
+    let config = sd.as_ref().map_or_else(Config::default, |fs| read_config(fs, &mut uart));
+    let mut deadline = sd.as_ref().map(|_| current_time() + config.timeout);
+    if sd.is_some() {
+        print_menu(&mut uart, &config, Some(config.timeout));
+    }
+
+    let mut loader = Loader::new();
     loop {
-        let mut uart = pi::uart::MiniUart::new();
-        let buffer = core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
-        uart.set_read_timeout(Duration::from_millis(750));
-        let xmodem = Xmodem::receive(uart, buffer);
+        loader.reset();
+        let mut console = Console { uart: &mut uart, first: None };
+        // Ask for CRC mode, which lets the sender use 1K blocks; a sender
+        // that does not answer gets asked for checksum mode.
+        let xmodem = Xmodem::receive_with_mode(&mut console, &mut loader, Mode::Crc, |_| {});
+        let key = console.first.filter(|_| loader.received == 0);
         match xmodem {
-            //Err(ref e) if e.kind() == shim::io::ErrorKind::TimedOut => {
-                //continue;
-            //},
-            Err(_) => {
-                //uart.write_str("Got an error other than timeout");
-                continue;
+            Ok(_) => match loader.finish() {
+                Ok(image) => boot(&mut uart, &image),
+                Err(e) => {
+                    let _ = writeln!(uart, "boot: rejected image: {}", e);
+                }
+            },
+            // Timeouts while no one is sending are expected; a rejected
+            // header is reported.
+            Err(_) => if let Some(e) = loader.error {
+                cancel(&mut uart);
+                let _ = writeln!(uart, "boot: rejected image: {}", e);
             },
-            Ok(_) => {
-                jump_to(BINARY_START);
+        }
+
+        // XMODEM's receiver rejects the header a ZMODEM sender starts with.
+        if key == Some(ZPAD) {
+            receive_zmodem(&mut uart, &mut loader);
+            continue;
+        }
+
+        let fs = match &sd {
+            Some(fs) => fs,
+            None => continue,
+        };
+        let choice = match key {
+            Some(key @ b'1'..=b'9') => Some((key - b'1') as usize).filter(|&i| i < config.images.len()),
+            Some(b'u') | Some(b'U') if deadline.is_some() => {
+                deadline = None;
+                print_menu(&mut uart, &config, None);
+                None
+            }
+            _ => deadline.filter(|&deadline| current_time() >= deadline).map(|_| 0),
+        };
+
+        if let Some(i) = choice {
+            let path = &config.images[i];
+            let _ = writeln!(uart, "boot: loading {}", path);
+            let image = fs.open_file(path.as_str())
+                .map_err(loader::Error::from)
+                .and_then(|file| loader::load_file(file, path));
+            match image {
+                Ok(image) => boot(&mut uart, &image),
+                Err(e) => {
+                    let _ = writeln!(uart, "boot: cannot boot {}: {}", path, e);
+                }
             }
+            // Booting from the SD card failed: wait for an upload instead of
+            // failing again.
+            deadline = None;
+            print_menu(&mut uart, &config, None);
         }
     }
 }
@@ -14,7 +14,7 @@ use core::fmt;
 use core::cmp::max;
 
 use crate::console::kprintln;
-use crate::mutex::Mutex;
+use crate::mutex::IrqSafeMutex;
 use pi::atags::{Atag, Atags};
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
@@ -25,7 +25,7 @@ pub trait LocalAlloc {
 }
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+pub struct Allocator(IrqSafeMutex<Option<AllocatorImpl>>);
 
 impl Allocator {
 	/// Returns an uninitialized `Allocator`.
@@ -33,7 +33,7 @@ impl Allocator {
 	/// The allocator must be initialized by calling `initialize()` before the
 	/// first memory allocation. Failure to do will result in panics.
 	pub const fn uninitialized() -> Self {
-		Allocator(Mutex::new(None))
+		Allocator(IrqSafeMutex::new(None))
 	}
 
 	/// Initializes the memory allocator.
@@ -67,17 +67,14 @@ unsafe impl GlobalAlloc for Allocator {
 	}
 }
 
-extern "C" {
-	static __text_end: u8;
-}
-
 /// Returns the (start address, end address) of the available memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
 /// This function is expected to return `Some` under all normal cirumstances.
 pub fn memory_map() -> Option<(usize, usize)> {
 	let page_size = 1 << 12;
-	let binary_end = unsafe { (&__text_end as *const u8) as usize };
+	// The kernel's symbol table is after its binary.
+	let binary_end = crate::symbols::kernel_end();
 
 	let atags = Atags::get();
 	for atag in atags {
@@ -2,9 +2,12 @@ use core::fmt;
 use pi::uart::MiniUart;
 use shim::io;
 
-use crate::mutex::Mutex;
+use crate::mutex::IrqSafeMutex;
 
 /// A global singleton allowing read/write access to the console.
+///
+/// Input goes through the line discipline in `tty` before it reaches
+/// readers; the console itself only moves bytes to and from the UART.
 pub struct Console {
     inner: Option<MiniUart>,
 }
@@ -32,14 +35,24 @@ impl Console {
 
     /// Reads a byte from the UART device, blocking until a byte is available.
     pub fn read_byte(&mut self) -> u8 {
-        //self.inner.unwrap().read_yte()
         self.inner().read_byte()
     }
 
+    /// Returns `true` if there is at least one byte ready to be read.
+    pub fn has_byte(&mut self) -> bool {
+        self.inner().has_byte()
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte)
     }
+
+    /// Makes the UART raise the `Aux` interrupt when input arrives. The
+    /// interrupt must be handled by calling `tty::receive()`.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.inner().enable_rx_interrupt();
+    }
 }
 
 impl io::Read for Console {
@@ -65,7 +78,7 @@ impl fmt::Write for Console {
 }
 
 /// Global `Console` singleton.
-pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+pub static CONSOLE: IrqSafeMutex<Console> = IrqSafeMutex::new(Console::new());
 
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
@@ -7,6 +7,7 @@ use shim::ioerr;
 use shim::path::Path;
 
 pub use fat32::traits;
+use fat32::traits::FileSystem as _;
 use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
 
 use self::sd::Sd;
@@ -75,6 +76,16 @@ impl FileSystem {
     }
 }
 
+impl FileSystem {
+    /// Returns a handle to the mounted file system.
+    fn handle(&self) -> io::Result<PiVFatHandle> {
+        match &*self.0.lock() {
+            None => ioerr!(Other, "file system uninitialized"),
+            Some(vfat) => Ok(vfat.clone()),
+        }
+    }
+}
+
 // FIXME: Implement `fat32::traits::FileSystem` for `&FileSystem`
 impl fat32::traits::FileSystem for &FileSystem {
     type File = File<PiVFatHandle>;
@@ -82,12 +93,26 @@ impl fat32::traits::FileSystem for &FileSystem {
     type Entry = Entry<PiVFatHandle>;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        let option: &mut Option<PiVFatHandle> = &mut self.0.lock();
-        let vfat = match option {
-            None => return ioerr!(Other, "file system uninitialized"),
-            Some(v) => v
-        };
+        self.handle()?.open(path)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        self.handle()?.create_file(path)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        self.handle()?.create_dir(path)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        self.handle()?.remove(path)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        self.handle()?.rename(from, to)
+    }
 
-        vfat.open(path)
+    fn usage(self) -> io::Result<traits::Usage> {
+        self.handle()?.usage()
     }
 }
@@ -103,7 +103,13 @@ impl BlockDevice for Sd {
         }
     }
 
+    /// `libsd` can only read sectors: the file system keeps its changes in
+    /// its sector cache, and flushing them fails.
+    ///
+    /// # Errors
+    ///
+    /// Always returns an error of kind `Other`.
     fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+        ioerr!(Other, "the SD card is read only")
     }
 }
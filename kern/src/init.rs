@@ -1,13 +1,14 @@
 use aarch64::*;
 
 use core::mem::zeroed;
-use core::ptr::write_volatile;
+use core::ptr::{read_volatile, write_volatile};
 
 mod oom;
 mod panic;
 
 use crate::kmain;
 use crate::param::*;
+use crate::{SCHEDULER, VMM};
 
 global_asm!(include_str!("init/vectors.s"));
 
@@ -110,3 +111,55 @@ unsafe fn kinit() -> ! {
     switch_to_el1();
     kmain();
 }
+
+/// Entry point of the secondary cores. `initialize_app_cores()` writes its
+/// address to each core's spin-table slot.
+#[no_mangle]
+pub unsafe extern "C" fn start2() -> ! {
+    let core = MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize;
+    SP.set(KERN_STACK_BASE - KERN_STACK_SIZE * core);
+    kinit2()
+}
+
+unsafe fn kinit2() -> ! {
+    switch_to_el2();
+    switch_to_el1();
+    kmain2()
+}
+
+unsafe fn kmain2() -> ! {
+    // Tell core 0 that this core has left the spin table.
+    write_volatile(SPINNING_BASE.add(affinity()), 0);
+
+    // Core 0 publishes the kernel page table before enabling its own MMU, so
+    // it can be read here with the caches still off.
+    while !VMM.is_initialized() {
+        nop();
+    }
+    VMM.setup();
+
+    while !SCHEDULER.is_initialized() {
+        nop();
+    }
+    SCHEDULER.start()
+}
+
+/// Releases the secondary cores from the firmware spin table and waits until
+/// each of them has picked up its entry point.
+///
+/// The secondary cores run with their caches off until they enable their MMU,
+/// so this must be called before core 0 enables its own; otherwise the writes
+/// to the spin table could linger in core 0's cache.
+pub unsafe fn initialize_app_cores() {
+    for core in 1..NCORES {
+        write_volatile(SPINNING_BASE.add(core), start2 as usize);
+    }
+    asm!("dsb sy" :::: "volatile");
+    sev();
+
+    for core in 1..NCORES {
+        while read_volatile(SPINNING_BASE.add(core)) != 0 {
+            nop();
+        }
+    }
+}
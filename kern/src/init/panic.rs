@@ -1,8 +1,13 @@
 use core::panic::PanicInfo;
 use crate::console::{kprintln, CONSOLE};
+use crate::symbols::{backtrace, Symbolize};
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
+    // The panicking code may hold the console lock, e.g. after acquiring it
+    // recursively; it never gets to release it.
+    unsafe { CONSOLE.force_unlock() };
+
     kprintln!("            (");
     kprintln!("       (      )     )");
     kprintln!("         )   (    (");
@@ -16,5 +21,8 @@ fn panic(_info: &PanicInfo) -> ! {
     kprintln!();
     kprintln!("---------- PANIC ----------");
     kprintln!("{}", _info);
+    kprintln!();
+    kprintln!("-------- BACKTRACE --------");
+    backtrace(|addr| kprintln!("  {}", Symbolize(addr)));
     loop {}
 }
@@ -1,5 +1,6 @@
 #![feature(alloc_error_handler)]
 #![feature(const_fn)]
+#![feature(const_vec_new)]
 #![feature(decl_macro)]
 #![feature(asm)]
 #![feature(global_asm)]
@@ -21,8 +22,12 @@ pub mod fs;
 pub mod mutex;
 pub mod shell;
 pub mod param;
+pub mod percore;
 pub mod process;
+pub mod symbols;
+pub mod sync;
 pub mod traps;
+pub mod tty;
 pub mod vm;
 
 use console::kprintln;
@@ -64,6 +69,8 @@ fn kmain() -> ! {
         ALLOCATOR.initialize();
         FILESYSTEM.initialize();
         IRQ.initialize();
+        #[cfg(not(test))]
+        init::initialize_app_cores();
         VMM.initialize();
         //VMM.setup();
         SCHEDULER.initialize();
@@ -1,12 +1,30 @@
 use core::fmt;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 use core::cell::UnsafeCell;
 use core::ops::{DerefMut, Deref, Drop};
 
+use aarch64::{cli, DAIF};
+
+use crate::percore::{getcpu, is_mmu_ready, preemption_disable, preemption_enable};
+
+/// The `owner` of a lock that no core holds.
+const NO_OWNER: usize = usize::max_value();
+
+/// A ticket spinlock.
+///
+/// Each core that wants the lock takes the next ticket and waits until the
+/// lock serves it, so cores acquire a contended lock in the order they asked
+/// for it. The lock records the core holding it; a core trying to acquire a
+/// lock it already holds would deadlock, so `lock()` panics instead.
+///
+/// A kernel thread holding the lock is not preempted (see
+/// `percore::preemption_disable()`), so that no other thread on its core
+/// ends up spinning on it.
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
-    lock: AtomicBool,
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
     owner: AtomicUsize
 }
 
@@ -23,42 +41,124 @@ unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> { }
 impl<T> Mutex<T> {
     pub const fn new(val: T) -> Mutex<T> {
         Mutex {
-            lock: AtomicBool::new(false),
-            owner: AtomicUsize::new(usize::max_value()),
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            owner: AtomicUsize::new(NO_OWNER),
             data: UnsafeCell::new(val)
         }
     }
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Returns `true` if the executing core holds the lock.
+    fn is_held_here(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == getcpu()
+    }
+
+    /// Acquires the lock if no core holds or waits for it. Returns `false`
+    /// otherwise, including when the executing core already holds it.
+    ///
+    /// Until the executing core has enabled its MMU, exclusive accesses are
+    /// unavailable and tickets are taken with plain loads and stores. Only
+    /// core 0 runs kernel code that locks at that point, so this is safe.
+    fn try_acquire(&self) -> bool {
+        let ticket = self.now_serving.load(Ordering::Acquire);
+        if is_mmu_ready() {
+            if self
+                .next_ticket
+                .compare_exchange(ticket, ticket.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                return false;
+            }
+        } else {
+            if self.next_ticket.load(Ordering::Relaxed) != ticket {
+                return false;
+            }
+            self.next_ticket.store(ticket.wrapping_add(1), Ordering::Relaxed);
+        }
+
+        self.owner.store(getcpu(), Ordering::Relaxed);
+        true
+    }
+
+    /// Takes a ticket and spins until the lock serves it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the executing core already holds the lock.
+    fn acquire(&self) {
+        if self.is_held_here() {
+            panic!("core {} acquired a lock it already holds", getcpu());
+        }
+
+        if !is_mmu_ready() {
+            let ticket = self.next_ticket.load(Ordering::Relaxed);
+            self.next_ticket.store(ticket.wrapping_add(1), Ordering::Relaxed);
+            // Without other cores running, the lock is free once the ticket is
+            // taken unless an interrupted context holds it.
+            if self.now_serving.load(Ordering::Relaxed) != ticket {
+                panic!("core {} acquired a lock held by an interrupted context", getcpu());
+            }
+        } else {
+            let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+            while self.now_serving.load(Ordering::Acquire) != ticket {
+                spin_loop_hint();
+            }
+        }
+
+        self.owner.store(getcpu(), Ordering::Relaxed);
+    }
+
+    /// Releases the lock, serving the next ticket. Only the holder writes
+    /// `now_serving`, so no read-modify-write is needed.
+    fn release(&self) {
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.now_serving.store(serving.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Attempts to acquire the lock without spinning. Returns `None` if the
+    /// lock is held or contended.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let this = 0;
-        if !self.lock.load(Ordering::Relaxed) || self.owner.load(Ordering::Relaxed) == this {
-            self.lock.store(true, Ordering::Relaxed);
-            self.owner.store(this, Ordering::Relaxed);
+        preemption_disable();
+        if self.try_acquire() {
             Some(MutexGuard { lock: &self })
         } else {
+            preemption_enable();
             None
         }
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Acquires the lock, spinning until it is available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the executing core already holds the lock, which would
+    /// otherwise deadlock.
     #[inline(never)]
     pub fn lock(&self) -> MutexGuard<T> {
-        // Wait until we can "aquire" the lock, then "acquire" it.
-        loop {
-            match self.try_lock() {
-                Some(guard) => return guard,
-                None => continue
-            }
+        preemption_disable();
+        self.acquire();
+        MutexGuard { lock: &self }
+    }
+
+    /// Releases the lock if the executing core holds it, without a guard.
+    ///
+    /// This is only meant for the panic handler: the panicking core never
+    /// returns to the code holding the lock, which would otherwise stay locked
+    /// forever.
+    pub unsafe fn force_unlock(&self) {
+        if self.is_held_here() {
+            self.release();
         }
     }
+}
 
-    fn unlock(&self) {
-        self.lock.store(false, Ordering::Relaxed);
+impl<'a, T: 'a> MutexGuard<'a, T> {
+    /// Returns the lock `guard` was returned by.
+    pub fn mutex(guard: &MutexGuard<'a, T>) -> &'a Mutex<T> {
+        guard.lock
     }
 }
 
@@ -78,7 +178,8 @@ impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
 
 impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
     fn drop(&mut self) {
-        self.lock.unlock()
+        self.lock.release();
+        preemption_enable();
     }
 }
 
@@ -90,3 +191,87 @@ impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
         }
     }
 }
+
+/// A `Mutex` that masks IRQs on the executing core while it is held.
+///
+/// Locks that interrupt handlers take must be `IrqSafeMutex`es: otherwise an
+/// interrupt arriving while the interrupted code holds the lock would spin on
+/// it forever. The previous interrupt mask is restored when the lock is
+/// released, so these locks nest. With IRQs masked the holder cannot be
+/// preempted anyway, so the preemption count is left alone.
+pub struct IrqSafeMutex<T>(Mutex<T>);
+
+pub struct IrqSafeMutexGuard<'a, T: 'a> {
+    lock: &'a Mutex<T>,
+    daif: u64
+}
+
+impl<'a, T> !Send for IrqSafeMutexGuard<'a, T> { }
+unsafe impl<'a, T: Sync> Sync for IrqSafeMutexGuard<'a, T> { }
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(val: T) -> IrqSafeMutex<T> {
+        IrqSafeMutex(Mutex::new(val))
+    }
+
+    /// Attempts to acquire the lock without spinning. Returns `None`, with the
+    /// interrupt mask unchanged, if the lock is held or contended.
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<T>> {
+        let daif = unsafe { DAIF.get() };
+        unsafe { cli() };
+        if self.0.try_acquire() {
+            Some(IrqSafeMutexGuard { lock: &self.0, daif })
+        } else {
+            unsafe { DAIF.set(daif) };
+            None
+        }
+    }
+
+    /// Masks IRQs and acquires the lock, spinning until it is available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the executing core already holds the lock.
+    #[inline(never)]
+    pub fn lock(&self) -> IrqSafeMutexGuard<T> {
+        let daif = unsafe { DAIF.get() };
+        unsafe { cli() };
+        self.0.acquire();
+        IrqSafeMutexGuard { lock: &self.0, daif }
+    }
+
+    /// See `Mutex::force_unlock()`. The interrupt mask is left unchanged.
+    pub unsafe fn force_unlock(&self) {
+        self.0.force_unlock()
+    }
+}
+
+impl<'a, T: 'a> Deref for IrqSafeMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { & *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for IrqSafeMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for IrqSafeMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+        unsafe { DAIF.set(self.daif) };
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IrqSafeMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("IrqSafeMutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("IrqSafeMutex").field("data", &"<locked>").finish()
+        }
+    }
+}
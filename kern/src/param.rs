@@ -22,6 +22,9 @@ pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK;
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 pub const KERN_STACK_BASE: usize = 0x80_000;
+/// The size of each core's kernel stack. Core `n` uses the stack growing down
+/// from `KERN_STACK_BASE - n * KERN_STACK_SIZE`.
+pub const KERN_STACK_SIZE: usize = 0x10_000;
 
 /// The `tick` time.
 // FIXME: When you're ready, change this to something more reasonable.
@@ -0,0 +1,70 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use aarch64::{affinity, cli, DAIF};
+
+use crate::param::NCORES;
+
+/// Whether each core has enabled its MMU and data cache. Exclusive loads and
+/// stores only work on cacheable memory, so atomic read-modify-write
+/// operations must not be used by a core before its flag is set.
+static MMU_READY: [AtomicBool; NCORES] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// The number of `Mutex`es held by the code running on each core. A kernel
+/// thread holding one must not be switched out, as another thread on the same
+/// core could then spin on the lock forever.
+static PREEMPTION: [AtomicUsize; NCORES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Returns the core currently executing.
+pub fn getcpu() -> usize {
+    affinity()
+}
+
+/// Returns `true` if the executing core has enabled its MMU.
+pub fn is_mmu_ready() -> bool {
+    MMU_READY[getcpu()].load(Ordering::Relaxed)
+}
+
+/// Records that the executing core has enabled its MMU.
+pub fn set_mmu_ready() {
+    MMU_READY[getcpu()].store(true, Ordering::Relaxed);
+}
+
+/// Adds `delta` to the preemption count of the executing core. IRQs are
+/// masked meanwhile so that the caller is not moved to another core between
+/// reading the core ID and updating its count.
+fn add_preemption(delta: isize) {
+    unsafe {
+        let daif = DAIF.get();
+        cli();
+        let count = &PREEMPTION[getcpu()];
+        count.store((count.load(Ordering::Relaxed) as isize + delta) as usize, Ordering::Relaxed);
+        DAIF.set(daif);
+    }
+}
+
+/// Prevents the scheduler from preempting the code running on the executing
+/// core until a matching `preemption_enable()`.
+pub fn preemption_disable() {
+    add_preemption(1);
+}
+
+/// Undoes one `preemption_disable()`.
+pub fn preemption_enable() {
+    add_preemption(-1);
+}
+
+/// Returns `true` if the code running on the executing core may be switched
+/// out.
+pub fn is_preemptible() -> bool {
+    PREEMPTION[getcpu()].load(Ordering::Relaxed) == 0
+}
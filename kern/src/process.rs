@@ -1,10 +1,20 @@
+mod fd;
+mod pipe;
+pub mod policy;
 mod process;
 mod scheduler;
+mod signal;
 mod stack;
 mod state;
+pub mod thread;
+mod timer;
 
-pub use self::process::{Id, Process};
-pub use self::scheduler::GlobalScheduler;
+pub use self::fd::{Descriptor, DescriptorTable, Fd};
+pub use self::pipe::{pipe, PipeReader, PipeWriter};
+pub use self::process::{Id, Image, Process};
+pub use self::scheduler::{GlobalScheduler, ProcessInfo};
+pub use self::signal::{exit_status, Action, Signal, Signals};
 pub use self::stack::Stack;
-pub use self::state::State;
+pub use self::state::{EventPollFn, State};
+pub use self::timer::TimerQueue;
 pub use crate::param::TICK;
@@ -0,0 +1,170 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::tty::{self, TTY};
+use crate::process::{EventPollFn, PipeReader, PipeWriter};
+
+/// Type alias for the type of a file descriptor number.
+pub type Fd = usize;
+
+/// The maximum number of descriptors a process may have open at once.
+pub const MAX_FDS: usize = 32;
+
+/// An open object a file descriptor refers to.
+#[derive(Debug, Clone)]
+pub enum Descriptor {
+    /// The kernel console, through its line discipline.
+    Console,
+    /// The read end of a pipe.
+    PipeReader(PipeReader),
+    /// The write end of a pipe.
+    PipeWriter(PipeWriter),
+}
+
+impl Descriptor {
+    /// Reads bytes from this object into `buf` and returns how many were read.
+    /// `Ok(Some(0))` signals end of file.
+    ///
+    /// Returns `Ok(None)` if no data is available yet; the caller should block
+    /// until `is_ready(false)` holds and try again.
+    ///
+    /// Returns `InvalidArgument` if the object cannot be read from.
+    pub fn read(&self, buf: &mut [u8]) -> OsResult<Option<usize>> {
+        match self {
+            Descriptor::Console => Ok(TTY.lock().read(buf)),
+            Descriptor::PipeReader(reader) => Ok(reader.read(buf)),
+            Descriptor::PipeWriter(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Writes bytes from `buf` to this object and returns how many were
+    /// written.
+    ///
+    /// Returns `Ok(None)` if nothing can be written yet; the caller should
+    /// block until `is_ready(true)` holds and try again.
+    ///
+    /// Returns `InvalidArgument` if the object cannot be written to, and
+    /// `IoErrorBrokenPipe` if it is a pipe whose read end is closed.
+    pub fn write(&self, buf: &[u8]) -> OsResult<Option<usize>> {
+        match self {
+            Descriptor::Console => {
+                tty::write(buf);
+                Ok(Some(buf.len()))
+            }
+            Descriptor::PipeWriter(writer) => writer.write(buf),
+            Descriptor::PipeReader(_) => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Returns `true` if `write()` (when `write` is `true`) or `read()`
+    /// (otherwise) would not block.
+    pub fn is_ready(&self, write: bool) -> bool {
+        match self {
+            Descriptor::Console => write || TTY.lock().is_ready(),
+            Descriptor::PipeReader(reader) => !write && reader.is_ready(),
+            Descriptor::PipeWriter(writer) => write && writer.is_ready(),
+        }
+    }
+
+    /// Performs the device-specific `request` with argument `arg` on this
+    /// object and returns its result. See `tty::ioctl()` for the requests a
+    /// console supports.
+    ///
+    /// Returns `InvalidArgument` if the object does not support `request`.
+    pub fn ioctl(&self, request: u64, arg: u64) -> OsResult<u64> {
+        match self {
+            Descriptor::Console => tty::ioctl(request, arg),
+            _ => Err(OsError::InvalidArgument),
+        }
+    }
+
+    /// Returns a poll function for `State::Waiting` that reports when
+    /// `is_ready(write)` holds.
+    pub fn poll_fn(&self, write: bool) -> EventPollFn {
+        let desc = self.clone();
+        Box::new(move |_| desc.is_ready(write))
+    }
+}
+
+/// A per-process table mapping file descriptor numbers to open objects.
+#[derive(Debug, Clone)]
+pub struct DescriptorTable {
+    entries: Vec<Option<Descriptor>>,
+}
+
+impl DescriptorTable {
+    /// Returns a new descriptor table with standard input, output and error
+    /// (descriptors 0, 1 and 2) connected to the console.
+    pub fn new() -> DescriptorTable {
+        let mut entries = Vec::new();
+        for _ in 0..3 {
+            entries.push(Some(Descriptor::Console));
+        }
+        DescriptorTable { entries }
+    }
+
+    /// Returns the object referred to by `fd`, if `fd` is open.
+    pub fn get(&self, fd: Fd) -> Option<&Descriptor> {
+        self.entries.get(fd).and_then(|entry| entry.as_ref())
+    }
+
+    /// Stores `desc` in the lowest free slot and returns its descriptor
+    /// number.
+    ///
+    /// Returns `NoMemory` if the table already holds `MAX_FDS` descriptors.
+    pub fn insert(&mut self, desc: Descriptor) -> OsResult<Fd> {
+        if let Some(fd) = self.entries.iter().position(|entry| entry.is_none()) {
+            self.entries[fd] = Some(desc);
+            return Ok(fd);
+        }
+
+        if self.entries.len() >= MAX_FDS {
+            return Err(OsError::NoMemory);
+        }
+        self.entries.push(Some(desc));
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Closes `fd` and returns the object it referred to.
+    ///
+    /// Returns `InvalidArgument` if `fd` is not open.
+    pub fn remove(&mut self, fd: Fd) -> OsResult<Descriptor> {
+        self.entries
+            .get_mut(fd)
+            .and_then(|entry| entry.take())
+            .ok_or(OsError::InvalidArgument)
+    }
+
+    /// Makes `new` refer to the same object as `old`, closing whatever `new`
+    /// referred to before.
+    ///
+    /// Returns `InvalidArgument` if `old` is not open or `new` is not below
+    /// `MAX_FDS`.
+    pub fn duplicate(&mut self, old: Fd, new: Fd) -> OsResult<Fd> {
+        let desc = self.get(old).cloned().ok_or(OsError::InvalidArgument)?;
+        self.set(new, desc)?;
+        Ok(new)
+    }
+
+    /// Makes `fd` refer to `desc`, closing whatever `fd` referred to before.
+    ///
+    /// Returns `InvalidArgument` if `fd` is not below `MAX_FDS`.
+    pub fn set(&mut self, fd: Fd, desc: Descriptor) -> OsResult<()> {
+        if fd >= MAX_FDS {
+            return Err(OsError::InvalidArgument);
+        }
+
+        while self.entries.len() <= fd {
+            self.entries.push(None);
+        }
+        self.entries[fd] = Some(desc);
+        Ok(())
+    }
+
+    /// Closes every descriptor in the table.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
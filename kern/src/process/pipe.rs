@@ -0,0 +1,155 @@
+use alloc::sync::Arc;
+use core::fmt;
+
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+
+/// The capacity of a pipe's ring buffer in bytes.
+pub const PIPE_SIZE: usize = 4096;
+
+/// The ring buffer shared by both ends of a pipe, together with the number of
+/// open descriptors referring to each end.
+struct Buffer {
+    data: [u8; PIPE_SIZE],
+    head: usize,
+    len: usize,
+    readers: usize,
+    writers: usize,
+}
+
+impl Buffer {
+    /// Moves up to `buf.len()` buffered bytes into `buf`. Returns the number of
+    /// bytes moved.
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = core::cmp::min(buf.len(), self.len);
+        for byte in buf[..n].iter_mut() {
+            *byte = self.data[self.head];
+            self.head = (self.head + 1) % PIPE_SIZE;
+        }
+        self.len -= n;
+        n
+    }
+
+    /// Appends as many bytes of `buf` as fit. Returns the number of bytes
+    /// appended.
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let n = core::cmp::min(buf.len(), PIPE_SIZE - self.len);
+        for &byte in buf[..n].iter() {
+            self.data[(self.head + self.len) % PIPE_SIZE] = byte;
+            self.len += 1;
+        }
+        n
+    }
+}
+
+/// The read end of a pipe.
+///
+/// Cloning a `PipeReader` opens another descriptor for the read end; the end is
+/// closed once every clone has been dropped.
+pub struct PipeReader(Arc<Mutex<Buffer>>);
+
+/// The write end of a pipe.
+///
+/// Cloning a `PipeWriter` opens another descriptor for the write end; the end
+/// is closed once every clone has been dropped.
+pub struct PipeWriter(Arc<Mutex<Buffer>>);
+
+/// Creates a new, empty pipe and returns its read and write ends.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let buffer = Arc::new(Mutex::new(Buffer {
+        data: [0; PIPE_SIZE],
+        head: 0,
+        len: 0,
+        readers: 1,
+        writers: 1,
+    }));
+    (PipeReader(buffer.clone()), PipeWriter(buffer))
+}
+
+impl PipeReader {
+    /// Reads buffered bytes into `buf` and returns how many were read.
+    ///
+    /// Returns `Some(0)` at end of file, i.e. when the pipe is empty and its
+    /// write end is closed. Returns `None` if the pipe is empty but may still
+    /// be written to, in which case the reader should block.
+    pub fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut pipe = self.0.lock();
+        if pipe.len == 0 && pipe.writers > 0 && !buf.is_empty() {
+            return None;
+        }
+        Some(pipe.read(buf))
+    }
+
+    /// Returns `true` if `read()` would not block.
+    pub fn is_ready(&self) -> bool {
+        let pipe = self.0.lock();
+        pipe.len > 0 || pipe.writers == 0
+    }
+}
+
+impl PipeWriter {
+    /// Writes as many bytes of `buf` as fit into the pipe and returns how many
+    /// were written.
+    ///
+    /// Returns `None` if the pipe is full, in which case the writer should
+    /// block.
+    ///
+    /// # Errors
+    ///
+    /// Returns `IoErrorBrokenPipe` if the read end of the pipe is closed.
+    pub fn write(&self, buf: &[u8]) -> OsResult<Option<usize>> {
+        let mut pipe = self.0.lock();
+        if pipe.readers == 0 {
+            return Err(OsError::IoErrorBrokenPipe);
+        }
+        if pipe.len == PIPE_SIZE && !buf.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(pipe.write(buf)))
+    }
+
+    /// Returns `true` if `write()` would not block.
+    pub fn is_ready(&self) -> bool {
+        let pipe = self.0.lock();
+        pipe.len < PIPE_SIZE || pipe.readers == 0
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> PipeReader {
+        self.0.lock().readers += 1;
+        PipeReader(self.0.clone())
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> PipeWriter {
+        self.0.lock().writers += 1;
+        PipeWriter(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.lock().readers -= 1;
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.lock().writers -= 1;
+    }
+}
+
+impl fmt::Debug for PipeReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PipeReader")
+    }
+}
+
+impl fmt::Debug for PipeWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PipeWriter")
+    }
+}
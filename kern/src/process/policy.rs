@@ -0,0 +1,108 @@
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use core::fmt;
+use core::time::Duration;
+
+use kernel_api::{MAX_NICE, MIN_NICE};
+
+use crate::param::TICK;
+use crate::process::Process;
+
+mod mlfq;
+mod priority;
+mod round_robin;
+mod stride;
+
+pub use self::mlfq::MultiLevelFeedback;
+pub use self::priority::Priority;
+pub use self::round_robin::RoundRobin;
+pub use self::stride::Stride;
+
+/// Per-process bookkeeping kept on behalf of the scheduling policy.
+#[derive(Debug, Default, Clone)]
+pub struct SchedInfo {
+    /// The niceness set with `nice` or `setpriority`, from `MIN_NICE` (most
+    /// favoured) to `MAX_NICE` (least favoured).
+    pub nice: i64,
+    /// The number of times the process was passed over while ready.
+    pub age: u64,
+    /// The feedback queue level of the process; `0` is the highest.
+    pub level: usize,
+    /// The virtual time the process has consumed, in stride units.
+    pub pass: u64,
+}
+
+impl SchedInfo {
+    /// Returns the scheduling data of a child forked from a process with this
+    /// data. Only the niceness is inherited.
+    pub fn fork(&self) -> SchedInfo {
+        SchedInfo { nice: self.nice, ..SchedInfo::default() }
+    }
+}
+
+/// Clamps `nice` to the valid range of niceness values.
+pub fn clamp_nice(nice: i64) -> i64 {
+    if nice < MIN_NICE {
+        MIN_NICE
+    } else if nice > MAX_NICE {
+        MAX_NICE
+    } else {
+        nice
+    }
+}
+
+/// A policy deciding which ready process runs next and for how long.
+///
+/// The scheduler keeps every process in a queue. The process that last ran
+/// is moved to the back of the queue when it leaves the CPU, so picking the
+/// first suitable process in queue order round-robins between equals.
+pub trait Policy: fmt::Debug + Send {
+    /// Returns the name that selects this policy on the kernel command line.
+    fn name(&self) -> &'static str;
+
+    /// Prepares the scheduling data of `process`, which is about to be added
+    /// to the queue.
+    fn admit(&mut self, _process: &mut Process) {}
+
+    /// Prepares the scheduling data of `process`, which is ready again after
+    /// waiting, sleeping or being stopped.
+    fn wake(&mut self, _process: &mut Process) {}
+
+    /// Updates the scheduling data of `processes`, every process on every
+    /// core, at time `now` before the next process is picked. Adjustments
+    /// that are not tied to one run queue, such as periodic boosts, belong
+    /// here rather than in `pick`.
+    fn refresh(&mut self, _now: Duration, _processes: &mut dyn Iterator<Item = &mut Process>) {}
+
+    /// Returns the index in `processes` of the process to run next. `ready`
+    /// lists the indices of the ready processes in queue order and is never
+    /// empty; the returned index must be one of them.
+    fn pick(&mut self, processes: &mut VecDeque<Process>, ready: &[usize]) -> usize;
+
+    /// Accounts for `process` leaving the CPU after running for `ran`.
+    /// `preempted` is `true` if it used up its whole time slice.
+    fn charge(&mut self, _process: &mut Process, _ran: Duration, _preempted: bool) {}
+
+    /// Returns how long `process` may run before it is preempted.
+    fn time_slice(&self, _process: &Process) -> Duration {
+        TICK
+    }
+}
+
+/// Returns the policy selected by a `sched=<name>` argument on the kernel
+/// command line `cmdline`: `rr`, `priority`, `mlfq` or `stride`. Round-robin
+/// is used if no policy or an unknown one is given.
+pub fn from_cmdline(cmdline: &str) -> Box<dyn Policy> {
+    let name = cmdline
+        .split_whitespace()
+        .filter(|arg| arg.starts_with("sched="))
+        .map(|arg| &arg["sched=".len()..])
+        .last();
+
+    match name {
+        Some("priority") => Box::new(Priority::new()),
+        Some("mlfq") => Box::new(MultiLevelFeedback::new()),
+        Some("stride") => Box::new(Stride::new()),
+        _ => Box::new(RoundRobin),
+    }
+}
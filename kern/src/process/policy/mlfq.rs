@@ -0,0 +1,74 @@
+use alloc::collections::vec_deque::VecDeque;
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use crate::param::TICK;
+use crate::process::policy::Policy;
+use crate::process::Process;
+
+/// The number of feedback queue levels.
+const LEVELS: usize = 4;
+
+/// How often every process is moved back to the highest level.
+const BOOST_PERIOD: Duration = Duration::from_secs(1);
+
+/// A multi-level feedback queue.
+///
+/// Processes start at the highest level, `0`, and the ready process at the
+/// highest level runs, round-robin among equals. The time slice doubles with
+/// every level. A process that uses up its whole slice moves down one level,
+/// while one that blocks before the end of its slice stays, so interactive
+/// processes keep a high priority. Every `BOOST_PERIOD`, all processes on
+/// every core are moved back to level `0` so that CPU-bound ones are not
+/// starved. Niceness is ignored.
+#[derive(Debug)]
+pub struct MultiLevelFeedback {
+    next_boost: Duration,
+}
+
+impl MultiLevelFeedback {
+    /// Returns a new multi-level feedback queue policy.
+    pub fn new() -> MultiLevelFeedback {
+        MultiLevelFeedback { next_boost: current_time() + BOOST_PERIOD }
+    }
+}
+
+impl Policy for MultiLevelFeedback {
+    fn name(&self) -> &'static str {
+        "mlfq"
+    }
+
+    fn admit(&mut self, process: &mut Process) {
+        process.sched.level = 0;
+    }
+
+    fn refresh(&mut self, now: Duration, processes: &mut dyn Iterator<Item = &mut Process>) {
+        if now >= self.next_boost {
+            for process in processes {
+                process.sched.level = 0;
+            }
+            self.next_boost = now + BOOST_PERIOD;
+        }
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, ready: &[usize]) -> usize {
+        let mut best = ready[0];
+        for &i in ready[1..].iter() {
+            if processes[i].sched.level < processes[best].sched.level {
+                best = i;
+            }
+        }
+        best
+    }
+
+    fn charge(&mut self, process: &mut Process, _ran: Duration, preempted: bool) {
+        if preempted && process.sched.level + 1 < LEVELS {
+            process.sched.level += 1;
+        }
+    }
+
+    fn time_slice(&self, process: &Process) -> Duration {
+        TICK * (1 << process.sched.level)
+    }
+}
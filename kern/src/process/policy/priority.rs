@@ -0,0 +1,57 @@
+use alloc::collections::vec_deque::VecDeque;
+
+use crate::process::policy::Policy;
+use crate::process::Process;
+
+/// The number of times a ready process must be passed over to gain one level
+/// of priority.
+const AGING_STEP: u64 = 2;
+
+/// Fixed priority scheduling with aging.
+///
+/// The ready process with the lowest niceness runs, round-robin among equals.
+/// Every time a ready process is passed over it ages, and each `AGING_STEP`
+/// passes raise its effective priority by one level, so that low priority
+/// processes are not starved. A process's age is reset whenever it runs.
+/// Only the ready processes of the core picking are aged, so a process ages
+/// as it is passed over on whichever core it is queued; no aging state is
+/// shared between cores.
+#[derive(Debug)]
+pub struct Priority;
+
+impl Priority {
+    /// Returns a new priority policy.
+    pub fn new() -> Priority {
+        Priority
+    }
+}
+
+/// Returns the effective priority of `process`; lower runs first.
+fn effective(process: &Process) -> i64 {
+    process.sched.nice - (process.sched.age / AGING_STEP) as i64
+}
+
+impl Policy for Priority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, ready: &[usize]) -> usize {
+        let mut best = ready[0];
+        for &i in ready[1..].iter() {
+            if effective(&processes[i]) < effective(&processes[best]) {
+                best = i;
+            }
+        }
+
+        for &i in ready.iter() {
+            let sched = &mut processes[i].sched;
+            if i == best {
+                sched.age = 0;
+            } else {
+                sched.age += 1;
+            }
+        }
+        best
+    }
+}
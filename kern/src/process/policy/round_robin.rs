@@ -0,0 +1,18 @@
+use alloc::collections::vec_deque::VecDeque;
+
+use crate::process::policy::Policy;
+use crate::process::Process;
+
+/// Runs every ready process in turn for one `TICK`. Niceness is ignored.
+#[derive(Debug)]
+pub struct RoundRobin;
+
+impl Policy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "rr"
+    }
+
+    fn pick(&mut self, _processes: &mut VecDeque<Process>, ready: &[usize]) -> usize {
+        ready[0]
+    }
+}
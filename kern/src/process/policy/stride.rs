@@ -0,0 +1,73 @@
+use alloc::collections::vec_deque::VecDeque;
+use core::time::Duration;
+
+use kernel_api::MAX_NICE;
+
+use crate::param::TICK;
+use crate::process::policy::Policy;
+use crate::process::Process;
+
+/// The pass a process with a single ticket advances by per `TICK` of CPU time.
+const STRIDE1: u64 = 1 << 20;
+
+/// The number of tickets each step of niceness below `MAX_NICE + 1` is worth.
+const TICKETS_PER_NICE: u64 = 10;
+
+/// Stride scheduling.
+///
+/// Each process holds tickets according to its niceness, from 10 at
+/// `MAX_NICE` to 400 at `MIN_NICE`, and receives CPU time in proportion to
+/// them. A process's pass advances by its stride, inversely proportional to
+/// its tickets, for every `TICK` it runs, and the ready process with the
+/// lowest pass runs next. New processes start at the pass of the process that
+/// ran last, so they neither starve nor monopolize the CPU, and processes that
+/// wake up resume from at least that pass, so that the time they spent blocked
+/// is not made up for.
+#[derive(Debug)]
+pub struct Stride {
+    /// The pass of the process picked last, the lowest of the ready ones.
+    global_pass: u64,
+}
+
+impl Stride {
+    /// Returns a new stride scheduling policy.
+    pub fn new() -> Stride {
+        Stride { global_pass: 0 }
+    }
+}
+
+/// Returns the stride of `process`.
+fn stride(process: &Process) -> u64 {
+    let tickets = (MAX_NICE + 1 - process.sched.nice) as u64 * TICKETS_PER_NICE;
+    STRIDE1 / tickets
+}
+
+impl Policy for Stride {
+    fn name(&self) -> &'static str {
+        "stride"
+    }
+
+    fn admit(&mut self, process: &mut Process) {
+        process.sched.pass = self.global_pass;
+    }
+
+    fn wake(&mut self, process: &mut Process) {
+        process.sched.pass = core::cmp::max(process.sched.pass, self.global_pass);
+    }
+
+    fn pick(&mut self, processes: &mut VecDeque<Process>, ready: &[usize]) -> usize {
+        let mut best = ready[0];
+        for &i in ready[1..].iter() {
+            if processes[i].sched.pass < processes[best].sched.pass {
+                best = i;
+            }
+        }
+        self.global_pass = processes[best].sched.pass;
+        best
+    }
+
+    fn charge(&mut self, process: &mut Process, ran: Duration, _preempted: bool) {
+        let used = ran.as_micros() as u64 * stride(process) / TICK.as_micros() as u64;
+        process.sched.pass += core::cmp::max(used, 1);
+    }
+}
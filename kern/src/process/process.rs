@@ -1,12 +1,15 @@
 use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
 use alloc::fmt;
+use alloc::string::{String, ToString};
 use shim::io;
 use shim::path::Path;
 
 use aarch64;
 
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::policy::SchedInfo;
+use crate::process::{DescriptorTable, Signal, Signals, Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use crate::console::kprintln;
@@ -25,13 +28,172 @@ pub type Id = u64;
 pub struct Process {
     /// The saved trap frame of a process.
     pub context: Box<TrapFrame>,
-    /// The memory allocation used for the process's stack.
+    /// The memory allocation used for the process's stack. Only kernel
+    /// threads run on it; user processes have their stack in `vmap`.
     pub stack: Stack,
-    /// The page table describing the Virtual Memory of the process
-    pub vmap: Box<UserPageTable>,
+    /// The page table describing the Virtual Memory of the process, or `None`
+    /// for a kernel thread, which only runs in the kernel's address space.
+    pub vmap: Option<Box<UserPageTable>>,
     /// The scheduling state of the process.
     pub state: State,
-    curr_img: VirtualAddr
+    /// The path of the program the process runs, or the name of a kernel
+    /// thread.
+    pub name: String,
+    /// The open file descriptors of the process.
+    pub fds: DescriptorTable,
+    /// The ID of the process that forked this one, if it is still alive.
+    pub parent: Option<Id>,
+    /// Set when a child of this process exits or is stopped; cleared when a
+    /// child is reaped.
+    pub child_exited: bool,
+    /// The pending signals and signal dispositions of the process.
+    pub signals: Signals,
+    /// Set while the process is stopped by a signal; it is not scheduled
+    /// until it is continued.
+    pub stopped: bool,
+    /// The niceness and scheduling policy bookkeeping of the process.
+    pub sched: SchedInfo,
+    curr_img: VirtualAddr,
+    /// The program break: the end of the heap, which starts at `curr_img`.
+    brk: VirtualAddr,
+    /// The addresses of the pages mapped with `mmap()`.
+    mmaps: BTreeSet<usize>,
+}
+
+/// The state saved on the user stack while a signal handler runs, and
+/// restored from it by `sigreturn`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    context: TrapFrame,
+    mask: u64,
+}
+
+/// A program loaded into its own address space, before it is given to a
+/// process.
+pub struct Image {
+    /// The page table mapping the program and its stack.
+    vmap: Box<UserPageTable>,
+    /// The end of the program, where its heap starts.
+    end: VirtualAddr,
+    /// The registers the program starts with.
+    context: TrapFrame,
+}
+
+impl Image {
+    /// Opens the program stored in the given path and loads it into a new
+    /// page table. Allocates one page for stack with read/write permission,
+    /// and N pages with read/write/execute permission to load file's contents.
+    /// Sets the trapframe `context` corresponding to the page table.
+    /// `sp` - the address of stack top
+    /// `elr` - the address of image base.
+    /// `ttbr0` - the base address of kernel page table
+    /// `ttbr1` - the base address of user page table
+    /// `spsr` - `F`, `A`, `D` bit should be set.
+    ///
+    /// Returns Os Error if the file can't be opened or read.
+    fn load<P: AsRef<Path>>(pn: P) -> OsResult<Image> {
+        use crate::VMM;
+
+        let mut vmap = Box::new(UserPageTable::new());
+
+        // allocate stack in virtual space
+        let stack_base = Process::get_stack_base();
+        if !stack_base.is_aligned(PAGE_SIZE) {
+            panic!("stack base is not aligned to page size");
+        }
+        vmap.alloc(stack_base, PagePerm::RW)?;
+
+        // open file and read content into virtual space
+        let mut file_entry = FILESYSTEM.open_file(pn)?;
+        let mut read_so_far = 0;
+        let mut end = Process::get_image_base();
+
+        while read_so_far < file_entry.size() {
+            let page = vmap.alloc(end, PagePerm::RW)?;
+            read_so_far += file_entry.read(page)? as u64;
+            end += VirtualAddr::from(PAGE_SIZE);
+            if end.as_u64() > Process::get_max_va().as_u64() {
+                panic!("virtual address too big");
+            }
+        }
+
+        let mut context = TrapFrame::default();
+        context.set_sp(Process::get_stack_top().as_u64());
+        context.set_elr(Process::get_image_base().as_u64());
+        context.set_ttbr0(VMM.get_baddr().as_u64());
+        context.set_ttbr1(vmap.get_baddr().as_u64());
+        context.set_aarch64();
+        context.set_el0();
+        context.unmask_irq();
+        context.set_fiq(); //F bit
+        context.set_serror_interrupt(); //A bit
+        context.set_d(); //D bit
+
+        Ok(Image { vmap, end, context })
+    }
+
+    /// Loads the program stored at `pn` with `load()` and passes it `argv`
+    /// and `envp` with `push_args()`.
+    ///
+    /// Returns the errors of both.
+    pub fn load_with_args<P: AsRef<Path>>(pn: P, argv: &[String], envp: &[String]) -> OsResult<Image> {
+        let mut image = Image::load(pn)?;
+        image.push_args(argv, envp)?;
+        Ok(image)
+    }
+
+    /// Copies `argv` and `envp` to the top of the program's stack page and
+    /// sets up the registers passed to the program's entry point:
+    ///
+    ///   * `x0` - the number of arguments
+    ///   * `x1` - the address of an array of (pointer, length) pairs, one for
+    ///     each argument, laid out like a `[&str]`
+    ///   * `x2` - the number of environment entries
+    ///   * `x3` - the address of the environment array, laid out like `x1`
+    ///
+    /// The stack pointer is moved below the copied data.
+    ///
+    /// Returns `NoMemory` if the data does not fit in the stack page.
+    fn push_args(&mut self, argv: &[String], envp: &[String]) -> OsResult<()> {
+        let base = Process::get_stack_base().as_usize();
+        let page = self.vmap.page_mut(Process::get_stack_base()).ok_or(OsError::NoMemory)?;
+
+        let mut sp = Process::get_stack_top().as_usize();
+        let mut push = |bytes: &[u8], align: usize| -> OsResult<usize> {
+            let start = (sp.checked_sub(bytes.len()).ok_or(OsError::NoMemory)?) & !(align - 1);
+            if start < base {
+                return Err(OsError::NoMemory);
+            }
+            page[start - base..start - base + bytes.len()].copy_from_slice(bytes);
+            sp = start;
+            Ok(start)
+        };
+
+        let mut arrays = [0usize; 2];
+        for (i, strings) in [argv, envp].iter().enumerate() {
+            let mut pairs = alloc::vec::Vec::with_capacity(strings.len() * 16);
+            for string in strings.iter() {
+                let ptr = push(string.as_bytes(), 1)?;
+                pairs.extend_from_slice(&(ptr as u64).to_le_bytes());
+                pairs.extend_from_slice(&(string.len() as u64).to_le_bytes());
+            }
+            arrays[i] = push(&pairs, 16)?;
+        }
+        let sp = push(&[], 16)?;
+
+        self.context.set_sp(sp as u64);
+        self.context.set_x_register(0, argv.len() as u64);
+        self.context.set_x_register(1, arrays[0] as u64);
+        self.context.set_x_register(2, envp.len() as u64);
+        self.context.set_x_register(3, arrays[1] as u64);
+        Ok(())
+    }
+}
+
+/// Rounds `addr` up to the next page boundary, or returns `None` on overflow.
+fn page_align_up(addr: usize) -> Option<usize> {
+    addr.checked_add(PAGE_SIZE - 1).map(|addr| addr & PAGE_MASK)
 }
 
 impl Process {
@@ -41,6 +203,11 @@ impl Process {
     /// If enough memory could not be allocated to start the process, returns
     /// `None`. Otherwise returns `Some` of the new `Process`.
     pub fn new() -> OsResult<Process> {
+        Process::with_vmap(Some(Box::new(UserPageTable::new())))
+    }
+
+    /// Creates a new process like `new()` with the page table `vmap`.
+    fn with_vmap(vmap: Option<Box<UserPageTable>>) -> OsResult<Process> {
         let frame: TrapFrame = Default::default();
         let context = Box::new(frame);
         let stack = match Stack::new() {
@@ -48,69 +215,337 @@ impl Process {
             None => return Err(OsError::NoMemory)
         };
         let state = State::Ready;
-        let vmap = Box::new(UserPageTable::new());
+        let fds = DescriptorTable::new();
         let curr_img = Process::get_image_base();
-        Ok(Process{ context, stack, state, vmap , curr_img})
+        let brk = curr_img;
+        let mmaps = BTreeSet::new();
+        let signals = Signals::new();
+        Ok(Process {
+            context,
+            stack,
+            state,
+            name: String::new(),
+            vmap,
+            fds,
+            parent: None,
+            child_exited: false,
+            signals,
+            stopped: false,
+            sched: SchedInfo::default(),
+            curr_img,
+            brk,
+            mmaps,
+        })
         //Ok(Process{ context, stack, state })
     }
 
-    /// Load a program stored in the given path by calling `do_load()` method.
-    /// Set trapframe `context` corresponding to the its page table.
-    /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
-    /// `ttbr0` - the base address of kernel page table
-    /// `ttbr1` - the base address of user page table
-    /// `spsr` - `F`, `A`, `D` bit should be set.
+    /// Creates a kernel thread named `name` that runs `entry(arg)` at EL1 on
+    /// its own stack, with the kernel page table and IRQs unmasked so that it
+    /// is preempted like any user process. The thread has no user page table and no open
+    /// descriptors; `entry` must end the thread with the `exit` system call.
     ///
-    /// Returns Os Error if do_load fails.
-    pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
+    /// Returns `NoMemory` if a stack could not be allocated.
+    pub fn kernel_thread(name: &str, entry: extern "C" fn(u64) -> !, arg: u64) -> OsResult<Process> {
         use crate::VMM;
 
-        let mut p = Process::do_load(pn)?;
+        let mut p = Process::with_vmap(None)?;
+        p.name = String::from(name);
+        p.fds.clear();
 
-        //FIXME: Set trapframe for the process.
-        p.context.set_sp(Self::get_stack_top().as_u64());
-        p.context.set_elr(Self::get_image_base().as_u64());
-        p.context.set_ttbr0(VMM.get_baddr().as_u64());
-        p.context.set_ttbr1(p.vmap.get_baddr().as_u64());
+        let kern_baddr = VMM.get_baddr().as_u64();
+        p.context.set_sp(p.stack.top().as_u64());
+        p.context.set_elr(entry as usize as u64);
+        p.context.set_x_register(0, arg);
+        p.context.set_ttbr0(kern_baddr);
+        p.context.set_ttbr1(kern_baddr);
         p.context.set_aarch64();
-        p.context.set_el0();
+        p.context.set_el1t();
         p.context.unmask_irq();
-        p.context.set_fiq(); //F bit
-        p.context.set_serror_interrupt(); //A bit
-        p.context.set_d(); //D bit
+        p.context.set_fiq();
+        p.context.set_serror_interrupt();
+        p.context.set_d();
 
         Ok(p)
     }
 
-    /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
-    fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        // creating the process
-        let mut process = Self::new()?;
+    /// Returns `true` if this is a kernel thread rather than a user process.
+    pub fn is_kernel_thread(&self) -> bool {
+        self.vmap.is_none()
+    }
 
-        // allocate stack in virtual space
-        let stack_base = Self::get_stack_base();
-        if !stack_base.is_aligned(PAGE_SIZE) {
-            panic!("stack base is not aligned to page size");
+    /// Returns the user page table of this process.
+    ///
+    /// Returns `InvalidArgument` for a kernel thread, which has none.
+    fn user_vmap(&mut self) -> OsResult<&mut UserPageTable> {
+        match self.vmap {
+            Some(ref mut vmap) => Ok(vmap),
+            None => Err(OsError::InvalidArgument),
         }
-        let stack_page = process.vmap.alloc(stack_base, PagePerm::RW);
+    }
 
-        // open file and read content into virtual space
-        let mut file_entry = FILESYSTEM.open_file(pn)?;
-        let mut read_so_far = 0;
+    /// Load a program stored in the given path by calling `Image::load()`.
+    ///
+    /// Returns Os Error if `Image::load()` fails.
+    pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
+        let image = Image::load(&pn)?;
+        Process::with_image(pn, image)
+    }
 
-        while read_so_far < file_entry.size() {
-            let page = process.vmap.alloc(process.curr_img, PagePerm::RW);
-            read_so_far += file_entry.read(page)? as u64;
-            process.curr_img += VirtualAddr::from(PAGE_SIZE);
-            if process.curr_img.as_u64() > Self::get_max_va().as_u64() {
-                panic!("virtual address too big");
+    /// Loads the program stored at `pn` like `load()` and passes it `argv` and
+    /// `envp` (see `Image::push_args()`).
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoEntry` if there is no file at `pn`, the `OsError` converted
+    /// from the file system's `io::Error` if it can't be read, and `NoMemory`
+    /// if `argv` and `envp` do not fit in the page of user stack or no kernel
+    /// stack could be allocated for the process.
+    pub fn load_with_args<P: AsRef<Path>>(pn: P, argv: &[String], envp: &[String]) -> OsResult<Process> {
+        let image = Image::load_with_args(&pn, argv, envp)?;
+        Process::with_image(pn, image)
+    }
+
+    /// Creates a process named after `pn` running the program `image`.
+    ///
+    /// Returns `NoMemory` if a stack could not be allocated.
+    fn with_image<P: AsRef<Path>>(pn: P, image: Image) -> OsResult<Process> {
+        let mut p = Process::with_vmap(Some(image.vmap))?;
+        p.name = pn.as_ref().display().to_string();
+        p.curr_img = image.end;
+        p.brk = image.end;
+        *p.context = image.context;
+        Ok(p)
+    }
+
+    /// Creates a child of this process, whose current trap frame is `tf`.
+    ///
+    /// The child receives a copy of `tf`, a copy-on-write duplicate of this
+    /// process's address space (see `UserPageTable::fork()`) and a copy of its
+    /// descriptor table. The child's `fork` returns `0`; setting the parent's
+    /// return value is left to the caller.
+    ///
+    /// Returns `NoMemory` if a stack or page table for the child could not be
+    /// allocated, and `InvalidArgument` if this is a kernel thread.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Process> {
+        let vmap = Box::new(self.user_vmap()?.fork()?);
+        let stack = Stack::new().ok_or(OsError::NoMemory)?;
+
+        let mut context = Box::new(*tf);
+        context.set_ttbr1(vmap.get_baddr().as_u64());
+        context.set_x_register(0, 0);
+        context.set_x_register(7, OsError::Ok as u64);
+
+        Ok(Process {
+            context,
+            stack,
+            vmap: Some(vmap),
+            state: State::Ready,
+            name: self.name.clone(),
+            fds: self.fds.clone(),
+            parent: Some(tf.get_tpidr()),
+            child_exited: false,
+            signals: self.signals.fork(),
+            stopped: false,
+            sched: self.sched.fork(),
+            curr_img: self.curr_img,
+            brk: self.brk,
+            mmaps: self.mmaps.clone(),
+        })
+    }
+
+    /// Replaces the program of this process, whose current trap frame is `tf`,
+    /// with `image`, the program stored at `pn`.
+    ///
+    /// The image is loaded beforehand with `Image::load_with_args()`, so that
+    /// the file system is not read while the process is borrowed from the
+    /// scheduler. `tf` is replaced by the new program's trap frame; the
+    /// process ID, parent, descriptor table and kernel stack are kept. Signal
+    /// handlers are reset to their default action.
+    pub fn exec<P: AsRef<Path>>(&mut self, pn: P, mut image: Image, tf: &mut TrapFrame) {
+        image.context.set_tpidr(tf.get_tpidr());
+
+        self.vmap = Some(image.vmap);
+        self.curr_img = image.end;
+        self.brk = image.end;
+        self.mmaps.clear();
+        self.signals.reset_handlers();
+        self.name = pn.as_ref().display().to_string();
+        *self.context = image.context;
+        *tf = image.context;
+    }
+
+    /// Sets up `tf`, the trap frame this process resumes with, to run the
+    /// signal handler at `handler` for `sig`.
+    ///
+    /// The registers in `tf` and the blocked signal set are saved in a
+    /// `SignalFrame` pushed on the user stack, and `sig` is blocked until the
+    /// handler returns. The handler is entered with `sig` in `x0`, the stack
+    /// pointer at the frame and `restorer`, which must issue `sigreturn`, as
+    /// its return address.
+    ///
+    /// Returns `BadAddress` if the frame does not fit on the user stack.
+    pub fn push_signal_frame(
+        &mut self,
+        sig: Signal,
+        handler: u64,
+        restorer: u64,
+        tf: &mut TrapFrame,
+    ) -> OsResult<()> {
+        let frame = SignalFrame { context: *tf, mask: self.signals.mask() as u64 };
+        let size = core::mem::size_of::<SignalFrame>();
+        let sp = tf.get_sp().checked_sub(size as u64).ok_or(OsError::BadAddress)? & !0xf;
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&frame as *const SignalFrame as *const u8, size)
+        };
+        self.user_vmap()?.copy_to_user(VirtualAddr::from(sp), bytes)?;
+
+        self.signals.block(sig);
+        tf.set_sp(sp);
+        tf.set_elr(handler);
+        tf.set_lr(restorer);
+        tf.set_x_register(0, sig);
+        Ok(())
+    }
+
+    /// Restores the state saved by `push_signal_frame()` from the frame at the
+    /// stack pointer of `tf` once a signal handler has returned. Only the
+    /// registers user code may change are restored (see
+    /// `TrapFrame::restore_user_state()`).
+    ///
+    /// Returns `BadAddress` if the frame is not mapped.
+    pub fn pop_signal_frame(&mut self, tf: &mut TrapFrame) -> OsResult<()> {
+        let mut frame = SignalFrame { context: TrapFrame::default(), mask: 0 };
+        let size = core::mem::size_of::<SignalFrame>();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(&mut frame as *mut SignalFrame as *mut u8, size)
+        };
+        if !self.user_vmap()?.copy_from_user(VirtualAddr::from(tf.get_sp()), bytes) {
+            return Err(OsError::BadAddress);
+        }
+
+        tf.restore_user_state(&frame.context);
+        self.signals.set_mask(frame.mask as u32);
+        Ok(())
+    }
+
+    /// Returns the current program break.
+    pub fn brk(&self) -> VirtualAddr {
+        self.brk
+    }
+
+    /// Moves the program break to `new`, mapping zeroed pages when the heap
+    /// grows and unmapping them when it shrinks. Returns the new break.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `new` lies below the end of the program
+    /// image, `NoVmSpace` if the heap would run into the stack or another
+    /// mapping, and `NoMemory` if pages could not be allocated. The break is
+    /// left unchanged on error.
+    pub fn set_brk(&mut self, new: VirtualAddr) -> OsResult<VirtualAddr> {
+        if new.as_usize() < self.curr_img.as_usize() {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let old_end = page_align_up(self.brk.as_usize()).ok_or(OsError::NoVmSpace)?;
+        let new_end = page_align_up(new.as_usize()).ok_or(OsError::NoVmSpace)?;
+        if new_end > Self::get_stack_base().as_usize() {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let mut page = old_end;
+        while page < new_end {
+            if let Err(e) = self.user_vmap()?.alloc_zeroed(VirtualAddr::from(page), PagePerm::RW) {
+                self.unmap_range(old_end, page);
+                return Err(e);
             }
+            page += PAGE_SIZE;
+        }
+        self.unmap_range(new_end, old_end);
+
+        self.brk = new;
+        Ok(new)
+    }
+
+    /// Maps `len` bytes, rounded up to whole pages, of zeroed anonymous memory
+    /// between the heap and the stack and returns its address.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `len` is zero, `NoVmSpace` if no large
+    /// enough unmapped range is left, and `NoMemory` if pages could not be
+    /// allocated.
+    pub fn mmap(&mut self, len: usize) -> OsResult<VirtualAddr> {
+        if len == 0 {
+            return Err(OsError::InvalidArgument);
         }
 
-        Ok(process)
+        let size = page_align_up(len).ok_or(OsError::NoVmSpace)?;
+        let floor = page_align_up(self.brk.as_usize()).ok_or(OsError::NoVmSpace)?;
+        let vmap = self.user_vmap()?;
+
+        // Search downwards from the stack for `size` bytes of unmapped pages.
+        let mut end = Self::get_stack_base().as_usize();
+        let start = loop {
+            let start = match end.checked_sub(size) {
+                Some(start) if start >= floor => start,
+                _ => return Err(OsError::NoVmSpace),
+            };
+
+            let used = (start..end)
+                .step_by(PAGE_SIZE)
+                .filter(|&page| vmap.is_valid(VirtualAddr::from(page)))
+                .last();
+            match used {
+                Some(page) => end = page,
+                None => break start,
+            }
+        };
+
+        for page in (start..start + size).step_by(PAGE_SIZE) {
+            if let Err(e) = self.user_vmap()?.alloc_zeroed(VirtualAddr::from(page), PagePerm::RW) {
+                self.unmap_range(start, page);
+                return Err(e);
+            }
+            self.mmaps.insert(page);
+        }
+        Ok(VirtualAddr::from(start))
+    }
+
+    /// Unmaps the `len` bytes, rounded up to whole pages, starting at the
+    /// page-aligned address `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `addr` is not page-aligned or if any page
+    /// in the range was not mapped with `mmap()`. Nothing is unmapped in that
+    /// case.
+    pub fn munmap(&mut self, addr: VirtualAddr, len: usize) -> OsResult<()> {
+        let start = addr.as_usize();
+        let end = page_align_up(len)
+            .and_then(|size| start.checked_add(size))
+            .ok_or(OsError::InvalidArgument)?;
+        if !addr.is_aligned(PAGE_SIZE) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        if (start..end).step_by(PAGE_SIZE).any(|page| !self.mmaps.contains(&page)) {
+            return Err(OsError::InvalidArgument);
+        }
+        self.unmap_range(start, end);
+        Ok(())
+    }
+
+    /// Unmaps every page in `[start, end)` and forgets any `mmap()` record of
+    /// them.
+    fn unmap_range(&mut self, start: usize, end: usize) {
+        for page in (start..end).step_by(PAGE_SIZE) {
+            if let Some(ref mut vmap) = self.vmap {
+                vmap.free(VirtualAddr::from(page));
+            }
+            self.mmaps.remove(&page);
+        }
     }
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
@@ -143,6 +578,10 @@ impl Process {
     ///
     ///   * The state is currently `Ready`.
     ///
+    ///   * The process is waiting or sleeping and a signal that has an effect
+    ///     is pending. The wait is abandoned; system calls that block restart
+    ///     once the signal has been handled.
+    ///
     ///   * An event being waited for has arrived.
     ///
     ///     If the process is currently waiting, the corresponding event
@@ -150,12 +589,26 @@ impl Process {
     ///     occured. If it has, the state is switched to `Ready` and this
     ///     function returns `true`.
     ///
-    /// Returns `false` in all other cases.
+    /// Returns `false` in all other cases, and always while the process is
+    /// stopped.
     pub fn is_ready(&mut self) -> bool {
         //kprintln!("checking ready");
         use core::mem::replace;
         use State::*;
         use crate::console::kprintln;
+        if self.stopped {
+            return false;
+        }
+
+        let blocked = match self.state {
+            Waiting(_) | Sleeping(_) => true,
+            _ => false,
+        };
+        if blocked && self.signals.is_deliverable() {
+            self.state = State::Ready;
+            return true;
+        }
+
         match self.state {
             Ready => {
                 //kprintln!("it's ready doe");
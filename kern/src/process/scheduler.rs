@@ -1,29 +1,52 @@
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
 use core::fmt;
 
 use aarch64::*;
 use core::time::Duration;
-use pi::timer::tick_in;
+use pi::timer::current_time;
 use crate::IRQ;
-use pi::interrupt::{Interrupt, Controller};
-
-use crate::console::{kprintln, kprint};
-
-use crate::mutex::Mutex;
-use crate::param::{PAGE_MASK, PAGE_SIZE, TICK, USER_IMG_BASE};
-use crate::process::{Id, Process, State};
+use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::{local_tick_in, LocalController, LocalInterrupt};
+use pi::atags::Atags;
+
+use crate::console::{kprintln, kprint, CONSOLE};
+
+use crate::mutex::IrqSafeMutex;
+use crate::param::{KERN_STACK_BASE, KERN_STACK_SIZE, NCORES, PAGE_MASK, PAGE_SIZE, USER_IMG_BASE};
+use crate::percore::{getcpu, is_preemptible};
+use crate::process::policy::{self, clamp_nice, Policy};
+use crate::process::{exit_status, thread, Action, Id, Process, Signal, State, TimerQueue};
+use crate::shell;
 use crate::traps::TrapFrame;
+use crate::tty;
 use crate::VMM;
+use kernel_api::{OsError, OsResult, SIGCHLD, SIGCONT, SIGKILL, SIGSEGV};
+
+/// A snapshot of a process, as returned by `GlobalScheduler::list()`.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub id: Id,
+    pub parent: Option<Id>,
+    pub name: String,
+    /// The core whose run queue holds the process.
+    pub core: usize,
+    pub state: &'static str,
+    pub nice: i64,
+    pub is_kernel_thread: bool,
+}
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
-pub struct GlobalScheduler(Mutex<Option<Scheduler>>);
+pub struct GlobalScheduler(IrqSafeMutex<Option<Scheduler>>);
 
 impl GlobalScheduler {
     /// Returns an uninitialized wrapper around a local scheduler.
     pub const fn uninitialized() -> GlobalScheduler {
-        GlobalScheduler(Mutex::new(None))
+        GlobalScheduler(IrqSafeMutex::new(None))
     }
 
     /// Enter a critical region and execute the provided closure with the
@@ -36,6 +59,11 @@ impl GlobalScheduler {
         f(guard.as_mut().expect("scheduler uninitialized"))
     }
 
+    /// Returns `true` once `initialize()` has set up the scheduler.
+    pub fn is_initialized(&self) -> bool {
+        self.0.lock().is_some()
+    }
+
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
@@ -70,33 +98,174 @@ impl GlobalScheduler {
         self.critical(|scheduler| scheduler.kill(tf))
     }
 
-    /// Starts executing processes in user space using timer interrupt based
-    /// preemptive scheduling. This method should not return under normal conditions.
+    /// Terminates the currently running process with exit status `status`
+    /// and returns that process's ID.
+    /// For more details, see the documentation on `Scheduler::exit()`.
+    #[must_use]
+    pub fn exit(&self, status: i32, tf: &mut TrapFrame) -> Option<Id> {
+        self.critical(|scheduler| scheduler.exit(status, tf))
+    }
+
+    /// Collects an exited child of the process `parent`.
+    /// For more details, see the documentation on `Scheduler::reap()`.
+    pub fn reap(&self, parent: Id, child: Option<Id>) -> OsResult<Option<(Id, i32)>> {
+        self.critical(|scheduler| scheduler.reap(parent, child))
+    }
+
+    /// Returns a snapshot of every process, ordered by process ID.
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        let mut list = self.critical(|scheduler| {
+            let mut list = Vec::new();
+            for (core, queue) in scheduler.queues.iter().enumerate() {
+                for proc in queue.processes.iter() {
+                    let state = match proc.state {
+                        _ if proc.stopped => "stopped",
+                        State::Ready => "ready",
+                        State::Running => "running",
+                        State::Waiting(_) => "waiting",
+                        State::Sleeping(_) => "sleeping",
+                        State::Zombie(_) => "zombie",
+                        State::Dead => "dead",
+                    };
+                    list.push(ProcessInfo {
+                        id: proc.context.get_tpidr(),
+                        parent: proc.parent,
+                        name: proc.name.clone(),
+                        core,
+                        state,
+                        nice: proc.sched.nice,
+                        is_kernel_thread: proc.is_kernel_thread(),
+                    });
+                }
+            }
+            list
+        });
+        list.sort_by_key(|info| info.id);
+        list
+    }
+
+    /// Sends the signal `sig` to the process `id`.
+    /// For more details, see the documentation on `Scheduler::signal()`.
+    pub fn signal(&self, id: Id, sig: Signal) -> OsResult<()> {
+        self.critical(|scheduler| scheduler.signal(id, sig))
+    }
+
+    /// Sends the signal `sig` to the foreground process, if there is one.
+    pub fn signal_foreground(&self, sig: Signal) {
+        self.critical(|scheduler| {
+            if let Some(id) = scheduler.foreground {
+                let _ = scheduler.signal(id, sig);
+            }
+        });
+    }
+
+    /// Makes `id` the foreground process, which receives the signals typed on
+    /// the console (see `tty::Tty::input()`).
+    pub fn set_foreground(&self, id: Option<Id>) {
+        self.critical(|scheduler| scheduler.foreground = id);
+    }
+
+    /// Delivers the pending signals of the process about to resume with `tf`,
+    /// switching to another process whenever one is terminated or stopped.
+    /// For more details, see the documentation on
+    /// `Scheduler::deliver_signal()`.
+    pub fn deliver_signals(&self, tf: &mut TrapFrame) {
+        while self.critical(|scheduler| scheduler.deliver_signal(tf)) {
+            self.switch_to(tf);
+        }
+    }
+
+    /// Sets the niceness of the process `id` and returns the new value.
+    /// For more details, see the documentation on `Scheduler::set_nice()`.
+    pub fn set_nice(&self, id: Id, nice: i64) -> OsResult<i64> {
+        self.critical(|scheduler| scheduler.set_nice(id, nice))
+    }
+
+    /// Returns the niceness of the process `id`, or `NoEntry` if there is no
+    /// such process.
+    pub fn nice(&self, id: Id) -> OsResult<i64> {
+        self.critical(|scheduler| {
+            scheduler.find(id).map(|proc| proc.sched.nice).ok_or(OsError::NoEntry)
+        })
+    }
+
+    /// Puts the current process to sleep until `deadline`, measured like
+    /// `current_time()`, and switches to the next process. For more details,
+    /// see the documentation on `Scheduler::sleep()`.
+    pub fn sleep(&self, deadline: Duration, tf: &mut TrapFrame) -> Id {
+        self.critical(|scheduler| scheduler.sleep(deadline, tf));
+        self.switch_to(tf)
+    }
+
+    /// Handles a timer interrupt taken with trap frame `tf`. Sleeping
+    /// processes whose deadline has passed are woken up, and the current
+    /// process is preempted once its time slice is used up, unless it is a
+    /// kernel thread holding a `Mutex`.
+    pub fn tick(&self, tf: &mut TrapFrame) {
+        let preemptible = is_preemptible();
+        if self.critical(|scheduler| scheduler.tick(current_time(), preemptible)) {
+            self.switch(State::Ready, tf);
+        }
+    }
+
+    /// Starts executing processes in user space on the executing core using
+    /// timer interrupt based preemptive scheduling. Every core calls this
+    /// method once it is initialized; each one is driven by its own generic
+    /// timer. This method should not return under normal conditions.
     pub fn start(&self) -> ! {
         use crate::SCHEDULER;
-        let mut controller = Controller::new();
-        controller.enable(Interrupt::Timer1);
-        tick_in(TICK);
-        IRQ.register(Interrupt::Timer1, Box::new(|frame| {
-            tick_in(TICK);
-            SCHEDULER.switch(State::Ready, frame);
+        let core = getcpu();
+        IRQ.register_local(LocalInterrupt::CntPnsIrq, Box::new(|frame| {
+            SCHEDULER.tick(frame);
         }));
+        LocalController::new(core).enable_local_timer();
+
+        // Shared interrupts are only delivered to core 0, which takes console
+        // input and sends the signals typed to the foreground process.
+        if core == 0 {
+            IRQ.register(Interrupt::Aux, Box::new(|_| {
+                if let Some(sig) = tty::receive() {
+                    SCHEDULER.signal_foreground(sig);
+                }
+            }));
+            CONSOLE.lock().enable_rx_interrupt();
+            Controller::new().enable(Interrupt::Aux);
+        }
+
+        // Generate a periodic event so that a core idling in `wfe` in
+        // `switch_to()` rechecks for work, such as processes woken up or
+        // added by other cores, about every millisecond.
+        unsafe {
+            CNTKCTL_EL1.set(CNTKCTL_EL1.get() | CNTKCTL_EL1::EVNTEN | (IDLE_EVENT_BIT << 4));
+        }
 
         //let process_id = SCHEDULER.switch_to(&mut trap_frame);
         let mut tf = Box::new(TrapFrame::default());
         self.switch_to(tf.as_mut());
+        let stack_top = KERN_STACK_BASE - KERN_STACK_SIZE * core;
         unsafe {
-            asm!("mov sp, $0
-                  bl context_restore
-
-                  adr x0, _start
-                  mov sp, x0
+            // The trap frame is copied to the top of this core's kernel stack
+            // and restored like HANDLER does: `context_restore` restores x0-x27,
+            // then x28, x29 and `lr` are popped. `sp` is left at the top of
+            // the stack for the exceptions taken from the process.
+            asm!("mov sp, x1
+                  sub sp, sp, $2
+                  mov x2, xzr
+              1:  ldr x3, [x0, x2]
+                  str x3, [sp, x2]
+                  add x2, x2, #8
+                  cmp x2, $2
+                  b.lo 1b
 
-                  mov lr, xzr
+                  bl context_restore
+                  ldp x28, x29, [sp], #16
+                  ldp lr, xzr, [sp], #16
                   eret"
                   :
-                  :"r"(tf)
-                  :"x0"
+                  :"{x0}"(tf.as_ref() as *const TrapFrame),
+                   "{x1}"(stack_top),
+                   "i"(TRAP_FRAME_SIZE)
+                  :"x2", "x3"
                   :"volatile");
             //asm!("mov sp, $0
                   //bl context_restore
@@ -118,26 +287,20 @@ impl GlobalScheduler {
         loop {}
     }
 
-    /// Initializes the scheduler and add userspace processes to the Scheduler
+    /// Initializes the scheduler and starts the kernel shell in a kernel
+    /// thread. User programs are started from the shell.
+    ///
+    /// The scheduling policy is selected with a `sched=<name>` argument on the
+    /// kernel command line (see `policy::from_cmdline()`).
     pub unsafe fn initialize(&self) {
-        let mut scheduler = Scheduler::new();
-        *self.0.lock() = Some(scheduler);
-
-        use shim::path::PathBuf;
-        let proc1 = Process::load(PathBuf::from("/fib.bin")).unwrap();
-        self.add(proc1);
-
-        let proc2 = Process::load(PathBuf::from("/fib.bin")).unwrap();
-        self.add(proc2);
-
-        let proc3 = Process::load(PathBuf::from("/fib.bin")).unwrap();
-        self.add(proc3);
+        let cmdline = Atags::get().filter_map(|atag| atag.cmd()).next().unwrap_or("");
+        let policy = policy::from_cmdline(cmdline);
+        kprintln!("scheduler: using the {} policy", policy.name());
 
-        let proc4 = Process::load(PathBuf::from("/fib.bin")).unwrap();
-        self.add(proc4);
+        let mut scheduler = Scheduler::new(policy);
+        *self.0.lock() = Some(scheduler);
 
-        let proc5 = Process::load(PathBuf::from("/fib.bin")).unwrap();
-        self.add(proc5);
+        thread::spawn("shell", || shell::shell("> ")).expect("failed to start the shell");
     }
 
 
@@ -148,8 +311,8 @@ impl GlobalScheduler {
     pub fn test_phase_3(&self, proc: &mut Process){
         use crate::vm::{VirtualAddr, PagePerm};
 
-        let mut page = proc.vmap.alloc(
-         VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX);
+        let mut page = proc.vmap.as_mut().unwrap().alloc(
+         VirtualAddr::from(USER_IMG_BASE as u64), PagePerm::RWX).expect("failed to allocate a page");
 
         let text = unsafe {
          core::slice::from_raw_parts(test_user_process as *const u8, 24)
@@ -160,28 +323,122 @@ impl GlobalScheduler {
     }
 }
 
+/// The run queue of a single core.
 #[derive(Debug)]
-pub struct Scheduler {
+struct RunQueue {
     processes: VecDeque<Process>,
+    /// The time at which the process running on the core was scheduled in.
+    slice_start: Duration,
+    /// The time at which the process running on the core is preempted.
+    slice_end: Duration,
+}
+
+impl RunQueue {
+    fn new() -> RunQueue {
+        RunQueue {
+            processes: VecDeque::new(),
+            slice_start: Duration::from_secs(0),
+            slice_end: Duration::from_secs(0),
+        }
+    }
+
+    /// Returns the number of processes in the queue that are running or ready
+    /// to run.
+    fn load(&self) -> usize {
+        self.processes
+            .iter()
+            .filter(|proc| match proc.state {
+                State::Running => true,
+                _ => is_migratable(proc),
+            })
+            .count()
+    }
+}
+
+/// Returns the process with ID `id` in any of `queues`. Unlike
+/// `Scheduler::find_mut()`, it leaves the other fields of the scheduler, such
+/// as its policy, free to borrow.
+fn find_in(queues: &mut [RunQueue], id: Id) -> Option<&mut Process> {
+    queues.iter_mut().flat_map(|queue| queue.processes.iter_mut()).find(|proc| proc.context.get_tpidr() == id)
+}
+
+/// Returns `true` if `proc` may be moved to another core's run queue: it is
+/// ready to run but not running.
+fn is_migratable(proc: &Process) -> bool {
+    match proc.state {
+        State::Ready => !proc.stopped,
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    /// One run queue per core. A process stays in the queue of the core it was
+    /// assigned to until load balancing moves it.
+    queues: Vec<RunQueue>,
     last_id: Option<Id>,
+    foreground: Option<Id>,
+    /// Decides which ready process runs next and for how long.
+    policy: Box<dyn Policy>,
+    /// Wake-up deadlines of sleeping processes.
+    timers: TimerQueue,
+    /// The last time the run queues were balanced.
+    last_balance: Duration,
 }
 
+/// The shortest delay the timer is programmed with, so that its compare value
+/// is not already in the past when it is written.
+const MIN_TIMER_DELAY: Duration = Duration::from_micros(50);
+
+/// The size of a `TrapFrame`. Its 16 byte alignment pads it with the slot
+/// for `xzr` that HANDLER pushes after `lr`.
+const TRAP_FRAME_SIZE: usize = core::mem::size_of::<TrapFrame>();
+
+/// How often the run queues are balanced.
+const BALANCE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The counter bit whose transitions generate the idle event stream. With the
+/// Raspberry Pi's 19.2MHz counter, bit 14 rises about every 1.7ms.
+const IDLE_EVENT_BIT: u64 = 14;
+
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
-    fn new() -> Scheduler {
-        let processes = VecDeque::new();
+    /// Returns a new `Scheduler` with an empty run queue for every core,
+    /// scheduling processes according to `policy`.
+    fn new(policy: Box<dyn Policy>) -> Scheduler {
+        let queues = (0..NCORES).map(|_| RunQueue::new()).collect();
         let last_id = None;
+        let foreground = None;
+        let timers = TimerQueue::new();
+        let last_balance = Duration::from_secs(0);
 
-        Self{ processes, last_id }
+        Self{ queues, last_id, foreground, policy, timers, last_balance }
     }
 
-    /// Adds a process to the scheduler's queue and returns that process's ID if
-    /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
+    /// Returns the run queue of the executing core.
+    fn queue(&mut self) -> &mut RunQueue {
+        &mut self.queues[getcpu()]
+    }
+
+    /// Returns an iterator over every process on every core.
+    fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.queues.iter().flat_map(|queue| queue.processes.iter())
+    }
+
+    /// Returns a mutable iterator over every process on every core.
+    fn processes_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.queues.iter_mut().flat_map(|queue| queue.processes.iter_mut())
+    }
+
+    /// Adds a process to the least loaded run queue and returns that process's
+    /// ID if a new process can be scheduled. The process ID is newly allocated
+    /// for the process and saved in its `trap_frame`. If no further processes
+    /// can be scheduled, returns `None`.
     ///
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
+    ///
+    /// If there is no foreground process and the new process is not a kernel
+    /// thread, it becomes the foreground process.
     fn add(&mut self, mut process: Process) -> Option<Id> {
         let id = match self.last_id {
             None => {0},
@@ -189,89 +446,414 @@ impl Scheduler {
         };
 
         process.context.set_tpidr(id);
-        self.processes.push_back(process);
+        let is_kernel_thread = process.is_kernel_thread();
+        self.policy.admit(&mut process);
+        let core = (0..NCORES).min_by_key(|&core| self.queues[core].load()).unwrap_or(0);
+        self.queues[core].processes.push_back(process);
         self.last_id = Some(id);
+        if self.foreground.is_none() && !is_kernel_thread {
+            self.foreground = Some(id);
+        }
+
+        // Wake up the core if it is idle.
+        sev();
         self.last_id
     }
 
-    /// Finds the currently running process, sets the current process's state
-    /// to `new_state`, prepares the context switch on `tf` by saving `tf`
-    /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// Finds the currently running process in the executing core's run
+    /// queue, sets the current process's state to `new_state`, prepares the
+    /// context switch on `tf` by saving `tf` into the current process, and
+    /// push the current process back to the end of the run queue.
     ///
-    /// If the `processes` queue is empty or there is no current process,
-    /// returns `false`. Otherwise, returns `true`.
+    /// If the run queue is empty or there is no current process, returns
+    /// `false`. Otherwise, returns `true`.
     fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
-        let mut del_idx = self.processes.len();
-        for i in 0..self.processes.len() {
-            let proc = self.processes.get(i).expect("can't get process");
-            if proc.context.get_tpidr() == tf.get_tpidr() {
-                del_idx = i;
-                break;
-            }
-        }
-
-        if del_idx == self.processes.len() {
-            return false;
-        }
+        let queue = &mut self.queues[getcpu()];
+        let del_idx = match queue
+            .processes
+            .iter()
+            .position(|proc| proc.context.get_tpidr() == tf.get_tpidr())
+        {
+            Some(i) => i,
+            None => return false,
+        };
 
-        let mut proc = self.processes.remove(del_idx).expect("failed to remove");
+        let mut proc = queue.processes.remove(del_idx).expect("failed to remove");
+        let now = current_time();
+        let ran = now.checked_sub(queue.slice_start).unwrap_or_default();
+        self.policy.charge(&mut proc, ran, now >= queue.slice_end);
         proc.state = new_state;
         *proc.context = *tf;
-        self.processes.push_back(proc);
+        queue.processes.push_back(proc);
         return true;
     }
 
-    /// Finds the next process to switch to, brings the next process to the
-    /// front of the `processes` queue, changes the next process's state to
-    /// `Running`, and performs context switch by restoring the next process`s
-    /// trap frame into `tf`.
+    /// Finds the next process to switch to on the executing core, as chosen
+    /// by the scheduling policy among the ready processes of its run queue,
+    /// brings the next process to the front of the queue, changes the next
+    /// process's state to `Running`, and performs context switch by restoring
+    /// the next process`s trap frame into `tf`. If the core has no ready
+    /// process, one is first taken from the busiest other core.
     ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        // print scheduling queue
-
-
-        let mut del_idx = self.processes.len();
-        for i in 0..self.processes.len() {
-            let proc = self.processes.get_mut(i).expect("can't get proc"); //is ready needs mut
-            if proc.is_ready() {
-                del_idx = i;
-                break;
-            }
+        let core = getcpu();
+        let now = current_time();
+        self.wake_sleepers(now);
+        self.policy.refresh(now, &mut self.queues.iter_mut().flat_map(|queue| queue.processes.iter_mut()));
+
+        let mut ready = self.ready_indices(core);
+        if ready.is_empty() && self.steal(core) {
+            ready = self.ready_indices(core);
         }
-
-        if del_idx == self.processes.len() {
+        if ready.is_empty() {
             return None;
         }
 
-        let mut proc = self.processes.remove(del_idx).expect("failed to remove");
+        let queue = &mut self.queues[core];
+        let del_idx = self.policy.pick(&mut queue.processes, &ready);
+        let mut proc = queue.processes.remove(del_idx).expect("failed to remove");
         proc.state = State::Running;
         let id = proc.context.get_tpidr();
         *tf = *proc.context;
-        self.processes.push_front(proc);
+        queue.slice_start = now;
+        queue.slice_end = now + self.policy.time_slice(&proc);
+        queue.processes.push_front(proc);
+        self.program_timer(now);
         return Some(id);
     }
 
-    /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Removes the dead process from the queue, drop the
-    /// dead process's instance, and returns the dead process's process ID.
+    /// Returns the indices of the processes in the run queue of `core` that
+    /// are ready to run. Those that stopped waiting or sleeping are woken up
+    /// with the policy.
+    fn ready_indices(&mut self, core: usize) -> Vec<usize> {
+        let policy = &mut self.policy;
+        self.queues[core]
+            .processes
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, proc)| {
+                let blocked = match proc.state {
+                    State::Waiting(_) | State::Sleeping(_) => true,
+                    _ => false,
+                };
+                if !proc.is_ready() {
+                    return None;
+                }
+                if blocked {
+                    policy.wake(proc);
+                }
+                Some(i)
+            })
+            .collect()
+    }
+
+    /// Moves a ready process that is not running from the run queue of `from`
+    /// to the back of the run queue of `to`.
+    ///
+    /// Returns `false` if `from` has no such process.
+    fn migrate(&mut self, from: usize, to: usize) -> bool {
+        let index = match self.queues[from].processes.iter().rposition(is_migratable) {
+            Some(index) => index,
+            None => return false,
+        };
+        let proc = self.queues[from].processes.remove(index).expect("failed to remove");
+        self.queues[to].processes.push_back(proc);
+        true
+    }
+
+    /// Moves a ready process from the busiest other core to `core`, which
+    /// has nothing to run.
+    ///
+    /// Returns `false` if no other core has a process waiting to run.
+    fn steal(&mut self, core: usize) -> bool {
+        let mut victims: Vec<usize> = (0..NCORES).filter(|&other| other != core).collect();
+        victims.sort_by_key(|&other| Reverse(self.queues[other].load()));
+        victims.into_iter().any(|victim| self.migrate(victim, core))
+    }
+
+    /// Balances the run queues by moving ready processes from the busiest
+    /// core to the least busy one until their loads differ by at most one.
+    fn balance(&mut self) {
+        loop {
+            let busiest = (0..NCORES).max_by_key(|&core| self.queues[core].load()).unwrap_or(0);
+            let idlest = (0..NCORES).min_by_key(|&core| self.queues[core].load()).unwrap_or(0);
+            if self.queues[busiest].load() <= self.queues[idlest].load() + 1 {
+                return;
+            }
+            if !self.migrate(busiest, idlest) {
+                return;
+            }
+        }
+    }
+
+    /// Schedules out the current process as `Sleeping` until `deadline` and
+    /// queues its wake-up. When it is woken up, its `sleep` system call
+    /// returns how long after `deadline` that happened.
+    ///
+    /// Returns `false` if there is no current process.
+    fn sleep(&mut self, deadline: Duration, tf: &mut TrapFrame) -> bool {
+        if !self.schedule_out(State::Sleeping(deadline), tf) {
+            return false;
+        }
+        self.timers.push(deadline, tf.get_tpidr());
+        true
+    }
+
+    /// Wakes up every sleeping process whose deadline is at or before `now`.
+    ///
+    /// Queue entries of processes that are no longer sleeping until that
+    /// deadline, because a signal interrupted the sleep or they exited, are
+    /// discarded.
+    fn wake_sleepers(&mut self, now: Duration) {
+        while let Some((deadline, id)) = self.timers.pop_expired(now) {
+            if let Some(proc) = find_in(&mut self.queues, id) {
+                match proc.state {
+                    State::Sleeping(until) if until == deadline => {
+                        proc.context.set_x_register(7, OsError::Ok as u64);
+                        proc.context.set_x_register(0, (now - deadline).as_millis() as u64);
+                        proc.state = State::Ready;
+                        self.policy.wake(proc);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Programs the executing core's timer to interrupt at the end of its
+    /// current time slice or at the nearest sleeper's deadline, whichever
+    /// comes first.
+    fn program_timer(&mut self, now: Duration) {
+        let core = getcpu();
+        let slice_end = self.queues[core].slice_end;
+        let next = match self.timers.next_deadline() {
+            Some(deadline) if deadline < slice_end => deadline,
+            _ => slice_end,
+        };
+        let delay = next.checked_sub(now).unwrap_or(MIN_TIMER_DELAY);
+        local_tick_in(core, if delay < MIN_TIMER_DELAY { MIN_TIMER_DELAY } else { delay });
+    }
+
+    /// Handles a timer interrupt of the executing core at time `now`: wakes
+    /// up the sleepers whose deadline has passed, balances the run queues if
+    /// they have not been balanced for `BALANCE_INTERVAL`, and reprograms the
+    /// timer.
+    ///
+    /// Returns `true` if the current time slice is used up and the current
+    /// process should be preempted; the timer is then reprogrammed by the
+    /// following `switch_to()`. If the current process is not `preemptible`,
+    /// its slice is extended until the next tick instead.
+    fn tick(&mut self, now: Duration, preemptible: bool) -> bool {
+        self.wake_sleepers(now);
+        if now >= self.last_balance + BALANCE_INTERVAL {
+            self.last_balance = now;
+            self.balance();
+        }
+        if preemptible && now >= self.queue().slice_end {
+            return true;
+        }
+        self.program_timer(now);
+        false
+    }
+
+    /// Returns a reference to the process with ID `id` if it is in any run
+    /// queue.
+    pub fn find(&self, id: Id) -> Option<&Process> {
+        self.processes().find(|proc| proc.context.get_tpidr() == id)
+    }
+
+    /// Returns a mutable reference to the process with ID `id` if it is in any
+    /// run queue.
+    pub fn find_mut(&mut self, id: Id) -> Option<&mut Process> {
+        find_in(&mut self.queues, id)
+    }
+
+    /// Kills currently running process by terminating it with exit status
+    /// `-1`. See `exit()` for details.
     fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        match self.schedule_out(State::Dead, tf) {
-            false => None,
-            true => {
-                let mut curr = self.processes.pop_back().unwrap();
-                let id = curr.context.get_tpidr();
-                drop(curr);
-                return Some(id);
+        self.exit(-1, tf)
+    }
+
+    /// Sets the niceness of the process `id` to `nice`, clamped to the range
+    /// from `MIN_NICE` to `MAX_NICE`, and returns the new niceness. The
+    /// scheduling policy takes it into account the next time it picks a
+    /// process.
+    ///
+    /// Returns `NoEntry` if there is no process `id`.
+    fn set_nice(&mut self, id: Id, nice: i64) -> OsResult<i64> {
+        let process = self.find_mut(id).ok_or(OsError::NoEntry)?;
+        process.sched.nice = clamp_nice(nice);
+        Ok(process.sched.nice)
+    }
+
+    /// Sends the signal `sig` to the process `id` by marking it pending; it is
+    /// acted upon the next time the process returns to user space. `SIGCONT`
+    /// and `SIGKILL` also resume a stopped process. A `sig` of `0` only checks
+    /// that the process exists.
+    ///
+    /// Returns `NoEntry` if there is no live process `id`, and
+    /// `InvalidArgument` if `sig` is not a valid signal number or `id` is a
+    /// kernel thread, which never returns to user space.
+    fn signal(&mut self, id: Id, sig: Signal) -> OsResult<()> {
+        let process = match find_in(&mut self.queues, id) {
+            Some(process) => process,
+            None => return Err(OsError::NoEntry),
+        };
+        if let State::Zombie(_) = process.state {
+            return Err(OsError::NoEntry);
+        }
+        if process.is_kernel_thread() {
+            return Err(OsError::InvalidArgument);
+        }
+
+        if sig == 0 {
+            return Ok(());
+        }
+        process.signals.raise(sig)?;
+        if (sig == SIGCONT || sig == SIGKILL) && process.stopped {
+            process.stopped = false;
+            self.policy.wake(process);
+        }
+        Ok(())
+    }
+
+    /// Delivers the next pending signal of the process about to resume with
+    /// `tf`.
+    ///
+    /// If the signal has a user handler, `tf` is set up to run it (see
+    /// `Process::push_signal_frame()`); if the handler's frame does not fit on
+    /// the user stack, the process is terminated as if by `SIGSEGV`. If the
+    /// signal's action terminates the process, it exits with the status given
+    /// by `exit_status()`. If the action stops the process, it is scheduled
+    /// out until it is continued, and its parent's `child_exited` flag is
+    /// raised so that a parent waiting for it notices.
+    ///
+    /// Returns `true` if the process was terminated or stopped, in which case
+    /// the caller must switch to another process.
+    fn deliver_signal(&mut self, tf: &mut TrapFrame) -> bool {
+        let id = tf.get_tpidr();
+        let process = match self.find_mut(id) {
+            Some(process) => process,
+            None => return false,
+        };
+
+        let (sig, action) = match process.signals.take() {
+            Some(next) => next,
+            None => return false,
+        };
+        match action {
+            Action::Handle { handler, restorer } => {
+                if process.push_signal_frame(sig, handler, restorer, tf).is_ok() {
+                    return false;
+                }
+                kprintln!("process {}: no stack space to handle signal {}", id, sig);
+                self.exit(exit_status(SIGSEGV), tf).is_some()
+            }
+            Action::Terminate => self.exit(exit_status(sig), tf).is_some(),
+            Action::Stop => {
+                process.stopped = true;
+                let parent = process.parent;
+                if let Some(parent) = parent.and_then(|parent| self.find_mut(parent)) {
+                    parent.child_exited = true;
+                }
+                self.schedule_out(State::Ready, tf)
+            }
+        }
+    }
+
+    /// Terminates the currently running process by scheduling it out as a
+    /// `Zombie` holding exit status `status`, and returns its process ID.
+    /// The process's descriptors are closed right away.
+    ///
+    /// The zombie stays in the queue until its parent collects it with
+    /// `reap()`, and the parent's `child_exited` flag is raised so that a
+    /// parent blocked in `wait` is woken up. A process without a parent has
+    /// nobody to collect it and is dropped immediately. Children of the exiting
+    /// process lose their parent, and those that already exited are dropped.
+    /// The parent is sent `SIGCHLD`, and takes over the foreground if the
+    /// process held it.
+    ///
+    /// If there is no current process, returns `None`.
+    fn exit(&mut self, status: i32, tf: &mut TrapFrame) -> Option<Id> {
+        if !self.schedule_out(State::Zombie(status), tf) {
+            return None;
+        }
+
+        let id = tf.get_tpidr();
+        if let Some(proc) = self.find_mut(id) {
+            proc.fds.clear();
+        }
+
+        for proc in self.processes_mut() {
+            if proc.parent == Some(id) {
+                proc.parent = None;
+            }
+        }
+
+        let parent = self.find(id).and_then(|proc| proc.parent);
+        if let Some(parent) = parent.and_then(|parent| self.find_mut(parent)) {
+            parent.child_exited = true;
+            let _ = parent.signals.raise(SIGCHLD);
+        }
+        if self.foreground == Some(id) {
+            self.foreground = parent;
+        }
+
+        for queue in self.queues.iter_mut() {
+            queue.processes.retain(|proc| match proc.state {
+                State::Zombie(_) => proc.parent.is_some(),
+                _ => true,
+            });
+        }
+        Some(id)
+    }
+
+    /// Collects an exited child of the process `parent`. If `child` is
+    /// `Some`, only the child with that ID is considered.
+    ///
+    /// If a matching child is a `Zombie`, removes it from the queue, drops it
+    /// and returns `Some` of its ID and exit status. If matching children
+    /// exist but none has exited yet, returns `None`. The parent's
+    /// `child_exited` flag is cleared in both cases.
+    ///
+    /// Returns `NoEntry` if `parent` has no matching child.
+    fn reap(&mut self, parent: Id, child: Option<Id>) -> OsResult<Option<(Id, i32)>> {
+        let mut found = false;
+        let mut zombie = None;
+        let procs = self.queues.iter().enumerate().flat_map(|(core, queue)| {
+            queue.processes.iter().enumerate().map(move |(i, proc)| (core, i, proc))
+        });
+        for (core, i, proc) in procs {
+            let id = proc.context.get_tpidr();
+            if proc.parent != Some(parent) || child.map_or(false, |child| child != id) {
+                continue;
+            }
+
+            found = true;
+            if let State::Zombie(status) = proc.state {
+                zombie = Some((core, i, id, status));
+                break;
+            }
+        }
+
+        if let Some(proc) = self.find_mut(parent) {
+            proc.child_exited = false;
+        }
+
+        match zombie {
+            Some((core, i, id, status)) => {
+                drop(self.queues[core].processes.remove(i));
+                Ok(Some((id, status)))
             }
+            None if found => Ok(None),
+            None => Err(OsError::NoEntry),
         }
     }
 }
 
 pub extern "C" fn start_shell1() {
-    use crate::shell;
     use kernel_api::syscall::sleep;
     let res = sleep(Duration::from_secs(5));
     kprintln!("milliseconds passed: {}", res.unwrap().as_millis());
@@ -280,19 +862,16 @@ pub extern "C" fn start_shell1() {
 
 
 pub extern "C" fn start_shell2() {
-    use crate::shell;
     shell::shell("user2> ");
 }
 
 
 pub extern "C" fn start_shell3() {
-    use crate::shell;
     shell::shell("user3> ");
 }
 
 
 pub extern "C" fn start_shell4() {
-    use crate::shell;
     shell::shell("user4> ");
 }
 
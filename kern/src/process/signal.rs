@@ -0,0 +1,209 @@
+use kernel_api::*;
+
+/// Type alias for the type of a signal number.
+pub type Signal = u64;
+
+/// Returns the exit status of a process terminated by `sig`.
+pub fn exit_status(sig: Signal) -> i32 {
+    128 + sig as i32
+}
+
+/// What happens to a process when one of its signals is delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// The process is terminated.
+    Terminate,
+    /// The process is stopped until it receives `SIGCONT`.
+    Stop,
+    /// The user handler at `handler` is run. It returns to `restorer`, which
+    /// issues `sigreturn`.
+    Handle { handler: u64, restorer: u64 },
+}
+
+/// A signal's disposition as registered with `sigaction`.
+#[derive(Debug, Clone, Copy)]
+struct Disposition {
+    handler: u64,
+    restorer: u64,
+}
+
+/// The signal state of a process: pending and blocked signals and the
+/// disposition of every signal.
+#[derive(Debug, Clone)]
+pub struct Signals {
+    pending: u32,
+    mask: u32,
+    dispositions: [Disposition; NSIG as usize],
+}
+
+/// Returns the bit representing `sig` in a signal set.
+fn bit(sig: Signal) -> u32 {
+    1 << sig
+}
+
+/// Returns `true` if `sig` always takes its default action.
+fn is_uncatchable(sig: Signal) -> bool {
+    sig == SIGKILL || sig == SIGSTOP
+}
+
+/// Returns `true` if the default action of `sig` stops the process.
+fn is_stop(sig: Signal) -> bool {
+    sig == SIGSTOP || sig == SIGTSTP
+}
+
+/// Returns the action taken for `sig` under the default disposition, or
+/// `None` if the signal is ignored by default.
+fn default_action(sig: Signal) -> Option<Action> {
+    match sig {
+        SIGCHLD | SIGCONT => None,
+        sig if is_stop(sig) => Some(Action::Stop),
+        _ => Some(Action::Terminate),
+    }
+}
+
+impl Signals {
+    /// Returns a signal state with no pending or blocked signals and every
+    /// signal set to its default action.
+    pub fn new() -> Signals {
+        Signals {
+            pending: 0,
+            mask: 0,
+            dispositions: [Disposition { handler: SIG_DFL, restorer: 0 }; NSIG as usize],
+        }
+    }
+
+    /// Returns the action delivering `sig` would take right now, or `None` if
+    /// the signal would be discarded.
+    fn action(&self, sig: Signal) -> Option<Action> {
+        let disposition = self.dispositions[sig as usize];
+        match disposition.handler {
+            SIG_DFL => default_action(sig),
+            SIG_IGN => None,
+            handler => Some(Action::Handle { handler, restorer: disposition.restorer }),
+        }
+    }
+
+    /// Returns the lowest pending signal that is not blocked.
+    fn next(&self) -> Option<Signal> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            None
+        } else {
+            Some(deliverable.trailing_zeros() as Signal)
+        }
+    }
+
+    /// Marks `sig` as pending. A pending `SIGCONT` discards pending stop
+    /// signals and vice versa.
+    ///
+    /// Returns `InvalidArgument` if `sig` is not a valid signal number.
+    pub fn raise(&mut self, sig: Signal) -> OsResult<()> {
+        if sig == 0 || sig >= NSIG {
+            return Err(OsError::InvalidArgument);
+        }
+
+        if sig == SIGCONT {
+            self.pending &= !(bit(SIGSTOP) | bit(SIGTSTP));
+        } else if is_stop(sig) {
+            self.pending &= !bit(SIGCONT);
+        }
+        self.pending |= bit(sig);
+        Ok(())
+    }
+
+    /// Raises `sig` on behalf of the kernel, for a fault the process cannot
+    /// continue past. If the signal is ignored or blocked, its disposition is
+    /// reset to the default so that delivering it terminates the process.
+    ///
+    /// Returns `true` if a user handler will run for the signal.
+    pub fn force(&mut self, sig: Signal) -> bool {
+        if self.dispositions[sig as usize].handler == SIG_IGN || self.mask & bit(sig) != 0 {
+            self.dispositions[sig as usize].handler = SIG_DFL;
+            self.mask &= !bit(sig);
+        }
+
+        let _ = self.raise(sig);
+        match self.action(sig) {
+            Some(Action::Handle { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if a pending signal would have an effect when delivered.
+    /// Pending signals that would be discarded are dropped.
+    pub fn is_deliverable(&mut self) -> bool {
+        while let Some(sig) = self.next() {
+            if self.action(sig).is_some() {
+                return true;
+            }
+            self.pending &= !bit(sig);
+        }
+        false
+    }
+
+    /// Removes the next deliverable signal from the pending set and returns it
+    /// along with the action to take.
+    ///
+    /// Returns `None` if no pending signal has an effect.
+    pub fn take(&mut self) -> Option<(Signal, Action)> {
+        while let Some(sig) = self.next() {
+            self.pending &= !bit(sig);
+            if let Some(action) = self.action(sig) {
+                return Some((sig, action));
+            }
+        }
+        None
+    }
+
+    /// Sets the handler of `sig` to `handler`, which is `SIG_DFL`, `SIG_IGN`
+    /// or the address of a user function returning to `restorer`. Returns the
+    /// previous handler.
+    ///
+    /// Returns `InvalidArgument` if `sig` is not a valid signal number or is
+    /// `SIGKILL` or `SIGSTOP`, whose actions cannot be changed.
+    pub fn set_handler(&mut self, sig: Signal, handler: u64, restorer: u64) -> OsResult<u64> {
+        if sig == 0 || sig >= NSIG || is_uncatchable(sig) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let old = self.dispositions[sig as usize].handler;
+        self.dispositions[sig as usize] = Disposition { handler, restorer };
+        if handler == SIG_IGN {
+            self.pending &= !bit(sig);
+        }
+        Ok(old)
+    }
+
+    /// Returns the set of blocked signals, one bit per signal number.
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Replaces the set of blocked signals. `SIGKILL` and `SIGSTOP` cannot be
+    /// blocked.
+    pub fn set_mask(&mut self, mask: u32) {
+        self.mask = mask & !(bit(SIGKILL) | bit(SIGSTOP));
+    }
+
+    /// Blocks `sig` in addition to the signals already blocked.
+    pub fn block(&mut self, sig: Signal) {
+        self.set_mask(self.mask | bit(sig));
+    }
+
+    /// Returns a copy of this state for a forked child: dispositions and
+    /// blocked signals are inherited, pending signals are not.
+    pub fn fork(&self) -> Signals {
+        Signals { pending: 0, ..self.clone() }
+    }
+
+    /// Resets every handled signal to its default action, as the handlers no
+    /// longer exist once a new program is loaded. Ignored signals stay
+    /// ignored.
+    pub fn reset_handlers(&mut self) {
+        for disposition in self.dispositions.iter_mut() {
+            if disposition.handler != SIG_IGN {
+                *disposition = Disposition { handler: SIG_DFL, restorer: 0 };
+            }
+        }
+    }
+}
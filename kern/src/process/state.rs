@@ -1,4 +1,5 @@
 use core::fmt;
+use core::time::Duration;
 
 use alloc::boxed::Box;
 
@@ -17,8 +18,15 @@ pub enum State {
     Ready,
     /// The process is waiting on an event to occur before it can be scheduled.
     Waiting(EventPollFn),
+    /// The process is sleeping until the given deadline, measured like
+    /// `pi::timer::current_time()`. It is woken up by the scheduler's timer
+    /// queue.
+    Sleeping(Duration),
     /// The process is currently running.
     Running,
+    /// The process has exited with the given status and is waiting for its
+    /// parent to collect it with `wait`.
+    Zombie(i32),
     /// The process is currently dead (ready to be reclaimed).
     Dead,
 }
@@ -29,6 +37,8 @@ impl fmt::Debug for State {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
+            State::Sleeping(deadline) => write!(f, "State::Sleeping({:?})", deadline),
+            State::Zombie(status) => write!(f, "State::Zombie({})", status),
             State::Dead => write!(f, "State::Dead"),
         }
     }
@@ -0,0 +1,100 @@
+use alloc::boxed::Box;
+use core::time::Duration;
+
+use aarch64::sp_sel;
+use kernel_api::syscall;
+use kernel_api::{OsError, OsResult};
+
+use crate::percore::is_preemptible;
+use crate::process::{EventPollFn, Id, Process};
+use crate::SCHEDULER;
+
+/// The system call number of `block_on()`. It lies outside the numbers of the
+/// user system calls and is rejected when issued from EL0.
+pub const NR_BLOCK: u16 = 0x100;
+
+/// The type of the closure a kernel thread runs.
+type ThreadFn = Box<dyn FnOnce() + Send>;
+
+/// The entry point of every kernel thread: runs the closure that `spawn()`
+/// leaked into `arg`, then exits the thread.
+extern "C" fn thread_start(arg: u64) -> ! {
+    let f = unsafe { Box::from_raw(arg as *mut ThreadFn) };
+    f();
+    syscall::exit(0)
+}
+
+/// Starts a kernel thread named `name` running `f` and returns its process
+/// ID.
+///
+/// The thread is scheduled like any process and is preempted by the timer,
+/// except while it holds a `Mutex`. It exits when `f` returns.
+///
+/// Returns `NoMemory` if the thread's stack could not be allocated or the
+/// scheduler could not take another process.
+pub fn spawn<F>(name: &str, f: F) -> OsResult<Id>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let f: Box<ThreadFn> = Box::new(Box::new(f));
+    let arg = Box::into_raw(f);
+    let id = Process::kernel_thread(name, thread_start, arg as u64)
+        .and_then(|thread| SCHEDULER.add(thread).ok_or(OsError::NoMemory));
+    if id.is_err() {
+        // The thread never ran, so `thread_start` did not take `f` back.
+        drop(unsafe { Box::from_raw(arg) });
+    }
+    id
+}
+
+/// Returns `true` if the executing code is a kernel thread. The kernel itself
+/// runs on the core's `SP_EL1` stack, while kernel threads run on their own
+/// stack in `SP_EL0`.
+pub fn is_kernel_thread() -> bool {
+    sp_sel() == 0
+}
+
+/// Puts the calling kernel thread to sleep until `poll_fn` returns `true`.
+///
+/// The scheduler calls `poll_fn` with IRQs masked while it looks for a process
+/// to run, so it must not take any lock but an `IrqSafeMutex`.
+///
+/// # Panics
+///
+/// Panics if the caller is not a kernel thread or holds a `Mutex`, which would
+/// stay locked while other threads run on its core.
+pub fn block_on(poll_fn: EventPollFn) {
+    assert!(is_kernel_thread(), "block_on() called outside a kernel thread");
+    assert!(is_preemptible(), "block_on() called while holding a Mutex");
+
+    let poll_fn = Box::into_raw(Box::new(poll_fn));
+    unsafe {
+        asm!("mov x0, $0
+              svc $1"
+             :
+             : "r"(poll_fn as u64), "i"(NR_BLOCK)
+             : "x0", "x7"
+             : "volatile");
+    }
+}
+
+/// Gives up the rest of the calling kernel thread's time slice.
+pub fn yield_now() {
+    block_on(Box::new(|_: &mut Process| true));
+}
+
+/// Puts the calling kernel thread to sleep for `span`.
+///
+/// # Panics
+///
+/// Panics under the same conditions as `block_on()`.
+pub fn sleep(span: Duration) {
+    assert!(is_kernel_thread(), "sleep() called outside a kernel thread");
+    assert!(is_preemptible(), "sleep() called while holding a Mutex");
+    let _ = syscall::sleep(span);
+}
+
+/// Returns the process ID of the calling kernel thread.
+pub fn current() -> Id {
+    syscall::getpid()
+}
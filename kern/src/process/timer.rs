@@ -0,0 +1,39 @@
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use core::time::Duration;
+
+use crate::process::Id;
+
+/// A queue of process wake-up deadlines, measured like `pi::timer`'s
+/// `current_time()`, ordered so that the nearest deadline is found in
+/// constant time.
+#[derive(Debug)]
+pub struct TimerQueue {
+    heap: BinaryHeap<Reverse<(Duration, Id)>>,
+}
+
+impl TimerQueue {
+    /// Returns an empty queue.
+    pub fn new() -> TimerQueue {
+        TimerQueue { heap: BinaryHeap::new() }
+    }
+
+    /// Schedules the process `id` to be woken up at `deadline`.
+    pub fn push(&mut self, deadline: Duration, id: Id) {
+        self.heap.push(Reverse((deadline, id)));
+    }
+
+    /// Returns the nearest deadline in the queue, if any.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.heap.peek().map(|&Reverse((deadline, _))| deadline)
+    }
+
+    /// Removes and returns the nearest entry if its deadline is at or before
+    /// `now`.
+    pub fn pop_expired(&mut self, now: Duration) -> Option<(Duration, Id)> {
+        match self.next_deadline() {
+            Some(deadline) if deadline <= now => self.heap.pop().map(|Reverse(entry)| entry),
+            _ => None,
+        }
+    }
+}
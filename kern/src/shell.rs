@@ -1,62 +1,40 @@
-use shim::io;
-use shim::path::{Path, PathBuf};
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-
-use stack_vec::StackVec;
-use core::str::from_utf8;
-use core::fmt::Write;
-use shim::io::Read;
-
-use pi::atags::Atags;
+use alloc::vec::Vec;
+use shim::io::Write as _;
+use shim::path::{Path, PathBuf};
 
 use fat32::traits::FileSystem;
-use fat32::traits::{Dir, Entry, Timestamp, Metadata};
+use fat32::traits::{Dir, Entry, Metadata, Timestamp};
 
-use crate::console::{kprint, kprintln, CONSOLE};
-use crate::ALLOCATOR;
+use crate::console::kprintln;
 use crate::FILESYSTEM;
 
-/// Error type for `Command` parse failures.
-#[derive(Debug)]
-enum Error {
-    Empty,
-    TooManyArgs,
-}
+mod editor;
+mod files;
+mod io;
+mod jobs;
+mod parser;
+mod transfer;
 
-/// A structure representing a single shell command.
-struct Command<'a> {
-    args: StackVec<'a, &'a str>,
-}
+use self::editor::LineEditor;
+use self::io::{fail, out, outln, Io};
+use self::jobs::Jobs;
+use self::parser::{Command, Connector, Pipeline, RedirectKind};
+use crate::process::thread::is_kernel_thread;
 
-impl<'a> Command<'a> {
-    /// Parse a command from a string `s` using `buf` as storage for the
-    /// arguments.
-    ///
-    /// # Errors
-    ///
-    /// If `s` contains no arguments, returns `Error::Empty`. If there are more
-    /// arguments than `buf` can hold, returns `Error::TooManyArgs`.
-    fn parse(s: &'a str, buf: &'a mut [&'a str]) -> Result<Command<'a>, Error> {
-        let mut args = StackVec::new(buf);
-        for arg in s.split(' ').filter(|a| !a.is_empty()) {
-            args.push(arg).map_err(|_| Error::TooManyArgs)?;
-        }
+/// The names of the built-in commands, completed by Tab.
+const COMMANDS: &[&str] = &[
+    "cat", "cd", "clear", "cp", "df", "du", "echo", "env", "exit", "fg", "find", "head", "hexdump", "jobs",
+    "kill", "ls", "mkdir", "mv", "ps", "pwd", "rb", "rm", "rmdir", "run", "rx", "rz", "sb", "source", "stat", "sx",
+    "sz", "tail", "touch", "unset", "wait",
+];
 
-        if args.is_empty() {
-            return Err(Error::Empty);
-        }
-
-        Ok(Command { args })
-    }
-
-    /// Returns this command's path. This is equivalent to the first argument.
-    fn path(&self) -> &str {
-        self.args[0]
-    }
-}
+/// How deeply `source` may nest before a script is assumed to source itself.
+const MAX_SOURCE_DEPTH: usize = 8;
 
-fn pwd(cwd: &PathBuf) {
-    kprintln!("{}", cwd.as_os_str().to_str().expect("cwd isn't valid utf-8"));
+fn pwd(cwd: &PathBuf, io: &mut Io) {
+    outln!(io, "{}", cwd.as_os_str().to_str().expect("cwd isn't valid utf-8"));
 }
 
 fn hash_entry<T: Entry>(hash: &mut String, entry: &T, show_hidden: bool) -> ::core::fmt::Result {
@@ -102,226 +80,405 @@ fn hash_entry<T: Entry>(hash: &mut String, entry: &T, show_hidden: bool) -> ::co
     }
 }
 
-
-fn ls(cwd: &PathBuf, query: StackVec<&str>) {
-    if query.len() > 3 {
-        kprintln!("Query can have at most three arguments.");
-        return;
+fn ls(cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() > 3 {
+        return fail!(io, "ls: at most two arguments are allowed");
     }
     let mut output = String::new();
     let mut final_dir = cwd.clone();
     let mut show_hidden = false;
-    for i in 1..query.len() {
-        let arg = query[i];
+    for &arg in args[1..].iter() {
         if arg == "-a" {
             show_hidden = true;
         } else {
-            use shim::path::Component;
-            let query_path = PathBuf::from(String::from(arg));
-            'outer: for comp in query_path.components() {
-                match comp {
-                    Component::RootDir => {
-                        final_dir = PathBuf::from("/");
-                    },
-                    Component::CurDir => {
-                        continue;
-                    },
-                    Component::ParentDir => {
-                        final_dir.pop();
-                    },
-                    Component::Normal(name) => {
-                        let dir = FILESYSTEM.open_dir(&final_dir).expect("Failed to read queried directory");
-                        let iterator = dir.entries().expect("Falied to create iterator");
-                        for entry in iterator {
-                            if entry.name() == name && entry.is_file() {
-                                kprintln!("ls query path contains a file");
-                                return;
-                            }
-                            if entry.name() == name {
-                                final_dir.push(name);
-                                continue 'outer;
-                            }
-                        }
-                        kprintln!("{:?} is not found", name);
-                        return;
-                    },
-                    _ => {
-                        kprintln!("Invalid ls target");
-                        return;
-                    }
-                }
-            }
+            final_dir = resolve(cwd, arg);
         }
     }
-    let dir = FILESYSTEM.open_dir(&final_dir).expect("Failed to read contents of ls final dir");
-    let iterator = dir.entries().expect("Failed to create iterator");
-    for entry in iterator {
-        hash_entry(&mut output, &entry, show_hidden);
+    let entries = match FILESYSTEM.open_dir(&final_dir).and_then(|dir| dir.entries()) {
+        Ok(entries) => entries,
+        Err(e) => return fail!(io, "ls: {}: {}", final_dir.display(), e),
+    };
+    for entry in entries {
+        let _ = hash_entry(&mut output, &entry, show_hidden);
     }
-    kprintln!("{}", output);
+    out!(io, "{}", output);
 }
 
-
-fn cat(cwd: &PathBuf, query: StackVec<&str>) -> () {
-    let dir = FILESYSTEM.open_dir(cwd).expect("Failed to read content of cwd");
-
-    let mut buf = String::new();
-    for (i, q) in query.iter().enumerate() {
-        let iterator = dir.entries().expect("Failed to create iterator from cwd");
-        if i == 0 {
-            continue;
-        }
-        for entry in iterator {
-            let mut entry_path = cwd.clone();
-            entry_path.push(entry.name());
-            //kprintln!("entry name: {}, is_dir: {}", entry.name(), entry.is_dir());
-            if entry.name() == *q {
-                if entry.is_dir() {
-                    kprintln!("{} is directory", q);
-                    return;
-                }
-                entry.into_file().unwrap().read_to_string(&mut buf);
-            }
+/// `cat [path...]`: writes the files, or the standard input if there are
+/// none, to the standard output.
+fn cat(cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        let data = io.read_all();
+        return io.write_bytes(&data);
+    }
+    for &arg in args[1..].iter() {
+        match files::read_all(&FILESYSTEM, &resolve(cwd, arg)) {
+            Ok(data) => io.write_bytes(&data),
+            Err(e) => fail!(io, "cat: {}: {}", arg, e),
         }
     }
-    kprintln!("{}", buf);
-
 }
 
-fn cd(cwd: &mut PathBuf, query: StackVec<&str>) {
-    if query.len() == 1 {
+fn cd(cwd: &mut PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() == 1 {
         *cwd = PathBuf::from("/");
         return;
     }
-    let dest = query[1];
-    let path = PathBuf::from(String::from(dest));
+    let path = resolve(cwd, args[1]);
+    match FILESYSTEM.open_dir(&path) {
+        Ok(_) => *cwd = path,
+        Err(e) => fail!(io, "cd: {}: {}", args[1], e),
+    }
+}
+
+/// Returns `path` resolved against `cwd`, without checking that it exists.
+fn resolve(cwd: &PathBuf, path: &str) -> PathBuf {
     use shim::path::Component;
-    for comp in path.components() {
+    let mut resolved = cwd.clone();
+    for comp in Path::new(path).components() {
         match comp {
-            Component::RootDir => {
-                *cwd = PathBuf::from("/");
-            },
-            Component::CurDir => {
-                continue;
-            },
+            Component::RootDir => resolved = PathBuf::from("/"),
             Component::ParentDir => {
-                cwd.pop();
-            },
-            Component::Normal(name) => {
-                let dir = FILESYSTEM.open_dir(&cwd).expect("Failed to read content of cwd");
-                let iterator = dir.entries().expect("Failed to create iterator from cwd");
-                for entry in iterator {
-                    if entry.is_dir() && entry.name() == name {
-                        cwd.push(name);
-                        return;
-                    }
-                }
-                kprintln!("Directory not found. You changed directory to the last viable dir in the given path");
-            },
-            _ => {
-                kprintln!("Invalid cd target");
+                resolved.pop();
             }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
         }
     }
+    resolved
+}
+
+/// Returns the words the word `word` typed after `before` may complete to:
+/// built-in command names for the first word of a line, paths relative to
+/// `cwd` otherwise. Directories are completed with a trailing `/`.
+fn complete(cwd: &PathBuf, before: &str, word: &str) -> Vec<String> {
+    if before.trim().is_empty() {
+        return COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|&command| String::from(command))
+            .collect();
+    }
+
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let entries = match FILESYSTEM.open_dir(&resolve(cwd, dir)).and_then(|dir| dir.entries()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates: Vec<String> = entries
+        .filter(|entry| entry.name().starts_with(prefix))
+        .filter(|entry| prefix.starts_with('.') || !entry.name().starts_with('.'))
+        .map(|entry| {
+            let mut candidate = String::from(dir);
+            candidate.push_str(entry.name());
+            if entry.is_dir() {
+                candidate.push('/');
+            }
+            candidate
+        })
+        .collect();
+    candidates.sort();
+    candidates
 }
 
 fn clear_screen() {
-    for i in 0..500 {
+    for _ in 0..500 {
         kprintln!();
     }
 }
 
-/// Starts a shell using `prefix` as the prefix for each line. This function
-/// returns if the `exit` command is called.
-pub fn shell(prefix: &str) {
-    //clear_screen();
-    kprintln!("Hello! Welcome to the shell!");
-    let mut cwd = PathBuf::from("/");
-    'outer: loop {
-        kprintln!("{}", cwd.display());
-        kprint!("{}", prefix);
-        let mut buffer = [0u8; 512];
-        let mut input = StackVec::new(&mut buffer);
-        let mut console = CONSOLE.lock();
-        'inner: loop {
-            let read_byte = console.read_byte();
-            if read_byte == '\r' as u8 || read_byte == '\n' as u8 {
-                kprintln!();
-                break 'inner;
-            } else if read_byte == 8 || read_byte == 127 {
-                if input.len() > 0 {
-                    input.pop();
-                    console.write_byte(8);
-                    console.write_byte(b' ');
-                    console.write_byte(8);
-                }
-            } else {
-                if input.push(read_byte).is_err() {
-                    kprintln!();
-                    kprintln!("Input exceeding 512 characters. Stop!");
-                    continue 'outer;
-                }
-                console.write_byte(read_byte);
+/// The state of a shell: its working directory, its variables and the exit
+/// status of the last pipeline, `$?`.
+struct Shell {
+    cwd: PathBuf,
+    vars: BTreeMap<String, String>,
+    status: i32,
+    /// The programs the shell started. Programs can only be waited for by a
+    /// kernel thread; a shell started from an exception handler runs
+    /// without job control.
+    jobs: Option<Jobs>,
+    /// Set by `exit`.
+    exited: bool,
+    /// How many `source` commands are running.
+    depth: usize,
+}
+
+impl Shell {
+    fn new() -> Shell {
+        Shell {
+            cwd: PathBuf::from("/"),
+            vars: BTreeMap::new(),
+            status: 0,
+            jobs: if is_kernel_thread() { Some(Jobs::new()) } else { None },
+            exited: false,
+            depth: 0,
+        }
+    }
+
+    /// Returns the value of the variable `name`, empty if it is not set.
+    fn lookup(&self, name: &str) -> String {
+        use alloc::string::ToString;
+        match name {
+            "?" => self.status.to_string(),
+            _ => self.vars.get(name).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Runs the command line `line`.
+    fn execute(&mut self, line: &str) {
+        let pipelines = match parser::parse(line) {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                kprintln!("syntax error: {}", e);
+                self.status = 2;
+                return;
+            }
+        };
+
+        let mut connector = Connector::Always;
+        for pipeline in pipelines.iter() {
+            let skip = match connector {
+                Connector::And => self.status != 0,
+                Connector::Or => self.status == 0,
+                _ => false,
+            };
+            connector = pipeline.connector;
+            if !skip {
+                self.status = self.run_pipeline(pipeline);
+            }
+            if self.exited {
+                return;
             }
         }
-        let mut stack_backend = [""; 64];
-        let command_string = from_utf8(input.as_slice());
-        match command_string {
-            Err(_) => {
-                kprintln!("Please give commands in valid utf-8 characters");
-            },
-            Ok(c) => {
-                let command = Command::parse(from_utf8(input.as_slice()).unwrap(), &mut stack_backend);
-                match command {
-                    Ok(c) => {
-                        match c.path() {
-                            "echo" => {
-                                for (i, v) in c.args.iter().enumerate() {
-                                    if i != 0 {
-                                        console.write_str(v);
-                                        console.write_str(" ");
-                                    }
-                                }
-                                kprintln!();
-                            },
-                            "exit" => {
-                                return;
-                            },
-                            "pwd" => {
-                                pwd(&cwd);
-                            },
-                            "ls" => {
-                                ls(&cwd, c.args);
-                            },
-                            "cat" => {
-                                if c.args.len() < 2 {
-                                    kprintln!("No argument given to cat");
-                                    continue;
-                                }
-                                cat(&cwd, c.args);
-                            },
-                            "cd" => {
-                                cd(&mut cwd, c.args);
-                            },
-                            "clear" => {
-                                clear_screen();
-                            }
-                            _ => {
-                                kprintln!("unknown command: {}", c.path());
-                            }
+    }
+
+    /// Runs the commands of `pipeline` one after the other, each reading the
+    /// output of the one before. Returns the exit status of the last one.
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+        let last = pipeline.commands.len() - 1;
+        let mut input = None;
+        let mut status = 0;
+        for (i, command) in pipeline.commands.iter().enumerate() {
+            let background = i == last && pipeline.connector == Connector::Background;
+            let (s, output) = self.run_redirected(command, input.take(), i != last, background);
+            status = s;
+            input = output;
+            if self.exited {
+                break;
+            }
+        }
+        status
+    }
+
+    /// Runs `command` with `input` as its standard input, or the console if it
+    /// is `None`, after applying its redirections. Output files are opened
+    /// before the command runs, which does not run if one can't be. Returns
+    /// its exit status and, if `piped` is set, its output for the next
+    /// command.
+    fn run_redirected(
+        &mut self,
+        command: &Command,
+        mut input: Option<Vec<u8>>,
+        piped: bool,
+        background: bool,
+    ) -> (i32, Option<Vec<u8>>) {
+        let lookup = |name: &str| self.lookup(name);
+        let words: Vec<String> = command.words.iter().map(|word| word.expand(lookup)).collect();
+        let assignments: Vec<(String, String)> = command
+            .assignments
+            .iter()
+            .map(|word| (word.assigns.clone().unwrap_or_default(), word.expand_value(lookup)))
+            .collect();
+
+        let mut output = None;
+        for redirect in command.redirects.iter() {
+            let path = resolve(&self.cwd, &redirect.path.expand(lookup));
+            match redirect.kind {
+                RedirectKind::Input => match files::read_all(&FILESYSTEM, &path) {
+                    Ok(data) => input = Some(data),
+                    Err(e) => {
+                        kprintln!("{}: {}", path.display(), e);
+                        return (1, if piped { Some(Vec::new()) } else { None });
+                    }
+                },
+                RedirectKind::Output | RedirectKind::Append => {
+                    let append = redirect.kind == RedirectKind::Append;
+                    match files::open_output(&FILESYSTEM, &path, append) {
+                        Ok(file) => output = Some((path, file)),
+                        Err(e) => {
+                            kprintln!("{}: {}", path.display(), e);
+                            return (1, if piped { Some(Vec::new()) } else { None });
                         }
-                    },
-                    Err(Error::TooManyArgs) => {
-                        kprintln!("Too many arguments. Stop!");
-                    },
-                    Err(Error::Empty) => {
-                        kprintln!("Empty command");
-                    },
-                    Err(_) => {
-                        kprintln!("Unknown parsing error");
                     }
                 }
             }
         }
+
+        let mut io = Io::new(input, piped || output.is_some());
+        let args: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+        let mut status = self.run_command(&args, &assignments, background, &mut io);
+        let data = io.into_output();
+        match output {
+            Some((path, mut file)) => {
+                if let Err(e) = file.write_all(&data.unwrap_or_default()) {
+                    kprintln!("{}: {}", path.display(), e);
+                    status = 1;
+                }
+                (status, if piped { Some(Vec::new()) } else { None })
+            }
+            None => (status, data),
+        }
+    }
+
+    /// Runs the command `args` with the streams `io`. A command without
+    /// words sets the variables in `assignments`; otherwise they are added
+    /// to the environment of a program the command runs. Returns the exit
+    /// status.
+    fn run_command(&mut self, args: &[&str], assignments: &[(String, String)], background: bool, io: &mut Io) -> i32 {
+        if args.is_empty() {
+            for (name, value) in assignments.iter() {
+                self.vars.insert(name.clone(), value.clone());
+            }
+            return 0;
+        }
+
+        let cwd = &mut self.cwd;
+        match args[0] {
+            "echo" => outln!(io, "{}", args[1..].join(" ")),
+            "exit" => {
+                self.exited = true;
+                match args.get(1).map(|arg| arg.parse()) {
+                    Some(Ok(status)) => return status,
+                    Some(Err(_)) => fail!(io, "exit: bad status: {}", args[1]),
+                    None => return self.status,
+                }
+            }
+            "pwd" => pwd(cwd, io),
+            "ls" => ls(cwd, args, io),
+            "cat" => cat(cwd, args, io),
+            "cd" => cd(cwd, args, io),
+            "clear" => clear_screen(),
+            "env" => {
+                for (name, value) in self.vars.iter() {
+                    outln!(io, "{}={}", name, value);
+                }
+            }
+            "unset" => {
+                for name in args[1..].iter() {
+                    self.vars.remove(*name);
+                }
+            }
+            "source" | "." => return self.source(args, io),
+            "mkdir" => files::mkdir(&FILESYSTEM, cwd, args, io),
+            "rmdir" => files::rmdir(&FILESYSTEM, cwd, args, io),
+            "rm" => files::rm(&FILESYSTEM, cwd, args, io),
+            "cp" => files::cp(&FILESYSTEM, cwd, args, io),
+            "mv" => files::mv(&FILESYSTEM, cwd, args, io),
+            "touch" => files::touch(&FILESYSTEM, cwd, args, io),
+            "stat" => files::stat(&FILESYSTEM, cwd, args, io),
+            "hexdump" => files::hexdump(&FILESYSTEM, cwd, args, io),
+            "head" => files::head(&FILESYSTEM, cwd, args, io),
+            "tail" => files::tail(&FILESYSTEM, cwd, args, io),
+            "du" => files::du(&FILESYSTEM, cwd, args, io),
+            "df" => files::df(&FILESYSTEM, io),
+            "find" => files::find(&FILESYSTEM, cwd, args, io),
+            "rb" => transfer::rb(&FILESYSTEM, cwd, args, io),
+            "rx" => transfer::rx(&FILESYSTEM, cwd, args, io),
+            "rz" => transfer::rz(&FILESYSTEM, cwd, args, io),
+            "sb" => transfer::sb(&FILESYSTEM, cwd, args, io),
+            "sx" => transfer::sx(&FILESYSTEM, cwd, args, io),
+            "sz" => transfer::sz(&FILESYSTEM, cwd, args, io),
+            "ps" => jobs::ps(io),
+            "kill" => jobs::kill(args, io),
+            "run" | "jobs" | "fg" | "wait" if self.jobs.is_none() => {
+                fail!(io, "{}: job control is unavailable in this shell", args[0]);
+            }
+            "run" => {
+                if args.len() < 2 {
+                    fail!(io, "usage: run path [args...] [&]");
+                    return io.status();
+                }
+                let mut vars = self.vars.clone();
+                for (name, value) in assignments.iter() {
+                    vars.insert(name.clone(), value.clone());
+                }
+                let env: Vec<String> = vars
+                    .iter()
+                    .map(|(name, value)| {
+                        let mut var = name.clone();
+                        var.push('=');
+                        var.push_str(value);
+                        var
+                    })
+                    .collect();
+                return self.jobs.as_mut().unwrap().run(cwd, &args[1..], &env, background, io);
+            }
+            "jobs" => self.jobs.as_ref().unwrap().list(io),
+            "fg" => return self.jobs.as_mut().unwrap().fg(args, io),
+            "wait" => return self.jobs.as_mut().unwrap().wait(args, io),
+            _ => fail!(io, "unknown command: {}", args[0]),
+        }
+        io.status()
+    }
+
+    /// `source path`: runs the lines of the script at `path` in this shell.
+    /// Returns the exit status of its last pipeline.
+    fn source(&mut self, args: &[&str], io: &mut Io) -> i32 {
+        if args.len() != 2 {
+            fail!(io, "usage: {} path", args[0]);
+            return io.status();
+        }
+        if self.depth >= MAX_SOURCE_DEPTH {
+            fail!(io, "{}: {}: nested too deeply", args[0], args[1]);
+            return io.status();
+        }
+        let script = match files::read_all(&FILESYSTEM, &resolve(&self.cwd, args[1])) {
+            Ok(data) => data,
+            Err(e) => {
+                fail!(io, "{}: {}: {}", args[0], args[1], e);
+                return io.status();
+            }
+        };
+        let script = match core::str::from_utf8(&script) {
+            Ok(script) => script,
+            Err(_) => {
+                fail!(io, "{}: {}: not valid UTF-8", args[0], args[1]);
+                return io.status();
+            }
+        };
+
+        self.depth += 1;
+        self.status = 0;
+        for line in script.lines() {
+            self.execute(line);
+            if self.exited {
+                break;
+            }
+        }
+        self.depth -= 1;
+        self.status
+    }
+}
+
+/// Starts a shell using `prefix` as the prefix for each line. This function
+/// returns if the `exit` command is called.
+pub fn shell(prefix: &str) {
+    kprintln!("Hello! Welcome to the shell!");
+    let mut shell = Shell::new();
+    let mut editor = LineEditor::new();
+    loop {
+        if let Some(jobs) = shell.jobs.as_mut() {
+            jobs.reap_finished();
+        }
+        kprintln!("{}", shell.cwd.display());
+        let line = editor.read_line(prefix, |before, word| complete(&shell.cwd, before, word));
+        shell.execute(&line);
+        if shell.exited {
+            return;
+        }
     }
 }
@@ -0,0 +1,361 @@
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use kernel_api::{TCGETS, TCSETS, TTY_DEFAULT, TTY_ECHO, TTY_ICANON, TTY_ISIG};
+
+use crate::console::{kprint, CONSOLE};
+use crate::tty;
+
+/// The number of lines kept in the history.
+const HISTORY_SIZE: usize = 32;
+
+/// The longest line that can be entered.
+const MAX_LINE: usize = 512;
+
+const ESC: u8 = 0x1b;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// A key read from the terminal. Escape sequences that are not recognized are
+/// read as `Key::Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(u8),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// A control character other than the ones above, by its letter.
+    Ctrl(u8),
+    Unknown,
+}
+
+/// Returns the length of the longest common prefix of all `words`.
+fn common_prefix_len(words: &[String]) -> usize {
+    let first = match words.first() {
+        Some(first) => first.as_bytes(),
+        None => return 0,
+    };
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .iter()
+            .zip(word.as_bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    len
+}
+
+/// Reads lines from the console with VT100 line editing and a history.
+///
+/// Supported keys: left/right and Ctrl-B/F move the cursor; Home/End and
+/// Ctrl-A/E jump to the start or end; up/down and Ctrl-P/N walk the history;
+/// Backspace and Delete (or Ctrl-D) erase; Ctrl-K, Ctrl-U and Ctrl-W kill to
+/// the end, to the start and the previous word; Ctrl-C discards the line;
+/// Tab completes the word before the cursor.
+pub struct LineEditor {
+    history: VecDeque<String>,
+}
+
+/// The line being edited.
+struct Line {
+    buf: Vec<u8>,
+    cursor: usize,
+}
+
+fn write_bytes(bytes: &[u8]) {
+    let mut console = CONSOLE.lock();
+    for &byte in bytes {
+        console.write_byte(byte);
+    }
+}
+
+/// Moves the terminal cursor `n` columns to the left.
+fn move_left(n: usize) {
+    let mut console = CONSOLE.lock();
+    for _ in 0..n {
+        console.write_byte(BACKSPACE);
+    }
+}
+
+fn read_byte() -> u8 {
+    let mut byte = [0u8];
+    while tty::read(&mut byte) == 0 {}
+    byte[0]
+}
+
+/// Reads the next key, decoding VT100 escape sequences.
+fn read_key() -> Key {
+    match read_byte() {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        BACKSPACE | DELETE => Key::Backspace,
+        ESC => read_escape(),
+        byte if byte < 0x20 => Key::Ctrl(byte + 0x40),
+        byte => Key::Char(byte),
+    }
+}
+
+/// Decodes the rest of an escape sequence: `ESC [ A` and friends for the
+/// arrow keys, `ESC [ n ~` or `ESC O H/F` for Home, End and Delete.
+fn read_escape() -> Key {
+    let kind = read_byte();
+    if kind != b'[' && kind != b'O' {
+        return Key::Unknown;
+    }
+    match read_byte() {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        digit @ b'0'..=b'9' => {
+            let mut n = (digit - b'0') as usize;
+            loop {
+                match read_byte() {
+                    b'~' => break,
+                    next @ b'0'..=b'9' => n = n * 10 + (next - b'0') as usize,
+                    _ => return Key::Unknown,
+                }
+            }
+            match n {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    }
+}
+
+impl Line {
+    fn new() -> Line {
+        Line { buf: Vec::new(), cursor: 0 }
+    }
+
+    /// Redraws the line from the cursor on, blanking `erased` more columns
+    /// that previously held text, and puts the cursor back.
+    fn redraw_tail(&self, erased: usize) {
+        write_bytes(&self.buf[self.cursor..]);
+        for _ in 0..erased {
+            write_bytes(b" ");
+        }
+        move_left(self.buf.len() - self.cursor + erased);
+    }
+
+    fn insert(&mut self, byte: u8) {
+        if self.buf.len() >= MAX_LINE {
+            return;
+        }
+        self.buf.insert(self.cursor, byte);
+        write_bytes(&[byte]);
+        self.cursor += 1;
+        self.redraw_tail(0);
+    }
+
+    fn insert_str(&mut self, s: &[u8]) {
+        for &byte in s {
+            self.insert(byte);
+        }
+    }
+
+    /// Removes the bytes between `start` and the cursor.
+    fn delete_back_to(&mut self, start: usize) {
+        let n = self.cursor - start;
+        if n == 0 {
+            return;
+        }
+        self.buf.drain(start..self.cursor);
+        move_left(n);
+        self.cursor = start;
+        self.redraw_tail(n);
+    }
+
+    /// Removes the bytes between the cursor and `end`.
+    fn delete_forward_to(&mut self, end: usize) {
+        let n = end - self.cursor;
+        if n == 0 {
+            return;
+        }
+        self.buf.drain(self.cursor..end);
+        self.redraw_tail(n);
+    }
+
+    fn move_to(&mut self, pos: usize) {
+        if pos < self.cursor {
+            move_left(self.cursor - pos);
+        } else {
+            write_bytes(&self.buf[self.cursor..pos]);
+        }
+        self.cursor = pos;
+    }
+
+    /// Replaces the whole line with `text`, leaving the cursor at its end.
+    fn replace(&mut self, text: &[u8]) {
+        self.move_to(0);
+        let erased = self.buf.len().saturating_sub(text.len());
+        self.buf = text.to_vec();
+        self.redraw_tail(erased);
+        self.move_to(self.buf.len());
+    }
+
+    /// Returns the start of the word before the cursor.
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        while start > 0 && self.buf[start - 1] == b' ' {
+            start -= 1;
+        }
+        while start > 0 && self.buf[start - 1] != b' ' {
+            start -= 1;
+        }
+        start
+    }
+}
+
+impl LineEditor {
+    /// Returns an editor with an empty history.
+    pub fn new() -> LineEditor {
+        LineEditor { history: VecDeque::new() }
+    }
+
+    /// Prints `prompt` and reads a line, which is returned without its
+    /// newline and added to the history if it is not blank.
+    ///
+    /// `complete` is called on Tab with the text before the word being
+    /// completed and that word, and returns the words it may complete to.
+    ///
+    /// The console's line discipline is switched to raw mode while the line
+    /// is edited and restored afterwards.
+    pub fn read_line<F>(&mut self, prompt: &str, mut complete: F) -> String
+    where
+        F: FnMut(&str, &str) -> Vec<String>,
+    {
+        let flags = tty::ioctl(TCGETS, 0).unwrap_or(TTY_DEFAULT);
+        let _ = tty::ioctl(TCSETS, flags & !(TTY_ICANON | TTY_ECHO | TTY_ISIG));
+
+        kprint!("{}", prompt);
+        let mut line = Line::new();
+        // The position in the history being shown; `history.len()` is the line
+        // being entered, which is kept in `pending` while browsing.
+        let mut index = self.history.len();
+        let mut pending = Vec::new();
+        loop {
+            match read_key() {
+                Key::Enter => break,
+                Key::Char(byte) => line.insert(byte),
+                Key::Backspace => {
+                    if line.cursor > 0 {
+                        line.delete_back_to(line.cursor - 1);
+                    }
+                }
+                Key::Delete | Key::Ctrl(b'D') => {
+                    if line.cursor < line.buf.len() {
+                        line.delete_forward_to(line.cursor + 1);
+                    }
+                }
+                Key::Left | Key::Ctrl(b'B') => {
+                    if line.cursor > 0 {
+                        line.move_to(line.cursor - 1);
+                    }
+                }
+                Key::Right | Key::Ctrl(b'F') => {
+                    if line.cursor < line.buf.len() {
+                        line.move_to(line.cursor + 1);
+                    }
+                }
+                Key::Home | Key::Ctrl(b'A') => line.move_to(0),
+                Key::End | Key::Ctrl(b'E') => line.move_to(line.buf.len()),
+                Key::Ctrl(b'K') => line.delete_forward_to(line.buf.len()),
+                Key::Ctrl(b'U') => line.delete_back_to(0),
+                Key::Ctrl(b'W') => line.delete_back_to(line.word_start()),
+                Key::Up | Key::Ctrl(b'P') => {
+                    if index > 0 {
+                        if index == self.history.len() {
+                            pending = line.buf.clone();
+                        }
+                        index -= 1;
+                        line.replace(self.history[index].as_bytes());
+                    }
+                }
+                Key::Down | Key::Ctrl(b'N') => {
+                    if index < self.history.len() {
+                        index += 1;
+                        if index == self.history.len() {
+                            line.replace(&pending);
+                        } else {
+                            line.replace(self.history[index].as_bytes());
+                        }
+                    }
+                }
+                Key::Ctrl(b'C') => {
+                    kprint!("^C\n");
+                    line = Line::new();
+                    break;
+                }
+                Key::Tab => self.complete(prompt, &mut line, &mut complete),
+                _ => {}
+            }
+        }
+        kprint!("\n");
+        let _ = tty::ioctl(TCSETS, flags);
+
+        let text = String::from_utf8_lossy(&line.buf).into_owned();
+        if !text.trim().is_empty() && self.history.back() != Some(&text) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(text.clone());
+        }
+        text
+    }
+
+    /// Completes the word before the cursor. A single candidate replaces the
+    /// word; several are completed to their common prefix, and listed if
+    /// that adds nothing.
+    fn complete<F>(&self, prompt: &str, line: &mut Line, complete: &mut F)
+    where
+        F: FnMut(&str, &str) -> Vec<String>,
+    {
+        let start = line.word_start();
+        // The line is split as bytes: `start` need not be a character
+        // boundary of the text once invalid UTF-8 is replaced.
+        let (before, word) = line.buf[..line.cursor].split_at(start);
+        let (before, word) = (String::from_utf8_lossy(before), String::from_utf8_lossy(word));
+        let candidates = complete(&before, &word);
+        match candidates.len() {
+            0 => {}
+            1 => {
+                let candidate = &candidates[0];
+                line.insert_str(&candidate.as_bytes()[word.len().min(candidate.len())..]);
+                if !candidate.ends_with('/') {
+                    line.insert(b' ');
+                }
+            }
+            _ => {
+                let len = common_prefix_len(&candidates);
+                if len > word.len() {
+                    line.insert_str(&candidates[0].as_bytes()[word.len()..len]);
+                } else {
+                    kprint!("\n");
+                    for candidate in &candidates {
+                        kprint!("{}  ", candidate);
+                    }
+                    kprint!("\n{}", prompt);
+                    write_bytes(&line.buf);
+                    move_left(line.buf.len() - line.cursor);
+                }
+            }
+        }
+    }
+}
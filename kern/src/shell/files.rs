@@ -0,0 +1,506 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+use fat32::traits::{Dir, Entry, File, FileSystem, Metadata, Timestamp};
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+use shim::path::{Path, PathBuf};
+
+use super::io::{fail, out, outln, Io};
+use super::resolve;
+
+/// The number of lines `head` and `tail` print by default.
+const DEFAULT_LINES: usize = 10;
+
+/// The size of the buffer `cp` copies through.
+const COPY_SIZE: usize = 4096;
+
+// The commands below only use the `fat32::traits` interface, so they work on
+// any `FileSystem`. They take the file system as `fs`, the shell's working
+// directory as `cwd`, the command line, including the command name, as
+// `args`, and their standard streams as `io`.
+//
+// Files written are not synced: the SD card cannot be written to, so the
+// changes stay in the file system's sector cache.
+
+/// Reports the failure `e` of `command` on `path`.
+fn report(io: &mut Io, command: &str, path: &str, e: io::Error) {
+    fail!(io, "{}: {}: {}", command, path, e);
+}
+
+/// Returns `true` for the `.` and `..` entries every directory but the root
+/// holds.
+fn is_dot(name: &str) -> bool {
+    name == "." || name == ".."
+}
+
+/// Splits `args` after the command name into the values of the options in
+/// `options`, each of which takes a numeric argument, and the remaining
+/// operands.
+///
+/// Returns `None` if an option lacks its argument or it is not a number.
+fn parse_options<'a>(args: &[&'a str], options: &[&str]) -> Option<(Vec<Option<usize>>, Vec<&'a str>)> {
+    let mut values = vec![None; options.len()];
+    let mut operands = Vec::new();
+    let mut iter = args.iter().skip(1);
+    while let Some(&arg) = iter.next() {
+        match options.iter().position(|&option| option == arg) {
+            Some(i) => values[i] = Some(iter.next()?.parse().ok()?),
+            None => operands.push(arg),
+        }
+    }
+    Some((values, operands))
+}
+
+/// Returns `to`, or `to` joined with the file name of `from` if `to` is a
+/// directory, as `cp` and `mv` do.
+fn destination<F: FileSystem + Copy>(fs: F, from: &Path, to: PathBuf) -> PathBuf {
+    match (fs.open(&to), from.file_name()) {
+        (Ok(ref entry), Some(name)) if entry.is_dir() => to.join(name),
+        _ => to,
+    }
+}
+
+/// Formats `ts` as `YYYY-MM-DD hh:mm:ss`.
+fn timestamp<T: Timestamp>(ts: T) -> String {
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        ts.year(),
+        ts.month(),
+        ts.day(),
+        ts.hour(),
+        ts.minute(),
+        ts.second()
+    );
+    s
+}
+
+/// Reads all of the file at `path`.
+pub fn read_all<F: FileSystem + Copy>(fs: F, path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    fs.open_file(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Opens the file at `path` for writing, creating it if it does not exist.
+/// The file is truncated unless `append` is set, in which case it is opened
+/// at its end.
+pub fn open_output<F: FileSystem + Copy>(fs: F, path: &Path, append: bool) -> io::Result<F::File> {
+    match fs.open(path) {
+        Ok(entry) => match entry.into_file() {
+            Some(mut file) if append => {
+                let end = file.size();
+                file.seek(SeekFrom::Start(end))?;
+                Ok(file)
+            }
+            Some(_) => {
+                fs.remove(path)?;
+                fs.create_file(path)
+            }
+            None => Err(io::Error::new(io::ErrorKind::Other, "is a directory")),
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => fs.create_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `data` to the file at `path`, which is created if it does not
+/// exist. The file is replaced unless `append` is set.
+pub fn write_file<F: FileSystem + Copy>(fs: F, path: &Path, data: &[u8], append: bool) -> io::Result<()> {
+    open_output(fs, path, append)?.write_all(data)
+}
+
+/// `mkdir path...`: creates directories.
+pub fn mkdir<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: mkdir path...");
+    }
+    for &arg in &args[1..] {
+        if let Err(e) = fs.create_dir(resolve(cwd, arg)) {
+            report(io, "mkdir", arg, e);
+        }
+    }
+}
+
+/// `rmdir path...`: removes empty directories.
+pub fn rmdir<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: rmdir path...");
+    }
+    for &arg in &args[1..] {
+        let path = resolve(cwd, arg);
+        let result = fs.open_dir(&path).and_then(|_| fs.remove(&path));
+        if let Err(e) = result {
+            report(io, "rmdir", arg, e);
+        }
+    }
+}
+
+/// Removes `path` and, if it is a directory, everything below it.
+fn remove_all<F: FileSystem + Copy>(fs: F, path: &Path) -> io::Result<()> {
+    if let Some(dir) = fs.open(path)?.into_dir() {
+        for entry in dir.entries()? {
+            if !is_dot(entry.name()) {
+                remove_all(fs, &path.join(entry.name()))?;
+            }
+        }
+    }
+    fs.remove(path)
+}
+
+/// `rm [-r] path...`: removes files, and directories with everything in them
+/// if `-r` is given.
+pub fn rm<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    let recursive = args.get(1) == Some(&"-r");
+    let paths = &args[if recursive { 2 } else { 1 }..];
+    if paths.is_empty() {
+        return fail!(io, "usage: rm [-r] path...");
+    }
+    for &arg in paths {
+        let path = resolve(cwd, arg);
+        let result = match fs.open(&path) {
+            Ok(ref entry) if entry.is_dir() && !recursive => {
+                Err(io::Error::new(io::ErrorKind::Other, "is a directory"))
+            }
+            Ok(_) if recursive => remove_all(fs, &path),
+            Ok(_) => fs.remove(&path),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            report(io, "rm", arg, e);
+        }
+    }
+}
+
+/// Copies the file at `from` to a new file at `to` and returns the number of
+/// bytes copied.
+fn copy<F: FileSystem + Copy>(fs: F, from: &Path, to: &Path) -> io::Result<u64> {
+    let mut src = fs.open_file(from)?;
+    let mut dst = fs.create_file(to)?;
+    let mut buf = vec![0; COPY_SIZE];
+    let mut copied = 0;
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok(copied)
+}
+
+/// `cp from to`: copies a file. If `to` is a directory, the copy is made in
+/// it under the same name.
+pub fn cp<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() != 3 {
+        return fail!(io, "usage: cp from to");
+    }
+    let from = resolve(cwd, args[1]);
+    let to = destination(fs, &from, resolve(cwd, args[2]));
+    if let Err(e) = copy(fs, &from, &to) {
+        report(io, "cp", args[1], e);
+    }
+}
+
+/// `mv from to`: moves a file or directory. If `to` is a directory, the entry
+/// is moved into it under the same name.
+pub fn mv<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() != 3 {
+        return fail!(io, "usage: mv from to");
+    }
+    let from = resolve(cwd, args[1]);
+    let to = destination(fs, &from, resolve(cwd, args[2]));
+    if let Err(e) = fs.rename(&from, &to) {
+        report(io, "mv", args[1], e);
+    }
+}
+
+/// `touch path...`: creates the files that do not exist yet.
+pub fn touch<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: touch path...");
+    }
+    for &arg in &args[1..] {
+        let path = resolve(cwd, arg);
+        let result = match fs.open(&path) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => fs.create_file(&path).map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            report(io, "touch", arg, e);
+        }
+    }
+}
+
+/// `stat path...`: prints the metadata of entries.
+pub fn stat<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: stat path...");
+    }
+    for &arg in &args[1..] {
+        let path = resolve(cwd, arg);
+        let entry = match fs.open(&path) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report(io, "stat", arg, e);
+                continue;
+            }
+        };
+
+        let metadata = entry.metadata();
+        let mut attributes = String::new();
+        for &(set, name) in &[
+            (metadata.read_only(), " read-only"),
+            (metadata.hidden(), " hidden"),
+            (metadata.system(), " system"),
+            (metadata.archive(), " archive"),
+        ] {
+            if set {
+                attributes.push_str(name);
+            }
+        }
+        if attributes.is_empty() {
+            attributes.push_str(" none");
+        }
+
+        outln!(io, "      Path: {}", path.display());
+        match entry.as_file() {
+            Some(file) => outln!(io, "      Type: file\n      Size: {}", file.size()),
+            None => outln!(io, "      Type: directory"),
+        }
+        outln!(io, "Attributes:{}", attributes);
+        outln!(io, "   Created: {}", timestamp(metadata.created()));
+        outln!(io, "  Modified: {}", timestamp(metadata.modified()));
+        outln!(io, "  Accessed: {}", timestamp(metadata.accessed()));
+    }
+}
+
+/// Reads the file at `path`, or the standard input if `path` is `None`,
+/// skipping `skip` bytes and stopping after `length` bytes.
+fn read_range<F: FileSystem + Copy>(
+    fs: F,
+    path: Option<&Path>,
+    skip: usize,
+    length: Option<usize>,
+    io: &mut Io,
+) -> io::Result<Vec<u8>> {
+    let mut data = match path {
+        Some(path) => {
+            let mut file = fs.open_file(path)?;
+            file.seek(SeekFrom::Start(core::cmp::min(skip as u64, file.size())))?;
+            let mut data = Vec::new();
+            match length {
+                Some(length) => file.take(length as u64).read_to_end(&mut data)?,
+                None => file.read_to_end(&mut data)?,
+            };
+            return Ok(data);
+        }
+        None => io.read_all(),
+    };
+    data.drain(..core::cmp::min(skip, data.len()));
+    if let Some(length) = length {
+        data.truncate(length);
+    }
+    Ok(data)
+}
+
+/// `hexdump [-n length] [-s skip] [path]`: prints the bytes of a file, or of
+/// the standard input, in hexadecimal and ASCII, starting `skip` bytes in and
+/// stopping after `length` bytes.
+pub fn hexdump<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    let (length, skip, arg) = match parse_options(args, &["-n", "-s"]) {
+        Some((ref values, ref operands)) if operands.len() <= 1 => {
+            (values[0], values[1].unwrap_or(0), operands.first().cloned())
+        }
+        _ => return fail!(io, "usage: hexdump [-n length] [-s skip] [path]"),
+    };
+
+    let path = arg.map(|arg| resolve(cwd, arg));
+    let data = match read_range(fs, path.as_ref().map(|path| path.as_path()), skip, length, io) {
+        Ok(data) => data,
+        Err(e) => return report(io, "hexdump", arg.unwrap_or("-"), e),
+    };
+
+    for (i, line) in data.chunks(16).enumerate() {
+        out!(io, "{:08x} ", skip + i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                out!(io, " ");
+            }
+            match line.get(j) {
+                Some(byte) => out!(io, " {:02x}", byte),
+                None => out!(io, "   "),
+            }
+        }
+        out!(io, "  |");
+        for &byte in line {
+            let c = if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' };
+            out!(io, "{}", c);
+        }
+        outln!(io, "|");
+    }
+    outln!(io, "{:08x}", skip + data.len());
+}
+
+/// Prints the lines of the file named by the operand of `args`, or of the
+/// standard input if there is none, selected by `select` from the line count
+/// given with `-n`.
+fn print_lines<F, S>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io, select: S)
+where
+    F: FileSystem + Copy,
+    S: FnOnce(&[u8], usize) -> &[u8],
+{
+    let command = args[0];
+    let (count, arg) = match parse_options(args, &["-n"]) {
+        Some((ref values, ref operands)) if operands.len() <= 1 => {
+            (values[0].unwrap_or(DEFAULT_LINES), operands.first().cloned())
+        }
+        _ => return fail!(io, "usage: {} [-n lines] [path]", command),
+    };
+
+    let data = match arg {
+        Some(arg) => match read_all(fs, &resolve(cwd, arg)) {
+            Ok(data) => data,
+            Err(e) => return report(io, command, arg, e),
+        },
+        None => io.read_all(),
+    };
+    io.write_bytes(select(&data, count));
+}
+
+/// `head [-n lines] [path]`: prints the first lines of a file or of the
+/// standard input.
+pub fn head<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    print_lines(fs, cwd, args, io, |data, count| {
+        let end = data
+            .iter()
+            .enumerate()
+            .filter(|&(_, &byte)| byte == b'\n')
+            .nth(count.wrapping_sub(1))
+            .map_or(data.len(), |(i, _)| i + 1);
+        &data[..if count == 0 { 0 } else { end }]
+    });
+}
+
+/// `tail [-n lines] [path]`: prints the last lines of a file or of the
+/// standard input.
+pub fn tail<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    print_lines(fs, cwd, args, io, |data, count| {
+        // A newline ending the file does not start another line.
+        let body = match data.last() {
+            Some(b'\n') => &data[..data.len() - 1],
+            _ => data,
+        };
+        let start = body
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|&(_, &byte)| byte == b'\n')
+            .nth(count.wrapping_sub(1))
+            .map_or(0, |(i, _)| i + 1);
+        &data[if count == 0 { data.len() } else { start }..]
+    });
+}
+
+/// Prints the total size of the files below the directory `dir` at `path`
+/// for every directory below it, and returns the total of `dir`.
+fn disk_usage<F: FileSystem + Copy>(dir: F::Dir, path: &Path, io: &mut Io) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in dir.entries()? {
+        if is_dot(entry.name()) {
+            continue;
+        }
+        let child = path.join(entry.name());
+        total += match entry.as_file().map(|file| file.size()) {
+            Some(size) => size,
+            None => match entry.into_dir() {
+                Some(dir) => disk_usage::<F>(dir, &child, io)?,
+                None => 0,
+            },
+        };
+    }
+    outln!(io, "{}\t{}", total, path.display());
+    Ok(total)
+}
+
+/// `du [path]`: prints the total size in bytes of the files below every
+/// directory below `path`, by default the working directory.
+pub fn du<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    let arg = args.get(1).cloned().unwrap_or(".");
+    let path = resolve(cwd, arg);
+    let result = fs.open(&path).and_then(|entry| match entry.into_dir() {
+        Some(dir) => disk_usage::<F>(dir, &path, io).map(|_| ()),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        report(io, "du", arg, e);
+    }
+}
+
+/// `df`: prints how much space the file system has and how much is used, in
+/// KiB.
+pub fn df<F: FileSystem + Copy>(fs: F, io: &mut Io) {
+    match fs.usage() {
+        Ok(usage) => {
+            let total = usage.total_blocks * usage.block_size / 1024;
+            let free = usage.free_blocks * usage.block_size / 1024;
+            let used = total - free;
+            let percent = if total == 0 { 0 } else { (used * 100 + total - 1) / total };
+            outln!(io, "{:>10} {:>10} {:>10} {:>4}", "Size(KiB)", "Used", "Avail", "Use%");
+            outln!(io, "{:>10} {:>10} {:>10} {:>3}%", total, used, free, percent);
+        }
+        Err(e) => report(io, "df", "/", e),
+    }
+}
+
+/// Returns `true` if `name` matches the shell pattern `pattern`, where `*`
+/// matches any run of characters and `?` any single one. Letters match
+/// regardless of their case, as FAT names do.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p.eq_ignore_ascii_case(n) => matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Prints the path of every entry below the directory `dir` at `path` whose
+/// name matches `pattern`.
+fn find_in<F: FileSystem + Copy>(dir: F::Dir, path: &Path, pattern: &str, io: &mut Io) -> io::Result<()> {
+    for entry in dir.entries()? {
+        if is_dot(entry.name()) {
+            continue;
+        }
+        let child = path.join(entry.name());
+        if matches(pattern.as_bytes(), entry.name().as_bytes()) {
+            outln!(io, "{}", child.display());
+        }
+        if let Some(dir) = entry.into_dir() {
+            find_in::<F>(dir, &child, pattern, io)?;
+        }
+    }
+    Ok(())
+}
+
+/// `find [path] [-name pattern]`: prints the paths of the entries below
+/// `path`, by default the working directory, whose names match `pattern`.
+pub fn find<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    let (arg, pattern) = match args {
+        [_] => (".", "*"),
+        [_, path] => (*path, "*"),
+        [_, "-name", pattern] => (".", *pattern),
+        [_, path, "-name", pattern] => (*path, *pattern),
+        _ => return fail!(io, "usage: find [path] [-name pattern]"),
+    };
+    let path = resolve(cwd, arg);
+    if let Err(e) = fs.open_dir(&path).and_then(|dir| find_in::<F>(dir, &path, pattern, io)) {
+        report(io, "find", arg, e);
+    }
+}
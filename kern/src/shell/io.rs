@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::console::kprint;
+use crate::tty;
+
+/// Writes to the standard output of a built-in command, like `print!`.
+pub macro out($io:expr, $($arg:tt)*) {
+    $io.print(format_args!($($arg)*))
+}
+
+/// Writes a line to the standard output of a built-in command, like
+/// `println!`.
+pub macro outln {
+    ($io:expr) => (out!($io, "\n")),
+    ($io:expr, $fmt:expr) => (out!($io, concat!($fmt, "\n"))),
+    ($io:expr, $fmt:expr, $($arg:tt)*) => (out!($io, concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Reports an error of a built-in command on the console, which serves as its
+/// standard error, and makes the command fail.
+pub macro fail {
+    ($io:expr, $fmt:expr) => ($io.fail(format_args!(concat!($fmt, "\n")))),
+    ($io:expr, $fmt:expr, $($arg:tt)*) => ($io.fail(format_args!(concat!($fmt, "\n"), $($arg)*)))
+}
+
+/// The standard streams of a built-in command. Either stream is the console
+/// unless it is redirected or part of a pipeline, in which case the shell
+/// moves its data in memory.
+#[derive(Debug, Default)]
+pub struct Io {
+    /// The standard input, if it is not the console.
+    input: Option<Vec<u8>>,
+    /// The standard output collected so far, if it is not the console.
+    output: Option<Vec<u8>>,
+    failed: bool,
+}
+
+impl Io {
+    /// Returns streams connected to the console.
+    pub fn console() -> Io {
+        Io::default()
+    }
+
+    /// Returns streams reading `input`, or the console if it is `None`, and
+    /// collecting the output if `capture` is set.
+    pub fn new(input: Option<Vec<u8>>, capture: bool) -> Io {
+        Io { input, output: if capture { Some(Vec::new()) } else { None }, failed: false }
+    }
+
+    /// Returns `true` if the output is collected rather than written to the
+    /// console.
+    pub fn is_captured(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Takes the standard input if it is not the console. Later reads see an
+    /// empty input.
+    pub fn take_input(&mut self) -> Option<Vec<u8>> {
+        self.input.as_mut().map(|input| core::mem::replace(input, Vec::new()))
+    }
+
+    /// Reads the standard input until its end. On the console, that is until
+    /// Ctrl-D is typed on an empty line.
+    pub fn read_all(&mut self) -> Vec<u8> {
+        if let Some(input) = self.take_input() {
+            return input;
+        }
+
+        let mut data = Vec::new();
+        let mut buf = [0; 256];
+        loop {
+            match tty::read(&mut buf) {
+                0 => return data,
+                n => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Writes `bytes` to the standard output.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        match self.output.as_mut() {
+            Some(output) => output.extend_from_slice(bytes),
+            None => tty::write(bytes),
+        }
+    }
+
+    /// Writes formatted text to the standard output. See `out!`.
+    pub fn print(&mut self, args: fmt::Arguments) {
+        match self.output.as_mut() {
+            Some(output) => {
+                let _ = Buffer(output).write_fmt(args);
+            }
+            None => kprint!("{}", args),
+        }
+    }
+
+    /// Writes formatted text to the console and records the failure. See
+    /// `fail!`.
+    pub fn fail(&mut self, args: fmt::Arguments) {
+        kprint!("{}", args);
+        self.failed = true;
+    }
+
+    /// Records that the command failed without reporting anything.
+    pub fn set_failed(&mut self) {
+        self.failed = true;
+    }
+
+    /// Returns the exit status of the command: 1 if it failed, 0 otherwise.
+    pub fn status(&self) -> i32 {
+        if self.failed {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Returns the collected output, if the output is not the console.
+    pub fn into_output(self) -> Option<Vec<u8>> {
+        self.output
+    }
+}
+
+/// Adapts a byte vector to `fmt::Write`.
+struct Buffer<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for Buffer<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult, SIGCONT, SIGTERM, SIGTSTP};
+use shim::path::PathBuf;
+
+use crate::console::kprintln;
+use crate::process::thread::{self, block_on};
+use crate::process::{exit_status, pipe, Descriptor, EventPollFn, Id, PipeReader, PipeWriter, Process};
+use crate::SCHEDULER;
+
+use super::io::{fail, outln, Io};
+use super::resolve;
+
+/// A program started by the shell that has not been collected yet.
+struct Job {
+    id: Id,
+    command: String,
+}
+
+/// The programs the shell started. The shell is their parent, so their exit
+/// statuses are collected here.
+pub struct Jobs {
+    shell: Id,
+    jobs: Vec<Job>,
+}
+
+/// How a wait for a job ended.
+enum WaitResult {
+    Exited(i32),
+    Stopped,
+    Gone,
+}
+
+/// The pipes connecting the standard streams of a foreground job to the
+/// shell when they are redirected or part of a pipeline.
+#[derive(Default)]
+struct Streams {
+    /// The write end of the job's standard input, the data to write to it and
+    /// how much of that is written. The end is closed once all of it is.
+    input: Option<(PipeWriter, Vec<u8>, usize)>,
+    /// The read end of the job's standard output.
+    output: Option<PipeReader>,
+}
+
+impl Streams {
+    /// Connects the standard input of `process` to a pipe fed from the input
+    /// of `io`, and its standard output to a pipe read into the output of
+    /// `io`, for each of them that is not the console.
+    fn connect(process: &mut Process, io: &mut Io) -> OsResult<Streams> {
+        let mut streams = Streams::default();
+        if let Some(data) = io.take_input() {
+            let (reader, writer) = pipe();
+            process.fds.set(0, Descriptor::PipeReader(reader))?;
+            streams.input = Some((writer, data, 0));
+        }
+        if io.is_captured() {
+            let (reader, writer) = pipe();
+            process.fds.set(1, Descriptor::PipeWriter(writer))?;
+            streams.output = Some(reader);
+        }
+        Ok(streams)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.input.is_some() || self.output.is_some()
+    }
+
+    /// Moves as much data through the pipes as they take without blocking.
+    fn pump(&mut self, io: &mut Io) {
+        let mut done = false;
+        if let Some((writer, data, written)) = self.input.as_mut() {
+            while *written < data.len() {
+                match writer.write(&data[*written..]) {
+                    Ok(Some(n)) => *written += n,
+                    Ok(None) => break,
+                    // The job closed its standard input.
+                    Err(_) => *written = data.len(),
+                }
+            }
+            done = *written == data.len();
+        }
+        if done {
+            self.input = None;
+        }
+
+        if let Some(reader) = self.output.as_ref() {
+            let mut buf = [0; 256];
+            while let Some(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                io.write_bytes(&buf[..n]);
+            }
+        }
+    }
+
+    /// Returns a poll function for `block_on()` that reports when a child of
+    /// the shell exited or stopped or `pump()` can move data.
+    fn poll_fn(&self) -> EventPollFn {
+        let writer = self.input.as_ref().map(|(writer, _, _)| writer.clone());
+        let reader = self.output.clone();
+        Box::new(move |shell: &mut Process| {
+            shell.child_exited
+                || writer.as_ref().map_or(false, |writer| writer.is_ready())
+                || reader.as_ref().map_or(false, |reader| reader.is_ready())
+        })
+    }
+}
+
+/// Prints how a job ended with `status`.
+fn report(id: Id, command: &str, status: i32) {
+    if status > exit_status(0) {
+        kprintln!("[{}] killed by signal {}\t{}", id, status - exit_status(0), command);
+    } else {
+        kprintln!("[{}] exited with status {}\t{}", id, status, command);
+    }
+}
+
+/// Parses a job argument, or returns the most recent job if there is none.
+fn parse_id(arg: Option<&&str>, jobs: &[Job]) -> Option<Id> {
+    match arg {
+        Some(arg) => arg.trim_start_matches('%').parse().ok(),
+        None => jobs.last().map(|job| job.id),
+    }
+}
+
+impl Jobs {
+    /// Returns an empty job table for the calling kernel thread.
+    pub fn new() -> Jobs {
+        Jobs { shell: thread::current(), jobs: Vec::new() }
+    }
+
+    fn remove(&mut self, id: Id) -> Option<Job> {
+        let i = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(i))
+    }
+
+    /// Starts the program at `args[0]`, resolved against `cwd`, with the
+    /// arguments `args` and the environment `env`, and returns its exit
+    /// status.
+    ///
+    /// A foreground program receives the signals typed on the console and is
+    /// waited for. Its standard input and output are connected to those of
+    /// `io` through pipes unless they are the console. A background program
+    /// is left running and must use the console.
+    pub fn run(&mut self, cwd: &PathBuf, args: &[&str], env: &[String], background: bool, io: &mut Io) -> i32 {
+        match self.start(cwd, args, env, background, io) {
+            Ok(status) => status,
+            Err(e) => {
+                fail!(io, "{}: {:?}", args[0], e);
+                io.status()
+            }
+        }
+    }
+
+    fn start(&mut self, cwd: &PathBuf, args: &[&str], env: &[String], background: bool, io: &mut Io) -> OsResult<i32> {
+        let path = resolve(cwd, args[0]);
+        let argv: Vec<String> = args.iter().map(|&arg| String::from(arg)).collect();
+        let mut process = Process::load_with_args(&path, &argv, env)?;
+        process.parent = Some(self.shell);
+        let mut streams = Streams::connect(&mut process, io)?;
+        if background && streams.is_connected() {
+            return Err(OsError::InvalidArgument);
+        }
+        let id = SCHEDULER.add(process).ok_or(OsError::NoMemory)?;
+
+        let mut command = argv.join(" ");
+        if background {
+            command.push_str(" &");
+        }
+        self.jobs.push(Job { id, command });
+        if background {
+            kprintln!("[{}]", id);
+            Ok(0)
+        } else {
+            Ok(self.foreground(id, &mut streams, io))
+        }
+    }
+
+    /// Waits for the job `id` to exit or, if `stop` is set, to be stopped,
+    /// moving data through `streams` meanwhile.
+    fn wait_for(&mut self, id: Id, stop: bool, streams: &mut Streams, io: &mut Io) -> WaitResult {
+        loop {
+            streams.pump(io);
+            match SCHEDULER.reap(self.shell, Some(id)) {
+                Ok(Some((_, status))) => {
+                    // Collect what the job left in its output pipe.
+                    streams.pump(io);
+                    return WaitResult::Exited(status);
+                }
+                Ok(None) => {}
+                Err(_) => return WaitResult::Gone,
+            }
+
+            let stopped = SCHEDULER.critical(|scheduler| scheduler.find(id).map_or(false, |proc| proc.stopped));
+            if stop && stopped {
+                return WaitResult::Stopped;
+            }
+            // The flag is raised when a child exits or stops after `reap()`
+            // cleared it.
+            block_on(streams.poll_fn());
+        }
+    }
+
+    /// Makes the job `id` the foreground process and waits until it exits or
+    /// is stopped. Returns its exit status, or that of a process terminated
+    /// by `SIGTSTP` if it was stopped.
+    fn foreground(&mut self, id: Id, streams: &mut Streams, io: &mut Io) -> i32 {
+        SCHEDULER.set_foreground(Some(id));
+        let result = self.wait_for(id, true, streams, io);
+        SCHEDULER.set_foreground(None);
+
+        match result {
+            WaitResult::Exited(status) => {
+                let job = self.remove(id);
+                if status > exit_status(0) {
+                    report(id, job.as_ref().map_or("", |job| &job.command), status);
+                }
+                status
+            }
+            WaitResult::Stopped => {
+                kprintln!("[{}] stopped", id);
+                exit_status(SIGTSTP)
+            }
+            WaitResult::Gone => {
+                self.remove(id);
+                0
+            }
+        }
+    }
+
+    /// Collects the jobs that exited in the background and reports them.
+    pub fn reap_finished(&mut self) {
+        loop {
+            match SCHEDULER.reap(self.shell, None) {
+                Ok(Some((id, status))) => {
+                    let command = self.remove(id).map_or(String::new(), |job| job.command);
+                    report(id, &command, status);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// `fg [id]`: continues the job `id`, or the most recent one, in the
+    /// foreground.
+    pub fn fg(&mut self, args: &[&str], io: &mut Io) -> i32 {
+        let id = match parse_id(args.get(1), &self.jobs) {
+            Some(id) => id,
+            None => {
+                fail!(io, "fg: no such job");
+                return io.status();
+            }
+        };
+        if SCHEDULER.signal(id, SIGCONT).is_err() {
+            fail!(io, "fg: no such job: {}", id);
+            return io.status();
+        }
+        if let Some(job) = self.jobs.iter().find(|job| job.id == id) {
+            kprintln!("{}", job.command);
+        }
+        self.foreground(id, &mut Streams::default(), io)
+    }
+
+    /// `wait [id]`: waits for the job `id`, or for every job, to exit.
+    /// Returns the exit status of the last job.
+    pub fn wait(&mut self, args: &[&str], io: &mut Io) -> i32 {
+        let ids: Vec<Id> = match args.get(1) {
+            Some(_) => parse_id(args.get(1), &self.jobs).into_iter().collect(),
+            None => self.jobs.iter().map(|job| job.id).collect(),
+        };
+        let mut last = 0;
+        for id in ids {
+            match self.wait_for(id, false, &mut Streams::default(), io) {
+                WaitResult::Exited(status) => {
+                    let command = self.remove(id).map_or(String::new(), |job| job.command);
+                    report(id, &command, status);
+                    last = status;
+                }
+                _ => fail!(io, "wait: no such job: {}", id),
+            }
+        }
+        if io.status() != 0 {
+            io.status()
+        } else {
+            last
+        }
+    }
+
+    /// `jobs`: lists the jobs that have not been collected.
+    pub fn list(&self, io: &mut Io) {
+        for job in self.jobs.iter() {
+            outln!(io, "[{}] {}", job.id, job.command);
+        }
+    }
+}
+
+/// `ps`: lists every process.
+pub fn ps(io: &mut Io) {
+    outln!(io, "{:>5} {:>5} {:>4} {:<9} {:>4}  {}", "PID", "PPID", "CPU", "STATE", "NICE", "COMMAND");
+    for info in SCHEDULER.list() {
+        let parent = info.parent.map_or(String::from("-"), |parent| parent.to_string());
+        let mut name = info.name;
+        if info.is_kernel_thread {
+            name.insert(0, '[');
+            name.push(']');
+        }
+        outln!(io, "{:>5} {:>5} {:>4} {:<9} {:>4}  {}", info.id, parent, info.core, info.state, info.nice, name);
+    }
+}
+
+/// `kill [-SIG] id...`: sends `SIG`, by default `SIGTERM`, to the processes.
+pub fn kill(args: &[&str], io: &mut Io) {
+    let mut sig = SIGTERM;
+    let mut ids = &args[1..];
+    if let Some(arg) = ids.first() {
+        if arg.starts_with('-') {
+            match arg[1..].parse() {
+                Ok(n) => sig = n,
+                Err(_) => return fail!(io, "kill: bad signal: {}", arg),
+            }
+            ids = &ids[1..];
+        }
+    }
+    if ids.is_empty() {
+        return fail!(io, "usage: kill [-SIG] id...");
+    }
+
+    for arg in ids {
+        let result = match arg.trim_start_matches('%').parse() {
+            Ok(id) => SCHEDULER.signal(id, sig),
+            Err(_) => Err(OsError::InvalidArgument),
+        };
+        if let Err(e) = result {
+            fail!(io, "kill: {}: {:?}", arg, e);
+        }
+    }
+}
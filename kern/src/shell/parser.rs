@@ -0,0 +1,371 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+
+/// A piece of a word: text taken literally, or a variable expanded when the
+/// command runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Literal(String),
+    /// `$NAME` or `${NAME}`. `$?` is the variable `?`.
+    Var(String),
+}
+
+/// A word of a command line after quotes and escapes have been removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    /// If the word starts with an unquoted `NAME=`, the name.
+    pub assigns: Option<String>,
+    /// The word, after `NAME=` if the word assigns.
+    pub parts: Vec<Part>,
+}
+
+impl Word {
+    /// Returns the word with its variables replaced by `lookup`. The `NAME=`
+    /// of an assignment is kept.
+    pub fn expand<F: Fn(&str) -> String>(&self, lookup: F) -> String {
+        let mut s = String::new();
+        if let Some(name) = &self.assigns {
+            s.push_str(name);
+            s.push('=');
+        }
+        s.push_str(&self.expand_value(lookup));
+        s
+    }
+
+    /// Returns the word, without the `NAME=` of an assignment, with its
+    /// variables replaced by `lookup`.
+    pub fn expand_value<F: Fn(&str) -> String>(&self, lookup: F) -> String {
+        let mut s = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(text) => s.push_str(text),
+                Part::Var(name) => s.push_str(&lookup(name)),
+            }
+        }
+        s
+    }
+}
+
+/// Where a redirection sends a stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    /// `< path`: read standard input from a file.
+    Input,
+    /// `> path`: write standard output to a file, replacing it.
+    Output,
+    /// `>> path`: append standard output to a file.
+    Append,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub kind: RedirectKind,
+    pub path: Word,
+}
+
+/// A simple command: the variables it assigns, its words and its
+/// redirections.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Command {
+    /// The `NAME=value` words before the command name.
+    pub assignments: Vec<Word>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl Command {
+    fn is_empty(&self) -> bool {
+        self.assignments.is_empty() && self.words.is_empty() && self.redirects.is_empty()
+    }
+}
+
+/// How a pipeline is connected to the one after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// `;` or the end of the line: the next pipeline always runs.
+    Always,
+    /// `&`: like `;`, but the pipeline runs in the background.
+    Background,
+    /// `&&`: the next pipeline runs if this one succeeded.
+    And,
+    /// `||`: the next pipeline runs if this one failed.
+    Or,
+}
+
+/// Commands connected by `|`, each reading the output of the one before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// How the pipeline is connected to the next one.
+    pub connector: Connector,
+}
+
+/// Error type for `parse()` failures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// A quote was not closed.
+    UnterminatedQuote,
+    /// A `${` was not closed by `}`.
+    UnterminatedVariable,
+    /// A redirection operator was not followed by a path.
+    MissingPath,
+    /// An operator was not preceded by a command, or `|`, `&&` or `||` was
+    /// not followed by one.
+    MissingCommand,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            Error::UnterminatedQuote => "unterminated quote",
+            Error::UnterminatedVariable => "unterminated ${",
+            Error::MissingPath => "missing path after redirection",
+            Error::MissingCommand => "missing command",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// A token of a command line.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    Pipe,
+    Redirect(RedirectKind),
+    Connector(Connector),
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits a command line into tokens.
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    /// Appends `c` to the literal text at the end of `parts`.
+    fn push_char(parts: &mut Vec<Part>, c: char) {
+        if let Some(Part::Literal(text)) = parts.last_mut() {
+            text.push(c);
+            return;
+        }
+        let mut text = String::new();
+        text.push(c);
+        parts.push(Part::Literal(text));
+    }
+
+    /// Reads the variable reference after a `$` and appends it to `parts`. A
+    /// `$` that starts no reference is taken literally.
+    fn variable(&mut self, parts: &mut Vec<Part>) -> Result<(), Error> {
+        match self.chars.peek() {
+            Some('?') => {
+                self.chars.next();
+                parts.push(Part::Var(String::from("?")));
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut name = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(Error::UnterminatedVariable),
+                    }
+                }
+                parts.push(Part::Var(name));
+            }
+            Some(&c) if is_name_start(c) => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !is_name_char(c) {
+                        break;
+                    }
+                    name.push(c);
+                    self.chars.next();
+                }
+                parts.push(Part::Var(name));
+            }
+            _ => Lexer::push_char(parts, '$'),
+        }
+        Ok(())
+    }
+
+    /// Reads a word starting at the next character, which is neither blank
+    /// nor an operator.
+    fn word(&mut self) -> Result<Word, Error> {
+        let mut word = Word { assigns: None, parts: Vec::new() };
+        // Whether the word so far is an unquoted name, which makes a
+        // following `=` an assignment.
+        let mut is_name = true;
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '|' | '&' | ';' | '<' | '>' => break,
+                '=' if is_name && word.assigns.is_none() && !word.parts.is_empty() => {
+                    self.chars.next();
+                    if let Some(Part::Literal(name)) = word.parts.pop() {
+                        word.assigns = Some(name);
+                    }
+                }
+                '\'' => {
+                    self.chars.next();
+                    loop {
+                        match self.chars.next() {
+                            Some('\'') => break,
+                            Some(c) => Lexer::push_char(&mut word.parts, c),
+                            None => return Err(Error::UnterminatedQuote),
+                        }
+                    }
+                }
+                '"' => {
+                    self.chars.next();
+                    loop {
+                        match self.chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match self.chars.peek() {
+                                Some(&c) if c == '"' || c == '\\' || c == '$' => {
+                                    self.chars.next();
+                                    Lexer::push_char(&mut word.parts, c);
+                                }
+                                _ => Lexer::push_char(&mut word.parts, '\\'),
+                            },
+                            Some('$') => self.variable(&mut word.parts)?,
+                            Some(c) => Lexer::push_char(&mut word.parts, c),
+                            None => return Err(Error::UnterminatedQuote),
+                        }
+                    }
+                }
+                '\\' => {
+                    self.chars.next();
+                    let c = self.chars.next().unwrap_or('\\');
+                    Lexer::push_char(&mut word.parts, c);
+                }
+                '$' => {
+                    self.chars.next();
+                    self.variable(&mut word.parts)?;
+                }
+                c => {
+                    self.chars.next();
+                    let valid = if word.parts.is_empty() { is_name_start(c) } else { is_name_char(c) };
+                    if !valid {
+                        is_name = false;
+                    }
+                    Lexer::push_char(&mut word.parts, c);
+                    continue;
+                }
+            }
+            is_name = false;
+        }
+        Ok(word)
+    }
+
+    /// Returns the next token, `None` at the end of the line or at a comment.
+    fn next_token(&mut self) -> Result<Option<Token>, Error> {
+        while let Some(&c) = self.chars.peek() {
+            if c != ' ' && c != '\t' {
+                break;
+            }
+            self.chars.next();
+        }
+
+        let c = match self.chars.peek() {
+            Some(&c) => c,
+            None => return Ok(None),
+        };
+        let token = match c {
+            '#' => return Ok(None),
+            '|' | '&' | '>' => {
+                self.chars.next();
+                let doubled = self.chars.peek() == Some(&c);
+                if doubled {
+                    self.chars.next();
+                }
+                match (c, doubled) {
+                    ('|', false) => Token::Pipe,
+                    ('|', true) => Token::Connector(Connector::Or),
+                    ('&', false) => Token::Connector(Connector::Background),
+                    ('&', true) => Token::Connector(Connector::And),
+                    (_, false) => Token::Redirect(RedirectKind::Output),
+                    (_, true) => Token::Redirect(RedirectKind::Append),
+                }
+            }
+            ';' => {
+                self.chars.next();
+                Token::Connector(Connector::Always)
+            }
+            '<' => {
+                self.chars.next();
+                Token::Redirect(RedirectKind::Input)
+            }
+            _ => Token::Word(self.word()?),
+        };
+        Ok(Some(token))
+    }
+}
+
+/// Parses the command line `line` into pipelines.
+///
+/// Words are split on unquoted blanks. Single quotes keep everything between
+/// them literally; double quotes keep everything but `$` references and the
+/// escapes `\"`, `\\` and `\$`. Outside quotes, a backslash keeps the next
+/// character literally. An unquoted `#` at the start of a word starts a
+/// comment.
+pub fn parse(line: &str) -> Result<Vec<Pipeline>, Error> {
+    let mut lexer = Lexer { chars: line.chars().peekable() };
+    let mut pipelines = Vec::new();
+    let mut commands = Vec::new();
+    let mut command = Command::default();
+    // Whether a command must follow, as after `|`, `&&` and `||`.
+    let mut expect_command = false;
+
+    while let Some(token) = lexer.next_token()? {
+        match token {
+            Token::Word(word) => {
+                if word.assigns.is_some() && command.words.is_empty() {
+                    command.assignments.push(word);
+                } else {
+                    command.words.push(word);
+                }
+            }
+            Token::Redirect(kind) => match lexer.next_token()? {
+                Some(Token::Word(path)) => command.redirects.push(Redirect { kind, path }),
+                _ => return Err(Error::MissingPath),
+            },
+            Token::Pipe => {
+                if command.is_empty() {
+                    return Err(Error::MissingCommand);
+                }
+                commands.push(core::mem::replace(&mut command, Command::default()));
+                expect_command = true;
+                continue;
+            }
+            Token::Connector(connector) => {
+                if command.is_empty() {
+                    return Err(Error::MissingCommand);
+                }
+                commands.push(core::mem::replace(&mut command, Command::default()));
+                pipelines.push(Pipeline { commands: core::mem::replace(&mut commands, Vec::new()), connector });
+                expect_command = connector == Connector::And || connector == Connector::Or;
+                continue;
+            }
+        }
+        expect_command = false;
+    }
+
+    if expect_command {
+        return Err(Error::MissingCommand);
+    }
+    if !command.is_empty() {
+        commands.push(command);
+        pipelines.push(Pipeline { commands, connector: Connector::Always });
+    }
+    Ok(pipelines)
+}
@@ -0,0 +1,325 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use fat32::traits::{File, FileSystem};
+use kernel_api::{TCGETS, TCSETS};
+use shim::io::{self, Cursor, Write};
+use shim::ioerr;
+use shim::path::{Path, PathBuf};
+use xmodem::{FileInfo, Progress, Xmodem, Ymodem};
+use zmodem::Zmodem;
+
+use crate::console::kprintln;
+use crate::tty;
+
+use super::files;
+use super::io::{fail, Io};
+use super::resolve;
+
+/// The byte an XMODEM receiver sends to ask for the first packet.
+const NAK: u8 = 0x15;
+
+/// The byte a YMODEM receiver sends to ask for block 0.
+const CRC: u8 = b'C';
+
+/// How long to wait for the host before repeating the handshake.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many `RETRY_INTERVAL`s to wait for a byte from the host before giving
+/// up on the transfer.
+const RETRIES: usize = 6;
+
+/// Whether the host asked for the first packet of the file being sent.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+fn progress(progress: Progress) {
+    if let Progress::Started = progress {
+        STARTED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The console in raw mode, carrying a transfer with the host. The terminal's
+/// mode is restored when it is dropped.
+struct Serial {
+    flags: u64,
+    /// The byte repeated every `RETRY_INTERVAL` until the host sends
+    /// anything. A receiver's `NAK` is lost if the host's sender was not
+    /// listening yet.
+    handshake: Option<u8>,
+}
+
+impl Serial {
+    fn open(handshake: Option<u8>) -> Serial {
+        let flags = tty::ioctl(TCGETS, 0).expect("TCGETS failed");
+        tty::ioctl(TCSETS, 0).expect("raw mode is a valid terminal mode");
+        // Drop whatever was typed ahead so that it is not taken for the
+        // host's answer.
+        let mut buf = [0; 64];
+        while tty::read_timeout(&mut buf, Duration::from_secs(0)).is_some() {}
+        Serial { flags, handshake }
+    }
+}
+
+impl io::Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for _ in 0..RETRIES {
+            if let Some(n) = tty::read_timeout(buf, RETRY_INTERVAL) {
+                self.handshake = None;
+                return Ok(n);
+            }
+            if let Some(byte) = self.handshake {
+                tty::write(&[byte]);
+            }
+        }
+        ioerr!(TimedOut, "timed out waiting for the host")
+    }
+}
+
+impl io::Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        tty::write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Serial {
+    fn drop(&mut self) {
+        let _ = tty::ioctl(TCSETS, self.flags);
+    }
+}
+
+/// `rx [path]`: receives a file from the host with XMODEM and writes it to
+/// `path`, or to the standard output if there is none. XMODEM pads the file
+/// to a multiple of 128 bytes.
+pub fn rx<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() > 2 {
+        return fail!(io, "usage: rx [path]");
+    }
+    // The file is created before the transfer, so that it is not started
+    // for nothing.
+    let mut file = match args.get(1) {
+        Some(arg) => match files::open_output(fs, &resolve(cwd, arg), false) {
+            Ok(file) => Some(file),
+            Err(e) => return fail!(io, "rx: {}: {}", arg, e),
+        },
+        None => None,
+    };
+
+    // The messages go to the console even if the output is redirected, like
+    // errors.
+    kprintln!("rx: waiting for the host to send with XMODEM");
+    let mut data = Vec::new();
+    let received = match Xmodem::receive(Serial::open(Some(NAK)), &mut data) {
+        Ok(received) => received,
+        Err(e) => return fail!(io, "rx: {}", e),
+    };
+
+    match file {
+        Some(ref mut file) => match file.write_all(&data) {
+            Ok(()) => kprintln!("rx: received {} bytes into {}", received, args[1]),
+            Err(e) => fail!(io, "rx: {}: {}", args[1], e),
+        },
+        None => io.write_bytes(&data),
+    }
+}
+
+/// `sx [path]`: sends the file at `path`, or the standard input if there is
+/// none, to the host with XMODEM.
+pub fn sx<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    let data = match args {
+        [_] => io.read_all(),
+        [_, arg] => match files::read_all(fs, &resolve(cwd, arg)) {
+            Ok(data) => data,
+            Err(e) => return fail!(io, "sx: {}: {}", arg, e),
+        },
+        _ => return fail!(io, "usage: sx [path]"),
+    };
+    kprintln!("sx: waiting for the host to receive with XMODEM");
+    STARTED.store(false, Ordering::Relaxed);
+    match Xmodem::transmit_with_progress(&data[..], Serial::open(None), progress) {
+        Ok(sent) => kprintln!("sx: sent {} bytes", sent),
+        Err(e) if !STARTED.load(Ordering::Relaxed) => fail!(io, "sx: the host did not start receiving: {}", e),
+        Err(e) => fail!(io, "sx: {}", e),
+    }
+}
+
+/// Returns the path in `dir` of a file received in a batch. Only the last
+/// component of its name is kept, so that the host cannot write outside of
+/// `dir`.
+fn batch_path(dir: &Path, info: &FileInfo) -> io::Result<PathBuf> {
+    let name = Path::new(info.name()).file_name().and_then(|name| name.to_str()).unwrap_or("");
+    if name.is_empty() || name == "." || name == ".." {
+        return ioerr!(InvalidInput, "bad file name");
+    }
+    Ok(dir.join(name))
+}
+
+/// Writes a file received in a batch into `dir`.
+fn save<F: FileSystem + Copy>(fs: F, dir: &Path, info: &FileInfo, data: &[u8], cmd: &str, io: &mut Io) {
+    let path = match batch_path(dir, info) {
+        Ok(path) => path,
+        Err(e) => return fail!(io, "{}: {}: {}", cmd, info.name(), e),
+    };
+    match files::write_file(fs, &path, data, false) {
+        Ok(()) => kprintln!("{}: received {} bytes into {}", cmd, info.length, path.display()),
+        Err(e) => fail!(io, "{}: {}: {}", cmd, path.display(), e),
+    }
+}
+
+/// Opens the file at `path` to receive `length` bytes into it. A shorter
+/// file is taken for the start of an interrupted transfer, and is opened at
+/// its end; any other is replaced. Returns the file and its offset.
+fn open_resumed<F: FileSystem + Copy>(fs: F, path: &Path, length: u64) -> io::Result<(F::File, u64)> {
+    let size = fs.open_file(path).map(|file| file.size()).unwrap_or(0);
+    let resume = size > 0 && size < length;
+    let file = files::open_output(fs, path, resume)?;
+    Ok((file, if resume { size } else { 0 }))
+}
+
+/// Reads the files named by `args` to send them in a batch. Everything is
+/// read first: the console cannot report errors once the transfer has
+/// started.
+fn read_batch<F: FileSystem + Copy>(
+    fs: F,
+    cwd: &PathBuf,
+    args: &[&str],
+    io: &mut Io,
+) -> Option<Vec<(FileInfo, Vec<u8>)>> {
+    let mut batch = Vec::new();
+    for arg in args[1..].iter() {
+        let path = resolve(cwd, arg);
+        let data = match files::read_all(fs, &path) {
+            Ok(data) => data,
+            Err(e) => {
+                fail!(io, "{}: {}: {}", args[0], arg, e);
+                return None;
+            }
+        };
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        match FileInfo::new(name, data.len() as u64, 0) {
+            Ok(info) => batch.push((info, data)),
+            Err(e) => {
+                fail!(io, "{}: {}: {}", args[0], arg, e);
+                return None;
+            }
+        }
+    }
+    Some(batch)
+}
+
+/// `rb [dir]`: receives a batch of files from the host with YMODEM into
+/// `dir`, by default the working directory. Each file keeps its name and
+/// exact length.
+pub fn rb<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() > 2 {
+        return fail!(io, "usage: rb [dir]");
+    }
+    let dir = resolve(cwd, args.get(1).unwrap_or(&"."));
+    if let Err(e) = fs.open_dir(&dir) {
+        return fail!(io, "rb: {}: {}", dir.display(), e);
+    }
+
+    kprintln!("rb: waiting for the host to send with YMODEM");
+    let mut ymodem = Ymodem::new(Serial::open(Some(CRC)));
+    loop {
+        let mut data = Vec::new();
+        match ymodem.receive(&mut data) {
+            Ok(Some(info)) => save(fs, &dir, &info, &data, "rb", io),
+            Ok(None) => return,
+            Err(e) => return fail!(io, "rb: {}", e),
+        }
+    }
+}
+
+/// `sb path...`: sends the files to the host in one batch with YMODEM.
+pub fn sb<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: sb path...");
+    }
+    let batch = match read_batch(fs, cwd, args, io) {
+        Some(batch) => batch,
+        None => return,
+    };
+
+    kprintln!("sb: waiting for the host to receive with YMODEM");
+    let mut ymodem = Ymodem::new(Serial::open(None));
+    for (info, data) in batch.iter() {
+        if let Err(e) = ymodem.send(info, &data[..]) {
+            return fail!(io, "sb: {}: {}", info.name(), e);
+        }
+    }
+    match ymodem.finish() {
+        Ok(()) => kprintln!("sb: sent {} files", batch.len()),
+        Err(e) => fail!(io, "sb: {}", e),
+    }
+}
+
+/// `rz [dir]`: receives a batch of files from the host with ZMODEM into
+/// `dir`, by default the working directory. Each file is written as it is
+/// received; one shorter than the file sent is resumed from its end.
+pub fn rz<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() > 2 {
+        return fail!(io, "usage: rz [dir]");
+    }
+    let dir = resolve(cwd, args.get(1).unwrap_or(&"."));
+    if let Err(e) = fs.open_dir(&dir) {
+        return fail!(io, "rz: {}: {}", dir.display(), e);
+    }
+
+    // `ZRINIT` is sent by the receiver itself, and repeated on timeouts.
+    kprintln!("rz: waiting for the host to send with ZMODEM");
+    let mut zmodem = Zmodem::new(Serial::open(None));
+    loop {
+        let mut name = None;
+        let mut path = None;
+        let received = zmodem.receive_with(|info| {
+            name = Some(String::from(info.name()));
+            let to = batch_path(&dir, info)?;
+            let opened = open_resumed(fs, &to, info.length)?;
+            path = Some(to);
+            Ok(opened)
+        });
+        match (received, name, path) {
+            (Ok(Some(info)), _, Some(path)) => {
+                kprintln!("rz: received {} bytes into {}", info.length, path.display())
+            }
+            (Ok(_), _, _) => return,
+            // The host was told to skip the file, and goes on with the batch.
+            (Err(e), Some(name), None) => fail!(io, "rz: {}: {}", name, e),
+            (Err(e), _, _) => {
+                let _ = zmodem.cancel();
+                return fail!(io, "rz: {}", e);
+            }
+        }
+    }
+}
+
+/// `sz path...`: sends the files to the host in one batch with ZMODEM.
+pub fn sz<F: FileSystem + Copy>(fs: F, cwd: &PathBuf, args: &[&str], io: &mut Io) {
+    if args.len() < 2 {
+        return fail!(io, "usage: sz path...");
+    }
+    let batch = match read_batch(fs, cwd, args, io) {
+        Some(batch) => batch,
+        None => return,
+    };
+
+    kprintln!("sz: waiting for the host to receive with ZMODEM");
+    let mut zmodem = Zmodem::new(Serial::open(None));
+    for (info, data) in batch.iter() {
+        if let Err(e) = zmodem.send(info, Cursor::new(&data[..])) {
+            let _ = zmodem.cancel();
+            return fail!(io, "sz: {}: {}", info.name(), e);
+        }
+    }
+    match zmodem.finish() {
+        Ok(()) => kprintln!("sz: sent {} files", batch.len()),
+        Err(e) => fail!(io, "sz: {}", e),
+    }
+}
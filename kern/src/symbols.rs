@@ -0,0 +1,67 @@
+use core::fmt;
+
+use bootimg::symbols::{Demangle, SymbolTable};
+
+extern "C" {
+    static __text_end: u8;
+}
+
+/// The most frames a backtrace goes up.
+const MAX_FRAMES: usize = 32;
+
+fn text_end() -> usize {
+    unsafe { &__text_end as *const u8 as usize }
+}
+
+/// Returns the kernel's symbol table, left right after the kernel by the
+/// bootloader, or `None` if the kernel was booted without one.
+pub fn table() -> Option<SymbolTable<'static>> {
+    // The table is never written to: it is not memory the allocator hands out.
+    unsafe { SymbolTable::find(text_end() as u64) }
+}
+
+/// Returns the end of the memory the kernel is in: its binary, then its
+/// symbol table if it has one.
+pub fn kernel_end() -> usize {
+    table().map_or(text_end(), |table| table.end() as usize)
+}
+
+/// Displays an address with the function it is in, if the kernel has a
+/// symbol table: `0x80a34 <kernel::kmain+0x24>`.
+pub struct Symbolize(pub usize);
+
+impl fmt::Display for Symbolize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}", self.0)?;
+        match table().and_then(|table| table.lookup(self.0 as u64)) {
+            Some((name, offset)) => write!(f, " <{}+{:#x}>", Demangle(name), offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Calls `f` with the address of the call in each frame of the stack, from
+/// the caller of `backtrace()` up, by following the frame records that `x29`
+/// points to.
+#[inline(never)]
+pub fn backtrace(mut f: impl FnMut(usize)) {
+    let mut fp: usize;
+    unsafe { asm!("mov $0, x29" : "=r"(fp) ::: "volatile") };
+    for _ in 0..MAX_FRAMES {
+        // A frame record is the caller's frame pointer, then the return
+        // address. Records are further up the stack at each frame; anything
+        // else is not a record.
+        if fp == 0 || fp % 16 != 0 {
+            break;
+        }
+        let (next, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        if lr < 4 {
+            break;
+        }
+        f(lr - 4);
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+}
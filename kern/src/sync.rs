@@ -0,0 +1,7 @@
+mod condvar;
+mod semaphore;
+mod wait_queue;
+
+pub use self::condvar::Condvar;
+pub use self::semaphore::Semaphore;
+pub use self::wait_queue::{WaitQueue, Waiter};
@@ -0,0 +1,62 @@
+use core::fmt;
+
+use crate::mutex::MutexGuard;
+use crate::sync::WaitQueue;
+
+/// A condition variable used together with a `Mutex` by kernel threads.
+pub struct Condvar {
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    /// Returns a new condition variable.
+    pub const fn new() -> Condvar {
+        Condvar { queue: WaitQueue::new() }
+    }
+
+    /// Releases the lock of `guard`, sleeps until notified and reacquires the
+    /// lock. Spurious wake-ups are possible, so the caller should recheck its
+    /// condition (see `wait_while()`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the caller is not a kernel thread or holds another `Mutex`
+    /// (see `thread::block_on()`).
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+        // Register before unlocking so that a notification sent as soon as
+        // the lock is released is not lost.
+        let waiter = self.queue.prepare();
+        drop(guard);
+        waiter.wait();
+        mutex.lock()
+    }
+
+    /// Waits on this condition variable as long as `condition` returns `true`
+    /// for the data protected by `guard`.
+    pub fn wait_while<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up one thread waiting on this condition variable.
+    pub fn notify_one(&self) {
+        self.queue.wake_one();
+    }
+
+    /// Wakes up every thread waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.queue.wake_all();
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Condvar").field("queue", &self.queue).finish()
+    }
+}
@@ -0,0 +1,66 @@
+use core::fmt;
+
+use crate::mutex::IrqSafeMutex;
+use crate::sync::WaitQueue;
+
+/// A counting semaphore whose waiters sleep instead of spinning.
+///
+/// `release()` may be called from interrupt handlers; `acquire()` may only be
+/// called by kernel threads.
+pub struct Semaphore {
+    count: IrqSafeMutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Returns a new semaphore holding `count` units.
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore { count: IrqSafeMutex::new(count), queue: WaitQueue::new() }
+    }
+
+    /// Takes a unit, sleeping until one is available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the caller is not a kernel thread or holds a `Mutex` (see
+    /// `thread::block_on()`).
+    pub fn acquire(&self) {
+        loop {
+            // Register before dropping the count lock so that a `release()`
+            // in between wakes this waiter up.
+            let waiter = {
+                let mut count = self.count.lock();
+                if *count > 0 {
+                    *count -= 1;
+                    return;
+                }
+                self.queue.prepare()
+            };
+            waiter.wait();
+        }
+    }
+
+    /// Takes a unit if one is available without sleeping. Returns `true` if a
+    /// unit was taken.
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count > 0 {
+            *count -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns a unit and wakes up a thread waiting for one.
+    pub fn release(&self) {
+        *self.count.lock() += 1;
+        self.queue.wake_one();
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore").field("count", &*self.count.lock()).finish()
+    }
+}
@@ -0,0 +1,106 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aarch64::sev;
+
+use crate::mutex::IrqSafeMutex;
+use crate::process::thread::block_on;
+use crate::process::Process;
+
+/// A queue of kernel threads sleeping until an event occurs.
+///
+/// A thread first registers with `prepare()`, then checks its condition and
+/// finally sleeps in `Waiter::wait()`. A wake-up that arrives in between is
+/// not lost: the waiter then returns from `wait()` right away.
+///
+/// The queue is protected by an `IrqSafeMutex`, so interrupt handlers may
+/// wake threads up. It can be created in a `static`.
+pub struct WaitQueue {
+    waiters: IrqSafeMutex<Vec<Arc<AtomicBool>>>,
+}
+
+/// A registration in a `WaitQueue`, returned by `WaitQueue::prepare()`.
+pub struct Waiter {
+    woken: Arc<AtomicBool>,
+}
+
+impl WaitQueue {
+    /// Returns a new, empty wait queue.
+    pub const fn new() -> WaitQueue {
+        WaitQueue { waiters: IrqSafeMutex::new(Vec::new()) }
+    }
+
+    /// Registers the caller at the back of the queue.
+    pub fn prepare(&self) -> Waiter {
+        let woken = Arc::new(AtomicBool::new(false));
+        self.waiters.lock().push(woken.clone());
+        Waiter { woken }
+    }
+
+    /// Puts the calling kernel thread to sleep until it is woken up.
+    pub fn wait(&self) {
+        self.prepare().wait();
+    }
+
+    /// Wakes up the longest waiting thread. Waiters that were dropped without
+    /// waiting are skipped.
+    ///
+    /// Returns `true` if a thread was woken up.
+    pub fn wake_one(&self) -> bool {
+        let mut waiters = self.waiters.lock();
+        while !waiters.is_empty() {
+            let woken = waiters.remove(0);
+            if Arc::strong_count(&woken) > 1 {
+                woken.store(true, Ordering::Release);
+                sev();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Wakes up every waiting thread and returns how many were woken up.
+    pub fn wake_all(&self) -> usize {
+        let mut count = 0;
+        for woken in self.waiters.lock().drain(..) {
+            if Arc::strong_count(&woken) > 1 {
+                woken.store(true, Ordering::Release);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            sev();
+        }
+        count
+    }
+}
+
+impl fmt::Debug for WaitQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitQueue").field("waiters", &self.waiters.lock().len()).finish()
+    }
+}
+
+impl Waiter {
+    /// Returns `true` if the waiter has already been woken up.
+    pub fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+
+    /// Puts the calling kernel thread to sleep until the waiter is woken up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the caller is not a kernel thread or holds a `Mutex` (see
+    /// `thread::block_on()`).
+    pub fn wait(self) {
+        if self.is_woken() {
+            return;
+        }
+        let woken = self.woken;
+        block_on(Box::new(move |_: &mut Process| woken.load(Ordering::Acquire)));
+    }
+}
@@ -1,3 +1,4 @@
+mod crash;
 mod frame;
 mod syndrome;
 mod syscall;
@@ -6,14 +7,21 @@ pub mod irq;
 pub use self::frame::TrapFrame;
 
 use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
 
 use crate::console::kprintln;
+use crate::percore::getcpu;
 use crate::IRQ;
+use crate::SCHEDULER;
 use crate::shell::shell;
+use crate::symbols::Symbolize;
+use crate::vm::VirtualAddr;
 
 use aarch64::regs::ELR_EL2;
+use aarch64::FAR_EL1;
+use kernel_api::{SIGBUS, SIGILL, SIGKILL, SIGSEGV, SIGTRAP};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 
 #[repr(u16)]
@@ -61,16 +69,90 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 unsafe {asm!("mov $0, x0":"=r"(temp)::"x0":"volatile");}
                 handle_syscall(n, tf);
             },
+            Syndrome::DataAbort { kind: Fault::Permission, .. }
+                if info.source == Source::LowerAArch64 =>
+            {
+                if !handle_cow_fault(tf) {
+                    signal_fault(info, esr, syndrome, tf);
+                }
+            }
             _ => {
+                if info.source != Source::LowerAArch64 {
+                    panic!("unhandled kernel exception: {:?} at {}", syndrome, Symbolize(tf.get_elr() as usize));
+                }
+
+                signal_fault(info, esr, syndrome, tf);
             }
         }
     } else if info.kind == Kind::Irq {
-        let iter = Interrupt::iter();
-        let controller = Controller::new();
-        for i in iter {
-            if controller.is_pending(*i) {
-                IRQ.invoke(*i, tf);
+        let core = getcpu();
+        let local_controller = LocalController::new(core);
+        for i in LocalInterrupt::iter() {
+            if local_controller.is_pending(*i) {
+                IRQ.invoke_local(*i, tf);
+            }
+        }
+
+        // Interrupts shared by all cores are only routed to core 0.
+        if core == 0 {
+            let iter = Interrupt::iter();
+            let controller = Controller::new();
+            for i in iter {
+                if controller.is_pending(*i) {
+                    IRQ.invoke(*i, tf);
+                }
             }
         }
     }
+
+    if tf.is_el0() {
+        SCHEDULER.deliver_signals(tf);
+    }
+}
+
+/// Resolves a permission fault raised by the user process of `tf` on a
+/// copy-on-write page. Returns `false` if the faulting address is not a
+/// copy-on-write page of the process.
+///
+/// If the page cannot be copied for lack of memory, the process cannot
+/// continue past the fault and is sent `SIGKILL`; the fault then needs no
+/// further handling and `true` is returned.
+fn handle_cow_fault(tf: &mut TrapFrame) -> bool {
+    let far = VirtualAddr::from(unsafe { FAR_EL1.get() });
+    let pid = tf.get_tpidr();
+    SCHEDULER.critical(|scheduler| {
+        let process = match scheduler.find_mut(pid) {
+            Some(process) => process,
+            None => return false,
+        };
+        match process.vmap.as_mut().map_or(Ok(false), |vmap| vmap.copy_on_write(far)) {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                kprintln!("[{:02}] out of memory copying the page at {:#x}", pid, far.as_usize());
+                process.signals.force(SIGKILL);
+                true
+            }
+        }
+    })
+}
+
+/// Raises the signal corresponding to `syndrome` on the user process of `tf`,
+/// which cannot continue past the fault. Unless the process handles the
+/// signal, a crash report is printed before the signal terminates it.
+fn signal_fault(info: Info, esr: u32, syndrome: Syndrome, tf: &mut TrapFrame) {
+    let sig = match syndrome {
+        Syndrome::InstructionAbort { .. } | Syndrome::DataAbort { .. } => SIGSEGV,
+        Syndrome::PCAlignmentFault | Syndrome::SpAlignmentFault => SIGBUS,
+        Syndrome::Breakpoint | Syndrome::Step | Syndrome::Watchpoint => SIGTRAP,
+        _ => SIGILL,
+    };
+
+    let pid = tf.get_tpidr();
+    let handled = SCHEDULER.critical(|scheduler| match scheduler.find_mut(pid) {
+        Some(process) => process.signals.force(sig),
+        None => false,
+    });
+    if !handled {
+        crash::report(info, esr, syndrome, tf);
+    }
 }
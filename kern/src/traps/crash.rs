@@ -0,0 +1,89 @@
+use aarch64::FAR_EL1;
+
+use crate::console::kprintln;
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::process::Process;
+use crate::traps::syndrome::Syndrome;
+use crate::traps::{Info, TrapFrame};
+use crate::vm::VirtualAddr;
+use crate::SCHEDULER;
+
+/// Number of instruction words dumped on each side of the faulting `ELR`.
+const INSN_WINDOW: u64 = 4;
+
+/// Reads the 32-bit word at user virtual address `va` of `process`.
+///
+/// Returns `None` if `va` is not word-aligned, lies outside the user address
+/// space, or is not mapped in the process's page table.
+fn read_user_word(process: &Process, va: u64) -> Option<u32> {
+    if va % 4 != 0 || (va as usize) < USER_IMG_BASE {
+        return None;
+    }
+
+    let page = VirtualAddr::from(va as usize & !(PAGE_SIZE - 1));
+    if process.vmap.as_ref().map_or(true, |vmap| vmap.is_invalid(page)) {
+        return None;
+    }
+
+    // The faulting process's table is still installed in `TTBR1_EL1`, so the
+    // mapping checked above is the one the kernel dereferences here.
+    Some(unsafe { core::ptr::read_volatile(va as *const u32) })
+}
+
+/// Prints the instructions surrounding `elr` in the address space of
+/// `process`. The faulting instruction is marked with an arrow.
+fn dump_instructions(process: &Process, elr: u64) {
+    kprintln!("  instructions:");
+    let start = elr.saturating_sub(INSN_WINDOW * 4) & !0b11;
+    for i in 0..(INSN_WINDOW * 2 + 1) {
+        let va = start + i * 4;
+        let marker = if va == elr { "->" } else { "  " };
+        match read_user_word(process, va) {
+            Some(word) => kprintln!("  {} {:#018x}: {:08x}", marker, va, word),
+            None => kprintln!("  {} {:#018x}: <unmapped>", marker, va),
+        }
+    }
+}
+
+/// Prints every general purpose register saved in `tf`.
+fn dump_registers(tf: &TrapFrame) {
+    kprintln!("  registers:");
+    for i in (0..30).step_by(2) {
+        kprintln!(
+            "    x{:<2} = {:#018x}  x{:<2} = {:#018x}",
+            i,
+            tf.get_x_register(i),
+            i + 1,
+            tf.get_x_register(i + 1)
+        );
+    }
+    kprintln!("    lr  = {:#018x}  sp  = {:#018x}", tf.get_x_register(30), tf.get_sp());
+}
+
+/// Prints a crash report for an unhandled synchronous exception raised by the
+/// user process whose trap frame is `tf`.
+///
+/// The report contains the decoded syndrome, the fault address, the saved
+/// special registers, every general purpose register and the instructions
+/// surrounding the faulting one.
+pub fn report(info: Info, esr: u32, syndrome: Syndrome, tf: &TrapFrame) {
+    let far = unsafe { FAR_EL1.get() };
+    let pid = tf.get_tpidr();
+
+    kprintln!();
+    kprintln!("---------- CRASH ----------");
+    kprintln!("  process {} killed by an unhandled exception", pid);
+    kprintln!("  {:?} from {:?}", info.kind, info.source);
+    kprintln!("  syndrome: {:?} (esr = {:#010x})", syndrome, esr);
+    kprintln!("  elr  = {:#018x}", tf.get_elr());
+    kprintln!("  far  = {:#018x}", far);
+    kprintln!("  spsr = {:#018x}", tf.get_spsr());
+    dump_registers(tf);
+
+    SCHEDULER.critical(|scheduler| {
+        if let Some(process) = scheduler.find(pid) {
+            dump_instructions(process, tf.get_elr());
+        }
+    });
+    kprintln!("---------------------------");
+}
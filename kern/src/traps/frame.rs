@@ -42,6 +42,10 @@ impl TrapFrame {
         self.sp = val;
     }
 
+    pub fn get_sp(&self) -> u64 {
+        self.sp
+    }
+
     pub fn unmask_irq(&mut self) {
         self.spsr = clear_bit(self.spsr, 7);
     }
@@ -55,6 +59,12 @@ impl TrapFrame {
         self.spsr = clear_bit(self.spsr, 3);
     }
 
+    /// Makes returning with this frame resume execution at EL1 using `SP_EL0`
+    /// as the stack pointer (EL1t), as kernel threads do.
+    pub fn set_el1t(&mut self) {
+        self.spsr = (self.spsr & !0b1111) | 0b0100;
+    }
+
     pub fn set_lr(&mut self, val: u64) {
         self.x_registers[30] = val;
     }
@@ -102,4 +112,23 @@ impl TrapFrame {
     pub fn set_d(&mut self) {
         self.spsr = set_bit(self.spsr, 9);
     }
+
+    /// Returns `true` if returning with this frame resumes execution at EL0.
+    pub fn is_el0(&self) -> bool {
+        self.spsr & 0b1111 == 0
+    }
+
+    /// Restores the registers user code is free to change from `saved`: the
+    /// general purpose and SIMD registers, the stack pointer, the return
+    /// address and the condition flags. The translation tables, the process
+    /// ID and the rest of `spsr` are kept, so a frame handed back by user
+    /// space cannot raise its privileges.
+    pub fn restore_user_state(&mut self, saved: &TrapFrame) {
+        const NZCV: u64 = 0xf << 28;
+        self.elr = saved.elr;
+        self.sp = saved.sp;
+        self.spsr = (self.spsr & !NZCV) | (saved.spsr & NZCV);
+        self.q_registers = saved.q_registers;
+        self.x_registers = saved.x_registers;
+    }
 }
@@ -1,22 +1,40 @@
 use alloc::boxed::Box;
 use pi::interrupt::Interrupt;
+use pi::local_interrupt::LocalInterrupt;
 
-use crate::mutex::Mutex;
+use crate::mutex::IrqSafeMutex;
+use crate::param::NCORES;
+use crate::percore::getcpu;
 use crate::traps::TrapFrame;
 use crate::console::kprintln;
 
 pub type IrqHandler = Box<dyn FnMut(&mut TrapFrame) + Send>;
 pub type IrqHandlers = [Option<IrqHandler>; Interrupt::MAX];
+pub type LocalIrqHandlers = [Option<IrqHandler>; LocalInterrupt::MAX];
 
-pub struct Irq(Mutex<Option<IrqHandlers>>);
+/// Handlers of the interrupts shared by all cores, which are delivered to
+/// core 0, and of each core's local interrupts. Every core has its own table
+/// of local handlers so that cores handling their timers do not contend.
+pub struct Irq(IrqSafeMutex<Option<IrqHandlers>>, [IrqSafeMutex<Option<LocalIrqHandlers>>; NCORES]);
 
 impl Irq {
     pub const fn uninitialized() -> Irq {
-        Irq(Mutex::new(None))
+        Irq(
+            IrqSafeMutex::new(None),
+            [
+                IrqSafeMutex::new(None),
+                IrqSafeMutex::new(None),
+                IrqSafeMutex::new(None),
+                IrqSafeMutex::new(None),
+            ],
+        )
     }
 
     pub fn initialize(&self) {
-        *self.0.lock() = Some([None, None, None, None, None, None, None, None]);
+        *self.0.lock() = Some(Default::default());
+        for local in self.1.iter() {
+            *local.lock() = Some(Default::default());
+        }
     }
 
     /// Register an irq handler for an interrupt.
@@ -27,6 +45,14 @@ impl Irq {
         }
     }
 
+    /// Register an irq handler for a local interrupt of the executing core.
+    /// The caller should assure that `initialize()` has been called before calling this function.
+    pub fn register_local(&self, int: LocalInterrupt, handler: IrqHandler) {
+        if let Some(ref mut handlers) = *self.1[getcpu()].lock() {
+            handlers[LocalInterrupt::to_index(int)] = Some(handler);
+        }
+    }
+
     /// Executes an irq handler for the givven interrupt.
     /// The caller should assure that `initialize()` has been called before calling this function.
     pub fn invoke(&self, int: Interrupt, tf: &mut TrapFrame) {
@@ -36,4 +62,14 @@ impl Irq {
             }
         }
     }
+
+    /// Executes the executing core's irq handler for the given local interrupt.
+    /// The caller should assure that `initialize()` has been called before calling this function.
+    pub fn invoke_local(&self, int: LocalInterrupt, tf: &mut TrapFrame) {
+        if let Some(ref mut handlers) = *self.1[getcpu()].lock() {
+            if let Some(ref mut handler) = handlers[LocalInterrupt::to_index(int)] {
+                handler(tf);
+            }
+        }
+    }
 }
@@ -1,10 +1,16 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::time::Duration;
 use pi::timer::current_time;
+use shim::path::PathBuf;
 
 use crate::console::{CONSOLE, kprint, kprintln};
-use crate::process::{State, Process};
+use crate::param::{PAGE_MASK, PAGE_SIZE};
+use crate::process::thread::NR_BLOCK;
+use crate::process::{pipe, Descriptor, EventPollFn, Image, Process, State};
 use crate::traps::TrapFrame;
+use crate::vm::VirtualAddr;
 use crate::SCHEDULER;
 use kernel_api::*;
 
@@ -13,6 +19,27 @@ const WRITE: u16 = NR_WRITE as u16;
 const GETPID: u16 = NR_GETPID as u16;
 const TIME: u16 = NR_TIME as u16;
 const EXIT: u16 = NR_EXIT as u16;
+const FORK: u16 = NR_FORK as u16;
+const EXEC: u16 = NR_EXEC as u16;
+const WAITPID: u16 = NR_WAITPID as u16;
+const BRK: u16 = NR_BRK as u16;
+const SBRK: u16 = NR_SBRK as u16;
+const MMAP: u16 = NR_MMAP as u16;
+const MUNMAP: u16 = NR_MUNMAP as u16;
+const READ: u16 = NR_READ as u16;
+const WRITE_FD: u16 = NR_WRITE_FD as u16;
+const CLOSE: u16 = NR_CLOSE as u16;
+const PIPE: u16 = NR_PIPE as u16;
+const DUP2: u16 = NR_DUP2 as u16;
+const KILL: u16 = NR_KILL as u16;
+const SIGACTION: u16 = NR_SIGACTION as u16;
+const SIGRETURN: u16 = NR_SIGRETURN as u16;
+const NICE: u16 = NR_NICE as u16;
+const SETPRIORITY: u16 = NR_SETPRIORITY as u16;
+const IOCTL: u16 = NR_IOCTL as u16;
+
+/// The maximum number of arguments or environment entries accepted by `exec`.
+const MAX_EXEC_STRINGS: u64 = 64;
 
 /// Sleep for `ms` milliseconds.
 ///
@@ -20,24 +47,13 @@ const EXIT: u16 = NR_EXIT as u16;
 ///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the approximate true elapsed time from when `sleep` was called to
-/// when `sleep` returned.
+/// when `sleep` returned. A sleep interrupted by a signal returns early with
+/// an elapsed time of `0`.
 pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
-    use crate::console::kprintln;
-    //kprintln!("ms is: {}", ms);
-
-    let end_time = current_time() + Duration::from_millis(ms as u64);
-    let poll_fn = Box::new(move |proc: &mut Process| -> bool {
-        let curr_time = current_time();
-        if curr_time < end_time {
-            return false;
-        } else {
-            proc.context.set_x_register(7, OsError::Ok as u64);
-            proc.context.set_x_register(0, (curr_time - end_time).as_millis() as u64);
-            return true;
-        }
-
-    });
-    SCHEDULER.switch(State::Waiting(poll_fn), tf);
+    let deadline = current_time() + Duration::from_millis(ms as u64);
+    tf.set_x_register(7, OsError::Ok as u64);
+    tf.set_x_register(0, 0);
+    SCHEDULER.sleep(deadline, tf);
 }
 
 /// Returns current time.
@@ -57,13 +73,13 @@ pub fn sys_time(tf: &mut TrapFrame) {
     tf.set_x_register(1, nanos as u64);
 }
 
-/// Kills current process.
+/// Terminates current process.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    kprintln!("sys exit");
-    SCHEDULER.kill(tf);
-    tf.set_x_register(7, OsError::Ok as u64);
+/// This system call takes one parameter: the exit status, which is kept until
+/// the parent collects it with `waitpid`. It does not return.
+pub fn sys_exit(status: i32, tf: &mut TrapFrame) {
+    let _ = SCHEDULER.exit(status, tf);
+    SCHEDULER.switch_to(tf);
 }
 
 /// Write to console.
@@ -93,6 +109,506 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.set_x_register(7, OsError::Ok as u64);
 }
 
+/// Creates a copy of the current process.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the child's process ID in the parent, and `0` in the child.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let child = SCHEDULER.critical(|scheduler| match scheduler.find_mut(pid) {
+        Some(parent) => parent.fork(tf),
+        None => Err(OsError::Unknown),
+    });
+
+    let result = child.and_then(|child| SCHEDULER.add(child).ok_or(OsError::NoMemory));
+    match result {
+        Ok(id) => {
+            tf.set_x_register(0, id);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Returns the `len` bytes at user virtual address `ptr` in the address space
+/// of `process`, or `BadAddress` if any of them is not mapped.
+fn user_bytes<'a>(process: &Process, ptr: u64, len: u64) -> OsResult<&'a [u8]> {
+    let vmap = process.vmap.as_ref().ok_or(OsError::BadAddress)?;
+    if !vmap.is_range_mapped(VirtualAddr::from(ptr), len as usize) {
+        return Err(OsError::BadAddress);
+    }
+
+    // The process's page table is installed in `TTBR1_EL1` while it traps.
+    Ok(unsafe { core::slice::from_raw_parts(ptr as *const u8, len as usize) })
+}
+
+/// Returns the `len` bytes at user virtual address `ptr` in the address space
+/// of `process` as a mutable slice, or `BadAddress` if any of them is not
+/// mapped. Copy-on-write pages in the range are copied first so that the
+/// kernel's writes stay private to `process`; `NoMemory` is returned if one
+/// could not be copied.
+fn user_bytes_mut<'a>(process: &mut Process, ptr: u64, len: u64) -> OsResult<&'a mut [u8]> {
+    let vmap = process.vmap.as_mut().ok_or(OsError::BadAddress)?;
+    if !vmap.is_range_mapped(VirtualAddr::from(ptr), len as usize) {
+        return Err(OsError::BadAddress);
+    }
+
+    if len > 0 {
+        let last = ptr as usize + (len as usize - 1);
+        let mut page = ptr as usize & PAGE_MASK;
+        loop {
+            vmap.copy_on_write(VirtualAddr::from(page))?;
+            match page.checked_add(PAGE_SIZE) {
+                Some(next) if next <= last => page = next,
+                _ => break,
+            }
+        }
+    }
+
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize) })
+}
+
+/// Copies the UTF-8 string of `len` bytes at user address `ptr` of `process`.
+fn user_string(process: &Process, ptr: u64, len: u64) -> OsResult<String> {
+    let bytes = user_bytes(process, ptr, len)?;
+    core::str::from_utf8(bytes)
+        .map(String::from)
+        .map_err(|_| OsError::InvalidArgument)
+}
+
+/// Copies `count` strings described by the array of (pointer, length) pairs
+/// at user address `ptr` of `process`, i.e. a `&[&str]` passed from user
+/// space.
+fn user_strings(process: &Process, ptr: u64, count: u64) -> OsResult<Vec<String>> {
+    if count > MAX_EXEC_STRINGS {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let pairs = user_bytes(process, ptr, count * 16)?;
+    let mut strings = Vec::new();
+    for pair in pairs.chunks(16) {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&pair[..8]);
+        let str_ptr = u64::from_le_bytes(word);
+        word.copy_from_slice(&pair[8..]);
+        let str_len = u64::from_le_bytes(word);
+        strings.push(user_string(process, str_ptr, str_len)?);
+    }
+    Ok(strings)
+}
+
+/// Replaces the program of the current process.
+///
+/// This system call takes six parameters: the address and length of the path
+/// of the program to run, and the address and length of the argument and
+/// environment arrays, each laid out as a `&[&str]`.
+///
+/// On success this system call does not return: the new program starts at its
+/// entry point with its arguments described in `x0`-`x3` (see
+/// `Image::load_with_args()`). Otherwise the usual status value is returned.
+pub fn sys_exec(tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let args: OsResult<(PathBuf, Vec<String>, Vec<String>)> = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find(pid).ok_or(OsError::Unknown)?;
+        let path = user_string(process, tf.get_x_register(0), tf.get_x_register(1))?;
+        let argv = user_strings(process, tf.get_x_register(2), tf.get_x_register(3))?;
+        let envp = user_strings(process, tf.get_x_register(4), tf.get_x_register(5))?;
+        Ok((PathBuf::from(path), argv, envp))
+    });
+
+    // The program is read from the file system without holding the
+    // scheduler's lock, which is only taken again to swap it in.
+    let result: OsResult<()> = args.and_then(|(path, argv, envp)| {
+        let image = Image::load_with_args(&path, &argv, &envp)?;
+        SCHEDULER.critical(|scheduler| {
+            let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+            process.exec(path, image, tf);
+            Ok(())
+        })
+    });
+
+    if let Err(e) = result {
+        tf.set_x_register(7, e as u64);
+    }
+}
+
+/// Blocks the current process until `poll_fn` reports that the event it
+/// waits for has happened. The process is then resumed at the `svc`
+/// instruction, so the interrupted system call is issued again.
+fn block_and_restart(poll_fn: EventPollFn, tf: &mut TrapFrame) {
+    tf.set_elr(tf.get_elr() - 4);
+    SCHEDULER.switch(State::Waiting(poll_fn), tf);
+}
+
+/// Performs a device-specific request on a file descriptor.
+///
+/// This system call takes three parameters: the descriptor, the request (such
+/// as `TCGETS` or `TCSETS`) and its argument.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the result of the request. Descriptors that do not support the
+/// request return `InvalidArgument`.
+pub fn sys_ioctl(fd: u64, request: u64, arg: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find(pid).ok_or(OsError::Unknown)?;
+        let desc = process.fds.get(fd as usize).ok_or(OsError::InvalidArgument)?;
+        desc.ioctl(request, arg)
+    });
+    match result {
+        Ok(value) => {
+            tf.set_x_register(0, value);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Blocks the calling kernel thread until a poll function returns `true`.
+///
+/// This system call is only available to kernel threads (see
+/// `thread::block_on()`). It takes one parameter: a pointer obtained from
+/// `Box::into_raw()` on a boxed `EventPollFn`, which the kernel takes over.
+pub fn sys_block(poll_fn: u64, tf: &mut TrapFrame) {
+    let poll_fn = unsafe { Box::from_raw(poll_fn as *mut EventPollFn) };
+    tf.set_x_register(7, OsError::Ok as u64);
+    SCHEDULER.switch(State::Waiting(*poll_fn), tf);
+}
+
+/// Waits for a child of the current process to exit and collects it.
+///
+/// This system call takes one parameter: the ID of the child to wait for, or
+/// `WAIT_ANY` to wait for any child.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the ID of the collected child and its exit status. If the
+/// process has no matching child, `NoEntry` is returned.
+///
+/// If no matching child has exited yet, the process is blocked until one of
+/// its children exits, and the system call is then issued again.
+pub fn sys_waitpid(target: u64, tf: &mut TrapFrame) {
+    let child = if target == WAIT_ANY { None } else { Some(target) };
+    match SCHEDULER.reap(tf.get_tpidr(), child) {
+        Ok(Some((id, status))) => {
+            tf.set_x_register(0, id);
+            tf.set_x_register(1, status as u64);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Ok(None) => {
+            let poll_fn = Box::new(|proc: &mut Process| -> bool {
+                core::mem::replace(&mut proc.child_exited, false)
+            });
+            block_and_restart(poll_fn, tf);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Runs `f` on the current process and stores its result in `tf`: on success
+/// the returned address in `x0` and `Ok` in `x7`, otherwise the error in `x7`.
+fn with_current<F>(tf: &mut TrapFrame, f: F)
+where
+    F: FnOnce(&mut Process) -> OsResult<VirtualAddr>,
+{
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| match scheduler.find_mut(pid) {
+        Some(process) => f(process),
+        None => Err(OsError::Unknown),
+    });
+
+    match result {
+        Ok(addr) => {
+            tf.set_x_register(0, addr.as_u64());
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Sets the program break of the current process.
+///
+/// This system call takes one parameter: the new program break. If it is `0`,
+/// the break is left unchanged.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the resulting program break.
+pub fn sys_brk(addr: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| match addr {
+        0 => Ok(process.brk()),
+        addr => process.set_brk(VirtualAddr::from(addr)),
+    });
+}
+
+/// Grows or shrinks the heap of the current process.
+///
+/// This system call takes one parameter: the signed number of bytes to move
+/// the program break by.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the previous program break, i.e. the start of the new memory
+/// when growing.
+pub fn sys_sbrk(increment: i64, tf: &mut TrapFrame) {
+    with_current(tf, |process| {
+        let old = process.brk();
+        let new = if increment < 0 {
+            old.as_u64().checked_sub(increment.wrapping_neg() as u64)
+        } else {
+            old.as_u64().checked_add(increment as u64)
+        };
+        process.set_brk(VirtualAddr::from(new.ok_or(OsError::NoVmSpace)?))?;
+        Ok(old)
+    });
+}
+
+/// Maps anonymous memory into the current process.
+///
+/// This system call takes one parameter: the number of bytes to map. The
+/// mapping is zeroed, readable and writable, and rounded up to whole pages.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping.
+pub fn sys_mmap(len: u64, tf: &mut TrapFrame) {
+    with_current(tf, |process| process.mmap(len as usize));
+}
+
+/// Unmaps anonymous memory from the current process.
+///
+/// This system call takes two parameters: the address and length of a range
+/// previously mapped with `mmap`.
+///
+/// It only returns the usual status value.
+pub fn sys_munmap(addr: u64, len: u64, tf: &mut TrapFrame) {
+    let addr = VirtualAddr::from(addr);
+    with_current(tf, |process| process.munmap(addr, len as usize).map(|_| addr));
+}
+
+/// Stores the outcome of a read or write on a descriptor in `tf`.
+///
+/// `Ok(Ok(n))` returns `n`, the number of bytes transferred, in `x0`.
+/// `Ok(Err(poll_fn))` means the transfer would block: the process waits until
+/// `poll_fn` reports the descriptor ready and then retries the call.
+fn finish_transfer(result: OsResult<Result<usize, EventPollFn>>, tf: &mut TrapFrame) {
+    match result {
+        Ok(Ok(n)) => {
+            tf.set_x_register(0, n as u64);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Ok(Err(poll_fn)) => block_and_restart(poll_fn, tf),
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Reads from a file descriptor.
+///
+/// This system call takes three parameters: the descriptor, and the address
+/// and length of the buffer to read into.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, which is `0` at end of file. If no
+/// data is available yet, the process is blocked until there is.
+pub fn sys_read(fd: u64, ptr: u64, len: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        let buf = user_bytes_mut(process, ptr, len)?;
+        let desc = process.fds.get(fd as usize).ok_or(OsError::InvalidArgument)?;
+        Ok(desc.read(buf)?.ok_or_else(|| desc.poll_fn(false)))
+    });
+    finish_transfer(result, tf);
+}
+
+/// Writes to a file descriptor.
+///
+/// This system call takes three parameters: the descriptor, and the address
+/// and length of the buffer to write.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written, which may be less than requested.
+/// If nothing can be written yet, the process is blocked until it can. Writing
+/// to a pipe whose read end is closed returns `IoErrorBrokenPipe` and raises
+/// `SIGPIPE`.
+pub fn sys_write_fd(fd: u64, ptr: u64, len: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        let buf = user_bytes(process, ptr, len)?;
+        let desc = process.fds.get(fd as usize).ok_or(OsError::InvalidArgument)?;
+        let written = desc.write(buf);
+        if let Err(OsError::IoErrorBrokenPipe) = written {
+            let _ = process.signals.raise(SIGPIPE);
+        }
+        Ok(written?.ok_or_else(|| desc.poll_fn(true)))
+    });
+    finish_transfer(result, tf);
+}
+
+/// Closes a file descriptor.
+///
+/// This system call takes one parameter: the descriptor to close.
+///
+/// It only returns the usual status value.
+pub fn sys_close(fd: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        process.fds.remove(fd as usize).map(|_| ())
+    });
+
+    match result {
+        Ok(()) => tf.set_x_register(7, OsError::Ok as u64),
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Creates a pipe.
+///
+/// This system call does not take parameter.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the descriptor of the read end and that of the write end.
+pub fn sys_pipe(tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        let (reader, writer) = pipe();
+        let read_fd = process.fds.insert(Descriptor::PipeReader(reader))?;
+        match process.fds.insert(Descriptor::PipeWriter(writer)) {
+            Ok(write_fd) => Ok((read_fd, write_fd)),
+            Err(e) => {
+                let _ = process.fds.remove(read_fd);
+                Err(e)
+            }
+        }
+    });
+
+    match result {
+        Ok((read_fd, write_fd)) => {
+            tf.set_x_register(0, read_fd as u64);
+            tf.set_x_register(1, write_fd as u64);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Duplicates a file descriptor.
+///
+/// This system call takes two parameters: the descriptor to duplicate and the
+/// descriptor number to make refer to the same object. If the latter is open,
+/// it is closed first.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new descriptor.
+pub fn sys_dup2(old: u64, new: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        process.fds.duplicate(old as usize, new as usize)
+    });
+
+    match result {
+        Ok(fd) => {
+            tf.set_x_register(0, fd as u64);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Sends a signal to a process.
+///
+/// This system call takes two parameters: the ID of the process and the
+/// signal number. A signal number of `0` only checks that the process exists.
+///
+/// It only returns the usual status value.
+pub fn sys_kill(pid: u64, sig: u64, tf: &mut TrapFrame) {
+    match SCHEDULER.signal(pid, sig) {
+        Ok(()) => tf.set_x_register(7, OsError::Ok as u64),
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Sets the action taken when the current process receives a signal.
+///
+/// This system call takes three parameters: the signal number, the handler
+/// (`SIG_DFL`, `SIG_IGN` or the address of a function taking the signal number)
+/// and the address the handler returns to, which must issue `sigreturn`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the previous handler.
+pub fn sys_sigaction(sig: u64, handler: u64, restorer: u64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER.critical(|scheduler| {
+        let process = scheduler.find_mut(pid).ok_or(OsError::Unknown)?;
+        process.signals.set_handler(sig, handler, restorer)
+    });
+
+    match result {
+        Ok(old) => {
+            tf.set_x_register(0, old);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Returns from a signal handler.
+///
+/// This system call does not take parameter; it must be issued with the stack
+/// pointer at the signal frame the handler was entered with.
+///
+/// It does not return to its caller: the process resumes where it was
+/// interrupted by the signal. If the signal frame is not mapped, the process
+/// is sent `SIGSEGV`.
+pub fn sys_sigreturn(tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    SCHEDULER.critical(|scheduler| {
+        if let Some(process) = scheduler.find_mut(pid) {
+            if process.pop_signal_frame(tf).is_err() {
+                process.signals.force(SIGSEGV);
+            }
+        }
+    });
+}
+
+/// Changes the niceness of the current process.
+///
+/// This system call takes one parameter: the signed amount to add to the
+/// niceness. The result is clamped to the range from `MIN_NICE` to
+/// `MAX_NICE`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new niceness.
+pub fn sys_nice(increment: i64, tf: &mut TrapFrame) {
+    let pid = tf.get_tpidr();
+    let result = SCHEDULER
+        .nice(pid)
+        .and_then(|nice| SCHEDULER.set_nice(pid, nice.saturating_add(increment)));
+
+    match result {
+        Ok(nice) => {
+            tf.set_x_register(0, nice as u64);
+            tf.set_x_register(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
+/// Sets the niceness of a process.
+///
+/// This system call takes two parameters: the ID of the process, and its new
+/// niceness, which is clamped to the range from `MIN_NICE` to `MAX_NICE`.
+///
+/// It only returns the usual status value.
+pub fn sys_setpriority(pid: u64, nice: i64, tf: &mut TrapFrame) {
+    match SCHEDULER.set_nice(pid, nice) {
+        Ok(_) => tf.set_x_register(7, OsError::Ok as u64),
+        Err(e) => tf.set_x_register(7, e as u64),
+    }
+}
+
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     use crate::console::kprintln;
     //unimplemented!("handle_syscall()")
@@ -112,7 +628,92 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
             sys_time(tf);
         },
         EXIT => {
-            sys_exit(tf);
+            let status = tf.get_x_register(0) as i32;
+            sys_exit(status, tf);
+        }
+        FORK => {
+            sys_fork(tf);
+        }
+        EXEC => {
+            sys_exec(tf);
+        }
+        WAITPID => {
+            let target = tf.get_x_register(0);
+            sys_waitpid(target, tf);
+        }
+        BRK => {
+            let addr = tf.get_x_register(0);
+            sys_brk(addr, tf);
+        }
+        SBRK => {
+            let increment = tf.get_x_register(0) as i64;
+            sys_sbrk(increment, tf);
+        }
+        MMAP => {
+            let len = tf.get_x_register(0);
+            sys_mmap(len, tf);
+        }
+        MUNMAP => {
+            let addr = tf.get_x_register(0);
+            let len = tf.get_x_register(1);
+            sys_munmap(addr, len, tf);
+        }
+        READ => {
+            let fd = tf.get_x_register(0);
+            let ptr = tf.get_x_register(1);
+            let len = tf.get_x_register(2);
+            sys_read(fd, ptr, len, tf);
+        }
+        WRITE_FD => {
+            let fd = tf.get_x_register(0);
+            let ptr = tf.get_x_register(1);
+            let len = tf.get_x_register(2);
+            sys_write_fd(fd, ptr, len, tf);
+        }
+        CLOSE => {
+            let fd = tf.get_x_register(0);
+            sys_close(fd, tf);
+        }
+        PIPE => {
+            sys_pipe(tf);
+        }
+        DUP2 => {
+            let old = tf.get_x_register(0);
+            let new = tf.get_x_register(1);
+            sys_dup2(old, new, tf);
+        }
+        KILL => {
+            let pid = tf.get_x_register(0);
+            let sig = tf.get_x_register(1);
+            sys_kill(pid, sig, tf);
+        }
+        SIGACTION => {
+            let sig = tf.get_x_register(0);
+            let handler = tf.get_x_register(1);
+            let restorer = tf.get_x_register(2);
+            sys_sigaction(sig, handler, restorer, tf);
+        }
+        SIGRETURN => {
+            sys_sigreturn(tf);
+        }
+        NICE => {
+            let increment = tf.get_x_register(0) as i64;
+            sys_nice(increment, tf);
+        }
+        SETPRIORITY => {
+            let pid = tf.get_x_register(0);
+            let nice = tf.get_x_register(1) as i64;
+            sys_setpriority(pid, nice, tf);
+        }
+        IOCTL => {
+            let fd = tf.get_x_register(0);
+            let request = tf.get_x_register(1);
+            let arg = tf.get_x_register(2);
+            sys_ioctl(fd, request, arg, tf);
+        }
+        NR_BLOCK if !tf.is_el0() => {
+            let poll_fn = tf.get_x_register(0);
+            sys_block(poll_fn, tf);
         }
         _ => {}
     }
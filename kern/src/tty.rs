@@ -0,0 +1,385 @@
+use core::fmt;
+use core::time::Duration;
+
+use aarch64::sev;
+use kernel_api::*;
+use pi::timer::current_time;
+
+use crate::console::{Console, CONSOLE};
+use crate::mutex::IrqSafeMutex;
+use crate::param::TICK;
+use crate::process::thread::{self, is_kernel_thread};
+use crate::process::Signal;
+use crate::sync::WaitQueue;
+
+/// The longest line that can be edited in canonical mode, including the byte
+/// that ends it.
+const LINE_SIZE: usize = 512;
+
+/// The number of input bytes the terminal can hold ahead of its readers.
+/// Input arriving while the buffer is full is dropped.
+const INPUT_SIZE: usize = 1024;
+
+/// The byte sent by Ctrl-C.
+const INTR: u8 = 0x03;
+/// The byte sent by Ctrl-D.
+const EOF: u8 = 0x04;
+/// The byte sent by backspace on most terminals.
+const ERASE: u8 = 0x7f;
+/// The byte sent by Ctrl-H, which also erases.
+const BACKSPACE: u8 = 0x08;
+/// The byte sent by Ctrl-U.
+const KILL: u8 = 0x15;
+/// The byte sent by Ctrl-Z.
+const SUSP: u8 = 0x1a;
+/// The byte sent by Ctrl-\.
+const QUIT: u8 = 0x1c;
+
+/// All mode flags a terminal understands.
+const TTY_FLAGS: u64 = TTY_ICANON | TTY_ECHO | TTY_ISIG | TTY_ICRNL | TTY_ONLCR;
+
+/// The line discipline of the console: turns the bytes typed on the UART into
+/// the input its readers see, and processes the output written to it.
+///
+/// The behaviour is selected by the `TTY_*` mode flags of `kernel_api`. In
+/// canonical mode, input is collected in a line that is handed to readers when
+/// it is ended by a newline or Ctrl-D. A Ctrl-D on an empty line makes the
+/// next read return end of file.
+pub struct Tty {
+    flags: u64,
+    /// The line being edited in canonical mode.
+    line: [u8; LINE_SIZE],
+    line_len: usize,
+    /// The input ready for readers. In canonical mode, every line in it is
+    /// ended by a newline or by an `EOF` byte, which is not returned.
+    input: [u8; INPUT_SIZE],
+    input_head: usize,
+    input_len: usize,
+    /// The number of ended lines in `input`.
+    lines: usize,
+}
+
+impl Tty {
+    const fn new() -> Tty {
+        Tty {
+            flags: TTY_DEFAULT,
+            line: [0; LINE_SIZE],
+            line_len: 0,
+            input: [0; INPUT_SIZE],
+            input_head: 0,
+            input_len: 0,
+            lines: 0,
+        }
+    }
+
+    fn is_set(&self, flag: u64) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Returns the current mode flags.
+    pub fn flags(&self) -> u64 {
+        self.flags
+    }
+
+    /// Replaces the mode flags. Leaving canonical mode hands the line being
+    /// edited to readers.
+    ///
+    /// Returns `InvalidArgument` if `flags` has bits other than the `TTY_*`
+    /// flags.
+    pub fn set_flags(&mut self, flags: u64) -> OsResult<()> {
+        if flags & !TTY_FLAGS != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let was_canonical = self.is_set(TTY_ICANON);
+        self.flags = flags;
+        if was_canonical && !self.is_set(TTY_ICANON) {
+            for i in 0..self.line_len {
+                let byte = self.line[i];
+                self.push_input(byte);
+            }
+            self.line_len = 0;
+        } else if !was_canonical && self.is_set(TTY_ICANON) {
+            self.lines = (0..self.input_len)
+                .filter(|i| self.input[(self.input_head + i) % INPUT_SIZE] == b'\n')
+                .count();
+        }
+        Ok(())
+    }
+
+    /// Appends `byte` to the input ready for readers. Returns `false` if there
+    /// is no room for it.
+    fn push_input(&mut self, byte: u8) -> bool {
+        if self.input_len == INPUT_SIZE {
+            return false;
+        }
+        self.input[(self.input_head + self.input_len) % INPUT_SIZE] = byte;
+        self.input_len += 1;
+        true
+    }
+
+    /// Hands the line being edited, ended by `end`, to readers. The line is
+    /// dropped if it does not fit in the input buffer.
+    fn end_line(&mut self, end: u8) {
+        if INPUT_SIZE - self.input_len > self.line_len {
+            for i in 0..self.line_len {
+                let byte = self.line[i];
+                self.push_input(byte);
+            }
+            self.push_input(end);
+            self.lines += 1;
+        }
+        self.line_len = 0;
+    }
+
+    /// Writes `byte` to `console`, translating a newline to a carriage return
+    /// and newline if `TTY_ONLCR` is set.
+    fn output(&self, byte: u8, console: &mut Console) {
+        if byte == b'\n' && self.is_set(TTY_ONLCR) {
+            console.write_byte(b'\r');
+        }
+        console.write_byte(byte);
+    }
+
+    /// Echoes the typed `byte` to `console` if `TTY_ECHO` is set. Control
+    /// bytes are echoed as `^` followed by a letter.
+    fn echo(&self, byte: u8, console: &mut Console) {
+        if !self.is_set(TTY_ECHO) {
+            return;
+        }
+        if byte < 0x20 && byte != b'\n' && byte != b'\t' {
+            console.write_byte(b'^');
+            console.write_byte(byte + 0x40);
+        } else {
+            self.output(byte, console);
+        }
+    }
+
+    /// Removes the last byte of the line being edited and erases it from the
+    /// screen. Returns `false` if the line is empty.
+    fn erase(&mut self, console: &mut Console) -> bool {
+        if self.line_len == 0 {
+            return false;
+        }
+        self.line_len -= 1;
+        if self.is_set(TTY_ECHO) {
+            for &byte in &[BACKSPACE, b' ', BACKSPACE] {
+                console.write_byte(byte);
+            }
+        }
+        true
+    }
+
+    /// Processes the typed `byte`, echoing to `console`.
+    ///
+    /// Returns the signal to send to the foreground process if `byte` is a
+    /// signal character and `TTY_ISIG` is set. The line being edited is then
+    /// discarded.
+    pub fn input(&mut self, mut byte: u8, console: &mut Console) -> Option<Signal> {
+        if byte == b'\r' && self.is_set(TTY_ICRNL) {
+            byte = b'\n';
+        }
+
+        if self.is_set(TTY_ISIG) {
+            let sig = match byte {
+                INTR => Some(SIGINT),
+                QUIT => Some(SIGQUIT),
+                SUSP => Some(SIGTSTP),
+                _ => None,
+            };
+            if sig.is_some() {
+                self.line_len = 0;
+                self.echo(byte, console);
+                self.echo(b'\n', console);
+                return sig;
+            }
+        }
+
+        if !self.is_set(TTY_ICANON) {
+            if self.push_input(byte) {
+                self.echo(byte, console);
+            }
+            return None;
+        }
+
+        match byte {
+            ERASE | BACKSPACE => {
+                self.erase(console);
+            }
+            KILL => while self.erase(console) {},
+            EOF => self.end_line(EOF),
+            b'\n' => {
+                self.echo(byte, console);
+                self.end_line(byte);
+            }
+            _ => {
+                // Keep room for the byte ending the line.
+                if self.line_len < LINE_SIZE - 1 {
+                    self.line[self.line_len] = byte;
+                    self.line_len += 1;
+                    self.echo(byte, console);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if `read()` would return `Some`.
+    pub fn is_ready(&self) -> bool {
+        if self.is_set(TTY_ICANON) {
+            self.lines > 0
+        } else {
+            self.input_len > 0
+        }
+    }
+
+    /// Reads input into `buf` and returns the number of bytes read. In
+    /// canonical mode, at most one line is read, and `Some(0)` signals end of
+    /// file.
+    ///
+    /// Returns `None` if no input is available yet: in canonical mode, until a
+    /// line has been ended.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.is_ready() {
+            return None;
+        }
+
+        let canonical = self.is_set(TTY_ICANON);
+        let mut n = 0;
+        while n < buf.len() && self.input_len > 0 {
+            let byte = self.input[self.input_head];
+            self.input_head = (self.input_head + 1) % INPUT_SIZE;
+            self.input_len -= 1;
+            if canonical && byte == EOF {
+                self.lines -= 1;
+                break;
+            }
+            buf[n] = byte;
+            n += 1;
+            if canonical && byte == b'\n' {
+                self.lines -= 1;
+                break;
+            }
+        }
+        Some(n)
+    }
+
+    /// Writes `buf` to `console` with output processing.
+    pub fn write(&self, buf: &[u8], console: &mut Console) {
+        for &byte in buf {
+            self.output(byte, console);
+        }
+    }
+}
+
+impl fmt::Debug for Tty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tty")
+            .field("flags", &self.flags)
+            .field("line_len", &self.line_len)
+            .field("input_len", &self.input_len)
+            .field("lines", &self.lines)
+            .finish()
+    }
+}
+
+/// Global `Tty` singleton for the console. It is always locked before
+/// `CONSOLE`.
+pub static TTY: IrqSafeMutex<Tty> = IrqSafeMutex::new(Tty::new());
+
+/// Kernel threads sleeping until terminal input is available.
+static READERS: WaitQueue = WaitQueue::new();
+
+/// Runs the bytes waiting in the UART through the line discipline.
+///
+/// Returns the last signal typed, if any.
+fn pump() -> Option<Signal> {
+    let mut tty = TTY.lock();
+    let mut console = CONSOLE.lock();
+    let mut signal = None;
+    while console.has_byte() {
+        let byte = console.read_byte();
+        if let Some(sig) = tty.input(byte, &mut console) {
+            signal = Some(sig);
+        }
+    }
+    signal
+}
+
+/// Handles the UART's receive interrupt: processes the input that arrived and
+/// wakes up the threads waiting for it.
+///
+/// Returns the signal to send to the foreground process, if one was typed.
+pub fn receive() -> Option<Signal> {
+    let signal = pump();
+    READERS.wake_all();
+    // Processes blocked reading the console are polled by the scheduler; wake
+    // up idle cores so that they notice.
+    sev();
+    signal
+}
+
+/// Reads input into `buf` like `Tty::read()`, waiting until some is
+/// available. A kernel thread sleeps meanwhile; other callers, which may run
+/// with IRQs masked, poll the UART themselves and drop any signal typed.
+pub fn read(buf: &mut [u8]) -> usize {
+    loop {
+        let waiter = {
+            let mut tty = TTY.lock();
+            if let Some(n) = tty.read(buf) {
+                return n;
+            }
+            if !is_kernel_thread() {
+                drop(tty);
+                pump();
+                continue;
+            }
+            READERS.prepare()
+        };
+        waiter.wait();
+    }
+}
+
+/// Reads input into `buf` like `read()`, but gives up and returns `None` if
+/// none arrives within `timeout`. A kernel thread polls for input once per
+/// scheduler tick meanwhile.
+pub fn read_timeout(buf: &mut [u8], timeout: Duration) -> Option<usize> {
+    let deadline = current_time() + timeout;
+    loop {
+        if let Some(n) = TTY.lock().read(buf) {
+            return Some(n);
+        }
+        if current_time() >= deadline {
+            return None;
+        }
+        if is_kernel_thread() {
+            thread::sleep(TICK);
+        } else {
+            pump();
+        }
+    }
+}
+
+/// Writes `buf` to the console with output processing.
+pub fn write(buf: &[u8]) {
+    let tty = TTY.lock();
+    tty.write(buf, &mut CONSOLE.lock());
+}
+
+/// Performs the terminal `request` with argument `arg`: `TCGETS` returns the
+/// mode flags and `TCSETS` replaces them.
+///
+/// Returns `InvalidArgument` for any other request or for unknown flags.
+pub fn ioctl(request: u64, arg: u64) -> OsResult<u64> {
+    match request {
+        TCGETS => Ok(TTY.lock().flags()),
+        TCSETS => {
+            TTY.lock().set_flags(arg)?;
+            // Readers may be able to proceed in the new mode.
+            READERS.wake_all();
+            sev();
+            Ok(0)
+        }
+        _ => Err(OsError::InvalidArgument),
+    }
+}
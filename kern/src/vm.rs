@@ -1,17 +1,26 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::console::kprintln;
 use crate::mutex::Mutex;
+use crate::percore::set_mmu_ready;
 
 use aarch64::*;
 
 mod address;
 mod pagetable;
+mod refcount;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
 pub use self::pagetable::*;
+pub use self::refcount::{PageRefs, PAGE_REFS};
 use crate::param::{KERNEL_MASK_BITS, USER_MASK_BITS};
 
 /// Thread-safe (locking) wrapper around a kernel page table.
-pub struct VMManager(Mutex<Option<KernPageTable>>);
+///
+/// The base address of the table is also kept in an atomic so that secondary
+/// cores can read it and enable their MMU without taking the lock, which
+/// they cannot do safely until their MMU is enabled.
+pub struct VMManager(Mutex<Option<KernPageTable>>, AtomicUsize);
 
 impl VMManager {
     /// Returns an uninitialized `VMManager`.
@@ -19,27 +28,36 @@ impl VMManager {
     /// The virtual memory manager must be initialized by calling `initialize()` and `setup()`
     /// before the first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        VMManager(Mutex::new(None))
+        VMManager(Mutex::new(None), AtomicUsize::new(0))
     }
 
     /// Initializes the virtual memory manager.
     /// The caller should assure that the method is invoked only once during the kernel
     /// initialization.
     pub fn initialize(&self) {
-        *self.0.lock() = Some(KernPageTable::new());
+        let kern_page_table = KernPageTable::new();
+        self.1.store(kern_page_table.get_baddr().as_usize(), Ordering::Relaxed);
+        *self.0.lock() = Some(kern_page_table);
         self.setup();
     }
 
+    /// Returns `true` once `initialize()` has built the kernel page table.
+    pub fn is_initialized(&self) -> bool {
+        self.1.load(Ordering::Relaxed) != 0
+    }
+
     /// Set up the virtual memory manager.
     /// The caller should assure that `initialize()` has been called before calling this function.
     /// Sets proper configuration bits to MAIR_EL1, TCR_EL1, TTBR0_EL1, and TTBR1_EL1 registers.
     ///
+    /// Every core must call this once to enable its own MMU. Afterwards the
+    /// core may use atomic read-modify-write operations (see `percore`).
+    ///
     /// # Panics
     ///
     /// Panics if the current system does not support 64KB memory translation granule size.
     pub fn setup(&self) {
-        let kern_page_table = self.0.lock();
-        let baddr = kern_page_table.as_ref().unwrap().get_baddr().as_u64();
+        let baddr = self.get_baddr().as_u64();
 
         unsafe {
             assert!(ID_AA64MMFR0_EL1.get_value(ID_AA64MMFR0_EL1::TGran64) == 0);
@@ -81,11 +99,13 @@ impl VMManager {
             asm!("dsb sy");
             isb();
         }
+        set_mmu_ready();
     }
 
     /// Returns the base address of the kernel page table as `PhysicalAddr`.
     pub fn get_baddr(&self) -> PhysicalAddr {
-        let table = self.0.lock();
-        table.as_ref().expect("failed to unwrap table in VMManager::get_baddr()").get_baddr()
+        let baddr = self.1.load(Ordering::Relaxed);
+        assert!(baddr != 0, "VMManager::get_baddr() called before initialize()");
+        PhysicalAddr::from(baddr)
     }
 }
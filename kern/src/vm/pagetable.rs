@@ -1,17 +1,19 @@
-use core::iter::Chain;
+use core::iter::{FlatMap, Flatten};
 use core::ops::{Deref, DerefMut};
 use core::slice::Iter;
 
+use alloc::alloc::handle_alloc_error;
 use alloc::boxed::Box;
 use alloc::fmt;
 use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::param::*;
-use crate::vm::{PhysicalAddr, VirtualAddr};
+use crate::vm::{PhysicalAddr, VirtualAddr, PAGE_REFS};
 use crate::ALLOCATOR;
 
 use aarch64::vmsa::*;
+use kernel_api::{OsError, OsResult};
 use shim::const_assert_size;
 
 use crate::console::kprintln;
@@ -31,6 +33,10 @@ const USER_RW: u64 = 0x01;
 const KERN_RO: u64 = 0x10;
 const USER_RO: u64 = 0x11;
 
+/// Software-defined bit of an L3 entry (ref. D5.3.3) marking a page that is
+/// shared read-only after `fork` and must be copied on the first write.
+const ENTRY_COW: u64 = 1 << 55;
+
 impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
@@ -127,39 +133,73 @@ impl L3PageTable {
     }
 }
 
-#[repr(C)]
-#[repr(align(65536))]
 pub struct PageTable {
-    pub l2: L2PageTable,
-    pub l3: [L3PageTable; 2],
+    pub l2: Box<L2PageTable>,
+    /// The L3 tables, each allocated when a page is first mapped in the
+    /// 512MB it covers.
+    pub l3: [Option<Box<L3PageTable>>; 3],
+    /// The permission of the L2 entries pointing to the L3 tables.
+    perm: u64,
 }
 
 impl PageTable {
     /// Returns a new `Box` containing `PageTable`.
-    /// Entries in L2PageTable should be initialized properly before return.
+    /// The L3 tables, and the L2 entries pointing to them, are added with
+    /// `perm` as pages are mapped.
     fn new(perm: u64) -> Box<PageTable> {
-        let mut page_table = Box::new(PageTable{
-            l2: L2PageTable::new(), l3: [L3PageTable::new(), L3PageTable::new()]
-        });
-
-        //use RawL2Entry::*;
-        for i in 0..2 {
-            page_table.l2.entries[i].set_masked(page_table.l3[i].as_ptr().as_u64(), RawL2Entry::ADDR);
-            page_table.l2.entries[i].set_value(EntrySh::ISh, RawL2Entry::SH);
-            page_table.l2.entries[i].set_bit(RawL2Entry::AF);
-            page_table.l2.entries[i].set_value(perm, RawL2Entry::AP);
-            page_table.l2.entries[i].set_value(EntryAttr::Mem, RawL2Entry::ATTR);
-            page_table.l2.entries[i].set_bit(RawL2Entry::TYPE);
-            page_table.l2.entries[i].set_bit(RawL2Entry::VALID);
+        // The 64KiB L2 table would not fit on a kernel stack, so it is
+        // allocated in place. An all-zero table has only invalid entries.
+        let layout = Layout::new::<L2PageTable>();
+        let l2 = unsafe { ALLOCATOR.alloc_zeroed(layout) } as *mut L2PageTable;
+        if l2.is_null() {
+            handle_alloc_error(layout);
+        }
+        let l2 = unsafe { Box::from_raw(l2) };
+        Box::new(PageTable { l2, l3: [None, None, None], perm })
+    }
 
+    /// Returns the L3 table at `l2_index`, allocating it and setting the L2
+    /// entry pointing to it if it does not exist yet. Returns `None` if the
+    /// table could not be allocated.
+    fn l3_mut(&mut self, l2_index: usize) -> Option<&mut L3PageTable> {
+        if self.l3[l2_index].is_none() {
+            // An all-zero table has only invalid entries.
+            let table = unsafe { ALLOCATOR.alloc_zeroed(Layout::new::<L3PageTable>()) } as *mut L3PageTable;
+            if table.is_null() {
+                return None;
+            }
+            let table = unsafe { Box::from_raw(table) };
+
+            let entry = &mut self.l2.entries[l2_index];
+            entry.set_masked(table.as_ptr().as_u64(), RawL2Entry::ADDR);
+            entry.set_value(EntrySh::ISh, RawL2Entry::SH);
+            entry.set_bit(RawL2Entry::AF);
+            entry.set_value(self.perm, RawL2Entry::AP);
+            entry.set_value(EntryAttr::Mem, RawL2Entry::ATTR);
+            entry.set_bit(RawL2Entry::TYPE);
+            entry.set_bit(RawL2Entry::VALID);
+            self.l3[l2_index] = Some(table);
         }
+        self.l3[l2_index].as_mut().map(|table| &mut **table)
+    }
 
-        page_table
+    /// Returns the L3 entry of the page-aligned virtual address `va`, or
+    /// `None` if no L3 table covers it yet.
+    fn entry(&self, va: VirtualAddr) -> Option<&L3Entry> {
+        let (l2_index, l3_index) = Self::locate(va);
+        self.l3[l2_index].as_ref().map(|table| &table.entries[l3_index])
+    }
+
+    /// Like `entry()`, but returns a mutable reference.
+    fn entry_mut(&mut self, va: VirtualAddr) -> Option<&mut L3Entry> {
+        let (l2_index, l3_index) = Self::locate(va);
+        self.l3[l2_index].as_mut().map(|table| &mut table.entries[l3_index])
     }
 
     /// Returns the (L2index, L3index) extracted from the given virtual address.
-    /// Since we are only supporting 1GB virtual memory in this system, L2index
-    /// should be smaller than 2.
+    /// Since we are only supporting 1.5GB virtual memory in this system (1GB of
+    /// RAM and peripherals plus the per-core peripherals above them), L2index
+    /// should be smaller than 3.
     ///
     /// # Panics
     ///
@@ -171,11 +211,13 @@ impl PageTable {
             panic!("Virtual address is not aligned to page size. Page size is {:x}, VA is {:x}", PAGE_SIZE, va.as_u64());
         }
 
-        let l2_index = va.l2_index();
+        // `l2_index()` only keeps bit 29, which indexes the 1GB of user
+        // addresses from `USER_IMG_BASE`; kernel addresses go up to 1.5GB.
+        let l2_index = if va.as_usize() >= USER_IMG_BASE { va.l2_index() } else { va.as_usize() >> 29 };
         let l3_index = va.l3_index();
 
-        if l2_index >= 2 {
-            panic!("l2 index is greater than or equal to 2");
+        if l2_index >= 3 {
+            panic!("l2 index is greater than or equal to 3");
         }
 
         (l2_index, l3_index)
@@ -184,9 +226,7 @@ impl PageTable {
     /// Returns `true` if the L3entry indicated by the given virtual address is valid.
     /// Otherwise, `false` is returned.
     pub fn is_valid(&self, va: VirtualAddr) -> bool {
-        let (l2_index, l3_index) = Self::locate(va);
-
-        self.l3[l2_index].entries[l3_index].is_valid()
+        self.entry(va).map_or(false, L3Entry::is_valid)
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is invalid.
@@ -196,11 +236,15 @@ impl PageTable {
     }
 
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
-    /// address.
+    /// address, allocating the L3 table covering it if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the L3 table could not be allocated.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
-        //unimplemented!("PageTable::set_entry()")
         let (l2_index, l3_index) = PageTable::locate(va);
-        self.l3[l2_index].entries[l3_index].0 = entry;
+        let table = self.l3_mut(l2_index).expect("Do not have enough memory to assign new page table");
+        table.entries[l3_index].0 = entry;
 
         self
     }
@@ -212,16 +256,23 @@ impl PageTable {
     }
 }
 
+/// Returns the entries of the L3 table `table`.
+fn l3_entries(table: &Box<L3PageTable>) -> Iter<L3Entry> {
+    table.entries.iter()
+}
+
 // FIXME: Implement `IntoIterator` for `&PageTable`.
 impl<'a> IntoIterator for &'a PageTable {
     type Item = &'a L3Entry;
-    type IntoIter = core::iter::Chain<core::slice::Iter<'a, L3Entry>, core::slice::Iter<'a, L3Entry>>;
+    type IntoIter = FlatMap<
+        Flatten<Iter<'a, Option<Box<L3PageTable>>>>,
+        Iter<'a, L3Entry>,
+        fn(&'a Box<L3PageTable>) -> Iter<'a, L3Entry>,
+    >;
 
+    /// Iterates over the entries of the L3 tables allocated so far.
     fn into_iter(self) -> Self::IntoIter {
-        let first = self.l3[0].entries.iter();
-        let second = self.l3[1].entries.iter();
-
-        return first.chain(second);
+        self.l3.iter().flatten().flat_map(l3_entries)
     }
 }
 
@@ -232,7 +283,8 @@ impl KernPageTable {
     /// created with `KERN_RW` permission.
     ///
     /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
-    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
+    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals,
+    /// including the per-core peripherals at `LOCAL_IO_BASE`.
     /// Each L3 entry should have correct value for lower attributes[10:0] as well
     /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
     /// more details.
@@ -283,6 +335,18 @@ pub enum PagePerm {
     RWX,
 }
 
+impl PagePerm {
+    /// Returns the user access permission of an L3 entry mapping a page with
+    /// this permission. Pages are never marked execute-never, so `RW` and
+    /// `RWX` map alike.
+    fn user_entry_perm(&self) -> u64 {
+        match self {
+            PagePerm::RW | PagePerm::RWX => EntryPerm::USER_RW,
+            PagePerm::RO => EntryPerm::USER_RO,
+        }
+    }
+}
+
 pub struct UserPageTable(Box<PageTable>);
 
 impl UserPageTable {
@@ -294,16 +358,17 @@ impl UserPageTable {
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page, with the user access `perm`.
+    /// Returns the allocated page.
+    ///
+    /// # Errors
+    /// Returns `NoMemory` if allocator fails to allocate a page or the L3
+    /// table covering it.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
     /// Panics if the virtual address has already been allocated.
-    /// Panics if allocator fails to allocate a page.
-    ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
         if va.as_usize() < USER_IMG_BASE {
             panic!("Virtual address given to UserPageTable::alloc() is less than the image base");
         }
@@ -312,31 +377,252 @@ impl UserPageTable {
             panic!("Virtual address given to UserPageTable::alloc() alerady has a page allocated");
         }
 
+        self.0.l3_mut(PageTable::locate(va).0).ok_or(OsError::NoMemory)?;
+        let addr = Self::alloc_page()?;
+        self.0.set_entry(va, Self::page_entry(addr, perm));
+
+        Ok(unsafe {
+            core::slice::from_raw_parts_mut(addr, PAGE_SIZE)
+        })
+    }
+
+    /// Allocates a zeroed page and maps it with `perm` at the page-aligned
+    /// virtual address `va`, like `alloc()` but without panicking.
+    ///
+    /// # Errors
+    /// Returns `NoVmSpace` if `va` is lower than `USER_IMG_BASE` or already
+    /// mapped, and `NoMemory` if allocator fails to allocate a page or the L3
+    /// table covering it.
+    pub fn alloc_zeroed(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<()> {
+        if va.as_usize() < USER_IMG_BASE || self.0.is_valid(va) {
+            return Err(OsError::NoVmSpace);
+        }
+        self.0.l3_mut(PageTable::locate(va).0).ok_or(OsError::NoMemory)?;
+
+        let addr = Self::alloc_page()?;
+        unsafe { addr.write_bytes(0, PAGE_SIZE); }
+        self.0.set_entry(va, Self::page_entry(addr, perm));
+        Ok(())
+    }
+
+    /// Unmaps the page at the page-aligned virtual address `va`, freeing it
+    /// unless it is still shared with another address space.
+    ///
+    /// Returns `false` if no page was mapped at `va`.
+    pub fn free(&mut self, va: VirtualAddr) -> bool {
+        if va.as_usize() < USER_IMG_BASE {
+            return false;
+        }
+
+        let entry = match self.0.entry_mut(va) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let addr = match entry.get_page_addr() {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        entry.set_invalid();
+        if PAGE_REFS.release(addr) {
+            unsafe { ALLOCATOR.dealloc(addr.as_usize() as *mut u8, Page::layout()); }
+        }
+        true
+    }
+
+    /// Allocates a physical page for a user mapping.
+    ///
+    /// Returns `NoMemory` if allocator fails to allocate a page.
+    fn alloc_page() -> OsResult<*mut u8> {
         let addr = unsafe{ ALLOCATOR.alloc(Page::layout()) };
         if addr == core::ptr::null_mut() {
-            panic!("Do not have enough memory to assign new page");
+            return Err(OsError::NoMemory);
         }
+        Ok(addr)
+    }
 
+    /// Returns a valid L3 entry giving user access with `perm` to the page at
+    /// `addr`.
+    fn page_entry(addr: *mut u8, perm: PagePerm) -> RawL3Entry {
         let mut entry = RawL3Entry::new(addr as u64);
         entry.set_bit(RawL3Entry::AF);
         entry.set_masked(addr as u64, RawL3Entry::ADDR);
         entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.set_value(perm.user_entry_perm(), RawL3Entry::AP);
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
         entry.set_bit(RawL3Entry::TYPE);
         entry.set_bit(RawL3Entry::VALID);
+        entry
+    }
 
-        self.0.set_entry(va, entry);
+    /// Returns a new `UserPageTable` mapping the same physical pages as `self`.
+    ///
+    /// Every writable page is downgraded to read-only and marked copy-on-write
+    /// in both tables, and every mapped page has its reference count in
+    /// `PAGE_REFS` incremented. The first write to such a page from either
+    /// address space is resolved by `copy_on_write()`.
+    ///
+    /// Returns `NoMemory` if an L3 table of the new table could not be
+    /// allocated.
+    pub fn fork(&mut self) -> OsResult<UserPageTable> {
+        let mut child = UserPageTable::new();
+        for l2_index in 0..self.0.l3.len() {
+            let table = match self.0.l3[l2_index].as_mut() {
+                Some(table) => table,
+                None => continue,
+            };
+            let child_table = child.0.l3_mut(l2_index).ok_or(OsError::NoMemory)?;
+            for l3_index in 0..table.entries.len() {
+                let entry = &mut table.entries[l3_index];
+                let addr = match entry.get_page_addr() {
+                    Some(addr) => addr,
+                    None => continue,
+                };
+
+                if entry.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW {
+                    entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                    entry.0.set_bit(ENTRY_COW);
+                }
+                PAGE_REFS.share(addr);
+                child_table.entries[l3_index] = *entry;
+            }
+        }
+        Ok(child)
+    }
 
-        unsafe {
-            core::slice::from_raw_parts_mut(addr, PAGE_SIZE)
+    /// Resolves a write permission fault at `va` on a copy-on-write page.
+    ///
+    /// If the page is still shared with another address space, its content is
+    /// copied into a freshly allocated page that replaces it in this table.
+    /// Otherwise this table is the last holder and the page is simply made
+    /// writable again.
+    ///
+    /// Returns `Ok(false)` if `va` is not mapped by a copy-on-write page, in
+    /// which case the fault is a genuine access violation, and `NoMemory`,
+    /// leaving the page shared, if the copy could not be allocated.
+    pub fn copy_on_write(&mut self, va: VirtualAddr) -> OsResult<bool> {
+        if va.as_usize() < USER_IMG_BASE {
+            return Ok(false);
+        }
+
+        let page = VirtualAddr::from(va.as_usize() & PAGE_MASK);
+        let entry = match self.0.entry_mut(page) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let addr = match entry.get_page_addr() {
+            Some(addr) if entry.0.get_masked(ENTRY_COW) != 0 => addr,
+            _ => return Ok(false),
+        };
+
+        if PAGE_REFS.is_shared(addr) {
+            let copy = Self::alloc_page()?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(addr.as_ptr(), copy, PAGE_SIZE);
+            }
+            PAGE_REFS.release(addr);
+            *entry = L3Entry(Self::page_entry(copy, PagePerm::RW));
+        } else {
+            entry.0.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+            entry.0.clear_bit(ENTRY_COW);
+        }
+        Ok(true)
+    }
+
+    /// Returns the page mapped at the page-aligned virtual address `va` as a
+    /// mutable slice, or `None` if no page is mapped there.
+    ///
+    /// Writes through the slice bypass copy-on-write; callers must only use it
+    /// on pages private to this table.
+    pub fn page_mut(&mut self, va: VirtualAddr) -> Option<&mut [u8]> {
+        let addr = self.0.entry(va)?.get_page_addr()?;
+        Some(unsafe { core::slice::from_raw_parts_mut(addr.as_usize() as *mut u8, PAGE_SIZE) })
+    }
+
+    /// Copies `data` to the user virtual address `va` through the physical
+    /// pages backing it, so the table need not be installed. Copy-on-write
+    /// pages in the range are copied first.
+    ///
+    /// Returns `BadAddress`, without writing anything, if part of the range is
+    /// not mapped, and `NoMemory` if a copy-on-write page could not be copied.
+    pub fn copy_to_user(&mut self, va: VirtualAddr, data: &[u8]) -> OsResult<()> {
+        if !self.is_range_mapped(va, data.len()) {
+            return Err(OsError::BadAddress);
+        }
+
+        let mut addr = va.as_usize();
+        let mut data = data;
+        while !data.is_empty() {
+            let page = VirtualAddr::from(addr & PAGE_MASK);
+            let offset = addr & !PAGE_MASK;
+            let n = core::cmp::min(data.len(), PAGE_SIZE - offset);
+            self.copy_on_write(page)?;
+            let dst = self.page_mut(page).expect("mapped page");
+            dst[offset..offset + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            addr = addr.wrapping_add(n);
+        }
+        Ok(())
+    }
+
+    /// Copies `buf.len()` bytes from the user virtual address `va` into `buf`
+    /// through the physical pages backing them.
+    ///
+    /// Returns `false`, leaving `buf` untouched, if part of the range is not
+    /// mapped.
+    pub fn copy_from_user(&self, va: VirtualAddr, buf: &mut [u8]) -> bool {
+        if !self.is_range_mapped(va, buf.len()) {
+            return false;
+        }
+
+        let mut addr = va.as_usize();
+        let mut done = 0;
+        while done < buf.len() {
+            let page = self.0.entry(VirtualAddr::from(addr & PAGE_MASK))
+                .and_then(L3Entry::get_page_addr)
+                .expect("mapped page");
+            let offset = addr & !PAGE_MASK;
+            let n = core::cmp::min(buf.len() - done, PAGE_SIZE - offset);
+            unsafe {
+                let src = (page.as_usize() + offset) as *const u8;
+                core::ptr::copy_nonoverlapping(src, buf[done..].as_mut_ptr(), n);
+            }
+            done += n;
+            addr = addr.wrapping_add(n);
+        }
+        true
+    }
+
+    /// Returns `true` if every page overlapping the `len` bytes starting at
+    /// `va` lies in the user address space and is mapped.
+    pub fn is_range_mapped(&self, va: VirtualAddr, len: usize) -> bool {
+        if len == 0 {
+            return true;
+        }
+
+        let start = va.as_usize();
+        let last = match start.checked_add(len - 1) {
+            Some(last) => last,
+            None => return false,
+        };
+        if start < USER_IMG_BASE {
+            return false;
+        }
+
+        let mut page = start & PAGE_MASK;
+        loop {
+            if self.0.is_invalid(VirtualAddr::from(page)) {
+                return false;
+            }
+            match page.checked_add(PAGE_SIZE) {
+                Some(next) if next <= last => page = next,
+                _ => return true,
+            }
         }
     }
 
     pub fn get_physical_address(&self, va: VirtualAddr) -> u64 {
-        let (l2_index, l3_index) = PageTable::locate(va);
-        let entry = self.0.l3[l2_index].entries[l3_index].0;
-        let addr_base = entry.get_value(RawL3Entry::ADDR);
+        let addr_base = self.0.entry(va).map_or(0, |entry| entry.0.get_value(RawL3Entry::ADDR));
         let addr_offset = va.get_offset() as u64;
         addr_base << 16 + addr_offset
     }
@@ -382,8 +668,12 @@ impl DerefMut for UserPageTable {
 impl Drop for UserPageTable {
     fn drop(&mut self) {
         for entry in self.0.into_iter() {
-            if entry.is_valid() {
-                unsafe { ALLOCATOR.dealloc(entry.get_addr() as *mut u8, Page::layout()); }
+            if let Some(addr) = entry.get_page_addr() {
+                // Pages still shared with a forked address space are freed by
+                // whichever table drops the last reference.
+                if PAGE_REFS.release(addr) {
+                    unsafe { ALLOCATOR.dealloc(addr.as_usize() as *mut u8, Page::layout()); }
+                }
             }
         }
     }
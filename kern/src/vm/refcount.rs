@@ -0,0 +1,65 @@
+use alloc::collections::BTreeMap;
+
+use crate::mutex::Mutex;
+use crate::vm::PhysicalAddr;
+
+/// Reference counts of physical pages shared between user page tables.
+///
+/// Only shared pages are tracked: a page without an entry is owned by exactly
+/// one page table. Pages become shared when an address space is duplicated by
+/// `fork` and stop being shared once every other holder has copied or dropped
+/// them.
+pub struct PageRefs(Mutex<Option<BTreeMap<usize, usize>>>);
+
+impl PageRefs {
+    /// Returns an empty reference count table.
+    pub const fn new() -> PageRefs {
+        PageRefs(Mutex::new(None))
+    }
+
+    /// Enter a critical region and execute the provided closure with the
+    /// internal map, creating it on first use.
+    fn critical<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut BTreeMap<usize, usize>) -> R,
+    {
+        let mut guard = self.0.lock();
+        f(guard.get_or_insert_with(BTreeMap::new))
+    }
+
+    /// Records one more page table holding the page at `addr`.
+    pub fn share(&self, addr: PhysicalAddr) {
+        self.critical(|refs| {
+            let count = refs.entry(addr.as_usize()).or_insert(1);
+            *count += 1;
+        });
+    }
+
+    /// Drops one reference to the page at `addr`.
+    ///
+    /// Returns `true` if the caller held the last reference, in which case
+    /// the caller now owns the page exclusively and is responsible for freeing
+    /// it. Otherwise returns `false` and the page must be left untouched.
+    pub fn release(&self, addr: PhysicalAddr) -> bool {
+        self.critical(|refs| match refs.get_mut(&addr.as_usize()) {
+            None => true,
+            Some(count) if *count > 2 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                refs.remove(&addr.as_usize());
+                false
+            }
+        })
+    }
+
+    /// Returns `true` if the page at `addr` is held by more than one page
+    /// table.
+    pub fn is_shared(&self, addr: PhysicalAddr) -> bool {
+        self.critical(|refs| refs.contains_key(&addr.as_usize()))
+    }
+}
+
+/// Global reference counts for pages shared between user processes.
+pub static PAGE_REFS: PageRefs = PageRefs::new();
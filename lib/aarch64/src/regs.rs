@@ -226,3 +226,24 @@ defreg!(CNTHCTL_EL2, [
 ]);
 
 defreg!(CNTVOFF_EL2);
+
+// (ref. D13.8: Generic Timer registers)
+defreg!(CNTFRQ_EL0);
+
+defreg!(CNTPCT_EL0);
+
+defreg!(CNTP_CTL_EL0, [
+    ISTATUS [2-2], // The timer condition is met
+    IMASK   [1-1], // Timer interrupt is masked
+    ENABLE  [0-0], // Timer is enabled
+]);
+
+defreg!(CNTP_TVAL_EL0);
+
+defreg!(CNTKCTL_EL1, [
+    EVNTI    [7-4], // Counter bit that triggers the event stream
+    EVNTDIR  [3-3], // Trigger on a 1 to 0 transition of the counter bit
+    EVNTEN   [2-2], // Enables the event stream
+    EL0VCTEN [1-1],
+    EL0PCTEN [0-0],
+]);
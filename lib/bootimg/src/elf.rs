@@ -0,0 +1,186 @@
+use core::str;
+
+use crate::Error;
+
+/// The first bytes of every ELF file.
+pub const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+/// Segment is executable.
+pub const PF_X: u32 = 1;
+
+/// Symbol is a function.
+pub const STT_FUNC: u8 = 2;
+/// Symbol has no type, like a label in assembly.
+pub const STT_NOTYPE: u8 = 0;
+
+fn u16_at(bytes: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([bytes[i], bytes[i + 1]])
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+fn u64_at(bytes: &[u8], i: usize) -> u64 {
+    u32_at(bytes, i) as u64 | (u32_at(bytes, i + 4) as u64) << 32
+}
+
+/// Returns `bytes[offset..offset + len]`, or `BadElf` if it is out of bounds.
+fn range(bytes: &[u8], offset: u64, len: u64) -> Result<&[u8], Error> {
+    let end = offset.checked_add(len).ok_or(Error::BadElf)?;
+    if end > bytes.len() as u64 {
+        return Err(Error::BadElf);
+    }
+    Ok(&bytes[offset as usize..end as usize])
+}
+
+/// A 64-bit little-endian AArch64 executable, in the ELF format.
+#[derive(Debug, Copy, Clone)]
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    entry: u64,
+    phdrs: &'a [u8],
+    shdrs: &'a [u8],
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the ELF file `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadElf` if `bytes` is not an ELF file, is not a 64-bit
+    /// little-endian AArch64 executable, or if its program headers or the
+    /// contents of its loadable segments are not in it.
+    pub fn parse(bytes: &'a [u8]) -> Result<Elf<'a>, Error> {
+        if bytes.len() < EHDR_SIZE || bytes[0..4] != ELF_MAGIC {
+            return Err(Error::BadElf);
+        }
+        if bytes[4] != ELFCLASS64 || bytes[5] != ELFDATA2LSB {
+            return Err(Error::BadElf);
+        }
+        if u16_at(bytes, 16) != ET_EXEC || u16_at(bytes, 18) != EM_AARCH64 {
+            return Err(Error::BadElf);
+        }
+
+        let phnum = u16_at(bytes, 56) as u64;
+        let shnum = u16_at(bytes, 60) as u64;
+        if phnum > 0 && u16_at(bytes, 54) as usize != PHDR_SIZE {
+            return Err(Error::BadElf);
+        }
+        if shnum > 0 && u16_at(bytes, 58) as usize != SHDR_SIZE {
+            return Err(Error::BadElf);
+        }
+
+        let elf = Elf {
+            bytes,
+            entry: u64_at(bytes, 24),
+            phdrs: range(bytes, u64_at(bytes, 32), phnum * PHDR_SIZE as u64)?,
+            // Section headers are only needed for the symbols, which are
+            // optional.
+            shdrs: range(bytes, u64_at(bytes, 40), shnum * SHDR_SIZE as u64).unwrap_or(&[]),
+        };
+        for i in 0..phnum as usize {
+            let phdr = &elf.phdrs[i * PHDR_SIZE..][..PHDR_SIZE];
+            if u32_at(phdr, 0) == PT_LOAD {
+                range(bytes, u64_at(phdr, 8), u64_at(phdr, 32))?;
+                if u64_at(phdr, 32) > u64_at(phdr, 40) {
+                    return Err(Error::BadElf);
+                }
+            }
+        }
+        Ok(elf)
+    }
+
+    /// Returns the entry point.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Returns an iterator over the loadable segments, in the order of the
+    /// program headers.
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + 'a {
+        let bytes = self.bytes;
+        self.phdrs.chunks(PHDR_SIZE).filter(|phdr| u32_at(phdr, 0) == PT_LOAD).map(move |phdr| Segment {
+            flags: u32_at(phdr, 4),
+            vaddr: u64_at(phdr, 16),
+            paddr: u64_at(phdr, 24),
+            data: &bytes[u64_at(phdr, 8) as usize..][..u64_at(phdr, 32) as usize],
+            mem_size: u64_at(phdr, 40),
+        })
+    }
+
+    /// Returns an iterator over the symbols of the symbol table, or `None` if
+    /// the file has none.
+    pub fn symbols(&self) -> Option<Symbols<'a>> {
+        let section = |i: usize| self.shdrs.get(i * SHDR_SIZE..(i + 1) * SHDR_SIZE);
+        let symtab = self.shdrs.chunks(SHDR_SIZE).find(|shdr| u32_at(shdr, 4) == SHT_SYMTAB)?;
+        let strtab = section(u32_at(symtab, 40) as usize)?;
+        Some(Symbols {
+            symbols: range(self.bytes, u64_at(symtab, 24), u64_at(symtab, 32)).ok()?.chunks(SYM_SIZE),
+            names: range(self.bytes, u64_at(strtab, 24), u64_at(strtab, 32)).ok()?,
+        })
+    }
+}
+
+/// A loadable segment of an ELF file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Segment<'a> {
+    /// The `PF_*` flags of the segment.
+    pub flags: u32,
+    /// The address the segment is loaded at in virtual memory.
+    pub vaddr: u64,
+    /// The address the segment is loaded at in physical memory.
+    pub paddr: u64,
+    /// The contents of the segment in the file.
+    pub data: &'a [u8],
+    /// The size of the segment in memory: the bytes after `data` are zeroed.
+    pub mem_size: u64,
+}
+
+/// A symbol of an ELF file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    /// The `STT_*` type of the symbol.
+    pub kind: u8,
+    pub value: u64,
+    pub size: u64,
+}
+
+/// An iterator over the symbols of an ELF file. Symbols whose names are not
+/// valid are skipped.
+#[derive(Debug, Clone)]
+pub struct Symbols<'a> {
+    symbols: core::slice::Chunks<'a, u8>,
+    names: &'a [u8],
+}
+
+impl<'a> Iterator for Symbols<'a> {
+    type Item = Symbol<'a>;
+
+    fn next(&mut self) -> Option<Symbol<'a>> {
+        loop {
+            let sym = self.symbols.next().filter(|sym| sym.len() == SYM_SIZE)?;
+            let name = match self.names.get(u32_at(sym, 0) as usize..) {
+                Some(name) => &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())],
+                None => continue,
+            };
+            if let Ok(name) = str::from_utf8(name) {
+                return Some(Symbol { name, kind: sym[4] & 0xf, value: u64_at(sym, 8), size: u64_at(sym, 16) });
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+#![no_std]
+
+#[cfg(test)]
+mod tests;
+
+pub mod elf;
+pub mod symbols;
+
+use core::fmt;
+use core::str;
+
+/// The first bytes of every image.
+pub const MAGIC: [u8; 4] = *b"RBIM";
+
+/// The version of the header's format that this crate reads and writes.
+pub const FORMAT: u16 = 1;
+
+/// The size of the header in bytes. The payload follows it.
+pub const HEADER_SIZE: usize = 64;
+
+/// The longest version string, in bytes, that a header holds.
+pub const VERSION_LEN: usize = 28;
+
+/// Returns the CRC-32 of `buf`, the one of Ethernet and zip files: reflected
+/// polynomial `0xEDB88320`.
+pub fn crc32(buf: &[u8]) -> u32 {
+    !buf.iter().fold(!0, |crc, &b| {
+        let mut crc = crc ^ b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+        crc
+    })
+}
+
+/// Why an image was rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The image does not start with `MAGIC`: it has no header.
+    BadMagic,
+    /// The header is of format `.0`, which is not `FORMAT`.
+    UnknownFormat(u16),
+    /// The header's own CRC-32 does not match: it was corrupted.
+    BadHeaderCrc,
+    /// The version string is not UTF-8.
+    BadVersion,
+    /// The payload does not fit where it is allowed to be loaded, or the entry
+    /// point is outside of it.
+    BadPlacement,
+    /// The image ended after `received` bytes of a payload of `length`.
+    Truncated { length: u32, received: u64 },
+    /// The payload's CRC-32 is `actual` instead of `expected`.
+    BadCrc { expected: u32, actual: u32 },
+    /// The payload is an ELF file that is malformed or not a 64-bit AArch64
+    /// executable.
+    BadElf,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadMagic => write!(f, "no image header"),
+            Error::UnknownFormat(format) => write!(f, "unknown header format {}", format),
+            Error::BadHeaderCrc => write!(f, "corrupted image header"),
+            Error::BadVersion => write!(f, "version string is not UTF-8"),
+            Error::BadPlacement => write!(f, "image does not fit in the memory it may be loaded to"),
+            Error::Truncated { length, received } => {
+                write!(f, "image truncated: received {} of {} bytes", received, length)
+            }
+            Error::BadCrc { expected, actual } => {
+                write!(f, "image corrupted: CRC-32 is {:#010x} instead of {:#010x}", actual, expected)
+            }
+            Error::BadElf => write!(f, "malformed or unsupported ELF file"),
+        }
+    }
+}
+
+/// The header that precedes a kernel image sent to the bootloader, so that
+/// the bootloader can tell a whole and intact image from a broken one before
+/// it jumps to it.
+///
+/// The header is `HEADER_SIZE` bytes, little-endian:
+///
+/// | offset | size | field                                         |
+/// |--------|------|-----------------------------------------------|
+/// | 0      | 4    | `MAGIC`                                       |
+/// | 4      | 2    | format, `FORMAT`                              |
+/// | 6      | 2    | size of the header, `HEADER_SIZE`             |
+/// | 8      | 4    | length of the payload                         |
+/// | 12     | 4    | CRC-32 of the payload                         |
+/// | 16     | 8    | load address of the payload                   |
+/// | 24     | 8    | entry point                                   |
+/// | 32     | 28   | version string, padded with NULs              |
+/// | 60     | 4    | CRC-32 of the 60 bytes before it              |
+///
+/// The payload is either a raw binary or an ELF file. An ELF file is loaded
+/// by its program headers and entered at its own entry point: the load
+/// address and entry point of the header only describe it.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// The length of the payload in bytes.
+    pub length: u32,
+    /// The CRC-32 of the payload.
+    pub crc32: u32,
+    /// The address the payload is loaded at.
+    pub load_addr: u64,
+    /// The address the bootloader jumps to once the payload is loaded.
+    pub entry: u64,
+    version: [u8; VERSION_LEN],
+}
+
+impl Header {
+    /// Returns the header for the payload `payload`, loaded at `load_addr`
+    /// and entered at `entry`. A `version` longer than `VERSION_LEN` bytes is
+    /// cut at the last character that fits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is 4 GiB or larger.
+    pub fn new(payload: &[u8], load_addr: u64, entry: u64, version: &str) -> Header {
+        assert!(payload.len() <= u32::MAX as usize, "payload too large");
+
+        let mut len = version.len().min(VERSION_LEN);
+        while !version.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut header = Header {
+            length: payload.len() as u32,
+            crc32: crc32(payload),
+            load_addr,
+            entry,
+            version: [0; VERSION_LEN],
+        };
+        header.version[..len].copy_from_slice(&version.as_bytes()[..len]);
+        header
+    }
+
+    /// Returns the version string of the image.
+    pub fn version(&self) -> &str {
+        let len = self.version.iter().position(|&b| b == 0).unwrap_or(VERSION_LEN);
+        str::from_utf8(&self.version[..len]).expect("versions are UTF-8")
+    }
+
+    /// Returns the header as it is sent before the payload.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&FORMAT.to_le_bytes());
+        bytes[6..8].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.length.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.load_addr.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.entry.to_le_bytes());
+        bytes[32..60].copy_from_slice(&self.version);
+        let crc = crc32(&bytes[..60]);
+        bytes[60..64].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Parses the header at the start of `bytes`, which holds at least
+    /// `HEADER_SIZE` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadMagic` if `bytes` does not start with a header,
+    /// `UnknownFormat` if it is of another format, `BadHeaderCrc` if it is
+    /// corrupted and `BadVersion` if its version string is not UTF-8.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is shorter than `HEADER_SIZE`.
+    pub fn parse(bytes: &[u8]) -> Result<Header, Error> {
+        let bytes = &bytes[..HEADER_SIZE];
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let u64_at = |i: usize| u32_at(i) as u64 | (u32_at(i + 4) as u64) << 32;
+
+        if bytes[0..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if u16_at(4) != FORMAT || u16_at(6) as usize != HEADER_SIZE {
+            return Err(Error::UnknownFormat(u16_at(4)));
+        }
+        if u32_at(60) != crc32(&bytes[..60]) {
+            return Err(Error::BadHeaderCrc);
+        }
+
+        let mut version = [0u8; VERSION_LEN];
+        version.copy_from_slice(&bytes[32..60]);
+        let len = version.iter().position(|&b| b == 0).unwrap_or(VERSION_LEN);
+        if str::from_utf8(&version[..len]).is_err() {
+            return Err(Error::BadVersion);
+        }
+
+        Ok(Header { length: u32_at(8), crc32: u32_at(12), load_addr: u64_at(16), entry: u64_at(24), version })
+    }
+
+    /// Checks that the payload fits in the memory from `start` to `end`,
+    /// excluded, and that the entry point is in it.
+    pub fn check_placement(&self, start: u64, end: u64) -> Result<(), Error> {
+        let payload_end = self.load_addr.checked_add(self.length as u64).ok_or(Error::BadPlacement)?;
+        if self.load_addr < start || payload_end > end {
+            return Err(Error::BadPlacement);
+        }
+        if self.entry < self.load_addr || self.entry >= payload_end {
+            return Err(Error::BadPlacement);
+        }
+        Ok(())
+    }
+
+    /// Checks that `payload` is the whole and intact payload of the image.
+    pub fn check_payload(&self, payload: &[u8]) -> Result<(), Error> {
+        if (payload.len() as u64) < self.length as u64 {
+            return Err(Error::Truncated { length: self.length, received: payload.len() as u64 });
+        }
+        let actual = crc32(&payload[..self.length as usize]);
+        if actual != self.crc32 {
+            return Err(Error::BadCrc { expected: self.crc32, actual });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Header")
+            .field("length", &self.length)
+            .field("crc32", &self.crc32)
+            .field("load_addr", &self.load_addr)
+            .field("entry", &self.entry)
+            .field("version", &self.version())
+            .finish()
+    }
+}
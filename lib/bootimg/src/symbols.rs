@@ -0,0 +1,295 @@
+use core::fmt;
+use core::slice;
+use core::str;
+
+use crate::crc32;
+use crate::elf::{Elf, Symbol, STT_FUNC, STT_NOTYPE};
+
+/// The first bytes of a symbol table.
+pub const SYMBOLS_MAGIC: [u8; 4] = *b"RSYM";
+
+/// The alignment of a symbol table. It starts at the first multiple of it
+/// after the end of the image it describes, where the image finds it.
+pub const TABLE_ALIGN: u64 = 4096;
+
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 16;
+
+/// The most components of a mangled name that are demangled.
+const MAX_COMPONENTS: usize = 32;
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+fn u64_at(bytes: &[u8], i: usize) -> u64 {
+    u32_at(bytes, i) as u64 | (u32_at(bytes, i + 4) as u64) << 32
+}
+
+/// Returns the address of the symbol table of an image that ends at
+/// `image_end`.
+pub fn table_addr(image_end: u64) -> u64 {
+    (image_end + TABLE_ALIGN - 1) & !(TABLE_ALIGN - 1)
+}
+
+/// Returns whether `symbol` is kept in a symbol table: functions and labels
+/// with an address.
+fn kept(symbol: &Symbol) -> bool {
+    (symbol.kind == STT_FUNC || symbol.kind == STT_NOTYPE) && symbol.value != 0 && !symbol.name.is_empty()
+}
+
+/// The table of the symbols of a kernel, left in memory by the bootloader
+/// right after the kernel so that the kernel can name the functions that
+/// addresses are in.
+///
+/// The table is little-endian:
+///
+/// | offset | size       | field                                       |
+/// |--------|------------|---------------------------------------------|
+/// | 0      | 4          | `SYMBOLS_MAGIC`                             |
+/// | 4      | 4          | number of symbols                           |
+/// | 8      | 4          | size of the names                           |
+/// | 12     | 4          | CRC-32 of the symbols and names             |
+/// | 16     | 8          | end of the image, before the table          |
+/// | 24     | 16 × count | symbols: address (8), size (4), name (4)    |
+/// |        |            | names, each ending with a NUL               |
+///
+/// The name of a symbol is the offset of its name in the names.
+#[derive(Debug, Copy, Clone)]
+pub struct SymbolTable<'a> {
+    image_end: u64,
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Returns the size in bytes of the table of the symbols of `elf`, or
+    /// `None` if it has none.
+    pub fn size_for(elf: &Elf) -> Option<usize> {
+        let (count, names) = elf.symbols()?.filter(kept).fold((0, 0), |(count, names), symbol| {
+            (count + 1, names + symbol.name.len() + 1)
+        });
+        match count {
+            0 => None,
+            _ => Some(HEADER_SIZE + count * ENTRY_SIZE + names),
+        }
+    }
+
+    /// Writes the table of the symbols of `elf`, an image that ends at
+    /// `image_end`, to the start of `dest`. Returns the size of the table, or
+    /// `None` if `elf` has no symbols or the table does not fit in `dest`.
+    pub fn write(elf: &Elf, image_end: u64, dest: &mut [u8]) -> Option<usize> {
+        let size = SymbolTable::size_for(elf)?;
+        if size > dest.len() {
+            return None;
+        }
+
+        let count = elf.symbols()?.filter(kept).count();
+        let names_start = HEADER_SIZE + count * ENTRY_SIZE;
+        let mut name = 0;
+        for (i, symbol) in elf.symbols()?.filter(kept).enumerate() {
+            let entry = &mut dest[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+            entry[0..8].copy_from_slice(&symbol.value.to_le_bytes());
+            entry[8..12].copy_from_slice(&(symbol.size.min(u32::MAX as u64) as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&(name as u32).to_le_bytes());
+            dest[names_start + name..][..symbol.name.len()].copy_from_slice(symbol.name.as_bytes());
+            dest[names_start + name + symbol.name.len()] = 0;
+            name += symbol.name.len() + 1;
+        }
+
+        dest[0..4].copy_from_slice(&SYMBOLS_MAGIC);
+        dest[4..8].copy_from_slice(&(count as u32).to_le_bytes());
+        dest[8..12].copy_from_slice(&(name as u32).to_le_bytes());
+        let crc = crc32(&dest[HEADER_SIZE..size]);
+        dest[12..16].copy_from_slice(&crc.to_le_bytes());
+        dest[16..24].copy_from_slice(&image_end.to_le_bytes());
+        Some(size)
+    }
+
+    /// Parses the table at the start of `bytes`. Returns `None` if there is
+    /// no intact table there.
+    pub fn parse(bytes: &'a [u8]) -> Option<SymbolTable<'a>> {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != SYMBOLS_MAGIC {
+            return None;
+        }
+        let size = SymbolTable::size_in(bytes)?;
+        if size > bytes.len() || crc32(&bytes[HEADER_SIZE..size]) != u32_at(bytes, 12) {
+            return None;
+        }
+
+        let names_start = HEADER_SIZE + u32_at(bytes, 4) as usize * ENTRY_SIZE;
+        Some(SymbolTable {
+            image_end: u64_at(bytes, 16),
+            entries: &bytes[HEADER_SIZE..names_start],
+            names: &bytes[names_start..size],
+        })
+    }
+
+    /// Returns the size of the table whose header is at the start of
+    /// `bytes`, if it is not absurdly large.
+    fn size_in(bytes: &[u8]) -> Option<usize> {
+        (u32_at(bytes, 4) as usize)
+            .checked_mul(ENTRY_SIZE)?
+            .checked_add(u32_at(bytes, 8) as usize)?
+            .checked_add(HEADER_SIZE)
+    }
+
+    /// Returns the table left for the image that ends at `image_end`, or
+    /// `None` if there is none.
+    ///
+    /// # Safety
+    ///
+    /// The memory from `table_addr(image_end)` to the end of the table must
+    /// be readable, and must not change while the table is used.
+    pub unsafe fn find(image_end: u64) -> Option<SymbolTable<'static>> {
+        let addr = table_addr(image_end) as *const u8;
+        let header = slice::from_raw_parts(addr, HEADER_SIZE);
+        if header[0..4] != SYMBOLS_MAGIC {
+            return None;
+        }
+        let table = SymbolTable::parse(slice::from_raw_parts(addr, SymbolTable::size_in(header)?))?;
+        // A table left for another image is not this one's.
+        match table.image_end == image_end {
+            true => Some(table),
+            false => None,
+        }
+    }
+
+    /// Returns the end of the image the table was left for.
+    pub fn image_end(&self) -> u64 {
+        self.image_end
+    }
+
+    /// Returns the end of the table in memory.
+    pub fn end(&self) -> u64 {
+        self.names.as_ptr() as u64 + self.names.len() as u64
+    }
+
+    /// Returns the number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Returns whether the table has no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the name of the symbol that `addr` is in, and the offset of
+    /// `addr` from the symbol. A symbol without a size is taken to extend to
+    /// the next one.
+    pub fn lookup(&self, addr: u64) -> Option<(&'a str, u64)> {
+        let mut best: Option<(u64, u32)> = None;
+        for entry in self.entries.chunks(ENTRY_SIZE) {
+            let (start, size) = (u64_at(entry, 0), u32_at(entry, 8) as u64);
+            let contains = addr >= start && (size == 0 || addr - start < size);
+            let later = match best {
+                Some((best, _)) => start > best,
+                None => true,
+            };
+            if contains && later {
+                best = Some((start, u32_at(entry, 12)));
+            }
+        }
+
+        let (start, name) = best?;
+        let name = self.names.get(name as usize..)?;
+        let name = &name[..name.iter().position(|&b| b == 0)?];
+        Some((str::from_utf8(name).ok()?, addr - start))
+    }
+}
+
+/// Displays a symbol name demangled, if it is a mangled Rust name, without
+/// its hash: `_ZN6kernel5kmain17h0123456789abcdefE` is displayed as
+/// `kernel::kmain`. Other names are displayed as they are.
+pub struct Demangle<'a>(pub &'a str);
+
+/// Splits the mangled name `name`, `_ZN`, then components each preceded by
+/// their length, then `E`, into its components.
+fn split(name: &str) -> Option<([&str; MAX_COMPONENTS], usize)> {
+    let mut rest = name.get(3..).filter(|_| name.starts_with("_ZN"))?;
+    let mut components = [""; MAX_COMPONENTS];
+    let mut count = 0;
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + len)?;
+        *components.get_mut(count)? = component;
+        count += 1;
+        rest = &rest[digits + len..];
+    }
+    match rest.len() == 1 && count > 0 {
+        true => Some((components, count)),
+        false => None,
+    }
+}
+
+fn is_hash(component: &str) -> bool {
+    component.len() == 17 && component.starts_with('h') && component[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Writes `component` of a mangled name with its escapes undone.
+fn unescape(component: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut rest = match component.starts_with("_$") {
+        true => &component[1..],
+        false => component,
+    };
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            f.write_str("::")?;
+            rest = &rest[2..];
+        } else if rest.starts_with('$') {
+            let end = match rest[1..].find('$') {
+                Some(end) => end + 1,
+                None => return f.write_str(rest),
+            };
+            let unescaped = match &rest[1..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                code if code.starts_with('u') => {
+                    match u32::from_str_radix(&code[1..], 16).ok().and_then(core::char::from_u32) {
+                        Some(c) => c,
+                        None => return f.write_str(rest),
+                    }
+                }
+                _ => return f.write_str(rest),
+            };
+            write!(f, "{}", unescaped)?;
+            rest = &rest[end + 1..];
+        } else {
+            let len = match rest.find(&['$', '.'][..]) {
+                Some(0) => 1,
+                Some(len) => len,
+                None => rest.len(),
+            };
+            f.write_str(&rest[..len])?;
+            rest = &rest[len..];
+        }
+    }
+    Ok(())
+}
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (components, mut count) = match split(self.0) {
+            Some(split) => split,
+            None => return f.write_str(self.0),
+        };
+        if count > 1 && is_hash(components[count - 1]) {
+            count -= 1;
+        }
+        for (i, component) in components[..count].iter().enumerate() {
+            if i > 0 {
+                f.write_str("::")?;
+            }
+            unescape(component, f)?;
+        }
+        Ok(())
+    }
+}
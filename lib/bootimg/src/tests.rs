@@ -0,0 +1,266 @@
+use crate::*;
+
+fn payload() -> [u8; 300] {
+    let mut payload = [0u8; 300];
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    payload
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn header_round_trip() {
+    let payload = payload();
+    let header = Header::new(&payload, 0x80000, 0x80000, "kernel 0.1.0");
+    let bytes = header.to_bytes();
+    assert_eq!(&bytes[0..4], b"RBIM");
+    assert_eq!(&bytes[8..12], &[44, 1, 0, 0]);
+    assert_eq!(&bytes[16..24], &[0, 0, 8, 0, 0, 0, 0, 0]);
+
+    let parsed = Header::parse(&bytes).expect("valid header");
+    assert_eq!(parsed, header);
+    assert_eq!(parsed.version(), "kernel 0.1.0");
+    assert_eq!(parsed.length, 300);
+    assert_eq!(parsed.check_payload(&payload), Ok(()));
+}
+
+#[test]
+fn long_versions_are_cut() {
+    let header = Header::new(b"x", 0, 0, "0123456789012345678901234567890");
+    assert_eq!(header.version(), "0123456789012345678901234567");
+
+    // A character is never split.
+    let header = Header::new(b"x", 0, 0, "012345678901234567890123456é");
+    assert_eq!(header.version(), "012345678901234567890123456");
+    let parsed = Header::parse(&header.to_bytes()).expect("valid header");
+    assert_eq!(parsed.version(), "012345678901234567890123456");
+}
+
+#[test]
+fn bad_headers() {
+    let payload = payload();
+    let bytes = Header::new(&payload, 0x80000, 0x80000, "v1").to_bytes();
+
+    // An image without a header.
+    assert_eq!(Header::parse(&payload), Err(Error::BadMagic));
+
+    let mut format = bytes;
+    format[4] = 2;
+    assert_eq!(Header::parse(&format), Err(Error::UnknownFormat(2)));
+
+    for i in 8..HEADER_SIZE {
+        let mut corrupted = bytes;
+        corrupted[i] ^= 0x10;
+        assert_eq!(Header::parse(&corrupted), Err(Error::BadHeaderCrc), "byte {}", i);
+    }
+}
+
+#[test]
+fn bad_payloads() {
+    let mut payload = payload();
+    let header = Header::new(&payload, 0x80000, 0x80000, "v1");
+
+    // Padding after the payload is ignored.
+    let mut padded = [0u8; 384];
+    padded[..300].copy_from_slice(&payload);
+    assert_eq!(header.check_payload(&padded), Ok(()));
+
+    assert_eq!(header.check_payload(&payload[..200]), Err(Error::Truncated { length: 300, received: 200 }));
+    payload[100] ^= 1;
+    match header.check_payload(&payload) {
+        Err(Error::BadCrc { expected, .. }) => assert_eq!(expected, header.crc32),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn placement() {
+    let payload = payload();
+    let header = Header::new(&payload, 0x80000, 0x80100, "v1");
+    assert_eq!(header.check_placement(0x80000, 0x4000000), Ok(()));
+    assert_eq!(header.check_placement(0x80000, 0x80000 + 300), Ok(()));
+    assert_eq!(header.check_placement(0x80000, 0x80000 + 299), Err(Error::BadPlacement));
+    assert_eq!(header.check_placement(0x90000, 0x4000000), Err(Error::BadPlacement));
+
+    // The entry point must be in the payload.
+    let header = Header::new(&payload, 0x80000, 0x80000 + 300, "v1");
+    assert_eq!(header.check_placement(0x80000, 0x4000000), Err(Error::BadPlacement));
+
+    let header = Header::new(&payload, u64::MAX - 10, u64::MAX - 10, "v1");
+    assert_eq!(header.check_placement(0, u64::MAX), Err(Error::BadPlacement));
+}
+
+mod elf_files {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use crate::elf::*;
+    use crate::symbols::*;
+    use crate::Error;
+
+    const KMAIN: &str = "_ZN6kernel5kmain17h0123456789abcdefE";
+
+    fn put(elf: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+        if elf.len() < offset + bytes.len() {
+            elf.resize(offset + bytes.len(), 0);
+        }
+        elf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn symbol(elf: &mut Vec<u8>, offset: usize, name: u32, kind: u8, value: u64, size: u64) {
+        put(elf, offset, &name.to_le_bytes());
+        put(elf, offset + 4, &[kind]);
+        put(elf, offset + 8, &value.to_le_bytes());
+        put(elf, offset + 16, &size.to_le_bytes());
+    }
+
+    fn section(elf: &mut Vec<u8>, offset: usize, kind: u32, at: u64, size: u64, link: u32) {
+        put(elf, offset + 4, &kind.to_le_bytes());
+        put(elf, offset + 24, &at.to_le_bytes());
+        put(elf, offset + 32, &size.to_le_bytes());
+        put(elf, offset + 40, &link.to_le_bytes());
+    }
+
+    /// An executable with code at 0x80000, data after it followed by 24 bytes
+    /// of BSS, and a symbol table.
+    fn kernel() -> Vec<u8> {
+        let mut elf = Vec::new();
+        put(&mut elf, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut elf, 16, &2u16.to_le_bytes());
+        put(&mut elf, 18, &183u16.to_le_bytes());
+        put(&mut elf, 24, &0x80000u64.to_le_bytes());
+        put(&mut elf, 32, &64u64.to_le_bytes());
+        put(&mut elf, 40, &0x200u64.to_le_bytes());
+        put(&mut elf, 54, &56u16.to_le_bytes());
+        put(&mut elf, 56, &2u16.to_le_bytes());
+        put(&mut elf, 58, &64u16.to_le_bytes());
+        put(&mut elf, 60, &3u16.to_le_bytes());
+
+        for (i, &(flags, offset, addr, file_size, mem_size)) in
+            [(5u32, 0x100u64, 0x80000u64, 16u64, 16u64), (6, 0x110, 0x80010, 8, 32)].iter().enumerate()
+        {
+            let phdr = 64 + i * 56;
+            put(&mut elf, phdr, &1u32.to_le_bytes());
+            put(&mut elf, phdr + 4, &flags.to_le_bytes());
+            put(&mut elf, phdr + 8, &offset.to_le_bytes());
+            put(&mut elf, phdr + 16, &addr.to_le_bytes());
+            put(&mut elf, phdr + 24, &addr.to_le_bytes());
+            put(&mut elf, phdr + 32, &file_size.to_le_bytes());
+            put(&mut elf, phdr + 40, &mem_size.to_le_bytes());
+        }
+        put(&mut elf, 0x100, &[0xAA; 16]);
+        put(&mut elf, 0x110, &[0xBB; 8]);
+
+        let names = std::format!("\0_start\0{}\0DATA\0", KMAIN);
+        put(&mut elf, 0x120, names.as_bytes());
+        symbol(&mut elf, 0x180 + 24, 1, 0, 0x80000, 0);
+        symbol(&mut elf, 0x180 + 48, 8, 2, 0x80004, 8);
+        // Neither an object nor a section is kept in a symbol table.
+        symbol(&mut elf, 0x180 + 72, 8 + KMAIN.len() as u32 + 1, 1, 0x80010, 8);
+        symbol(&mut elf, 0x180 + 96, 0, 3, 0x80000, 0);
+
+        section(&mut elf, 0x200 + 64, 2, 0x180, 5 * 24, 2);
+        section(&mut elf, 0x200 + 128, 3, 0x120, names.len() as u64, 0);
+        elf.resize(0x200 + 3 * 64, 0);
+        elf
+    }
+
+    #[test]
+    fn segments() {
+        let bytes = kernel();
+        let elf = Elf::parse(&bytes).expect("valid ELF");
+        assert_eq!(elf.entry(), 0x80000);
+
+        let segments: Vec<_> = elf.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].paddr, 0x80000);
+        assert_eq!(segments[0].data, &[0xAA; 16][..]);
+        assert_eq!(segments[0].flags & PF_X, PF_X);
+        assert_eq!(segments[1].paddr, 0x80010);
+        assert_eq!(segments[1].data, &[0xBB; 8][..]);
+        assert_eq!(segments[1].mem_size, 32);
+    }
+
+    #[test]
+    fn symbols() {
+        let bytes = kernel();
+        let elf = Elf::parse(&bytes).expect("valid ELF");
+        let names: Vec<_> = elf.symbols().expect("symbols").map(|symbol| symbol.name).collect();
+        assert_eq!(names, ["", "_start", KMAIN, "DATA", ""]);
+    }
+
+    #[test]
+    fn bad_elfs() {
+        let elf = kernel();
+        assert_eq!(Elf::parse(&elf[..40]).err(), Some(Error::BadElf));
+
+        // 32-bit, big-endian, or not for AArch64.
+        for &(offset, byte) in &[(4, 1), (5, 2), (18, 62)] {
+            let mut bad = elf.clone();
+            bad[offset] = byte;
+            assert_eq!(Elf::parse(&bad).err(), Some(Error::BadElf), "byte {}", offset);
+        }
+
+        // A segment past the end of the file.
+        let mut bad = elf.clone();
+        put(&mut bad, 64 + 56 + 32, &0x1000u64.to_le_bytes());
+        assert_eq!(Elf::parse(&bad).err(), Some(Error::BadElf));
+
+        // Section headers past the end leave no symbols, but the file loads.
+        let mut stripped = elf.clone();
+        put(&mut stripped, 40, &0x10000u64.to_le_bytes());
+        assert!(Elf::parse(&stripped).expect("valid ELF").symbols().is_none());
+    }
+
+    #[test]
+    fn symbol_table() {
+        let bytes = kernel();
+        let elf = Elf::parse(&bytes).expect("valid ELF");
+        let size = SymbolTable::size_for(&elf).expect("symbols");
+        assert_eq!(size, 24 + 2 * 16 + 7 + KMAIN.len() + 1);
+
+        let mut dest = [0u8; 256];
+        assert_eq!(SymbolTable::write(&elf, 0x80030, &mut dest[..size - 1]), None);
+        assert_eq!(SymbolTable::write(&elf, 0x80030, &mut dest), Some(size));
+
+        let table = SymbolTable::parse(&dest).expect("valid table");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.image_end(), 0x80030);
+        assert_eq!(table.lookup(0x80002), Some(("_start", 2)));
+        assert_eq!(table.lookup(0x80006), Some((KMAIN, 2)));
+        // Past the end of `kmain`, `_start` has no size and goes on.
+        assert_eq!(table.lookup(0x8000c), Some(("_start", 0xc)));
+        assert_eq!(table.lookup(0x7fffc), None);
+
+        for i in 24..size {
+            let mut corrupted = dest;
+            corrupted[i] ^= 1;
+            assert!(SymbolTable::parse(&corrupted).is_none(), "byte {}", i);
+        }
+        assert_eq!(table_addr(0x80030), 0x81000);
+        assert_eq!(table_addr(0x81000), 0x81000);
+    }
+
+    #[test]
+    fn demangle() {
+        let demangled = |name| std::format!("{}", Demangle(name));
+        assert_eq!(demangled(KMAIN), "kernel::kmain");
+        assert_eq!(demangled("_ZN4core9panicking5panic17h1f2e3d4c5b6a7980E"), "core::panicking::panic");
+        assert_eq!(
+            demangled("_ZN61_$LT$kernel..console..Console$u20$as$u20$core..fmt..Write$GT$9write_str17h0011223344556677E"),
+            "<kernel::console::Console as core::fmt::Write>::write_str"
+        );
+        assert_eq!(demangled("_ZN6kernel5traps16handle_exception28_$u7b$$u7b$closure$u7d$$u7d$E"),
+                   "kernel::traps::handle_exception::{{closure}}");
+        assert_eq!(demangled("_start"), "_start");
+        assert_eq!(demangled("_ZN6kernelE"), "kernel");
+        assert_eq!(demangled("_ZN99kernelE"), "_ZN99kernelE");
+    }
+}
@@ -475,3 +475,188 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+/// An in-memory copy of an image, shared by the file systems mounted from it
+/// so that a test can mount it again to see what was written to it.
+#[derive(Clone)]
+struct SharedImage(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().expect("all okay").read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("all okay").write_sector(n, buf)
+    }
+}
+
+macro image_from_resource($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    SharedImage(Arc::new(Mutex::new(Cursor::new(data))))
+}}
+
+fn mount(image: &SharedImage) -> StdVFatHandle {
+    VFat::<StdVFatHandle>::from(image.clone()).expect("failed to initialize VFAT from image")
+}
+
+fn flush(vfat: &StdVFatHandle) {
+    vfat.lock(|fat| fat.flush()).expect("flush to the image");
+}
+
+fn read_file<P: AsRef<Path>>(vfat: &StdVFatHandle, path: P) -> Vec<u8> {
+    let mut data = Vec::new();
+    vfat.open_file(path).expect("file exists").read_to_end(&mut data).expect("read file");
+    data
+}
+
+fn names<P: AsRef<Path>>(vfat: &StdVFatHandle, path: P) -> Vec<String> {
+    let dir = vfat.open_dir(path).expect("directory exists");
+    let mut names: Vec<String> = dir.entries().expect("entries iterator").map(|e| e.name().to_string()).collect();
+    names.sort();
+    names
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[test]
+fn test_write_new_file() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+    let data = pattern(20000);
+
+    let mut file = vfat.create_file("/Written file.txt").expect("create file");
+    file.write_all(&data[..1000]).expect("write file");
+    file.write_all(&data[1000..]).expect("write file");
+    file.sync().expect("sync file");
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(read_file(&vfat, "/Written file.txt"), data);
+
+    let vfat = mount(&image);
+    let entry = vfat.open("/written FILE.TXT").expect("file exists");
+    assert_eq!(entry.name(), "Written file.txt");
+    assert_eq!(read_file(&vfat, "/Written file.txt"), data);
+}
+
+#[test]
+fn test_overwrite_and_append() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+
+    let mut file = vfat.create_file("/NEW.TXT").expect("create file");
+    file.write_all(b"hello world").expect("write file");
+    file.seek(io::SeekFrom::Start(6)).expect("seek");
+    file.write_all(b"there").expect("overwrite");
+    assert_eq!(file.seek(io::SeekFrom::End(0)).expect("seek to end"), 11);
+    file.write_all(b"!\n").expect("append");
+    file.sync().expect("sync file");
+
+    let vfat = mount(&image);
+    assert_eq!(vfat.open("/NEW.TXT").expect("file exists").name(), "NEW.TXT");
+    assert_eq!(read_file(&vfat, "/NEW.TXT"), b"hello there!\n");
+}
+
+#[test]
+fn test_create_errors() {
+    let vfat = mount(&image_from_resource!("mock1.fat32.img"));
+
+    vfat.create_file("/exists").expect("create file");
+    expect_variant!(vfat.create_file("/EXISTS").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists);
+    expect_variant!(vfat.create_dir("/exists").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists);
+    expect_variant!(vfat.create_file("/missing/file").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    expect_variant!(vfat.create_file("/exists/file").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    expect_variant!(vfat.create_file("/bad:name").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_create_dir() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+
+    vfat.create_dir("/new dir").expect("create directory");
+    let mut file = vfat.create_file("/new dir/inner.txt").expect("create file in new directory");
+    file.write_all(b"inside").expect("write file");
+    flush(&vfat);
+
+    let vfat = mount(&image);
+    assert!(names(&vfat, "/").contains(&"new dir".to_string()));
+    assert_eq!(names(&vfat, "/new dir"), vec![".", "..", "inner.txt"]);
+    assert_eq!(read_file(&vfat, "/new dir/inner.txt"), b"inside");
+}
+
+#[test]
+fn test_remove() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+    let free = vfat.usage().expect("usage").free_blocks;
+
+    vfat.create_dir("/gone").expect("create directory");
+    vfat.create_file("/gone/file").expect("create file").write_all(&pattern(5000)).expect("write file");
+    assert!(vfat.usage().expect("usage").free_blocks < free);
+    expect_variant!(vfat.remove("/gone"), Err(ref e) if e.kind() == io::ErrorKind::Other);
+
+    vfat.remove("/gone/file").expect("remove file");
+    vfat.remove("/gone").expect("remove empty directory");
+    expect_variant!(vfat.open("/gone").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    assert_eq!(vfat.usage().expect("usage").free_blocks, free);
+    flush(&vfat);
+
+    let vfat = mount(&image);
+    expect_variant!(vfat.open("/gone").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    assert_eq!(vfat.usage().expect("usage").free_blocks, free);
+}
+
+#[test]
+fn test_rename() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+    let data = pattern(3000);
+
+    vfat.create_dir("/a").expect("create directory");
+    vfat.create_dir("/b").expect("create directory");
+    vfat.create_file("/a/file.txt").expect("create file").write_all(&data).expect("write file");
+
+    vfat.rename("/a/file.txt", "/b/renamed file.txt").expect("move file");
+    expect_variant!(vfat.open("/a/file.txt").map(|_| ()), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+    assert_eq!(read_file(&vfat, "/b/renamed file.txt"), data);
+
+    vfat.rename("/a", "/b/moved").expect("move directory");
+    expect_variant!(vfat.rename("/b", "/b/moved/b"), Err(ref e) if e.kind() == io::ErrorKind::InvalidInput);
+    flush(&vfat);
+
+    let vfat = mount(&image);
+    assert_eq!(names(&vfat, "/b"), vec![".", "..", "moved", "renamed file.txt"]);
+    let parent = match vfat.open_dir("/b/moved").expect("moved directory").find("..").expect("parent entry") {
+        vfat::Entry::Dir(parent) => parent,
+        _ => panic!("'..' is not a directory"),
+    };
+    assert!(parent.entries().expect("entries iterator").any(|e| e.name() == "moved"));
+}
+
+#[test]
+fn test_directory_grows() {
+    let image = image_from_resource!("mock1.fat32.img");
+    let vfat = mount(&image);
+
+    vfat.create_dir("/many").expect("create directory");
+    let name = |i: usize| format!("a long file name {:03}.txt", i);
+    for i in 0..100 {
+        vfat.create_file(format!("/many/{}", name(i))).expect("create file");
+    }
+    for i in (0..100).step_by(2) {
+        vfat.remove(format!("/many/{}", name(i))).expect("remove file");
+    }
+    for i in 100..150 {
+        vfat.create_file(format!("/many/{}", name(i))).expect("create file");
+    }
+    flush(&vfat);
+
+    let vfat = mount(&image);
+    let mut expected: Vec<String> = (1..100).step_by(2).chain(100..150).map(name).collect();
+    expected.extend(vec![".".to_string(), "..".to_string()]);
+    expected.sort();
+    assert_eq!(names(&vfat, "/many"), expected);
+}
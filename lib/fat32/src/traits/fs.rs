@@ -1,3 +1,4 @@
+use shim::ioerr;
 use shim::{io, path::Path};
 
 use crate::traits::Metadata;
@@ -65,7 +66,21 @@ pub trait Entry: Sized {
     }
 }
 
+/// The space used on a file system, as returned by `FileSystem::usage()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// The size in bytes of the blocks in which space is allocated.
+    pub block_size: u64,
+    /// The number of blocks available for files and directories.
+    pub total_blocks: u64,
+    /// The number of those blocks that are free.
+    pub free_blocks: u64,
+}
+
 /// Trait implemented by file systems.
+///
+/// Only `open()` must be implemented. The methods that modify the file system
+/// return an error kind of `Other` unless the file system supports them.
 pub trait FileSystem: Sized {
     /// The type of files in this file system.
     type File: File;
@@ -113,4 +128,54 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates an empty file at `path` and returns it. `path` must be
+    /// absolute.
+    ///
+    /// # Errors
+    ///
+    /// If the parent of `path` is not an existing directory, an error kind of
+    /// `InvalidInput` or `NotFound` is returned, as for `open()`.
+    ///
+    /// If an entry already exists at `path`, an error kind of `AlreadyExists`
+    /// is returned.
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        ioerr!(Other, "creating files is not supported")
+    }
+
+    /// Creates an empty directory at `path` and returns it. `path` must be
+    /// absolute.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `create_file()`.
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::Dir> {
+        ioerr!(Other, "creating directories is not supported")
+    }
+
+    /// Removes the file or empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()`, an error kind of
+    /// `Other` is returned if `path` is a directory that is not empty.
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        ioerr!(Other, "removing entries is not supported")
+    }
+
+    /// Moves the entry at `from` to `to`, which must not exist yet. Both paths
+    /// must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()` on `from`, the error
+    /// conditions of `create_file()` apply to `to`.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        ioerr!(Other, "renaming entries is not supported")
+    }
+
+    /// Returns how much space the file system has and how much of it is free.
+    fn usage(self) -> io::Result<Usage> {
+        ioerr!(Other, "querying the space usage is not supported")
+    }
 }
@@ -34,6 +34,18 @@ pub trait Metadata: Sized {
     /// Whether the entry should be "hidden" from directory traversals.
     fn hidden(&self) -> bool;
 
+    /// Whether the entry belongs to the operating system. Defaults to
+    /// `false` for file systems without the notion.
+    fn system(&self) -> bool {
+        false
+    }
+
+    /// Whether the entry changed since it was last backed up. Defaults to
+    /// `false` for file systems without the notion.
+    fn archive(&self) -> bool {
+        false
+    }
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp;
 
@@ -5,5 +5,5 @@ mod metadata;
 
 pub use self::block_device::BlockDevice;
 pub use self::dummy::Dummy;
-pub use self::fs::{Dir, Entry, File, FileSystem};
+pub use self::fs::{Dir, Entry, File, FileSystem, Usage};
 pub use self::metadata::{Metadata, Timestamp};
@@ -92,14 +92,10 @@ impl CachedPartition {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        if !self.cache.contains_key(&sector) {
-            let mut buf = vec![0; self.device.sector_size() as usize];
-            self.device.read_sector(self.virtual_to_physical(sector).expect("virtual to physical address translation failed"), buf.as_mut_slice())?;
-            self.cache.insert(sector, CacheEntry{data: buf, dirty: false});
-        }
-
+        self.get(sector)?;
         match self.cache.get_mut(&sector) {
             Some(entry) => {
+                entry.dirty = true;
                 Ok(entry.data.as_mut_slice())
             },
             None => {
@@ -142,6 +138,27 @@ impl CachedPartition {
     }
 }
 
+impl CachedPartition {
+    /// Writes the sectors changed through `get_mut()` back to the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the disk fails. The sectors
+    /// that were not written stay dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let factor = self.factor();
+        let device_sector_size = self.device.sector_size() as usize;
+        for (&sector, entry) in self.cache.iter_mut().filter(|(_, entry)| entry.dirty) {
+            let physical_sector = self.partition.start + sector * factor;
+            for (i, data) in entry.data.chunks(device_sector_size).enumerate() {
+                self.device.write_sector(physical_sector + i as u64, data)?;
+            }
+            entry.dirty = false;
+        }
+        Ok(())
+    }
+}
+
 // FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
 // `write_sector` methods should only read/write from/to cached sectors.
 impl BlockDevice for CachedPartition {
@@ -21,14 +21,25 @@ pub struct Dir<HANDLE: VFatHandle> {
     vfat: HANDLE,
     first_cluster: Cluster,
     metadata: Metadata,
-    name: String
+    name: String,
+    /// Where the directory's entry is; the root directory has none.
+    pos: Option<EntryPos>,
 }
 
+/// Where the 32-byte entries of a file or directory are in the directory
+/// holding it: the first is its first long file name entry, if it has any,
+/// and the last its regular entry.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct EntryPos {
+    pub dir: Cluster,
+    pub first: usize,
+    pub last: usize,
+}
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
     pub fn new(vfat: HANDLE, first_cluster: Cluster, metadata: Metadata) -> Self{
         let name = metadata.get_short_name().to_string();
-        Dir{vfat, first_cluster, metadata, name}
+        Dir{vfat, first_cluster, metadata, name, pos: None}
     }
 
     pub fn is_end(&self) -> bool {
@@ -47,12 +58,20 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
         &self.metadata
     }
 
-    pub fn from_regular_entry(handle: HANDLE, entry: VFatRegularDirEntry, name: String) -> Self {
+    pub(crate) fn from_regular_entry(handle: HANDLE, entry: VFatRegularDirEntry, name: String, pos: EntryPos) -> Self {
         let vfat = handle.clone();
         //let first_cluster = Cluster::from(((entry.first_cluster_high as u32) << 16) + (entry.first_cluster_low as u32));
         let first_cluster = entry.get_cluster();
         let metadata = entry.get_metadata();
-        Dir{vfat, first_cluster, metadata, name}
+        Dir{vfat, first_cluster, metadata, name, pos: Some(pos)}
+    }
+
+    pub(crate) fn first_cluster(&self) -> Cluster {
+        self.first_cluster
+    }
+
+    pub(crate) fn pos(&self) -> Option<EntryPos> {
+        self.pos
     }
 }
 
@@ -61,6 +80,8 @@ pub struct EntryIterator<HANDLE: VFatHandle> {
     //curr_entry: Entry<HANDLE>
     chain: Vec<VFatDirEntry>,
     vfat: HANDLE,
+    /// The first cluster of the directory.
+    dir: Cluster,
     //curr_entry: Entry<HANDLE>
     index: usize,
 }
@@ -86,7 +107,7 @@ pub struct VFatRegularDirEntry {
 impl VFatRegularDirEntry {
     pub fn get_metadata(&self) -> Metadata {
         let creation_time = Timestamp::new(self.creation_date, self.creation_time);
-        let last_access_date = Timestamp::new(self.creation_date, Default::default());
+        let last_access_date = Timestamp::new(self.last_access_date, Default::default());
         let last_modification_date = Timestamp::new(self.last_modification_date, self.last_modification_time);
         Metadata::new(&self.file_name, &self.file_extension, self.attribute, creation_time, last_access_date, last_modification_date, self.file_size)
     }
@@ -228,7 +249,7 @@ impl<HANDLE: VFatHandle> EntryIterator<HANDLE> {
             Ok(_) => {
                 let chain = unsafe {buf.cast::<VFatDirEntry>()};
 
-                return Ok(EntryIterator{vfat, chain, index: 0});
+                return Ok(EntryIterator{vfat, chain, dir: root.first_cluster, index: 0});
             }
         }
 
@@ -264,11 +285,13 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
     type Item = Entry<HANDLE>;
     fn next(&mut self) -> Option<Self::Item> {
         'outer: loop {
-            let entry = unsafe {self.chain[self.index].unknown};
+            // A full directory has no end marker.
+            let entry = unsafe {self.chain.get(self.index)?.unknown};
+            let first = self.index;
             if entry.attribute.is_lfn() {
                 let mut vec: Vec<String> = Vec::new();
                 'inner1: loop {
-                    let lfn_entry = unsafe {self.chain[self.index].long_filename};
+                    let lfn_entry = unsafe {self.chain.get(self.index)?.long_filename};
                     if lfn_entry.is_end() {
                         return None;
                     } else if lfn_entry.is_deleted_or_unused() {
@@ -289,7 +312,7 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                     vec.insert(lfn_entry.sequence_number as usize, final_name);
                 }
                 'inner2: loop {
-                    let reg_entry = unsafe {self.chain[self.index].regular};
+                    let reg_entry = unsafe {self.chain.get(self.index)?.regular};
                     if reg_entry.get_metadata().is_end() {
                         return None;
                     } else if reg_entry.is_deleted_or_unused() {
@@ -302,12 +325,13 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                         let lfn_name = combine_string(&vec);
                         //println!("LFN: {}", lfn_name);
                         //println!("LFN name: {}, first_cluster: {}, size: {}", lfn_name, reg_entry.get_cluster().inner(), reg_entry.file_size);
-                        let result = Entry::from_regular_entry(reg_entry, self.vfat.clone(), lfn_name);
+                        let pos = EntryPos { dir: self.dir, first, last: self.index - 1 };
+                        let result = Entry::from_regular_entry(reg_entry, self.vfat.clone(), lfn_name, pos);
                         return Some(result);
                     }
                 }
             } else {
-                let reg_entry = unsafe {self.chain[self.index].regular};
+                let reg_entry = unsafe {self.chain.get(self.index)?.regular};
                 let metadata = reg_entry.get_metadata();
                 if metadata.is_end() {
                     return None;
@@ -320,7 +344,8 @@ impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
                 self.index += 1;
                 //println!("SFN name: {}, first_cluster: {}, size: {}", metadata.get_short_name(), reg_entry.get_cluster().inner(), reg_entry.file_size);
                 let name = metadata.get_short_name();
-                return Some(Entry::from_regular_entry(reg_entry, self.vfat.clone(), name.to_string()));
+                let pos = EntryPos { dir: self.dir, first, last: self.index - 1 };
+                return Some(Entry::from_regular_entry(reg_entry, self.vfat.clone(), name.to_string(), pos));
             }
         }
     }
@@ -336,3 +361,137 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
         Ok(EntryIterator::new_from_dir(self)?)
     }
 }
+
+/// The bytes other than letters and digits allowed in short names.
+const SHORT_NAME_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// The characters not allowed in long names, besides control characters.
+const LONG_NAME_INVALID: &str = "\"*/:<>?\\|";
+
+/// The number of UTF-16 code units of a long name in each entry.
+const LFN_CHARS_PER_ENTRY: usize = 13;
+
+/// The offsets of the UTF-16 code units in a long file name entry.
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_NAME_SPECIAL.contains(&byte)
+}
+
+/// Splits `name` at its last dot, which does not start it, into its base and
+/// its extension.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    }
+}
+
+/// Returns the 11 bytes of the short name `name`, padded with spaces, or
+/// `None` if `name` is not a short name: at most 8 upper case characters, then
+/// optionally a dot and at most 3 more.
+pub(crate) fn short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension) = split_extension(name);
+    let valid = |part: &str, max: usize| part.len() <= max && part.bytes().all(is_short_char);
+    if base.is_empty() || !valid(base, 8) || !valid(extension, 3) || name.ends_with('.') {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    Some(short)
+}
+
+/// Returns the short name that goes with the long name `name`, like
+/// `LONGNA~1TXT` for `long name.txt`: the first characters of its base and of
+/// its extension, made valid, then the first number that makes the short name
+/// not `taken`.
+///
+/// # Errors
+///
+/// Returns an error of `AlreadyExists` if every number is taken.
+pub(crate) fn alias(name: &str, taken: impl Fn(&[u8; 11]) -> bool) -> io::Result<[u8; 11]> {
+    let convert = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() as u32 {
+                byte if byte < 0x80 && is_short_char(byte as u8) => byte as u8,
+                _ => b'_',
+            })
+            .take(max)
+            .collect()
+    };
+    let (base, extension) = split_extension(name);
+    let (base, extension) = (convert(base, 8), convert(extension, 3));
+
+    let mut short = [b' '; 11];
+    short[8..8 + extension.len()].copy_from_slice(&extension);
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let kept = core::cmp::min(base.len(), 8 - tail.len());
+        short[..8].copy_from_slice(b"        ");
+        short[..kept].copy_from_slice(&base[..kept]);
+        short[kept..kept + tail.len()].copy_from_slice(tail.as_bytes());
+        if !taken(&short) {
+            return Ok(short);
+        }
+    }
+    ioerr!(AlreadyExists, "too many similar names in the directory")
+}
+
+/// Returns the checksum of the short name `short` that its long file name
+/// entries hold.
+fn checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte))
+}
+
+/// Returns the long file name entries of `name` for the short name `short`,
+/// in the order they are stored: the last part of the name first.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `name` is not a valid long name: it
+/// is empty, longer than 255 characters, ends with a dot or a space, or holds
+/// a character not allowed.
+pub(crate) fn long_entries(name: &str, short: &[u8; 11]) -> io::Result<Vec<[u8; 32]>> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let invalid = |c: char| c < ' ' || LONG_NAME_INVALID.contains(c);
+    if units.is_empty() || units.len() > 255 || name.ends_with('.') || name.ends_with(' ') || name.contains(invalid) {
+        return ioerr!(InvalidInput, "invalid file name");
+    }
+
+    let count = (units.len() + LFN_CHARS_PER_ENTRY - 1) / LFN_CHARS_PER_ENTRY;
+    let checksum = checksum(short);
+    Ok((1..=count).rev().map(|sequence| {
+        let mut entry = [0u8; 32];
+        entry[0] = sequence as u8 | if sequence == count { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            // The name ends with a NUL, then is padded with 0xFFFF.
+            let unit = match (sequence - 1) * LFN_CHARS_PER_ENTRY + i {
+                k if k < units.len() => units[k],
+                k if k == units.len() => 0,
+                _ => 0xFFFF,
+            };
+            entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entry
+    }).collect())
+}
+
+/// Returns a regular entry with the attributes `attributes` that starts at
+/// `first_cluster`, without a name and empty.
+pub(crate) fn regular_entry(attributes: u8, first_cluster: Cluster) -> [u8; 32] {
+    let mut entry = [0u8; 32];
+    entry[11] = attributes;
+    set_entry_cluster(&mut entry, first_cluster);
+    entry
+}
+
+/// Sets the first cluster of the regular entry `entry` to `cluster`.
+pub(crate) fn set_entry_cluster(entry: &mut [u8; 32], cluster: Cluster) {
+    let cluster = cluster.inner();
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
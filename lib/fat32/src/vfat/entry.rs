@@ -1,6 +1,6 @@
 use crate::traits;
 use alloc::string::String;
-use crate::vfat::{Dir, File, Metadata, VFatHandle, Cluster, dir::VFatDirEntry, dir::VFatUnknownDirEntry, dir::VFatRegularDirEntry};
+use crate::vfat::{Dir, File, Metadata, VFatHandle, Cluster, dir::EntryPos, dir::VFatDirEntry, dir::VFatUnknownDirEntry, dir::VFatRegularDirEntry};
 use core::fmt;
 use shim::io;
 
@@ -79,11 +79,11 @@ impl<HANDLE: VFatHandle> Entry<HANDLE> {
         }
     }
 
-    pub fn from_regular_entry(entry: VFatRegularDirEntry, handle: HANDLE, name: String) -> Self {
+    pub(crate) fn from_regular_entry(entry: VFatRegularDirEntry, handle: HANDLE, name: String, pos: EntryPos) -> Self {
         if entry.is_dir() {
-            Entry::Dir(Dir::from_regular_entry(handle, entry, name))
+            Entry::Dir(Dir::from_regular_entry(handle, entry, name, pos))
         } else {
-            Entry::File(File::from_regular_entry(handle, entry, name))
+            Entry::File(File::from_regular_entry(handle, entry, name, pos))
         }
     }
 }
@@ -5,7 +5,7 @@ use shim::ioerr;
 use shim::io::{self, SeekFrom, Seek};
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFat, VFatHandle, dir::VFatRegularDirEntry};
+use crate::vfat::{Cluster, Metadata, VFat, VFatHandle, dir::EntryPos, dir::VFatRegularDirEntry};
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -14,13 +14,13 @@ pub struct File<HANDLE: VFatHandle> {
     metadata: Metadata,
     name: String,
     cursor: u64,
+    /// Where the file's entry is, to record its size and first cluster when
+    /// it is written to.
+    pos: EntryPos,
 }
 
 
 impl<HANDLE: VFatHandle> File<HANDLE> {
-    pub fn new(vfat: HANDLE, first_cluster: Cluster, metadata: Metadata, name: String) -> Self{
-        File{vfat: vfat.clone(), first_cluster, metadata, name, cursor: 0}
-    }
 
     pub fn is_end(&self) -> bool {
         self.metadata.is_end()
@@ -34,19 +34,29 @@ impl<HANDLE: VFatHandle> File<HANDLE> {
         &self.metadata
     }
 
-    pub fn from_regular_entry(handle: HANDLE, entry: VFatRegularDirEntry, name: String) -> Self {
+    pub(crate) fn from_regular_entry(handle: HANDLE, entry: VFatRegularDirEntry, name: String, pos: EntryPos) -> Self {
         let vfat = handle.clone();
         //let first_cluster = Cluster::from(entry.get_cluster());
         let first_cluster = entry.get_cluster();
         let metadata = entry.get_metadata();
-        File{vfat, first_cluster, metadata, name, cursor: 0}
+        File{vfat, first_cluster, metadata, name, cursor: 0, pos}
+    }
+
+    pub(crate) fn first_cluster(&self) -> Cluster {
+        self.first_cluster
+    }
+
+    pub(crate) fn pos(&self) -> EntryPos {
+        self.pos
     }
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
+    /// Writes the sectors changed on the file system to the disk, along with
+    /// the file's.
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!("File::sync()")
+        self.vfat.lock(|vfat: &mut VFat<HANDLE>| vfat.flush())
     }
 
     fn size(&self) -> u64 {
@@ -89,12 +99,37 @@ impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
+    /// Writes `buf` at the cursor, overwriting the bytes there and growing the
+    /// file past its end. The writes stay in the sector cache until `flush()`
+    /// or `sync()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if there are not enough free clusters left,
+    /// and of `InvalidInput` if the file would grow past 4 GiB.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("File::write()")
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = self.cursor + buf.len() as u64;
+        if end > u32::max_value() as u64 {
+            return ioerr!(InvalidInput, "file too large for FAT32");
+        }
+
+        let size = core::cmp::max(end, self.metadata.get_file_size() as u64) as u32;
+        let (cursor, first_cluster, pos) = (self.cursor, self.first_cluster, self.pos);
+        self.first_cluster = self.vfat.lock(|vfat: &mut VFat<HANDLE>| -> io::Result<Cluster> {
+            let first_cluster = vfat.write_chain(first_cluster, cursor, buf)?;
+            vfat.update_entry(pos, first_cluster, size)?;
+            Ok(first_cluster)
+        })?;
+        self.metadata.set_file_size(size);
+        self.cursor = end;
+        Ok(buf.len())
     }
-    
+
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!("File::flush()")
+        traits::File::sync(self)
     }
 }
 
@@ -115,7 +150,7 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
         let position = match _pos {
             SeekFrom::Start(p) => p as i64,
-            SeekFrom::End(p) => self.metadata.get_file_size() as i64 - 1 + p,
+            SeekFrom::End(p) => self.metadata.get_file_size() as i64 + p,
             SeekFrom::Current(p) => self.cursor as i64 + p
         };
 
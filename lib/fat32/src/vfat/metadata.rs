@@ -57,6 +57,10 @@ impl Attributes {
         self.0
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
     pub fn is_hidden(&self) -> bool {
         self.0 & 0x02 != 0
         //self.0 == 0x02
@@ -252,6 +256,10 @@ impl Metadata {
     pub fn get_file_size(&self) -> u32 {
         self.file_size
     }
+
+    pub(crate) fn set_file_size(&mut self, file_size: u32) {
+        self.file_size = file_size;
+    }
 }
 
 /// Gets the value at bit range starting at `start` and ending at `end` (both indices are inclusive)
@@ -296,7 +304,7 @@ impl traits::Metadata for Metadata {
     type Timestamp = Timestamp;
 
     fn read_only(&self) -> bool {
-        self.attribute.0 == 0x01
+        self.attribute.is_read_only()
     }
 
     fn hidden(&self) -> bool {
@@ -304,6 +312,14 @@ impl traits::Metadata for Metadata {
         self.attribute.is_hidden()
     }
 
+    fn system(&self) -> bool {
+        self.attribute.is_system()
+    }
+
+    fn archive(&self) -> bool {
+        self.attribute.is_archive()
+    }
+
     fn created(&self) -> Self::Timestamp {
         self.creation_time
     }
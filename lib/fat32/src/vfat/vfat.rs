@@ -13,10 +13,11 @@ use shim::path::Component;
 use shim::path::Path;
 
 use crate::mbr::{MasterBootRecord, PartitionEntry};
-use crate::traits::{BlockDevice, FileSystem};
+use crate::traits::{BlockDevice, FileSystem, Usage};
 use crate::util::{SliceExt, VecExt};
 use crate::vfat::{BiosParameterBlock, CachedPartition, Partition};
 use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status, Metadata};
+use crate::vfat::dir::{self, EntryPos};
 
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
@@ -31,9 +32,14 @@ pub struct VFat<HANDLE: VFatHandle> {
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    num_fats: u8,
     fat_start_sector: u64,
     data_start_sector: u64,
+    /// The number of data clusters, numbered from 2.
+    num_clusters: u32,
     rootdir_cluster: Cluster,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
@@ -71,9 +77,12 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         let ebpb = ebpb_option.expect("ebpb unwrap failed");
         //dbg!(ebpb);
         let partition = Partition{start: first_sector as u64, num_sectors: ebpb.total_logical_sectors(), sector_size: ebpb.bytes_per_sector as u64};
+        let data_start_sector = ebpb.num_reserved_sectors as u64 + ebpb.num_fats as u64 * ebpb.sectors_per_fat as u64;
+        let num_clusters = (ebpb.total_logical_sectors().saturating_sub(data_start_sector) / ebpb.sectors_per_cluster as u64) as u32;
         let vfat = VFat{phantom: PhantomData, device: CachedPartition::new(device, partition), bytes_per_sector: ebpb.bytes_per_sector,
-                        sectors_per_cluster: ebpb.sectors_per_cluster, sectors_per_fat: ebpb.sectors_per_fat,
-                        fat_start_sector: ebpb.num_reserved_sectors as u64, data_start_sector: ebpb.num_reserved_sectors as u64 + ebpb.num_fats as u64 * ebpb.sectors_per_fat as u64, rootdir_cluster: Cluster::from(ebpb.rootdir_cluster as u32)};
+                        sectors_per_cluster: ebpb.sectors_per_cluster, sectors_per_fat: ebpb.sectors_per_fat, num_fats: ebpb.num_fats,
+                        fat_start_sector: ebpb.num_reserved_sectors as u64, data_start_sector, num_clusters, rootdir_cluster: Cluster::from(ebpb.rootdir_cluster as u32),
+                        next_free: 2};
         Ok(HANDLE::new(vfat))
     }
 
@@ -155,6 +164,292 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     pub fn bytes_per_cluster(&self) -> u64 {
         self.bytes_per_sector as u64 * self.sectors_per_cluster as u64
     }
+
+    /// Returns the number of data clusters the FAT marks as free.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        let mut free = 0;
+        for i in 2..self.num_clusters + 2 {
+            if self.fat_entry(Cluster::from(i))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    /// Writes the sectors changed since the last flush to the disk.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+
+    /// Returns the clusters of the chain that starts at `start`.
+    fn chain(&mut self, start: Cluster) -> io::Result<Vec<Cluster>> {
+        let mut chain = vec![start];
+        loop {
+            match self.fat_entry(*chain.last().unwrap())?.status() {
+                Status::Eoc(_) => return Ok(chain),
+                Status::Data(next) if chain.len() <= self.num_clusters as usize => chain.push(next),
+                Status::Data(_) => return ioerr!(InvalidData, "cluster chain loops"),
+                _ => return ioerr!(InvalidData, "cluster chain is broken"),
+            }
+        }
+    }
+
+    /// Sets the entry of `cluster` to `value` in every copy of the FAT.
+    fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let entries_per_sector = self.bytes_per_sector as u32 / 4;
+        let offset = (cluster.inner() % entries_per_sector) as usize * 4;
+        for fat in 0..self.num_fats as u64 {
+            let sector = self.fat_start_sector + fat * self.sectors_per_fat as u64
+                + (cluster.inner() / entries_per_sector) as u64;
+            let buf = self.device.get_mut(sector)?;
+            let old = u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]);
+            // The high 4 bits are reserved and kept.
+            let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            buf[offset..offset + 4].copy_from_slice(&new.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Allocates a free cluster, zeroed, as the end of a chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if no cluster is free.
+    fn alloc_cluster(&mut self) -> io::Result<Cluster> {
+        for i in 0..self.num_clusters {
+            let cluster = Cluster::from((self.next_free - 2 + i) % self.num_clusters + 2);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                self.set_fat_entry(cluster, 0x0FFF_FFFF)?;
+                let zeros = vec![0u8; self.bytes_per_cluster() as usize];
+                self.write_cluster(cluster, 0, &zeros)?;
+                self.next_free = cluster.inner();
+                return Ok(cluster);
+            }
+        }
+        ioerr!(Other, "no free space on the file system")
+    }
+
+    /// Allocates `count` clusters and chains them after `last`, the end of a
+    /// chain, if it is given. Returns the new clusters. If not all of them
+    /// could be allocated, none are.
+    fn extend_chain(&mut self, last: Option<Cluster>, count: usize) -> io::Result<Vec<Cluster>> {
+        let mut new = Vec::new();
+        while new.len() < count {
+            match self.alloc_cluster() {
+                Ok(cluster) => {
+                    if let Some(&prev) = new.last().or(last.as_ref()) {
+                        self.set_fat_entry(prev, cluster.inner())?;
+                    }
+                    new.push(cluster);
+                }
+                Err(e) => {
+                    if let Some(last) = last {
+                        self.set_fat_entry(last, 0x0FFF_FFFF)?;
+                    }
+                    for cluster in new {
+                        self.set_fat_entry(cluster, 0)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(new)
+    }
+
+    /// Marks the clusters of the chain that starts at `start` as free.
+    pub(crate) fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        for cluster in self.chain(start)? {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` to `cluster`, starting `offset` bytes in, as far as the
+    /// cluster goes. Returns the number of bytes written.
+    fn write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let first_sector = cluster.get_start_sector(self.sectors_per_cluster as u64, self.data_start_sector);
+        let end = core::cmp::min(self.bytes_per_cluster() as usize, offset + buf.len());
+        let mut at = offset;
+        while at < end {
+            let sector = self.device.get_mut(first_sector + (at / bytes_per_sector) as u64)?;
+            let start = at % bytes_per_sector;
+            let n = core::cmp::min(bytes_per_sector - start, end - at);
+            sector[start..start + n].copy_from_slice(&buf[at - offset..at - offset + n]);
+            at += n;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+
+    /// Writes `buf` at byte `offset` of the chain that starts at `start`,
+    /// growing the chain as needed. An empty chain starts at cluster 0.
+    /// Returns the start of the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if there are not enough free clusters for
+    /// the chain to grow; nothing is written then.
+    pub(crate) fn write_chain(&mut self, start: Cluster, offset: u64, buf: &[u8]) -> io::Result<Cluster> {
+        if buf.is_empty() {
+            return Ok(start);
+        }
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let needed = ((offset + buf.len() as u64 + bytes_per_cluster - 1) / bytes_per_cluster) as usize;
+        let mut chain = match start.inner() {
+            0 => Vec::new(),
+            _ => self.chain(start)?,
+        };
+        if chain.len() < needed {
+            let new = self.extend_chain(chain.last().cloned(), needed - chain.len())?;
+            chain.extend(new);
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let at = offset + written as u64;
+            let cluster = chain[(at / bytes_per_cluster) as usize];
+            written += self.write_cluster(cluster, (at % bytes_per_cluster) as usize, &buf[written..])?;
+        }
+        Ok(chain[0])
+    }
+
+    /// Returns the 32-byte entries of the directory that starts at `dir`.
+    fn dir_entries(&mut self, dir: Cluster) -> io::Result<Vec<[u8; 32]>> {
+        let mut data = Vec::new();
+        self.read_chain(dir, &mut data)?;
+        Ok(data.chunks(32).map(|chunk| {
+            let mut entry = [0u8; 32];
+            entry.copy_from_slice(chunk);
+            entry
+        }).collect())
+    }
+
+    /// Returns the regular entry at `pos`.
+    pub(crate) fn read_entry(&mut self, pos: EntryPos) -> io::Result<[u8; 32]> {
+        match self.dir_entries(pos.dir)?.get(pos.last) {
+            Some(entry) => Ok(*entry),
+            None => ioerr!(NotFound, "directory entry is gone"),
+        }
+    }
+
+    /// Records `first_cluster` and `size` in the regular entry at `pos`.
+    pub(crate) fn update_entry(&mut self, pos: EntryPos, first_cluster: Cluster, size: u32) -> io::Result<()> {
+        let mut entry = self.read_entry(pos)?;
+        dir::set_entry_cluster(&mut entry, first_cluster);
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        self.write_chain(pos.dir, pos.last as u64 * 32, &entry)?;
+        Ok(())
+    }
+
+    /// Adds an entry named `name` to the directory that starts at `dir`, with
+    /// the attributes, first cluster, size and timestamps of the regular entry
+    /// `regular`. A long file name is added if `name` is not a short one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `name` is not a valid file name.
+    pub(crate) fn add_entry(&mut self, dir: Cluster, name: &str, mut regular: [u8; 32]) -> io::Result<()> {
+        let entries = self.dir_entries(dir)?;
+        let end = entries.iter().position(|entry| entry[0] == 0).unwrap_or(entries.len());
+        let taken: Vec<&[u8]> = entries[..end].iter()
+            .filter(|entry| entry[0] != 0xE5 && entry[11] != 0x0F)
+            .map(|entry| &entry[..11])
+            .collect();
+
+        let mut new = Vec::new();
+        let short = match dir::short_name(name) {
+            Some(short) => short,
+            None => {
+                let short = dir::alias(name, |short| taken.contains(&&short[..]))?;
+                new.extend(dir::long_entries(name, &short)?);
+                short
+            }
+        };
+        regular[..11].copy_from_slice(&short);
+        new.push(regular);
+
+        // The first run of free entries long enough, which may go past the
+        // end of the directory.
+        let mut index = end;
+        let mut run = 0;
+        for (i, entry) in entries[..end].iter().enumerate() {
+            run = if entry[0] == 0xE5 { run + 1 } else { 0 };
+            if run == new.len() {
+                index = i + 1 - run;
+                break;
+            }
+        }
+        if index == end {
+            index -= entries[..end].iter().rev().take_while(|entry| entry[0] == 0xE5).count();
+        }
+
+        for (i, entry) in new.iter().enumerate() {
+            self.write_chain(dir, ((index + i) * 32) as u64, entry)?;
+        }
+        // Entries past the end marker may hold anything; the directory must
+        // still end after the new entries.
+        let after = index + new.len();
+        if after > end && entries.get(after).map_or(false, |entry| entry[0] != 0) {
+            self.write_chain(dir, (after * 32) as u64, &[0u8; 32])?;
+        }
+        Ok(())
+    }
+
+    /// Marks the entries at `pos` as deleted.
+    pub(crate) fn delete_entry(&mut self, pos: EntryPos) -> io::Result<()> {
+        for i in pos.first..=pos.last {
+            self.write_chain(pos.dir, (i * 32) as u64, &[0xE5])?;
+        }
+        Ok(())
+    }
+}
+
+/// The attribute of files changed since they were last backed up.
+const ARCHIVE: u8 = 0x20;
+/// The attribute of directories.
+const DIRECTORY: u8 = 0x10;
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Writes the `.` and `..` entries of the directory that starts at `dir`,
+    /// in the directory that starts at `parent`.
+    fn write_dots(&mut self, dir: Cluster, parent: Cluster) -> io::Result<()> {
+        // The root directory is referred to as cluster 0.
+        let parent = if parent == self.rootdir_cluster { Cluster::from(0) } else { parent };
+        for (i, (name, cluster)) in [(&b"."[..], dir), (&b".."[..], parent)].iter().enumerate() {
+            let mut entry = match self.dir_entries(dir)?.get(i) {
+                Some(entry) if entry[0] == b'.' => *entry,
+                _ => dir::regular_entry(DIRECTORY, *cluster),
+            };
+            entry[..11].copy_from_slice(b"           ");
+            entry[..name.len()].copy_from_slice(name);
+            dir::set_entry_cluster(&mut entry, *cluster);
+            self.write_chain(dir, (i * 32) as u64, &entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the directory `path` is in and the name `path` ends with, for an
+/// entry to be made at `path`.
+///
+/// # Errors
+///
+/// Returns an error of `AlreadyExists` if there is an entry at `path`, and
+/// the errors of `open()` for the parent of `path`.
+fn new_entry_in<'p, HANDLE: VFatHandle>(vfat: &HANDLE, path: &'p Path) -> io::Result<(Dir<HANDLE>, &'p str)> {
+    let (parent, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return ioerr!(InvalidInput, "path has no file name"),
+    };
+    let parent = match vfat.open(parent)? {
+        Entry::Dir(d) => d,
+        Entry::File(_) => return ioerr!(InvalidInput, "not a directory"),
+    };
+    match parent.find(name) {
+        Ok(_) => ioerr!(AlreadyExists, "entry already exists"),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok((parent, name)),
+        Err(e) => Err(e),
+    }
 }
 
 impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
@@ -205,4 +500,83 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
         }
         return Ok(stack.pop().expect("stack is empty"));
     }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = new_entry_in(self, path.as_ref())?;
+        self.lock(|fat: &mut VFat<HANDLE>| -> io::Result<()> {
+            fat.add_entry(parent.first_cluster(), name, dir::regular_entry(ARCHIVE, Cluster::from(0)))
+        })?;
+        self.open_file(path)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = new_entry_in(self, path.as_ref())?;
+        self.lock(|fat: &mut VFat<HANDLE>| -> io::Result<()> {
+            let cluster = fat.extend_chain(None, 1)?[0];
+            let result = fat.add_entry(parent.first_cluster(), name, dir::regular_entry(DIRECTORY, cluster))
+                .and_then(|_| fat.write_dots(cluster, parent.first_cluster()));
+            if result.is_err() {
+                fat.free_chain(cluster)?;
+            }
+            result
+        })?;
+        self.open_dir(path)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (pos, first_cluster) = match self.open(path)? {
+            Entry::File(file) => (file.pos(), file.first_cluster()),
+            Entry::Dir(d) => {
+                use crate::traits::{Dir as _, Entry as _};
+                if d.entries()?.any(|entry| entry.name() != "." && entry.name() != "..") {
+                    return ioerr!(Other, "directory not empty");
+                }
+                match d.pos() {
+                    Some(pos) => (pos, d.first_cluster()),
+                    None => return ioerr!(InvalidInput, "cannot remove the root directory"),
+                }
+            }
+        };
+        self.lock(|fat: &mut VFat<HANDLE>| -> io::Result<()> {
+            fat.delete_entry(pos)?;
+            if first_cluster.inner() != 0 {
+                fat.free_chain(first_cluster)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (pos, moved_dir) = match self.open(from)? {
+            Entry::File(file) => (file.pos(), None),
+            Entry::Dir(d) => match d.pos() {
+                Some(pos) => (pos, Some(d.first_cluster())),
+                None => return ioerr!(InvalidInput, "cannot move the root directory"),
+            },
+        };
+        if moved_dir.is_some() && to.starts_with(from) {
+            return ioerr!(InvalidInput, "cannot move a directory into itself");
+        }
+        let (parent, name) = new_entry_in(self, to)?;
+        self.lock(|fat: &mut VFat<HANDLE>| -> io::Result<()> {
+            let regular = fat.read_entry(pos)?;
+            fat.add_entry(parent.first_cluster(), name, regular)?;
+            fat.delete_entry(pos)?;
+            if let Some(cluster) = moved_dir {
+                fat.write_dots(cluster, parent.first_cluster())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn usage(self) -> io::Result<Usage> {
+        self.lock(|fat: &mut VFat<HANDLE>| -> io::Result<Usage> {
+            Ok(Usage {
+                block_size: fat.bytes_per_cluster(),
+                total_blocks: fat.num_clusters as u64,
+                free_blocks: fat.free_clusters()? as u64,
+            })
+        })
+    }
 }
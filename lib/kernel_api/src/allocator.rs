@@ -0,0 +1,120 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::cmp::max;
+use core::ptr;
+
+use crate::syscall::{mmap, munmap, sbrk};
+
+/// log2 of the smallest block handed out by the allocator (8 bytes).
+const MIN_SHIFT: usize = 3;
+
+/// Number of size classes served from the heap: 2^3 up to 2^15 bytes.
+const BINS: usize = 13;
+
+/// Blocks larger than this are mapped with `mmap` and returned with `munmap`.
+const MAX_BIN_SIZE: usize = 1 << (MIN_SHIFT + BINS - 1);
+
+/// The largest alignment `mmap` guarantees (the kernel's 64KiB page size).
+const MMAP_ALIGN: usize = 64 * 1024;
+
+/// Free lists of power-of-two sized blocks, one per size class. Each free block
+/// stores the address of the next free block of its class.
+struct Bins {
+    free: [*mut usize; BINS],
+}
+
+/// A memory allocator for user programs.
+///
+/// Small requests are rounded up to a power of two and served from size-class
+/// free lists that are refilled by growing the heap with `sbrk`. Requests
+/// larger than 32KiB get their own anonymous mapping from `mmap`.
+///
+/// User processes are single threaded, so the allocator does no locking.
+///
+/// # Usage
+///
+/// ```rust,ignore
+/// #![feature(alloc_error_handler)]
+/// extern crate alloc;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: kernel_api::allocator::Allocator = kernel_api::allocator::Allocator::new();
+///
+/// #[alloc_error_handler]
+/// fn oom(_layout: core::alloc::Layout) -> ! {
+///     kernel_api::syscall::exit(1)
+/// }
+/// ```
+pub struct Allocator(UnsafeCell<Bins>);
+
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    /// Returns an allocator with empty free lists.
+    pub const fn new() -> Allocator {
+        Allocator(UnsafeCell::new(Bins { free: [ptr::null_mut(); BINS] }))
+    }
+}
+
+/// Returns the block size used for `layout`: its size and alignment rounded up
+/// to a power of two of at least 8 bytes.
+fn block_size(layout: Layout) -> usize {
+    max(max(layout.size(), layout.align()), 1 << MIN_SHIFT).next_power_of_two()
+}
+
+/// Returns the size class serving blocks of `size` bytes.
+fn bin_index(size: usize) -> usize {
+    size.trailing_zeros() as usize - MIN_SHIFT
+}
+
+impl Bins {
+    /// Carves a new block of `size` bytes, aligned to `size`, out of the heap.
+    unsafe fn grow(&mut self, size: usize) -> *mut u8 {
+        let brk = match sbrk(0) {
+            Ok(brk) => brk as usize,
+            Err(_) => return ptr::null_mut(),
+        };
+
+        let padding = (size - brk % size) % size;
+        match sbrk((padding + size) as isize) {
+            Ok(_) => (brk + padding) as *mut u8,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout);
+        if size > MAX_BIN_SIZE {
+            if layout.align() > MMAP_ALIGN {
+                return ptr::null_mut();
+            }
+            return mmap(layout.size()).unwrap_or(ptr::null_mut());
+        }
+
+        let bins = &mut *self.0.get();
+        let bin = bin_index(size);
+        let head = bins.free[bin];
+        if head.is_null() {
+            return bins.grow(size);
+        }
+
+        bins.free[bin] = *head as *mut usize;
+        head as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(layout);
+        if size > MAX_BIN_SIZE {
+            let _ = munmap(ptr, layout.size());
+            return;
+        }
+
+        let bins = &mut *self.0.get();
+        let bin = bin_index(size);
+        let block = ptr as *mut usize;
+        *block = bins.free[bin] as usize;
+        bins.free[bin] = block;
+    }
+}
@@ -1,4 +1,4 @@
-#![feature(asm)]
+#![feature(asm, global_asm)]
 #![no_std]
 
 use core::fmt;
@@ -7,6 +7,8 @@ use shim::io;
 
 #[cfg(feature = "user-space")]
 pub mod syscall;
+#[cfg(feature = "user-space")]
+pub mod allocator;
 
 pub type OsResult<T> = core::result::Result<T, OsError>;
 
@@ -28,6 +30,7 @@ pub enum OsError {
     IoErrorInvalidData = 103,
     IoErrorInvalidInput = 104,
     IoErrorTimedOut = 105,
+    IoErrorBrokenPipe = 106,
 
     InvalidSocket = 200,
     SocketAlreadyOpen = 201,
@@ -51,6 +54,7 @@ impl core::convert::From<u64> for OsError {
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            106 => OsError::IoErrorBrokenPipe,
 
             200 => OsError::InvalidSocket,
             201 => OsError::SocketAlreadyOpen,
@@ -68,6 +72,7 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidData => OsError::IoErrorInvalidData,
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
+            io::ErrorKind::BrokenPipe => OsError::IoErrorBrokenPipe,
             io::ErrorKind::NotFound => OsError::NoEntry,
             _ => OsError::IoError,
         }
@@ -79,3 +84,88 @@ pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
+pub const NR_FORK: usize = 6;
+pub const NR_EXEC: usize = 7;
+pub const NR_WAITPID: usize = 8;
+pub const NR_BRK: usize = 9;
+pub const NR_SBRK: usize = 10;
+pub const NR_MMAP: usize = 11;
+pub const NR_MUNMAP: usize = 12;
+pub const NR_READ: usize = 13;
+pub const NR_WRITE_FD: usize = 14;
+pub const NR_CLOSE: usize = 15;
+pub const NR_PIPE: usize = 16;
+pub const NR_DUP2: usize = 17;
+pub const NR_KILL: usize = 18;
+pub const NR_SIGACTION: usize = 19;
+pub const NR_SIGRETURN: usize = 20;
+pub const NR_NICE: usize = 21;
+pub const NR_SETPRIORITY: usize = 22;
+pub const NR_IOCTL: usize = 23;
+
+/// Descriptor of a process's standard input.
+pub const STDIN: u64 = 0;
+/// Descriptor of a process's standard output.
+pub const STDOUT: u64 = 1;
+/// Descriptor of a process's standard error.
+pub const STDERR: u64 = 2;
+
+/// Process ID passed to `waitpid` to wait for any child.
+pub const WAIT_ANY: u64 = core::u64::MAX;
+
+/// The number of signals, including the unused signal number `0`.
+pub const NSIG: u64 = 32;
+
+pub const SIGHUP: u64 = 1;
+pub const SIGINT: u64 = 2;
+pub const SIGQUIT: u64 = 3;
+pub const SIGILL: u64 = 4;
+pub const SIGTRAP: u64 = 5;
+pub const SIGABRT: u64 = 6;
+pub const SIGBUS: u64 = 7;
+pub const SIGFPE: u64 = 8;
+pub const SIGKILL: u64 = 9;
+pub const SIGUSR1: u64 = 10;
+pub const SIGSEGV: u64 = 11;
+pub const SIGUSR2: u64 = 12;
+pub const SIGPIPE: u64 = 13;
+pub const SIGALRM: u64 = 14;
+pub const SIGTERM: u64 = 15;
+pub const SIGCHLD: u64 = 17;
+pub const SIGCONT: u64 = 18;
+pub const SIGSTOP: u64 = 19;
+pub const SIGTSTP: u64 = 20;
+
+/// Handler value selecting a signal's default action.
+pub const SIG_DFL: u64 = 0;
+/// Handler value ignoring a signal.
+pub const SIG_IGN: u64 = 1;
+
+/// The niceness of the most favoured processes.
+pub const MIN_NICE: i64 = -20;
+/// The niceness of the least favoured processes.
+pub const MAX_NICE: i64 = 19;
+
+/// `ioctl` request returning the mode flags of the terminal behind a console
+/// descriptor.
+pub const TCGETS: u64 = 1;
+/// `ioctl` request replacing the mode flags of the terminal behind a console
+/// descriptor with its argument.
+pub const TCSETS: u64 = 2;
+
+/// Terminal mode flag: input is line-buffered and can be edited with erase
+/// (backspace) and kill (Ctrl-U) until the line is ended; Ctrl-D ends input.
+/// Without it, every byte is available to readers as soon as it is typed.
+pub const TTY_ICANON: u64 = 1 << 0;
+/// Terminal mode flag: typed bytes are echoed back.
+pub const TTY_ECHO: u64 = 1 << 1;
+/// Terminal mode flag: Ctrl-C, Ctrl-\ and Ctrl-Z send `SIGINT`, `SIGQUIT`
+/// and `SIGTSTP` to the foreground process instead of being read.
+pub const TTY_ISIG: u64 = 1 << 2;
+/// Terminal mode flag: a typed carriage return is read as a newline.
+pub const TTY_ICRNL: u64 = 1 << 3;
+/// Terminal mode flag: a written newline is output as carriage return and
+/// newline.
+pub const TTY_ONLCR: u64 = 1 << 4;
+/// The mode flags of a terminal that has not been configured.
+pub const TTY_DEFAULT: u64 = TTY_ICANON | TTY_ECHO | TTY_ISIG | TTY_ICRNL | TTY_ONLCR;
@@ -57,15 +57,16 @@ pub fn time() -> Duration {
     Duration::from_secs(secs) + Duration::from_nanos(nanos)
 }
 
-pub fn exit() -> ! {
+pub fn exit(status: i32) -> ! {
     let mut ecode: u64;
 
     unsafe {
-        asm!("svc $1
+        asm!("mov x0, $1
+              svc $2
               mov $0, x7"
               :"=r"(ecode)
-              : "i"(NR_EXIT)
-              : "x7"
+              : "r"(status as u64), "i"(NR_EXIT)
+              : "x0", "x7"
               : "volatile");
     }
 
@@ -104,15 +105,393 @@ pub fn getpid() -> u64 {
     pid
 }
 
+pub fn fork() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pid: u64;
+
+    unsafe {
+        asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(pid), "=r"(ecode)
+              : "i"(NR_FORK)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, pid)
+}
+
+/// Replaces the current program with the one at `path`, passing it `args` and
+/// `env`. Only returns if the program could not be started.
+pub fn exec(path: &str, args: &[&str], env: &[&str]) -> OsError {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              mov x4, $5
+              mov x5, $6
+              svc $7
+              mov $0, x7"
+              : "=r"(ecode)
+              : "r"(path.as_ptr()), "r"(path.len()),
+                "r"(args.as_ptr()), "r"(args.len()),
+                "r"(env.as_ptr()), "r"(env.len()),
+                "i"(NR_EXEC)
+              : "x0", "x1", "x2", "x3", "x4", "x5", "x7"
+              : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
+/// Waits for the child `pid` to exit, or any child if `pid` is `WAIT_ANY`.
+/// Returns the collected child's ID and exit status.
+pub fn waitpid(pid: u64) -> OsResult<(u64, i32)> {
+    let mut ecode: u64;
+    let mut child: u64;
+    let mut status: u64;
+
+    unsafe {
+        asm!("mov x0, $3
+              svc $4
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+              : "=r"(child), "=r"(status), "=r"(ecode)
+              : "r"(pid), "i"(NR_WAITPID)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, (child, status as i32))
+}
+
+/// Waits for any child to exit. Returns its ID and exit status.
+pub fn wait() -> OsResult<(u64, i32)> {
+    waitpid(WAIT_ANY)
+}
+
+/// Sets the program break to `addr` and returns the resulting break. Passing
+/// a null pointer returns the current break.
+pub fn brk(addr: *mut u8) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut new: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(new), "=r"(ecode)
+              : "r"(addr as u64), "i"(NR_BRK)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, new as *mut u8)
+}
+
+/// Moves the program break by `increment` bytes and returns the previous
+/// break.
+pub fn sbrk(increment: isize) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut old: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(old), "=r"(ecode)
+              : "r"(increment as u64), "i"(NR_SBRK)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, old as *mut u8)
+}
+
+/// Maps `len` bytes of zeroed anonymous memory and returns its address.
+pub fn mmap(len: usize) -> OsResult<*mut u8> {
+    let mut ecode: u64;
+    let mut addr: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(addr), "=r"(ecode)
+              : "r"(len as u64), "i"(NR_MMAP)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, addr as *mut u8)
+}
+
+/// Unmaps `len` bytes at `addr` previously mapped with `mmap`.
+pub fn munmap(addr: *mut u8, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+              : "=r"(ecode)
+              : "r"(addr as u64), "r"(len as u64), "i"(NR_MUNMAP)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Reads from the descriptor `fd` into `buf`, blocking until data is
+/// available. Returns the number of bytes read, which is `0` at end of file.
+pub fn read(fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut n: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(n), "=r"(ecode)
+              : "r"(fd), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READ)
+              : "x0", "x1", "x2", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, n as usize)
+}
+
+/// Writes `buf` to the descriptor `fd`, blocking until at least part of it
+/// can be written. Returns the number of bytes written.
+pub fn write_fd(fd: u64, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut n: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(n), "=r"(ecode)
+              : "r"(fd), "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_WRITE_FD)
+              : "x0", "x1", "x2", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, n as usize)
+}
+
+/// Writes all of `buf` to the descriptor `fd`.
+///
+/// Returns `IoError` if a write accepts no bytes, which would otherwise be
+/// retried forever.
+pub fn write_all(fd: u64, mut buf: &[u8]) -> OsResult<()> {
+    while !buf.is_empty() {
+        let n = write_fd(fd, buf)?;
+        if n == 0 {
+            return Err(OsError::IoError);
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// Closes the descriptor `fd`.
+pub fn close(fd: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+              : "=r"(ecode)
+              : "r"(fd), "i"(NR_CLOSE)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Creates a pipe and returns the descriptors of its read and write ends.
+pub fn pipe() -> OsResult<(u64, u64)> {
+    let mut ecode: u64;
+    let mut read_fd: u64;
+    let mut write_fd: u64;
+
+    unsafe {
+        asm!("svc $3
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+              : "=r"(read_fd), "=r"(write_fd), "=r"(ecode)
+              : "i"(NR_PIPE)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, (read_fd, write_fd))
+}
+
+/// Makes the descriptor `new` refer to the same object as `old`, closing
+/// `new` first if it is open. Returns `new`.
+pub fn dup2(old: u64, new: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut fd: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(fd), "=r"(ecode)
+              : "r"(old), "r"(new), "i"(NR_DUP2)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, fd)
+}
+
+/// Sends the signal `sig` to the process `pid`.
+pub fn kill(pid: u64, sig: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+              : "=r"(ecode)
+              : "r"(pid), "r"(sig), "i"(NR_KILL)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+// Signal handlers return here; the stack pointer is back at the signal frame
+// the kernel pushed, which `sigreturn` (system call 20) restores.
+global_asm!("
+.global __sigreturn_trampoline
+__sigreturn_trampoline:
+    svc 20
+");
+
+extern "C" {
+    fn __sigreturn_trampoline();
+}
+
+/// Sets the handler of the signal `sig` and returns the previous one.
+///
+/// `handler` is `SIG_DFL`, `SIG_IGN` or the address of an
+/// `extern "C" fn(sig: u64)`, which runs on the current stack with `sig`
+/// blocked until it returns.
+pub fn sigaction(sig: u64, handler: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut old: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(old), "=r"(ecode)
+              : "r"(sig), "r"(handler), "r"(__sigreturn_trampoline as u64),
+                "i"(NR_SIGACTION)
+              : "x0", "x1", "x2", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, old)
+}
+
+/// Adds `increment` to the niceness of the current process and returns the
+/// new niceness. A higher niceness means a lower priority.
+pub fn nice(increment: i64) -> OsResult<i64> {
+    let mut ecode: u64;
+    let mut nice: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(nice), "=r"(ecode)
+              : "r"(increment as u64), "i"(NR_NICE)
+              : "x0", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, nice as i64)
+}
+
+/// Sets the niceness of the process `pid` to `nice`, from `MIN_NICE` (highest
+/// priority) to `MAX_NICE` (lowest priority).
+pub fn setpriority(pid: u64, nice: i64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+              : "=r"(ecode)
+              : "r"(pid), "r"(nice as u64), "i"(NR_SETPRIORITY)
+              : "x0", "x1", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Performs the device-specific `request` with argument `arg` on the
+/// descriptor `fd`, such as `TCGETS` or `TCSETS` on a console descriptor.
+/// Returns the request's result.
+pub fn ioctl(fd: u64, request: u64, arg: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut result: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+              : "=r"(result), "=r"(ecode)
+              : "r"(fd), "r"(request), "r"(arg), "i"(NR_IOCTL)
+              : "x0", "x1", "x2", "x7"
+              : "volatile");
+    }
+
+    err_or!(ecode, result)
+}
 
 struct Console;
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for b in s.bytes() {
-            write(b);
-        }
-        Ok(())
+        write_all(STDOUT, s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
 
@@ -132,5 +511,5 @@ macro_rules! println {
 
 pub fn vprint(args: fmt::Arguments) {
     let mut c = Console;
-    c.write_fmt(args).unwrap();
+    let _ = c.write_fmt(args);
 }
@@ -1,6 +1,9 @@
 /// The address where I/O peripherals are mapped to.
 pub const IO_BASE: usize = 0x3F000000;
-pub const IO_BASE_END: usize = 0x40000000;
+pub const IO_BASE_END: usize = 0x40000000 + 0x10000;
+
+/// The address where the per-core (QA7) peripherals are mapped to.
+pub const LOCAL_IO_BASE: usize = 0x40000000;
 
 /// The base address of the `GPIO` registers
 pub const GPIO_BASE: usize = IO_BASE + 0x200000;
@@ -10,6 +10,7 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
@@ -18,11 +19,11 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 9;
 
     pub fn iter() -> core::slice::Iter<'static, Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart].into_iter()
+        [Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Uart].into_iter()
     }
 
     pub fn to_index(i: Interrupt) -> usize {
@@ -31,11 +32,12 @@ impl Interrupt {
             Timer1 => 0,
             Timer3 => 1,
             Usb => 2,
-            Gpio0 => 3,
-            Gpio1 => 4,
-            Gpio2 => 5,
-            Gpio3 => 6,
-            Uart => 7,
+            Aux => 3,
+            Gpio0 => 4,
+            Gpio1 => 5,
+            Gpio2 => 6,
+            Gpio3 => 7,
+            Uart => 8,
         }
     }
 
@@ -45,11 +47,12 @@ impl Interrupt {
             0 => Timer1,
             1 => Timer3,
             2 => Usb,
-            3 => Gpio0,
-            4 => Gpio1,
-            5 => Gpio2,
-            6 => Gpio3,
-            7 => Uart,
+            3 => Aux,
+            4 => Gpio0,
+            5 => Gpio1,
+            6 => Gpio2,
+            7 => Gpio3,
+            8 => Uart,
             _ => panic!("Unknown interrupt: {}", i),
         }
     }
@@ -63,6 +66,7 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
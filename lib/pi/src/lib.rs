@@ -9,5 +9,6 @@ pub mod atags;
 pub mod common;
 pub mod gpio;
 pub mod interrupt;
+pub mod local_interrupt;
 pub mod timer;
 pub mod uart;
@@ -0,0 +1,118 @@
+use crate::common::LOCAL_IO_BASE;
+use core::time::Duration;
+
+use aarch64::*;
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+/// The base address of the per-core interrupt registers (QA7 rev3.4).
+const LOCAL_INT_BASE: usize = LOCAL_IO_BASE;
+
+/// Interrupt sources of a single core, numbered by their bit in the core's
+/// IRQ source register.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalInterrupt {
+    CntPsIrq = 0,
+    CntPnsIrq = 1,
+    CntHpIrq = 2,
+    CntVIrq = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    Gpu = 8,
+    Pmu = 9,
+    AxiOutstanding = 10,
+    LocalTimer = 11,
+}
+
+impl LocalInterrupt {
+    pub const MAX: usize = 12;
+
+    pub fn iter() -> core::slice::Iter<'static, LocalInterrupt> {
+        use LocalInterrupt::*;
+        [
+            CntPsIrq, CntPnsIrq, CntHpIrq, CntVIrq, Mailbox0, Mailbox1, Mailbox2, Mailbox3, Gpu,
+            Pmu, AxiOutstanding, LocalTimer,
+        ]
+        .into_iter()
+    }
+
+    pub fn to_index(i: LocalInterrupt) -> usize {
+        i as usize
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    _unused0: Reserved<u32>,
+    CORE_TIMER_PRESCALER: Volatile<u32>,
+    GPU_INT_ROUTING: Volatile<u32>,
+    PMU_INT_ROUTING_SET: Volatile<u32>,
+    PMU_INT_ROUTING_CLR: Volatile<u32>,
+    _unused1: Reserved<u32>,
+    CORE_TIMER_LS: Volatile<u32>,
+    CORE_TIMER_MS: Volatile<u32>,
+    LOCAL_INT_ROUTING: Volatile<u32>,
+    _unused2: [Reserved<u32>; 3],
+    LOCAL_TIMER_CONTROL: Volatile<u32>,
+    LOCAL_TIMER_FLAGS: Volatile<u32>,
+    _unused3: Reserved<u32>,
+    CORE_TIMER_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_MAILBOX_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_IRQ_SOURCE: [ReadVolatile<u32>; 4],
+    CORE_FIQ_SOURCE: [ReadVolatile<u32>; 4],
+}
+
+/// The interrupt controller of a single core. Used to route the core's
+/// generic timer to it and to check which of its interrupts are pending.
+pub struct LocalController {
+    core: usize,
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the interrupt controller of core `core`.
+    pub fn new(core: usize) -> LocalController {
+        LocalController {
+            core,
+            registers: unsafe { &mut *(LOCAL_INT_BASE as *mut Registers) },
+        }
+    }
+
+    /// Enables the non-secure physical timer of this core and routes its
+    /// interrupt to the core as an IRQ.
+    pub fn enable_local_timer(&mut self) {
+        unsafe {
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0::ENABLE);
+        }
+        self.registers.CORE_TIMER_INT_CONTROL[self.core].or_mask(1 << (LocalInterrupt::CntPnsIrq as u32));
+    }
+
+    /// Returns `true` if `int` is pending on this core. Otherwise, returns
+    /// `false`.
+    pub fn is_pending(&self, int: LocalInterrupt) -> bool {
+        self.registers.CORE_IRQ_SOURCE[self.core].has_mask(1 << (int as u32))
+    }
+
+    /// Sets up a match in this core's timer to occur `t` duration from now.
+    /// The match also clears a pending timer interrupt.
+    ///
+    /// The generic timer is per core, so this must be called on the core
+    /// this controller belongs to.
+    pub fn tick_in(&mut self, t: Duration) {
+        unsafe {
+            let ticks = CNTFRQ_EL0.get() * t.as_micros() as u64 / 1_000_000;
+            CNTP_TVAL_EL0.set(ticks);
+        }
+    }
+}
+
+/// Sets up a match in the timer of the executing core to occur `t` duration
+/// from now.
+pub fn local_tick_in(core: usize, t: Duration) {
+    let mut controller = LocalController::new(core);
+    controller.tick_in(t);
+}
@@ -17,6 +17,15 @@ const MU_REG_BASE: usize = IO_BASE + 0x215040;
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
 const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
 
+/// Enum representing bit fields of the `AUX_MU_IER_REG` register. The
+/// BCM2837 documentation has the receive and transmit bits swapped; these are
+/// the corrected positions.
+#[repr(u8)]
+enum IerStatus {
+    RxInterrupt = 1,
+    TxInterrupt = 1 << 1,
+}
+
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
 enum LsrStatus {
@@ -93,6 +102,29 @@ impl MiniUart {
         self.timeout = Some(t);
     }
 
+    /// Enables the receive interrupt, which the UART raises on the `Aux`
+    /// interrupt line while its receive FIFO holds at least one byte. Reading
+    /// the bytes out clears the interrupt.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.registers.IER.or_mask(IerStatus::RxInterrupt as u8);
+    }
+
+    /// Disables the receive interrupt.
+    pub fn disable_rx_interrupt(&mut self) {
+        self.registers.IER.and_mask(!(IerStatus::RxInterrupt as u8));
+    }
+
+    /// Enables the transmit interrupt, which the UART raises on the `Aux`
+    /// interrupt line while its transmit FIFO is empty.
+    pub fn enable_tx_interrupt(&mut self) {
+        self.registers.IER.or_mask(IerStatus::TxInterrupt as u8);
+    }
+
+    /// Disables the transmit interrupt.
+    pub fn disable_tx_interrupt(&mut self) {
+        self.registers.IER.and_mask(!(IerStatus::TxInterrupt as u8));
+    }
+
     /// Write the byte `byte`. This method blocks until there is space available
     /// in the output FIFO.
     pub fn write_byte(&mut self, byte: u8) {
@@ -1,17 +1,22 @@
 mod parsers;
+mod term;
 
 use serial;
 use structopt;
 use structopt_derive::StructOpt;
-use xmodem::Xmodem;
+use bootimg::elf::Elf;
+use bootimg::Header;
+use xmodem::{Mode, Xmodem};
+use zmodem::{FileInfo, Zmodem};
 
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use structopt::StructOpt;
 use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
 
-use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate, parse_addr};
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
@@ -19,6 +24,13 @@ struct Opt {
     #[structopt(short = "i", help = "Input file (defaults to stdin if not set)", parse(from_os_str))]
     input: Option<PathBuf>,
 
+    #[structopt(short = "o", help = "Output file when receiving (defaults to stdout if not set)",
+                parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(short = "R", long = "receive", help = "Read from TTY instead of writing to it")]
+    receive: bool,
+
     #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
                 help = "Set baud rate", default_value = "115200")]
     baud_rate: BaudRate,
@@ -44,14 +56,43 @@ struct Opt {
 
     #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
     raw: bool,
+
+    #[structopt(short = "z", long = "zmodem",
+                help = "Use ZMODEM instead of XMODEM, resuming into an existing output file")]
+    zmodem: bool,
+
+    #[structopt(short = "k", long = "kernel",
+                help = "Send the input as a kernel image for the bootloader, behind an image header")]
+    kernel: bool,
+
+    #[structopt(long = "load-addr", parse(try_from_str = "parse_addr"),
+                help = "Set the address the bootloader loads the kernel image at", default_value = "0x80000")]
+    load_addr: u64,
+
+    #[structopt(long = "entry", parse(try_from_str = "parse_addr"),
+                help = "Set the entry point of the kernel image (defaults to its load address)")]
+    entry: Option<u64>,
+
+    #[structopt(long = "image-version",
+                help = "Set the version string of the kernel image (defaults to the input file name)")]
+    image_version: Option<String>,
+
+    #[structopt(long = "term", help = "Open a terminal on the TTY after sending")]
+    term: bool,
+
+    #[structopt(long = "watch", parse(from_os_str),
+                help = "Open a terminal and upload the file as a kernel image each time it changes and the board asks")]
+    watch: Option<PathBuf>,
+
+    #[structopt(short = "e", long = "echo", help = "Echo typed keys in the terminal")]
+    echo: bool,
+
+    #[structopt(short = "l", long = "log", parse(from_os_str),
+                help = "Append everything the TTY sends in the terminal to a file")]
+    log: Option<PathBuf>,
 }
 
 fn main() {
-    use std::fs::File;
-    use std::io::{self, BufReader};
-    use std::io::Read;
-    use std::io::Write;
-
     let opt = Opt::from_args();
     let mut port = serial::open(&opt.tty_path).expect("path points to invalid TTY");
     port.set_timeout(Duration::new(opt.timeout, 0)).expect("set timeout failed");
@@ -62,32 +103,174 @@ fn main() {
     settings.set_stop_bits(opt.stop_bits);
     port.write_settings(&settings).expect("write settings failed");
 
-    // FIXME: Implement the `ttywrite` utility.
-    let progress_fn = |progress| {
-        println!("Progress: {:?}", progress);
+    if opt.receive {
+        return receive(&opt, port);
+    }
+    if opt.watch.is_some() {
+        return term::session(&opt, port);
+    }
+
+    let input = read_input(&opt, opt.input.as_ref().map(|fp| fp.as_path())).expect("Input fails to read");
+    let num_bytes = send(&opt, &mut port, &input, true).expect("Transmission failed");
+    let protocol = if opt.zmodem { "zmodem" } else if opt.raw { "raw" } else { "xmodem" };
+    println!("Wrote {} bytes to output using {}", num_bytes, protocol);
+
+    if opt.term {
+        term::session(&opt, port);
+    }
+}
+
+/// Receives from `port` into the output file of `opt`, or stdout. Messages go
+/// to stderr so that they do not mix with the data.
+///
+/// In raw mode, everything is copied until `port` times out.
+fn receive<P: SerialDevice>(opt: &Opt, mut port: P) {
+    use std::fs::File;
+    use std::io::{self, ErrorKind, Write};
+
+    if opt.zmodem {
+        return receive_zmodem(opt, port);
+    }
+
+    let mut output: Box<dyn Write> = match &opt.output {
+        Some(fp) => Box::new(File::create(&fp).expect("Output file fails to open")),
+        None => Box::new(io::stdout()),
     };
-    match &opt.input {
-        Some(fp) => {
-            let file = File::open(&fp).expect("Input file fails to open");
-            let mut buf = BufReader::new(file);
-            if opt.raw {
-                let num_bytes = io::copy(&mut buf, &mut port).expect("Raw write failed");
-                println!("Wrote {} bytes to output using raw", num_bytes);
-            } else {
-                let num_bytes = Xmodem::transmit_with_progress(buf, port, progress_fn).expect("Xmodem transmisison failed");
-                println!("Wrote {} bytes to output using xmodem", num_bytes);
+    if opt.raw {
+        let mut buf = [0u8; 1024];
+        let mut num_bytes = 0;
+        loop {
+            match io::Read::read(&mut port, &mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    output.write_all(&buf[..n]).expect("Raw write failed");
+                    num_bytes += n;
+                }
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => break,
+                Err(e) => panic!("Raw read failed: {}", e),
             }
+        }
+        eprintln!("Read {} bytes from input using raw", num_bytes);
+    } else {
+        let progress_fn = |progress| {
+            eprintln!("Progress: {:?}", progress);
+        };
+        let num_bytes = Xmodem::receive_with_mode(port, output, Mode::Crc, progress_fn).expect("Xmodem reception failed");
+        eprintln!("Read {} bytes from input using xmodem", num_bytes);
+    }
+}
+
+/// What is sent: the input file, or stdin, read whole.
+struct Input {
+    /// The name the file is sent under with ZMODEM; stdin is sent as `stdin`.
+    name: String,
+    /// The modification time of the file in seconds since the epoch, or 0.
+    mtime: u64,
+    data: Vec<u8>,
+}
+
+/// Reads the file at `path`, or stdin. A kernel image, with `-k` or
+/// `--watch`, gets an image header in front of it, with the load address,
+/// entry point and version of `opt`. The kernel may be a raw binary or an ELF
+/// file.
+fn read_input(opt: &Opt, path: Option<&Path>) -> io::Result<Input> {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::time::UNIX_EPOCH;
+
+    let mut data = Vec::new();
+    let (name, mtime) = match path {
+        Some(fp) => {
+            File::open(fp)?.read_to_end(&mut data)?;
+            let mtime = fs::metadata(fp)?.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+            let name = fp.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            (name.to_string(), mtime)
         },
         None => {
-            let stdin = io::stdin();
-            let mut handle = stdin.lock();
-            if opt.raw {
-                let num_bytes = io::copy(&mut handle, &mut port).expect("Raw write failed");
-                println!("Wrote {} bytes to output using raw", num_bytes);
-            } else {
-                let num_bytes = Xmodem::transmit_with_progress(handle, port, progress_fn).expect("Xmodem transmission failed");
-                println!("Wrote {} bytes to output using xmodem", num_bytes);
-            }
+            io::stdin().read_to_end(&mut data)?;
+            ("stdin".to_string(), 0)
+        }
+    };
+
+    if opt.kernel || opt.watch.is_some() {
+        if data.len() > u32::max_value() as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "kernel image too large"));
+        }
+        let version = opt.image_version.as_ref().unwrap_or(&name);
+        // An ELF file is loaded by its own program headers; the header only
+        // describes where it goes.
+        let (load_addr, entry) = match Elf::parse(&data) {
+            Ok(elf) => (elf.segments().map(|segment| segment.paddr).min().unwrap_or(0), elf.entry()),
+            Err(_) => (opt.load_addr, opt.entry.unwrap_or(opt.load_addr)),
+        };
+        let header = Header::new(&data, load_addr, entry, version);
+        data.splice(0..0, header.to_bytes().iter().cloned());
+    }
+    Ok(Input { name, mtime, data })
+}
+
+/// Sends `input` to `port` with the protocol of `opt`, printing the progress
+/// if `verbose`. Returns the number of bytes sent.
+fn send<P: io::Read + io::Write>(opt: &Opt, mut port: P, input: &Input, verbose: bool) -> io::Result<u64> {
+    if opt.zmodem {
+        let progress_fn: zmodem::ProgressFn = match verbose {
+            true => |progress| println!("Progress: {:?}", progress),
+            false => |_| {},
+        };
+        send_zmodem(port, input, progress_fn)
+    } else if opt.raw {
+        port.write_all(&input.data)?;
+        Ok(input.data.len() as u64)
+    } else {
+        let progress_fn: xmodem::ProgressFn = match verbose {
+            true => |progress| println!("Progress: {:?}", progress),
+            false => |_| {},
+        };
+        Xmodem::transmit_with_mode(&input.data[..], port, Mode::Crc1k, progress_fn).map(|n| n as u64)
+    }
+}
+
+/// Sends `input` to `port` with ZMODEM as a batch of one file. Returns the
+/// number of bytes sent.
+fn send_zmodem<P: io::Read + io::Write>(port: P, input: &Input, f: zmodem::ProgressFn) -> io::Result<u64> {
+    use std::io::Cursor;
+
+    let mut zmodem = Zmodem::new_with_progress(port, f);
+    let info = FileInfo::new(&input.name, input.data.len() as u64, input.mtime)?;
+    let num_bytes = zmodem.send(&info, Cursor::new(&input.data[..]))?;
+    zmodem.finish()?;
+    Ok(num_bytes)
+}
+
+/// Receives a batch of files from `port` with ZMODEM. With an output file,
+/// the first file of the batch is resumed into it from its current length;
+/// without one, each file is written to stdout.
+fn receive_zmodem<P: SerialDevice>(opt: &Opt, port: P) {
+    use std::fs::OpenOptions;
+    use std::io::{self, Write};
+
+    let progress_fn = |progress| {
+        eprintln!("Progress: {:?}", progress);
+    };
+    let mut zmodem = Zmodem::new_with_progress(port, progress_fn);
+    let (mut output, mut offset): (Box<dyn Write>, u64) = match &opt.output {
+        Some(fp) => {
+            let file = OpenOptions::new().append(true).create(true).open(&fp)
+                .expect("Output file fails to open");
+            let len = file.metadata().expect("Output file metadata fails to read").len();
+            (Box::new(file), len)
+        },
+        None => (Box::new(io::stdout()), 0),
+    };
+
+    while let Some(info) = zmodem.receive_from(&mut output, offset).expect("Zmodem reception failed") {
+        eprintln!("Read {} bytes of {} from input using zmodem", info.length - offset, info.name());
+        offset = 0;
+        if opt.output.is_some() {
+            // The rest of the batch has nowhere to go.
+            output = Box::new(io::sink());
         }
     }
 }
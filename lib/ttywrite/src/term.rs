@@ -0,0 +1,278 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serial::core::SerialDevice;
+use termios::{cfmakeraw, tcsetattr, Termios, TCSANOW};
+
+use crate::Opt;
+
+/// The escape key, `Ctrl-]`. The key after it is a command for `ttywrite`.
+const ESCAPE: u8 = 0x1D;
+
+/// How long a read from the port waits for the board in the terminal.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often the watched file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the watched file must stay the same after a change before it is
+/// uploaded, so that a build still writing it is not sent half-written.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+const NAK: u8 = 0x15;
+
+/// The start of the `ZRINIT` header a ZMODEM receiver sends.
+const ZRINIT: &[u8] = b"**\x18B01";
+
+/// Puts the terminal on stdin in raw mode, so that every key goes to the
+/// board as it is typed. The terminal's mode is restored when it is dropped.
+struct RawStdin {
+    saved: Option<Termios>,
+}
+
+impl RawStdin {
+    fn new() -> RawStdin {
+        let fd = io::stdin().as_raw_fd();
+        // Not a terminal: there is no mode to change.
+        let saved = match Termios::from_fd(fd) {
+            Ok(saved) => saved,
+            Err(_) => return RawStdin { saved: None },
+        };
+        let mut raw = saved;
+        cfmakeraw(&mut raw);
+        tcsetattr(fd, TCSANOW, &raw).expect("set terminal raw mode failed");
+        RawStdin { saved: Some(saved) }
+    }
+}
+
+impl Drop for RawStdin {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            let _ = tcsetattr(io::stdin().as_raw_fd(), TCSANOW, saved);
+        }
+    }
+}
+
+/// Prints a message from `ttywrite` itself. The terminal is raw, so lines end
+/// with CR LF.
+macro_rules! note {
+    ($($arg:tt)*) => {
+        eprint!("\r\n[ttywrite] {}\r\n", format_args!($($arg)*))
+    };
+}
+
+/// Returns a channel of the bytes typed on stdin, read by another thread.
+fn spawn_stdin() -> Receiver<u8> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for byte in stdin.lock().bytes() {
+            match byte {
+                Ok(byte) if tx.send(byte).is_ok() => continue,
+                _ => break,
+            }
+        }
+    });
+    rx
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Waits for the watched file to change and settle.
+struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    changed: Option<Instant>,
+    checked: Instant,
+}
+
+impl Watch {
+    fn new(path: &Path) -> Watch {
+        Watch { path: path.to_owned(), modified: modified(path), changed: None, checked: Instant::now() }
+    }
+
+    /// Returns `true` once, when the file has changed and then stayed the
+    /// same for `SETTLE_TIME`.
+    fn poll(&mut self) -> bool {
+        if self.checked.elapsed() < WATCH_INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            self.changed = Some(Instant::now());
+            return false;
+        }
+        match self.changed {
+            Some(changed) if changed.elapsed() >= SETTLE_TIME && modified.is_some() => {
+                self.changed = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Recognizes the board asking for an upload in what it sends: two XMODEM
+/// handshakes in a row from the bootloader, or a ZMODEM receiver's `ZRINIT`.
+struct Handshake {
+    zmodem: bool,
+    last: Option<u8>,
+    matched: usize,
+}
+
+impl Handshake {
+    fn new(zmodem: bool) -> Handshake {
+        Handshake { zmodem, last: None, matched: 0 }
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.zmodem {
+            self.matched = match byte {
+                byte if byte == ZRINIT[self.matched] => self.matched + 1,
+                byte if byte == ZRINIT[0] => 1,
+                _ => 0,
+            };
+            if self.matched == ZRINIT.len() {
+                self.matched = 0;
+                return true;
+            }
+            return false;
+        }
+
+        // A single `C` is as likely to be the kernel's output.
+        let handshake = byte == b'C' || byte == NAK;
+        let found = handshake && self.last.map_or(false, |last| last == b'C' || last == NAK);
+        self.last = Some(byte);
+        found
+    }
+}
+
+/// Uploads `path` to the board the way a one-shot `ttywrite` would, with the
+/// port's timeout set to the one of `opt`. Returns the number of bytes sent.
+fn upload<P: SerialDevice>(opt: &Opt, port: &mut P, path: &Path) -> io::Result<u64> {
+    let input = crate::read_input(opt, Some(path))?;
+    port.set_timeout(Duration::new(opt.timeout, 0))?;
+    let result = crate::send(opt, &mut *port, &input, false);
+    port.set_timeout(POLL_INTERVAL)?;
+    result
+}
+
+/// Runs a full-duplex terminal on `port` until the user quits with the escape
+/// key, then `q`.
+///
+/// Everything the board sends is printed, and logged to the log file of `opt`
+/// if there is one. Keys go to the board, and are echoed locally if `opt`
+/// says so. The escape key followed by `r` uploads the input file again once
+/// the board asks for it, as does a change to the watched file.
+pub fn session<P: SerialDevice>(opt: &Opt, mut port: P) {
+    let input = opt.input.as_ref().or(opt.watch.as_ref());
+    let mut log = opt.log.as_ref().map(|fp| {
+        OpenOptions::new().append(true).create(true).open(fp).expect("Log file fails to open")
+    });
+    let mut watch = opt.watch.as_ref().map(|fp| Watch::new(fp));
+    let mut handshake = Handshake::new(opt.zmodem);
+    let mut echo = opt.echo;
+    // The watched file is uploaded a first time as soon as the board asks.
+    let mut pending = opt.watch.is_some();
+    let mut escaped = false;
+
+    port.set_timeout(POLL_INTERVAL).expect("set timeout failed");
+    let _raw = RawStdin::new();
+    let keys = spawn_stdin();
+    note!("terminal on {}: Ctrl-] then q quits, r uploads again, e toggles echo", opt.tty_path.display());
+    if pending {
+        note!("waiting for the board to ask for {}", input.unwrap().display());
+    }
+
+    let stdout = io::stdout();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = match port.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return note!("reading {} failed: {}", opt.tty_path.display(), e),
+        };
+        {
+            let mut out = stdout.lock();
+            out.write_all(&buf[..n]).and_then(|_| out.flush()).expect("stdout write failed");
+        }
+        if let Some(log) = &mut log {
+            log.write_all(&buf[..n]).expect("Log write failed");
+        }
+
+        // A raw upload goes as soon as it is asked for: there is no handshake.
+        let asked = buf[..n].iter().fold(opt.raw, |asked, &byte| handshake.push(byte) || asked);
+        if asked && pending {
+            let path = input.unwrap();
+            match upload(opt, &mut port, path) {
+                Ok(n) => note!("uploaded {} bytes of {}", n, path.display()),
+                Err(e) => note!("upload of {} failed: {}", path.display(), e),
+            }
+            pending = false;
+        }
+
+        if let Some(watch) = &mut watch {
+            if watch.poll() {
+                note!("{} changed; waiting for the board to ask for it", watch.path.display());
+                pending = true;
+            }
+        }
+
+        loop {
+            let key = match keys.try_recv() {
+                Ok(key) => key,
+                Err(TryRecvError::Empty) => break,
+                // Stdin is closed: nothing more can be typed, but a watch
+                // goes on.
+                Err(TryRecvError::Disconnected) if watch.is_none() => return,
+                Err(TryRecvError::Disconnected) => break,
+            };
+
+            if escaped {
+                escaped = false;
+                match key {
+                    b'q' | b'Q' | b'.' => return note!("bye"),
+                    b'e' | b'E' => {
+                        echo = !echo;
+                        note!("local echo {}", if echo { "on" } else { "off" });
+                        continue;
+                    }
+                    b'r' | b'R' => {
+                        match input {
+                            Some(path) => {
+                                note!("waiting for the board to ask for {}", path.display());
+                                pending = true;
+                            }
+                            None => note!("there is no input file to upload"),
+                        }
+                        continue;
+                    }
+                    ESCAPE => {}
+                    _ => {
+                        note!("Ctrl-] then q quits, r uploads again, e toggles echo, Ctrl-] sends Ctrl-]");
+                        continue;
+                    }
+                }
+            } else if key == ESCAPE {
+                escaped = true;
+                continue;
+            }
+
+            if echo {
+                let mut out = stdout.lock();
+                let _ = out.write_all(&[key]).and_then(|_| out.flush());
+            }
+            port.write_all(&[key]).expect("write to TTY failed");
+        }
+    }
+}
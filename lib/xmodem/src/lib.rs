@@ -8,21 +8,53 @@ use shim::ioerr;
 #[cfg(test)] mod tests;
 mod read_ext;
 mod progress;
+mod ymodem;
 
 pub use progress::{Progress, ProgressFn};
+pub use ymodem::{FileInfo, Ymodem};
 
 use read_ext::ReadExt;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+/// Sent by a receiver instead of `NAK` to ask for CRC mode.
+const CRC: u8 = b'C';
+
+/// The number of times a receiver asks for CRC mode, waiting for one timeout
+/// of the inner stream each time, before it falls back to checksum mode.
+const CRC_ATTEMPTS: usize = 3;
+
+/// The number of times a 1K block is sent before the sender falls back to
+/// 128-byte blocks.
+const BLOCK_1K_ATTEMPTS: usize = 2;
+
+/// The variant of the XMODEM protocol used by a transfer.
+///
+/// The receiver picks the variant: it asks for checksum mode with `NAK` or
+/// for CRC mode with `C`. A sender follows the receiver, up to the variant it
+/// is set to; a receiver that is not answered in CRC mode falls back to
+/// checksum mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// 128-byte blocks with an 8-bit checksum: the original protocol.
+    Checksum,
+    /// 128-byte blocks with a CRC-16: XMODEM-CRC.
+    Crc,
+    /// 1024-byte blocks with a CRC-16, with 128-byte blocks for the end of
+    /// the data: XMODEM-1K. A receiver treats it like `Crc`, since it accepts
+    /// blocks of either size.
+    Crc1k,
+}
 
 /// Implementation of the XMODEM protocol.
 pub struct Xmodem<R> {
     packet: u8,
     started: bool,
+    mode: Mode,
     inner: R,
     progress: ProgressFn
 }
@@ -48,34 +80,22 @@ impl Xmodem<()> {
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
+    #[inline]
+    pub fn transmit_with_progress<R, W>(data: R, to: W, f: ProgressFn) -> io::Result<usize>
         where W: io::Read + io::Write, R: io::Read
     {
-        let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
+        Xmodem::transmit_with_mode(data, to, Mode::Crc, f)
+    }
 
-            return ioerr!(BrokenPipe, "bad transmit");
-        }
+    /// Transmits `data` to the receiver `to` like
+    /// [`Xmodem::transmit_with_progress()`], using at most the variant `mode`
+    /// of the protocol. See [`Mode`] for how the variant is chosen.
+    pub fn transmit_with_mode<R, W>(data: R, to: W, mode: Mode, f: ProgressFn) -> io::Result<usize>
+        where W: io::Read + io::Write, R: io::Read
+    {
+        let mut transmitter = Xmodem::new_with_progress(to, f);
+        transmitter.set_mode(mode);
+        transmitter.send_data(data, 0)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
@@ -92,30 +112,32 @@ impl Xmodem<()> {
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W, f: ProgressFn) -> io::Result<usize>
+    #[inline]
+    pub fn receive_with_progress<R, W>(from: R, into: W, f: ProgressFn) -> io::Result<usize>
+       where R: io::Read + io::Write, W: io::Write
+    {
+        Xmodem::receive_with_mode(from, into, Mode::Checksum, f)
+    }
+
+    /// Receives `data` from `from` like [`Xmodem::receive_with_progress()`],
+    /// asking the sender for the variant `mode` of the protocol. See [`Mode`]
+    /// for how the variant is chosen.
+    pub fn receive_with_mode<R, W>(from: R, mut into: W, mode: Mode, f: ProgressFn) -> io::Result<usize>
        where R: io::Read + io::Write, W: io::Write
     {
         let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
+        receiver.set_mode(mode);
+        let mut packet = [0u8; 1024];
         let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => break 'next_packet,
-                    Ok(n) => {
-                        received += n;
-                        into.write_all(&packet)?;
-                        continue 'next_packet;
-                    }
+        loop {
+            match receiver.receive_block(&mut packet)? {
+                0 => return Ok(received),
+                n => {
+                    received += n;
+                    into.write_all(&packet[..n])?;
                 }
             }
-
-            return ioerr!(BrokenPipe, "bad receive");
         }
-
-        Ok(received)
     }
 }
 
@@ -123,12 +145,24 @@ fn get_checksum(buf: &[u8]) -> u8 {
     return buf.iter().fold(0, |a, b| a.wrapping_add(*b));
 }
 
+/// Returns the CRC-16 of `buf` used by XMODEM: polynomial `0x1021`, initial
+/// value 0, no reflection.
+fn get_crc(buf: &[u8]) -> u16 {
+    buf.iter().fold(0, |crc, &b| {
+        let mut crc = crc ^ ((b as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
 impl<T: io::Read + io::Write> Xmodem<T> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading).
     pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: progress::noop}
+        Xmodem { packet: 1, started: false, mode: Mode::Checksum, inner, progress: progress::noop}
     }
 
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
@@ -137,7 +171,22 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
     pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: f }
+        Xmodem { packet: 1, started: false, mode: Mode::Checksum, inner, progress: f }
+    }
+
+    /// Sets the variant of the protocol to use: the one a receiver asks for,
+    /// or the best one a sender may follow the receiver to. Has no effect
+    /// once the transfer has started. The default is `Mode::Checksum`.
+    pub fn set_mode(&mut self, mode: Mode) {
+        if !self.started {
+            self.mode = mode;
+        }
+    }
+
+    /// Returns the variant of the protocol in use. Once the transfer has
+    /// started, this is the variant the sender and receiver agreed on.
+    pub fn mode(&self) -> Mode {
+        self.mode
     }
 
     /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
@@ -219,8 +268,40 @@ impl<T: io::Read + io::Write> Xmodem<T> {
 
     }
 
+    /// Asks the sender to start in the mode set with [`Xmodem::set_mode()`]
+    /// and returns the first byte it sends. A request for CRC mode is repeated
+    /// each time reading from the inner stream times out, and is replaced by
+    /// a request for checksum mode after `CRC_ATTEMPTS` attempts.
+    fn start_receive(&mut self) -> io::Result<u8> {
+        let mut attempts = 0;
+        loop {
+            let request = if self.mode == Mode::Checksum { NAK } else { CRC };
+            self.write_byte(request)?;
+            if !self.started {
+                self.started = true;
+                (self.progress)(Progress::Started);
+            }
+
+            match self.read_byte(true) {
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut && self.mode != Mode::Checksum => {
+                    attempts += 1;
+                    if attempts == CRC_ATTEMPTS {
+                        self.mode = Mode::Checksum;
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
     /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// protocol. On success, returns the number of bytes read: 128, or 1024
+    /// for a 1K block.
+    ///
+    /// Before the first packet, the sender is asked to start in the mode set
+    /// with [`Xmodem::set_mode()`]. A sender that does not answer a request
+    /// for CRC mode before the inner stream times out three times is asked
+    /// for checksum mode instead.
     ///
     /// The progress callback is called with `Progress::Started` when reception
     /// for the first packet has started and subsequently with
@@ -232,72 +313,107 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
+    ///   * The sender's first byte for a packet isn't `EOT`, `SOH` or `STX`.
     ///   * The sender doesn't send a second `EOT` after the first.
     ///   * The received packet numbers don't match the expected values.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// An error of kind `Interrupted` is returned if a packet checksum or CRC
+    /// fails.
     ///
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
+    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`, or if
+    /// `buf.len() < 1024` and the sender starts a 1K block, which is then
+    /// cancelled.
     pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if buf.len() < 128 {
             return ioerr!(UnexpectedEof, "Buffer length less than 128");
         }
-        //TODO: start of transmision stuff
-        if self.started == false {
-            self.write_byte(NAK)?;
-            self.started = true;
-            (self.progress)(Progress::Started);
-        }
-        
-        let first_byte = self.read_byte(true)?;
+
+        let first_byte = if self.started {
+            self.read_byte(true)?
+        } else {
+            self.start_receive()?
+        };
         if first_byte == EOT {
             //performs end of transmission
             self.write_byte(NAK)?;
-            //self.packet += 1;
             self.packet = self.packet.wrapping_add(1);
             match self.expect_byte(EOT, "second EOT not sent") {
                 Ok(_n) => {
                     self.write_byte(ACK)?;
-                    //self.packet += 1;
                     self.packet = self.packet.wrapping_add(1);
-                    Ok(0) //CHECK: is this the right number to return?
+                    Ok(0)
                 },
                 Err(_e) => ioerr!(InvalidData, "second EOT not sent")
             }
-        } else if first_byte == SOH {
+        } else if first_byte == SOH || first_byte == STX {
+            let size = if first_byte == STX { 1024 } else { 128 };
+            if buf.len() < size {
+                self.write_byte(CAN)?;
+                return ioerr!(UnexpectedEof, "Buffer length less than 1024");
+            }
+
             self.expect_byte_or_cancel(self.packet, "packet number mismatch")?;
             self.expect_byte_or_cancel(255_u8.wrapping_sub(self.packet), "packet number 1's complement match error")?;
-            for i in 0..128 {
-                buf[i] = self.read_byte(false)?;
+            let block = &mut buf[..size];
+            for byte in block.iter_mut() {
+                *byte = self.read_byte(false)?;
             }
-            //self.read_exact(buf);
-            match self.expect_byte(get_checksum(buf), "checksum fails") {
-                Ok(_n) => {
-                    self.write_byte(ACK)?;
-                    self.packet = self.packet.wrapping_add(1);
-                    Ok(buf.len())
-                },
-                Err(_e) => {
-                    self.write_byte(NAK)?;
-                    return ioerr!(Interrupted, "checksum not equal");
-                }
+
+            let valid = if self.mode == Mode::Checksum {
+                self.read_byte(false)? == get_checksum(block)
+            } else {
+                let high = self.read_byte(false)?;
+                let low = self.read_byte(false)?;
+                ((high as u16) << 8 | low as u16) == get_crc(block)
+            };
+            if valid {
+                self.write_byte(ACK)?;
+                self.packet = self.packet.wrapping_add(1);
+                Ok(size)
+            } else {
+                self.write_byte(NAK)?;
+                ioerr!(Interrupted, "checksum not equal")
             }
         } else {
-            //neither SOH nor EOT
-            ioerr!(InvalidData, "first byte is neither SOH nor EOT")
+            //neither SOH, STX nor EOT
+            ioerr!(InvalidData, "first byte is neither SOH, STX nor EOT")
         }
     }
 
+    /// Waits for the receiver to ask for the first packet and agrees on the
+    /// mode: checksum mode for `NAK`, and CRC mode for `C`. A sender set to
+    /// `Mode::Checksum` ignores `C` and keeps waiting for `NAK`.
+    fn start_transmit(&mut self) -> io::Result<()> {
+        loop {
+            match self.read_byte(true)? {
+                NAK => {
+                    self.mode = Mode::Checksum;
+                    break;
+                }
+                CRC if self.mode != Mode::Checksum => break,
+                CRC => continue,
+                _ => return ioerr!(InvalidData, "expected NAK or C to start the transmission"),
+            }
+        }
+
+        self.started = true;
+        (self.progress)(Progress::Started);
+        Ok(())
+    }
+
     /// Sends (uploads) a single packet to the inner stream using the XMODEM
     /// protocol. If `buf` is empty, end of transmissions is sent. Users of this
     /// interface should ensure that `write_packet(&[])` is called when data
     /// transmission is complete. On success, returns the number of bytes
     /// written.
     ///
+    /// The packet is a 1K block of the first 1024 bytes of `buf` if the
+    /// receiver agreed on `Mode::Crc1k` and `buf` holds that many, and a block
+    /// of the first 128 bytes otherwise.
+    ///
     /// The progress callback is called with `Progress::Waiting` before waiting
     /// for the receiver's `NAK`, `Progress::Started` when transmission of the
     /// first packet has started and subsequently with `Progress::Packet` when a
@@ -309,7 +425,7 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The receiver's first byte isn't a `NAK`.
+    ///   * The receiver's first byte isn't a `NAK` or `C`.
     ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
     ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
     ///   * The receiver responds to a complete packet with something besides
@@ -323,18 +439,8 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     ///
     /// An error of kind `Interrupted` is returned if a packet checksum fails.
     pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
-        //start of transmission
-        if self.started == false {
-            self.expect_byte(NAK, "hello this is where it's coming from")?;
-            self.started = true;
-            (self.progress)(Progress::Started);
-            //loop {
-                //if self.expect_byte(NAK, "expected NAK for the first byte of transmission").is_ok() {
-                    //self.started = true;
-                    //(self.progress)(Progress::Started);
-                    //break;
-                //}
-            //}
+        if !self.started {
+            self.start_transmit()?;
         }
         if buf.len() == 0 {
             self.write_byte(EOT)?;
@@ -353,36 +459,98 @@ impl<T: io::Read + io::Write> Xmodem<T> {
             if buf.len() < 128 {
                 return ioerr!(UnexpectedEof, "packet length isn't 128 or 0");
             }
-            let mut num_bytes = 0;
-            self.write_byte(SOH)?;
+
+            let (header, size) = if buf.len() >= 1024 && self.mode == Mode::Crc1k {
+                (STX, 1024)
+            } else {
+                (SOH, 128)
+            };
+            let block = &buf[..size];
+            self.write_byte(header)?;
             self.write_byte(self.packet)?;
             self.write_byte(255 - self.packet)?;
-            for i in 0..128 {
-                if i < buf.len() {
-                    self.write_byte(buf[i])?;
-                    num_bytes += 1;
-                } else {
-                    self.write_byte(0)?;
-                }
+            self.inner.write_all(block)?;
+            if self.mode == Mode::Checksum {
+                self.write_byte(get_checksum(block))?;
+            } else {
+                let crc = get_crc(block);
+                self.write_byte((crc >> 8) as u8)?;
+                self.write_byte(crc as u8)?;
             }
-            self.write_byte(get_checksum(buf))?;
+
             match self.read_byte(true)? {
                 NAK => {
                     return ioerr!(Interrupted, "check failed")
                 },
                 ACK => {
-                    //self.packet += 1;
                     self.packet = self.packet.wrapping_add(1);
-                    return Ok(num_bytes);
+                    return Ok(size);
                 },
                 _ => {
-                    //self.write_byte(CAN)?;
                     return ioerr!(InvalidData, "response to complete packet isn't ACK or NAK");
                 }
             }
         }
     }
 
+    /// Sends all of `data`, padded with `pad` to whole blocks, and then the
+    /// end of transmission. Returns the number of bytes sent, excluding
+    /// padding.
+    fn send_data<R: io::Read>(&mut self, mut data: R, pad: u8) -> io::Result<usize> {
+        let mut packet = [0u8; 1024];
+        let mut written = 0;
+        loop {
+            let n = data.read_max(&mut packet)?;
+            if n == 0 {
+                self.write_packet(&[])?;
+                return Ok(written);
+            }
+
+            // Whatever does not fill a 1K block is sent in 128-byte blocks.
+            let end = (n + 127) / 128 * 128;
+            packet[n..end].iter_mut().for_each(|b| *b = pad);
+            let mut sent = 0;
+            while sent < end {
+                sent += self.send_block(&packet[sent..end])?;
+            }
+            written += n;
+        }
+    }
+
+    /// Reads a packet into `buf` with [`Xmodem::read_packet()`], asking for it
+    /// again each time it arrives damaged, up to 10 times. Returns the number
+    /// of bytes read, 0 at the end of transmission.
+    fn receive_block(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for _ in 0..10 {
+            match self.read_packet(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+
+        ioerr!(BrokenPipe, "bad receive")
+    }
+
+    /// Sends a packet from `buf` with [`Xmodem::write_packet()`], sending it
+    /// again each time the receiver rejects it, up to 10 times. A 1K block
+    /// rejected `BLOCK_1K_ATTEMPTS` times is sent again as a 128-byte block,
+    /// as are all later ones, in case the receiver cannot take 1K blocks.
+    /// Returns the number of bytes sent.
+    fn send_block(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for attempt in 1..=10 {
+            match self.write_packet(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                    if attempt == BLOCK_1K_ATTEMPTS && buf.len() >= 1024 && self.mode == Mode::Crc1k {
+                        self.mode = Mode::Crc;
+                    }
+                }
+                result => return result,
+            }
+        }
+
+        ioerr!(BrokenPipe, "bad transmit")
+    }
+
     /// Flush this output stream, ensuring that all intermediately buffered
     /// contents reach their destination.
     ///
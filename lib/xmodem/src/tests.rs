@@ -227,3 +227,309 @@ fn test_eot() {
 
     assert_eq!(&buffer[..], &[NAK, EOT, NAK, EOT, ACK]);
 }
+
+/// A stream reading the bytes of a script, in which `None` is a read that
+/// times out, and recording the bytes written to it.
+struct Script(Vec<Option<u8>>, Vec<u8>);
+
+impl io::Read for Script {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.0.remove(0) {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+        }
+    }
+}
+
+impl io::Write for Script {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc() {
+    assert_eq!(get_crc(b"123456789"), 0x31C3);
+    assert_eq!(get_crc(&[]), 0);
+}
+
+#[test]
+fn test_crc_transmission() {
+    let mut input = [0u8; 256];
+    (0..256usize).for_each(|i| input[i] = i as u8);
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc, progress::noop).expect("transmit okay");
+        rx.2
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 256];
+        Xmodem::receive_with_mode(&mut tx, &mut output[..], Mode::Crc, progress::noop).expect("receive okay");
+        (output, tx.2)
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let (output, tx_buf) = rx_thread.join().expect("rx join okay");
+    assert_eq!(&input[..], &output[..]);
+
+    // check packets 1 and 2, each ended by a big-endian CRC
+    let crc = get_crc(&input[..128]);
+    assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(&rx_buf[3..131], &input[..128]);
+    assert_eq!(&rx_buf[131..133], &[(crc >> 8) as u8, crc as u8]);
+    let crc = get_crc(&input[128..]);
+    assert_eq!(&rx_buf[133..136], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[264..266], &[(crc >> 8) as u8, crc as u8]);
+    assert_eq!(&rx_buf[266..], &[EOT, EOT]);
+
+    // the receiver asked for CRC mode
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_1k_transmission() {
+    let mut input = vec![0u8; 2048 + 300];
+    input.iter_mut().enumerate().for_each(|(i, b)| *b = (i % 251) as u8);
+    let expected = input.clone();
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let sent = Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc1k, progress::noop);
+        (sent.expect("transmit okay"), rx.2)
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = vec![];
+        let received = Xmodem::receive_with_mode(&mut tx, &mut output, Mode::Crc1k, progress::noop);
+        (received.expect("receive okay"), output)
+    });
+
+    let (sent, rx_buf) = tx_thread.join().expect("tx join okay");
+    let (received, output) = rx_thread.join().expect("rx join okay");
+    assert_eq!(sent, 2048 + 300);
+    assert_eq!(received, 2048 + 384);
+    assert_eq!(&output[..2048 + 300], &expected[..]);
+    assert!(output[2048 + 300..].iter().all(|&b| b == 0));
+
+    // two 1K blocks, then the rest in 128-byte blocks
+    let block_1k = 3 + 1024 + 2;
+    assert_eq!(&rx_buf[0..3], &[STX, 1, 255 - 1]);
+    assert_eq!(&rx_buf[block_1k..block_1k + 3], &[STX, 2, 255 - 2]);
+    assert_eq!(&rx_buf[2 * block_1k..2 * block_1k + 3], &[SOH, 3, 255 - 3]);
+    assert_eq!(rx_buf.len(), 2 * block_1k + 3 * (3 + 128 + 2) + 2);
+}
+
+#[test]
+fn test_1k_sender_follows_checksum_receiver() {
+    let input = [7u8; 1024];
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit_with_mode(&input[..], &mut rx, Mode::Crc1k, progress::noop).expect("transmit okay");
+        rx.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 1024];
+        Xmodem::receive(&mut tx, &mut output[..]).expect("receive okay");
+        output
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let output = rx_thread.join().expect("rx join okay");
+    assert_eq!(&input[..], &output[..]);
+    assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(rx_buf[131], get_checksum(&input[..128]));
+    assert_eq!(rx_buf.len(), 8 * (3 + 128 + 1) + 2);
+}
+
+#[test]
+fn test_crc_request_fallback() {
+    // The sender never answers `C`, so the receiver falls back to `NAK`.
+    let mut script = Script(vec![None, None, None, Some(EOT), Some(EOT)], vec![]);
+    let mut xmodem = Xmodem::new(&mut script);
+    xmodem.set_mode(Mode::Crc);
+    let mut packet = [0u8; 128];
+    assert_eq!(xmodem.read_packet(&mut packet).expect("read EOT"), 0);
+    assert_eq!(xmodem.mode(), Mode::Checksum);
+    assert_eq!(&script.1, &[CRC, CRC, CRC, NAK, NAK, ACK]);
+
+    // The sender answers the second `C`.
+    let mut script = Script(vec![None, Some(EOT), Some(EOT)], vec![]);
+    let mut xmodem = Xmodem::new(&mut script);
+    xmodem.set_mode(Mode::Crc);
+    assert_eq!(xmodem.read_packet(&mut packet).expect("read EOT"), 0);
+    assert_eq!(xmodem.mode(), Mode::Crc);
+    assert_eq!(&script.1, &[CRC, CRC, NAK, ACK]);
+
+    // A receiver in checksum mode does not retry.
+    let mut script = Script(vec![None], vec![]);
+    let e = Xmodem::new(&mut script).read_packet(&mut packet).expect_err("timed out");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(&script.1, &[NAK]);
+}
+
+#[test]
+fn test_sender_negotiation() {
+    // A checksum-only sender ignores `C` and waits for `NAK`.
+    let mut script = Script(vec![Some(CRC), Some(CRC), Some(NAK), Some(NAK), Some(ACK)], vec![]);
+    let mut xmodem = Xmodem::new(&mut script);
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.mode(), Mode::Checksum);
+    assert_eq!(&script.1, &[EOT, EOT]);
+
+    // A CRC sender follows a receiver asking for checksum mode...
+    let mut script = Script(vec![Some(NAK), Some(NAK), Some(ACK)], vec![]);
+    let mut xmodem = Xmodem::new(&mut script);
+    xmodem.set_mode(Mode::Crc1k);
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.mode(), Mode::Checksum);
+
+    // ...or CRC mode.
+    let mut script = Script(vec![Some(CRC), Some(NAK), Some(ACK)], vec![]);
+    let mut xmodem = Xmodem::new(&mut script);
+    xmodem.set_mode(Mode::Crc1k);
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.mode(), Mode::Crc1k);
+
+    // Anything else is an error.
+    let mut script = Script(vec![Some(ACK)], vec![]);
+    let e = Xmodem::new(&mut script).write_packet(&[]).expect_err("bad start");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_1k_fallback() {
+    // The receiver rejects the 1K block twice, then takes 128-byte blocks.
+    let mut replies = vec![Some(CRC), Some(NAK), Some(NAK)];
+    replies.extend(vec![Some(ACK); 8]);
+    replies.extend(vec![Some(NAK), Some(ACK)]);
+    let mut script = Script(replies, vec![]);
+
+    let input = [1u8; 1024];
+    let sent = Xmodem::transmit_with_mode(&input[..], &mut script, Mode::Crc1k, progress::noop);
+    assert_eq!(sent.expect("transmit okay"), 1024);
+
+    let block_1k = 3 + 1024 + 2;
+    assert_eq!(&script.1[0..3], &[STX, 1, 255 - 1]);
+    assert_eq!(&script.1[block_1k..block_1k + 3], &[STX, 1, 255 - 1]);
+    assert_eq!(&script.1[2 * block_1k..2 * block_1k + 3], &[SOH, 1, 255 - 1]);
+    assert_eq!(script.1.len(), 2 * block_1k + 8 * (3 + 128 + 2) + 2);
+}
+
+#[test]
+fn test_block_0() {
+    let info = FileInfo::new("kernel.bin", 1000, 0o14000000000).expect("valid info");
+    let mut block = [0xFFu8; 128];
+    let len = info.encode(&mut block).expect("encode okay");
+    let fields = b"kernel.bin\01000 14000000000\0";
+    assert_eq!(len, fields.len());
+    assert_eq!(&block[..fields.len()], &fields[..]);
+    assert!(block[fields.len()..].iter().all(|&b| b == 0));
+    assert_eq!(FileInfo::decode(&block).expect("decode okay"), Some((info, Some(1000))));
+
+    // The modification time is optional, and so is the length.
+    let info = FileInfo::new("a", 5, 0).expect("valid info");
+    assert_eq!(info.encode(&mut block).expect("encode okay"), 4);
+    assert_eq!(&block[..4], b"a\05\0");
+    let (decoded, length) = FileInfo::decode(b"a\0\0").expect("decode okay").expect("a file");
+    assert_eq!((decoded.name(), decoded.length, decoded.mtime, length), ("a", 0, 0, None));
+
+    // An empty block 0 ends the batch.
+    assert_eq!(FileInfo::decode(&[0; 128]).expect("decode okay"), None);
+    let e = FileInfo::decode(b"a\0ten\0").expect_err("bad length");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_file_info_names() {
+    assert_eq!(FileInfo::new("dir/file.txt", 0, 0).expect("valid").name(), "dir/file.txt");
+    let long = "x".repeat(FileInfo::MAX_NAME_LEN + 1);
+    for name in &["", "a\0b", &long[..]] {
+        let e = FileInfo::new(name, 0, 0).expect_err("invalid name");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_ymodem_batch() {
+    let files: Vec<(FileInfo, Vec<u8>)> = vec![
+        (FileInfo::new("one", 1000, 0o1234).unwrap(), (0..1000).map(|i| i as u8).collect()),
+        (FileInfo::new("empty", 0, 0).unwrap(), vec![]),
+        (FileInfo::new("two", 2500, 0).unwrap(), (0..2500).map(|i| (i % 7) as u8).collect()),
+    ];
+    let expected = files.clone();
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(rx);
+        for (info, data) in files.iter() {
+            assert_eq!(ymodem.send(info, &data[..]).expect("send okay"), info.length);
+        }
+        ymodem.finish().expect("finish okay");
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(tx);
+        let mut received = vec![];
+        loop {
+            let mut data = vec![];
+            match ymodem.receive(&mut data).expect("receive okay") {
+                Some(info) => received.push((info, data)),
+                None => return received,
+            }
+        }
+    });
+
+    tx_thread.join().expect("tx join okay");
+    let received = rx_thread.join().expect("rx join okay");
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn test_ymodem_raw_transmission() {
+    let input = [3u8; 200];
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(&mut rx);
+        let info = FileInfo::new("f", 200, 0).unwrap();
+        ymodem.send(&info, &input[..]).expect("send okay");
+        ymodem.finish().expect("finish okay");
+        rx.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(&mut tx);
+        let mut output = vec![];
+        assert!(ymodem.receive(&mut output).expect("receive okay").is_some());
+        assert!(ymodem.receive(&mut output).expect("receive okay").is_none());
+        (output, tx.2)
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let (output, tx_buf) = rx_thread.join().expect("rx join okay");
+    assert_eq!(&output[..], &input[..]);
+
+    // block 0, then the data padded with CP/M EOF, then the empty block 0
+    let block = 3 + 128 + 2;
+    assert_eq!(&rx_buf[0..5], &[SOH, 0, 255, b'f', 0]);
+    assert_eq!(&rx_buf[block..block + 3], &[SOH, 1, 255 - 1]);
+    assert_eq!(&rx_buf[2 * block..2 * block + 3], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[2 * block + 3 + 72..3 * block - 2], &[0x1A; 56][..]);
+    assert_eq!(&rx_buf[3 * block..3 * block + 2], &[EOT, EOT]);
+    assert_eq!(&rx_buf[3 * block + 2..3 * block + 5], &[SOH, 0, 255]);
+    assert_eq!(rx_buf.len(), 4 * block + 2);
+
+    assert_eq!(&tx_buf, &[CRC, ACK, CRC, ACK, ACK, NAK, ACK, CRC, ACK]);
+}
@@ -0,0 +1,271 @@
+use core::cmp::min;
+use core::fmt::{self, Write};
+use core::str;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::{progress, Mode, ProgressFn, Xmodem};
+
+/// The byte padding the last block of a file, CP/M's end of file.
+const CPMEOF: u8 = 0x1A;
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The description of a file sent in block 0 of a YMODEM transfer.
+#[derive(Clone, Copy)]
+pub struct FileInfo {
+    name: [u8; FileInfo::MAX_NAME_LEN],
+    name_len: usize,
+    /// The exact length of the file in bytes.
+    pub length: u64,
+    /// The modification time in seconds since the Unix epoch, 0 if unknown.
+    pub mtime: u64,
+}
+
+impl FileInfo {
+    /// The longest file name, in bytes, that a `FileInfo` holds.
+    pub const MAX_NAME_LEN: usize = 64;
+
+    /// Returns the description of the file `name` of `length` bytes last
+    /// modified at `mtime`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `name` is empty, longer than
+    /// `MAX_NAME_LEN` bytes or contains a NUL byte.
+    pub fn new(name: &str, length: u64, mtime: u64) -> io::Result<FileInfo> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes.len() > FileInfo::MAX_NAME_LEN || bytes.contains(&0) {
+            return ioerr!(InvalidInput, "invalid file name");
+        }
+
+        let mut info = FileInfo { name: [0; FileInfo::MAX_NAME_LEN], name_len: bytes.len(), length, mtime };
+        info.name[..bytes.len()].copy_from_slice(bytes);
+        Ok(info)
+    }
+
+    /// Returns the name of the file.
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name[..self.name_len]).expect("file names are UTF-8")
+    }
+
+    /// Writes block 0 describing the file into `block`: the name, a NUL, the
+    /// length in decimal and, if known, a space and the modification time in
+    /// octal. The rest of the block is zeroed. ZMODEM's `ZFILE` subpacket has
+    /// the same format.
+    ///
+    /// Returns the length of the description, including its last NUL.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the description does not fit
+    /// in `block`.
+    pub fn encode(&self, block: &mut [u8]) -> io::Result<usize> {
+        if block.len() <= self.name_len + 1 {
+            return ioerr!(InvalidInput, "block 0 overflow");
+        }
+        block.iter_mut().for_each(|b| *b = 0);
+        block[..self.name_len].copy_from_slice(&self.name[..self.name_len]);
+
+        // The last byte stays NUL to end the fields.
+        let end = block.len() - 1;
+        let mut fields = Fields { buf: &mut block[self.name_len + 1..end], len: 0 };
+        let result = match self.mtime {
+            0 => write!(fields, "{}", self.length),
+            mtime => write!(fields, "{} {:o}", self.length, mtime),
+        };
+        let len = fields.len;
+        result.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block 0 overflow"))?;
+        Ok(self.name_len + 1 + len + 1)
+    }
+
+    /// Parses block 0, or a ZMODEM `ZFILE` subpacket. Returns `None` for the
+    /// empty block 0 that ends a batch. Otherwise returns the description of
+    /// the file and its length, if the sender gave it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the block is malformed.
+    pub fn decode(block: &[u8]) -> io::Result<Option<(FileInfo, Option<u64>)>> {
+        let name_len = block.iter().position(|&b| b == 0).unwrap_or(block.len());
+        if name_len == 0 {
+            return Ok(None);
+        }
+        let name = str::from_utf8(&block[..name_len]).map_err(|_| invalid_data("file name isn't UTF-8"))?;
+
+        let rest = block.get(name_len + 1..).unwrap_or(&[]);
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        let fields = str::from_utf8(&rest[..end]).map_err(|_| invalid_data("bad block 0 fields"))?;
+        let mut fields = fields.split(' ').filter(|field| !field.is_empty());
+        let length = match fields.next() {
+            Some(field) => Some(field.parse().map_err(|_| invalid_data("bad file length"))?),
+            None => None,
+        };
+        let mtime = match fields.next() {
+            Some(field) => u64::from_str_radix(field, 8).map_err(|_| invalid_data("bad modification time"))?,
+            None => 0,
+        };
+
+        let info = FileInfo::new(name, length.unwrap_or(0), mtime).map_err(|_| invalid_data("bad file name"))?;
+        Ok(Some((info, length)))
+    }
+}
+
+impl PartialEq for FileInfo {
+    fn eq(&self, other: &FileInfo) -> bool {
+        self.name() == other.name() && self.length == other.length && self.mtime == other.mtime
+    }
+}
+
+impl Eq for FileInfo {}
+
+impl fmt::Debug for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileInfo")
+            .field("name", &self.name())
+            .field("length", &self.length)
+            .field("mtime", &self.mtime)
+            .finish()
+    }
+}
+
+/// Formats the fields of block 0 into a byte slice.
+struct Fields<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Fields<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Implementation of the YMODEM protocol: batches of files sent with XMODEM,
+/// each preceded by a block 0 that gives its name, exact length and
+/// modification time.
+///
+/// Block 0 lets the receiver drop the padding of the last block, which
+/// XMODEM alone cannot tell from data. The data is sent in CRC mode, in 1K
+/// blocks unless the sender is set otherwise with [`Ymodem::set_mode()`]. A
+/// batch is ended by an empty block 0.
+pub struct Ymodem<T> {
+    inner: T,
+    mode: Mode,
+    progress: ProgressFn,
+}
+
+impl<T: io::Read + io::Write> Ymodem<T> {
+    /// Returns a new `Ymodem` instance transferring files over `inner`.
+    pub fn new(inner: T) -> Self {
+        Ymodem::new_with_progress(inner, progress::noop)
+    }
+
+    /// Returns a new `Ymodem` instance transferring files over `inner`. The
+    /// function `f` is called with the progress of each file's transfer, as
+    /// for [`Xmodem::new_with_progress()`].
+    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
+        Ymodem { inner, mode: Mode::Crc1k, progress: f }
+    }
+
+    /// Sets the best variant of XMODEM the sender may use for the data. The
+    /// default is `Mode::Crc1k`. A receiver always asks for CRC mode.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Returns an XMODEM transfer over the inner stream that starts with
+    /// block 0 in the variant `mode`.
+    fn xmodem(&mut self, mode: Mode) -> Xmodem<&mut T> {
+        let mut xmodem = Xmodem::new_with_progress(&mut self.inner, self.progress);
+        xmodem.set_mode(mode);
+        xmodem.packet = 0;
+        xmodem
+    }
+
+    /// Sends the file described by `info`, reading its `info.length` bytes
+    /// from `data`. Returns the number of bytes sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails. An error of kind
+    /// `UnexpectedEof` is returned if `data` ends before `info.length` bytes,
+    /// in which case the receiver got the shorter file.
+    pub fn send<R: io::Read>(&mut self, info: &FileInfo, data: R) -> io::Result<u64> {
+        let mut block = [0u8; 128];
+        info.encode(&mut block)?;
+
+        let mut xmodem = self.xmodem(self.mode);
+        xmodem.send_block(&block)?;
+        // The receiver asks for the data as it asked for block 0.
+        xmodem.started = false;
+        let sent = xmodem.send_data(data.take(info.length), CPMEOF)? as u64;
+        if sent < info.length {
+            return ioerr!(UnexpectedEof, "file shorter than its length");
+        }
+        Ok(sent)
+    }
+
+    /// Ends the batch, telling the receiver that no file follows.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut xmodem = self.xmodem(self.mode);
+        xmodem.send_block(&[0; 128]).map(|_| ())
+    }
+
+    /// Receives the next file of the batch and writes its contents into
+    /// `into`. Returns the description of the file, or `None` once the sender
+    /// ended the batch.
+    ///
+    /// Exactly `length` bytes are written: the padding of the last block is
+    /// dropped. If the sender did not give the length, all blocks are written
+    /// and the returned `length` is their size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails or block 0 is malformed. An
+    /// error of kind `UnexpectedEof` is returned if the sender sent less data
+    /// than the length it gave.
+    pub fn receive<W: io::Write>(&mut self, mut into: W) -> io::Result<Option<FileInfo>> {
+        let mut xmodem = self.xmodem(Mode::Crc);
+        let mut packet = [0u8; 1024];
+        let n = xmodem.receive_block(&mut packet)?;
+        if n == 0 {
+            return ioerr!(InvalidData, "expected block 0");
+        }
+        let (mut info, length) = match FileInfo::decode(&packet[..n])? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        // Ask for the data as for block 0.
+        xmodem.started = false;
+        let mut received = 0;
+        loop {
+            let n = match xmodem.receive_block(&mut packet)? {
+                0 => break,
+                n => n as u64,
+            };
+            let n = match length {
+                Some(length) => min(n, length - received),
+                None => n,
+            };
+            into.write_all(&packet[..n as usize])?;
+            received += n;
+        }
+
+        if received < length.unwrap_or(received) {
+            return ioerr!(UnexpectedEof, "file shorter than its length");
+        }
+        info.length = received;
+        Ok(Some(info))
+    }
+}
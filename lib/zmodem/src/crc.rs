@@ -0,0 +1,25 @@
+/// Continues the CRC-16 `crc` of some bytes with `buf`: polynomial `0x1021`,
+/// initial value 0, no reflection, as in XMODEM. Headers and data subpackets
+/// send it high byte first.
+pub fn crc16(crc: u16, buf: &[u8]) -> u16 {
+    buf.iter().fold(crc, |crc, &b| {
+        let mut crc = crc ^ ((b as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Continues the CRC-32 `crc` of some bytes with `buf`, starting from 0: the
+/// CRC-32 of Ethernet and zip files, reflected polynomial `0xEDB88320`. Headers
+/// and data subpackets send it low byte first.
+pub fn crc32(crc: u32, buf: &[u8]) -> u32 {
+    !buf.iter().fold(!crc, |crc, &b| {
+        let mut crc = crc ^ b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+        crc
+    })
+}
@@ -0,0 +1,396 @@
+use shim::io;
+use shim::ioerr;
+
+use crate::crc::{crc16, crc32};
+
+/// Starts a header, once for binary headers and twice for hex headers.
+const ZPAD: u8 = b'*';
+/// Escapes the byte that follows. The same byte as `CAN`.
+const ZDLE: u8 = 0x18;
+/// The format of a binary header with a CRC-16.
+const ZBIN: u8 = b'A';
+/// The format of a hex header, always with a CRC-16.
+const ZHEX: u8 = b'B';
+/// The format of a binary header with a CRC-32.
+const ZBIN32: u8 = b'C';
+/// Escaped `0x7F`.
+const ZRUB0: u8 = b'l';
+/// Escaped `0xFF`.
+const ZRUB1: u8 = b'm';
+
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// The number of `CAN` bytes in a row that cancel a transfer.
+const CANCEL_LEN: usize = 5;
+
+/// The largest data subpacket, before escaping.
+pub const SUBPACKET_LEN: usize = 1024;
+
+// Frame types.
+pub const ZRQINIT: u8 = 0;
+pub const ZRINIT: u8 = 1;
+pub const ZSINIT: u8 = 2;
+pub const ZACK: u8 = 3;
+pub const ZFILE: u8 = 4;
+pub const ZSKIP: u8 = 5;
+pub const ZNAK: u8 = 6;
+pub const ZFIN: u8 = 8;
+pub const ZRPOS: u8 = 9;
+pub const ZDATA: u8 = 10;
+pub const ZEOF: u8 = 11;
+
+// Flags of `ZRINIT`, in `ZF0`.
+/// The receiver can send and receive at the same time.
+pub const CANFDX: u8 = 0x01;
+/// The receiver can receive data while writing it out.
+pub const CANOVIO: u8 = 0x02;
+/// The receiver can check CRC-32s.
+pub const CANFC32: u8 = 0x20;
+
+/// How a data subpacket ends, telling the receiver what follows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameEnd {
+    /// `ZCRCE`: the frame ends and a header follows.
+    End = b'h' as isize,
+    /// `ZCRCG`: the frame goes on with another subpacket.
+    Go = b'i' as isize,
+    /// `ZCRCQ`: the frame goes on, and the receiver answers with `ZACK`.
+    Query = b'j' as isize,
+    /// `ZCRCW`: the frame ends, and the receiver answers with `ZACK`.
+    Wait = b'k' as isize,
+}
+
+/// A frame header: its type and four bytes of data, either a position with
+/// its low byte first or flags, with `ZF0` last.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub kind: u8,
+    pub data: [u8; 4],
+}
+
+impl Header {
+    pub fn new(kind: u8, data: [u8; 4]) -> Header {
+        Header { kind, data }
+    }
+
+    /// Returns a header of type `kind` carrying the position `position`.
+    pub fn with_position(kind: u8, position: u32) -> Header {
+        Header { kind, data: position.to_le_bytes() }
+    }
+
+    pub fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        [self.kind, self.data[0], self.data[1], self.data[2], self.data[3]]
+    }
+}
+
+/// A byte read from the inner stream after unescaping.
+enum Escaped {
+    Byte(u8),
+    End(FrameEnd),
+}
+
+/// Whether `byte` is sent escaped: `ZDLE` itself, and the flow control bytes
+/// that a serial link might swallow.
+fn needs_escape(byte: u8) -> bool {
+    match byte & 0x7F {
+        ZDLE | 0x10 | XON | XOFF => true,
+        _ => false,
+    }
+}
+
+fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789abcdef"[(nibble & 0xF) as usize]
+}
+
+fn hex_value(digit: u8) -> io::Result<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => ioerr!(InvalidData, "bad hex header"),
+    }
+}
+
+/// The bytes of a frame being written, escaped as they are added so that
+/// they go out in one write.
+struct Encoder {
+    buf: [u8; 2 * SUBPACKET_LEN + 32],
+    len: usize,
+}
+
+impl Encoder {
+    fn new() -> Encoder {
+        Encoder { buf: [0; 2 * SUBPACKET_LEN + 32], len: 0 }
+    }
+
+    fn push_raw(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if needs_escape(byte) {
+                self.push_raw(&[ZDLE, byte ^ 0x40]);
+            } else {
+                self.push_raw(&[byte]);
+            }
+        }
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push_raw(&[hex_digit(byte >> 4), hex_digit(byte)]);
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// The framing layer of ZMODEM over the inner stream: headers, escaping and
+/// data subpackets.
+pub struct Port<T> {
+    pub inner: T,
+    /// Whether binary headers and data subpackets are written with CRC-32s.
+    pub crc32: bool,
+    /// Whether the last header read was a binary header with a CRC-32, as are
+    /// the data subpackets that follow it.
+    rx_crc32: bool,
+    /// The number of `CAN` bytes read in a row.
+    cancels: usize,
+}
+
+impl<T: io::Read + io::Write> Port<T> {
+    pub fn new(inner: T) -> Port<T> {
+        Port { inner, crc32: false, rx_crc32: false, cancels: 0 }
+    }
+
+    /// Reads a byte, skipping flow control bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `ConnectionAborted` once `CANCEL_LEN` `CAN`
+    /// bytes are read in a row.
+    fn read_byte(&mut self) -> io::Result<u8> {
+        loop {
+            let mut buf = [0u8; 1];
+            self.inner.read_exact(&mut buf)?;
+            match buf[0] {
+                XON | XOFF | 0x91 | 0x93 => continue,
+                ZDLE => {
+                    self.cancels += 1;
+                    if self.cancels >= CANCEL_LEN {
+                        self.cancels = 0;
+                        return ioerr!(ConnectionAborted, "transfer cancelled");
+                    }
+                }
+                _ => self.cancels = 0,
+            }
+            return Ok(buf[0]);
+        }
+    }
+
+    /// Reads a byte, unescaping it or returning the end of a subpacket.
+    fn read_escaped(&mut self) -> io::Result<Escaped> {
+        let byte = self.read_byte()?;
+        if byte != ZDLE {
+            return Ok(Escaped::Byte(byte));
+        }
+
+        // More `CAN`s are the start of a cancellation, which `read_byte`
+        // reports once it is complete.
+        let mut byte = self.read_byte()?;
+        while byte == ZDLE {
+            byte = self.read_byte()?;
+        }
+        match byte {
+            b'h' => Ok(Escaped::End(FrameEnd::End)),
+            b'i' => Ok(Escaped::End(FrameEnd::Go)),
+            b'j' => Ok(Escaped::End(FrameEnd::Query)),
+            b'k' => Ok(Escaped::End(FrameEnd::Wait)),
+            ZRUB0 => Ok(Escaped::Byte(0x7F)),
+            ZRUB1 => Ok(Escaped::Byte(0xFF)),
+            byte if byte & 0x60 == 0x40 => Ok(Escaped::Byte(byte ^ 0x40)),
+            _ => ioerr!(InvalidData, "bad escape sequence"),
+        }
+    }
+
+    /// Reads escaped bytes into `buf`, which may not be the end of a
+    /// subpacket.
+    fn read_escaped_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        for byte in buf.iter_mut() {
+            match self.read_escaped()? {
+                Escaped::Byte(b) => *byte = b,
+                Escaped::End(_) => return ioerr!(InvalidData, "unexpected end of subpacket"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the next header, skipping anything before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the inner stream fails or the transfer
+    /// is cancelled. An error of kind `Interrupted` is returned if the CRC of
+    /// the header fails, and of kind `InvalidData` if it is malformed.
+    pub fn read_header(&mut self) -> io::Result<Header> {
+        loop {
+            if self.read_byte()? != ZPAD {
+                continue;
+            }
+            let mut byte = self.read_byte()?;
+            while byte == ZPAD {
+                byte = self.read_byte()?;
+            }
+            if byte != ZDLE {
+                continue;
+            }
+
+            return match self.read_byte()? {
+                ZBIN => self.read_binary_header(false),
+                ZBIN32 => self.read_binary_header(true),
+                ZHEX => self.read_hex_header(),
+                _ => continue,
+            };
+        }
+    }
+
+    fn read_binary_header(&mut self, wide: bool) -> io::Result<Header> {
+        let mut bytes = [0u8; 5];
+        self.read_escaped_exact(&mut bytes)?;
+        let valid = if wide {
+            let mut crc = [0u8; 4];
+            self.read_escaped_exact(&mut crc)?;
+            u32::from_le_bytes(crc) == crc32(0, &bytes)
+        } else {
+            let mut crc = [0u8; 2];
+            self.read_escaped_exact(&mut crc)?;
+            u16::from_be_bytes(crc) == crc16(0, &bytes)
+        };
+        if !valid {
+            return ioerr!(Interrupted, "bad header CRC");
+        }
+
+        self.rx_crc32 = wide;
+        Ok(Header::new(bytes[0], [bytes[1], bytes[2], bytes[3], bytes[4]]))
+    }
+
+    fn read_hex_header(&mut self) -> io::Result<Header> {
+        let mut bytes = [0u8; 7];
+        for byte in bytes.iter_mut() {
+            let high = hex_value(self.read_byte()? & 0x7F)?;
+            let low = hex_value(self.read_byte()? & 0x7F)?;
+            *byte = high << 4 | low;
+        }
+        if u16::from_be_bytes([bytes[5], bytes[6]]) != crc16(0, &bytes[..5]) {
+            return ioerr!(Interrupted, "bad header CRC");
+        }
+
+        // The header ends with CR LF, which may have the high bit set.
+        if self.read_byte()? & 0x7F == b'\r' {
+            self.read_byte()?;
+        }
+        self.rx_crc32 = false;
+        Ok(Header::new(bytes[0], [bytes[1], bytes[2], bytes[3], bytes[4]]))
+    }
+
+    /// Reads a data subpacket into `buf`. Returns its length and how it ends.
+    /// The subpacket is checked with the CRC of the header before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the inner stream fails or the transfer
+    /// is cancelled. An error of kind `Interrupted` is returned if the CRC of
+    /// the subpacket fails, and of kind `InvalidData` if it is malformed or
+    /// longer than `buf`.
+    pub fn read_subpacket(&mut self, buf: &mut [u8]) -> io::Result<(usize, FrameEnd)> {
+        let mut len = 0;
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => {
+                    if len == buf.len() {
+                        return ioerr!(InvalidData, "subpacket too long");
+                    }
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Escaped::End(end) => break end,
+            }
+        };
+
+        let valid = if self.rx_crc32 {
+            let mut crc = [0u8; 4];
+            self.read_escaped_exact(&mut crc)?;
+            u32::from_le_bytes(crc) == crc32(crc32(0, &buf[..len]), &[end as u8])
+        } else {
+            let mut crc = [0u8; 2];
+            self.read_escaped_exact(&mut crc)?;
+            u16::from_be_bytes(crc) == crc16(crc16(0, &buf[..len]), &[end as u8])
+        };
+        if !valid {
+            return ioerr!(Interrupted, "bad subpacket CRC");
+        }
+        Ok((len, end))
+    }
+
+    /// Writes `header` as a hex header, the format receivers use.
+    pub fn write_hex_header(&mut self, header: &Header) -> io::Result<()> {
+        let bytes = header.bytes();
+        let mut encoder = Encoder::new();
+        encoder.push_raw(&[ZPAD, ZPAD, ZDLE, ZHEX]);
+        encoder.push_hex(&bytes);
+        encoder.push_hex(&crc16(0, &bytes).to_be_bytes());
+        encoder.push_raw(&[b'\r', b'\n' | 0x80]);
+        // Resume a sender that stopped on a lost XOFF, unless it is the last
+        // header it reads.
+        if header.kind != ZACK && header.kind != ZFIN {
+            encoder.push_raw(&[XON]);
+        }
+        self.inner.write_all(encoder.bytes())
+    }
+
+    /// Writes `header` as a binary header, with a CRC-32 if `crc32` is set.
+    pub fn write_binary_header(&mut self, header: &Header) -> io::Result<()> {
+        let bytes = header.bytes();
+        let mut encoder = Encoder::new();
+        if self.crc32 {
+            encoder.push_raw(&[ZPAD, ZDLE, ZBIN32]);
+            encoder.push(&bytes);
+            encoder.push(&crc32(0, &bytes).to_le_bytes());
+        } else {
+            encoder.push_raw(&[ZPAD, ZDLE, ZBIN]);
+            encoder.push(&bytes);
+            encoder.push(&crc16(0, &bytes).to_be_bytes());
+        }
+        self.inner.write_all(encoder.bytes())
+    }
+
+    /// Writes `data`, at most `SUBPACKET_LEN` bytes, as a data subpacket ending
+    /// with `end`.
+    pub fn write_subpacket(&mut self, data: &[u8], end: FrameEnd) -> io::Result<()> {
+        let mut encoder = Encoder::new();
+        encoder.push(data);
+        encoder.push_raw(&[ZDLE, end as u8]);
+        if self.crc32 {
+            encoder.push(&crc32(crc32(0, data), &[end as u8]).to_le_bytes());
+        } else {
+            encoder.push(&crc16(crc16(0, data), &[end as u8]).to_be_bytes());
+        }
+        self.inner.write_all(encoder.bytes())
+    }
+
+    /// Cancels the transfer: the other side stops on five `CAN` bytes. The
+    /// backspaces erase them from a terminal that is not in a transfer.
+    pub fn cancel(&mut self) -> io::Result<()> {
+        self.inner.write_all(&[ZDLE; 8])?;
+        self.inner.write_all(&[0x08; 8])
+    }
+}
@@ -0,0 +1,480 @@
+#![cfg_attr(feature = "no_std", no_std)]
+
+use core::cmp::min;
+
+use shim::io;
+use shim::ioerr;
+
+#[cfg(test)] mod tests;
+mod crc;
+mod frame;
+mod progress;
+
+pub use progress::{Progress, ProgressFn};
+pub use xmodem::FileInfo;
+
+use frame::*;
+
+/// The number of times in a row a header or subpacket is waited for, or
+/// fails its CRC, before the transfer is given up.
+const RETRIES: usize = 10;
+
+/// The default number of bytes a sender streams before it waits for the
+/// receiver to acknowledge them.
+const WINDOW: u16 = 8 * 1024;
+
+/// Whether an error reading a header or subpacket is the kind that asking
+/// for it again recovers from.
+fn is_recoverable(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::InvalidData => true,
+        _ => false,
+    }
+}
+
+/// Implementation of the ZMODEM protocol: batches of files streamed in data
+/// subpackets checked with CRC-32s.
+///
+/// Unlike XMODEM, the sender does not wait for each block to be acknowledged.
+/// It streams up to a window of data, and only then waits for the receiver,
+/// which asks for the data again from a position with `ZRPOS` after an error.
+/// A receiver may also ask for a file from an offset to resume an
+/// interrupted transfer. Each file is described with the same [`FileInfo`]
+/// as YMODEM's, and a batch is ended with [`Zmodem::finish()`].
+///
+/// All bytes that a serial link may treat specially are escaped with `ZDLE`,
+/// so the inner stream may use software flow control.
+pub struct Zmodem<T> {
+    port: Port<T>,
+    /// Whether the sender and receiver have exchanged `ZRQINIT` and `ZRINIT`.
+    started: bool,
+    window: u16,
+    /// The size of the receiver's buffer, 0 if it takes a full stream.
+    receiver_window: u16,
+    progress: ProgressFn,
+}
+
+impl<T: io::Read + io::Write> Zmodem<T> {
+    /// Returns a new `Zmodem` instance transferring files over `inner`.
+    pub fn new(inner: T) -> Self {
+        Zmodem::new_with_progress(inner, progress::noop)
+    }
+
+    /// Returns a new `Zmodem` instance transferring files over `inner`. The
+    /// function `f` is called with the progress of each file's transfer. See
+    /// the [`Progress`] enum for more information.
+    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
+        Zmodem { port: Port::new(inner), started: false, window: WINDOW, receiver_window: 0, progress: f }
+    }
+
+    /// Sets the window: the most bytes a sender streams before it waits for
+    /// the receiver, or the size of the buffer a receiver tells the sender it
+    /// has. 0 streams each file whole. The default is 8 KiB.
+    ///
+    /// A sender waits for the smaller of its window and the receiver's. A
+    /// smaller window loses less data to an error, but waits more often.
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    /// Reads the next header, or returns `None` after an error that asking
+    /// again recovers from, counted by `attempts`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the inner stream fails, the transfer is cancelled,
+    /// or `attempts` reaches `RETRIES`.
+    fn read_header(&mut self, attempts: &mut usize) -> io::Result<Option<Header>> {
+        match self.port.read_header() {
+            Ok(header) => Ok(Some(header)),
+            Err(ref e) if is_recoverable(e) && *attempts + 1 < RETRIES => {
+                *attempts += 1;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Cancels the transfer in progress, in either direction.
+    pub fn cancel(&mut self) -> io::Result<()> {
+        self.started = false;
+        self.port.cancel()
+    }
+
+    /// Tells the sender that the receiver is ready for a file.
+    fn send_zrinit(&mut self) -> io::Result<()> {
+        let [low, high] = self.window.to_le_bytes();
+        self.port.write_hex_header(&Header::new(ZRINIT, [low, high, 0, CANFDX | CANOVIO | CANFC32]))
+    }
+
+    /// Asks for the data of the file from `position`.
+    fn send_zrpos(&mut self, position: u64) -> io::Result<()> {
+        self.port.write_hex_header(&Header::with_position(ZRPOS, position as u32))
+    }
+
+    /// Waits for the receiver to be ready, sending `ZRQINIT` until it answers
+    /// with `ZRINIT`, and agrees on CRC-32s if it takes them.
+    fn start_send(&mut self) -> io::Result<()> {
+        let mut attempts = 0;
+        loop {
+            (self.progress)(Progress::Waiting);
+            self.port.write_hex_header(&Header::new(ZRQINIT, [0; 4]))?;
+            match self.read_header(&mut attempts)? {
+                Some(header) if header.kind == ZRINIT => break self.accept_zrinit(&header),
+                _ => continue,
+            }
+        }
+
+        self.started = true;
+        Ok(())
+    }
+
+    fn accept_zrinit(&mut self, header: &Header) {
+        self.receiver_window = u16::from_le_bytes([header.data[0], header.data[1]]);
+        self.port.crc32 = header.data[3] & CANFC32 != 0;
+    }
+
+    /// Sends the file described by `info`, reading its `info.length` bytes
+    /// from `data`. Returns the number of bytes sent: fewer than the length if
+    /// the receiver resumed the file from an offset, and 0 if it skipped it.
+    ///
+    /// The receiver may ask for any part of the file again, so `data` is
+    /// seeked to each position the data is sent from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails. An error of kind
+    /// `UnexpectedEof` is returned if `data` ends before `info.length` bytes,
+    /// in which case the receiver got the shorter file. An error of kind
+    /// `InvalidInput` is returned if the file is 4 GiB or larger, which
+    /// ZMODEM's positions cannot describe.
+    pub fn send<R: io::Read + io::Seek>(&mut self, info: &FileInfo, mut data: R) -> io::Result<u64> {
+        if info.length > u32::max_value() as u64 {
+            return ioerr!(InvalidInput, "file too large");
+        }
+        if !self.started {
+            self.start_send()?;
+        }
+
+        let mut packet = [0u8; SUBPACKET_LEN];
+        let len = info.encode(&mut packet)?;
+        let mut position = match self.send_zfile(&packet[..len])? {
+            Some(position) => position,
+            None => return Ok(0),
+        };
+        let start = position;
+        (self.progress)(Progress::Started);
+
+        let window = match (self.window, self.receiver_window) {
+            (0, window) | (window, 0) => window as u64,
+            (ours, theirs) => min(ours, theirs) as u64,
+        };
+        let mut attempts = 0;
+        'data: loop {
+            // Stream from `position` until the end of the file, or of the
+            // window.
+            data.seek(io::SeekFrom::Start(position))?;
+            self.port.write_binary_header(&Header::with_position(ZDATA, position as u32))?;
+            let mut streamed = 0;
+            loop {
+                let len = min(SUBPACKET_LEN as u64, info.length.saturating_sub(position)) as usize;
+                let n = read_max(&mut data, &mut packet[..len])?;
+                position += n as u64;
+                streamed += n as u64;
+                let end = if n < SUBPACKET_LEN || position == info.length {
+                    FrameEnd::End
+                } else if window != 0 && streamed + SUBPACKET_LEN as u64 > window {
+                    FrameEnd::Wait
+                } else {
+                    FrameEnd::Go
+                };
+                self.port.write_subpacket(&packet[..n], end)?;
+                (self.progress)(Progress::Position(position));
+
+                match end {
+                    FrameEnd::Go | FrameEnd::Query => continue,
+                    FrameEnd::End => break,
+                    FrameEnd::Wait => loop {
+                        match self.read_header(&mut attempts)? {
+                            Some(header) if header.kind == ZACK => {
+                                attempts = 0;
+                                continue 'data;
+                            }
+                            Some(header) if header.kind == ZRPOS => {
+                                position = self.rewind(&header, info)?;
+                                continue 'data;
+                            }
+                            Some(_) => continue,
+                            // Send again from the same position; a receiver
+                            // that lost the end of the window asks for it.
+                            None => continue 'data,
+                        }
+                    },
+                }
+            }
+
+            // Wait for the receiver to take the end of the file, or to ask
+            // for part of it again.
+            loop {
+                self.port.write_binary_header(&Header::with_position(ZEOF, position as u32))?;
+                let header = loop {
+                    match self.read_header(&mut attempts)? {
+                        Some(header) if header.kind == ZACK => continue,
+                        header => break header,
+                    }
+                };
+                match header {
+                    Some(header) if header.kind == ZRINIT => {
+                        self.accept_zrinit(&header);
+                        break 'data;
+                    }
+                    Some(header) if header.kind == ZRPOS => {
+                        position = self.rewind(&header, info)?;
+                        continue 'data;
+                    }
+                    _ => continue,
+                }
+            }
+        }
+
+        if position < info.length {
+            return ioerr!(UnexpectedEof, "file shorter than its length");
+        }
+        Ok(position - start)
+    }
+
+    /// Sends the `ZFILE` frame describing a file until the receiver asks for
+    /// its data. Returns the position the receiver asked for, or `None` if it
+    /// skipped the file.
+    fn send_zfile(&mut self, description: &[u8]) -> io::Result<Option<u64>> {
+        let mut attempts = 0;
+        loop {
+            self.port.write_binary_header(&Header::new(ZFILE, [0; 4]))?;
+            self.port.write_subpacket(description, FrameEnd::Wait)?;
+            let header = loop {
+                match self.read_header(&mut attempts)? {
+                    // The `ZRINIT` that ended the last file, sent again.
+                    Some(header) if header.kind == ZRINIT => continue,
+                    header => break header,
+                }
+            };
+            match header {
+                Some(header) if header.kind == ZRPOS => return Ok(Some(header.position() as u64)),
+                Some(header) if header.kind == ZSKIP => return Ok(None),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Returns the position that the receiver's `ZRPOS` asks to send the file
+    /// `info` from.
+    fn rewind(&mut self, header: &Header, info: &FileInfo) -> io::Result<u64> {
+        let position = header.position() as u64;
+        if position > info.length {
+            self.cancel()?;
+            return ioerr!(InvalidData, "receiver asked for data past the end of the file");
+        }
+        (self.progress)(Progress::Rewind(position));
+        Ok(position)
+    }
+
+    /// Ends the batch, telling the receiver that no file follows.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.start_send()?;
+        }
+
+        let mut attempts = 0;
+        loop {
+            self.port.write_hex_header(&Header::new(ZFIN, [0; 4]))?;
+            if let Some(header) = self.read_header(&mut attempts)? {
+                if header.kind == ZFIN {
+                    // "Over and out".
+                    self.started = false;
+                    return self.port.inner.write_all(b"OO");
+                }
+            }
+        }
+    }
+
+    /// Receives the next file of the batch and writes its contents into
+    /// `into`. Returns the description of the file, or `None` once the sender
+    /// ended the batch. The returned `length` is the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transfer fails or the file's description is
+    /// malformed. An error of kind `UnexpectedEof` is returned if the sender
+    /// sent less data than the length it gave; the batch goes on.
+    pub fn receive<W: io::Write>(&mut self, into: W) -> io::Result<Option<FileInfo>> {
+        self.receive_from(into, 0)
+    }
+
+    /// Receives the next file of the batch like [`Zmodem::receive()`], asking
+    /// the sender to resume it from `offset`: `into` already holds the first
+    /// `offset` bytes, and only the rest is written. The returned `length` is
+    /// the length of the whole file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Zmodem::receive()`]. An error of kind
+    /// `InvalidInput` is returned if the sender's file is shorter than
+    /// `offset`; the sender is told to skip the file, and the batch goes on.
+    pub fn receive_from<W: io::Write>(&mut self, into: W, offset: u64) -> io::Result<Option<FileInfo>> {
+        self.receive_with(|_| Ok((into, offset)))
+    }
+
+    /// Receives the next file of the batch like [`Zmodem::receive_from()`],
+    /// calling `open` with the file's description to get where to write it
+    /// and the offset to resume it from. `open` is not called once the sender
+    /// ended the batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Zmodem::receive_from()`]. If `open` fails, the
+    /// sender is told to skip the file, its error is returned, and the batch
+    /// goes on.
+    pub fn receive_with<W, F>(&mut self, open: F) -> io::Result<Option<FileInfo>>
+    where
+        W: io::Write,
+        F: FnOnce(&FileInfo) -> io::Result<(W, u64)>,
+    {
+        let mut packet = [0u8; SUBPACKET_LEN];
+        let (mut info, length) = match self.receive_zfile(&mut packet)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let (mut into, offset) = match open(&info) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.port.write_hex_header(&Header::new(ZSKIP, [0; 4]))?;
+                return Err(e);
+            }
+        };
+        if offset > length.unwrap_or(offset) {
+            self.port.write_hex_header(&Header::new(ZSKIP, [0; 4]))?;
+            return ioerr!(InvalidInput, "file shorter than the offset");
+        }
+
+        let mut received = offset;
+        self.send_zrpos(received)?;
+        (self.progress)(Progress::Started);
+        let mut attempts = 0;
+        loop {
+            let header = match self.read_header(&mut attempts)? {
+                Some(header) => header,
+                None => {
+                    self.send_zrpos(received)?;
+                    continue;
+                }
+            };
+
+            match header.kind {
+                ZDATA if header.position() as u64 == received => {}
+                // Data from before the last `ZRPOS`, or a `ZFILE` sent again
+                // because it was lost.
+                ZDATA | ZFILE => {
+                    self.send_zrpos(received)?;
+                    continue;
+                }
+                ZEOF if header.position() as u64 == received => break,
+                // A `ZEOF` from before the last `ZRPOS` is ignored.
+                _ => continue,
+            }
+
+            loop {
+                let (n, end) = match self.port.read_subpacket(&mut packet) {
+                    Ok(subpacket) => subpacket,
+                    Err(ref e) if is_recoverable(e) && attempts + 1 < RETRIES => {
+                        attempts += 1;
+                        (self.progress)(Progress::Rewind(received));
+                        self.send_zrpos(received)?;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                into.write_all(&packet[..n])?;
+                received += n as u64;
+                attempts = 0;
+                (self.progress)(Progress::Position(received));
+                match end {
+                    FrameEnd::Go => continue,
+                    FrameEnd::Query => self.port.write_hex_header(&Header::with_position(ZACK, received as u32))?,
+                    FrameEnd::Wait => {
+                        self.port.write_hex_header(&Header::with_position(ZACK, received as u32))?;
+                        break;
+                    }
+                    FrameEnd::End => break,
+                }
+            }
+        }
+
+        if received < length.unwrap_or(received) {
+            return ioerr!(UnexpectedEof, "file shorter than its length");
+        }
+        info.length = received;
+        Ok(Some(info))
+    }
+
+    /// Tells the sender that the receiver is ready and waits for the next
+    /// file's description, read into `packet`. Returns `None` once the
+    /// sender ends the batch.
+    fn receive_zfile(&mut self, packet: &mut [u8]) -> io::Result<Option<(FileInfo, Option<u64>)>> {
+        let mut attempts = 0;
+        self.send_zrinit()?;
+        loop {
+            let header = match self.read_header(&mut attempts)? {
+                Some(header) => header,
+                None => {
+                    self.send_zrinit()?;
+                    continue;
+                }
+            };
+
+            match header.kind {
+                ZRQINIT => self.send_zrinit()?,
+                ZSINIT => {
+                    // The sender's options are not used, but acknowledged.
+                    match self.port.read_subpacket(packet) {
+                        Ok(_) => self.port.write_hex_header(&Header::new(ZACK, [0; 4]))?,
+                        Err(ref e) if is_recoverable(e) => self.port.write_hex_header(&Header::new(ZNAK, [0; 4]))?,
+                        Err(e) => return Err(e),
+                    }
+                }
+                ZFILE => match self.port.read_subpacket(packet) {
+                    Ok((n, _)) => match FileInfo::decode(&packet[..n])? {
+                        Some(file) => return Ok(Some(file)),
+                        None => return ioerr!(InvalidData, "file without a name"),
+                    },
+                    Err(ref e) if is_recoverable(e) => self.port.write_hex_header(&Header::new(ZNAK, [0; 4]))?,
+                    Err(e) => return Err(e),
+                },
+                ZFIN => {
+                    self.port.write_hex_header(&Header::new(ZFIN, [0; 4]))?;
+                    // The sender's "OO" is read so that it is not taken for
+                    // input after the transfer. It may not come.
+                    let mut over = [0u8; 2];
+                    let _ = self.port.inner.read_exact(&mut over);
+                    return Ok(None);
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Reads from `data` until `buf` is full or `data` ends. Returns the number
+/// of bytes read.
+fn read_max<R: io::Read>(data: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match data.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
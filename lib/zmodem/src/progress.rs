@@ -0,0 +1,24 @@
+/// Enum representing how much progress has been made sending or receiving a
+/// file.
+///
+/// A value of this type is passed in to the progress callback supplied to
+/// [`Zmodem::new_with_progress()`]. It is intended to be used by progress
+/// indicators or for debugging purposes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// Waiting for the receiver to answer `ZRQINIT`.
+    Waiting,
+    /// The data of a file has started.
+    Started,
+    /// The first `.0` bytes of the file were sent or received.
+    Position(u64),
+    /// The receiver asked for the data again from position `.0`, after an
+    /// error.
+    Rewind(u64),
+}
+
+/// Type for progress callbacks.
+pub type ProgressFn = fn(Progress);
+
+/// Noop progress callback.
+pub fn noop(_: Progress) {  }
@@ -0,0 +1,373 @@
+use super::*;
+use crate::crc::{crc16, crc32};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// One end of a link between two threads. Reads time out like a serial port,
+/// and the byte written at `corrupt`, if any, is flipped on the way.
+struct Pipe {
+    tx: Sender<u8>,
+    rx: Receiver<u8>,
+    written: usize,
+    corrupt: Option<usize>,
+}
+
+fn pipe() -> (Pipe, Pipe) {
+    let ((tx1, rx1), (tx2, rx2)) = (channel(), channel());
+    (Pipe { tx: tx1, rx: rx2, written: 0, corrupt: None }, Pipe { tx: tx2, rx: rx1, written: 0, corrupt: None })
+}
+
+impl io::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.rx.recv_timeout(Duration::from_millis(200)) {
+            Ok(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            Err(RecvTimeoutError::Timeout) => ioerr!(TimedOut, "pipe timed out"),
+            Err(RecvTimeoutError::Disconnected) => Ok(0),
+        }
+    }
+}
+
+impl io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let byte = if self.corrupt == Some(self.written) { byte ^ 0x01 } else { byte };
+            self.written += 1;
+            // The other end may be gone once it has what it needs.
+            let _ = self.tx.send(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A stream that reads from a script and records what is written.
+struct Script(Cursor<Vec<u8>>, Vec<u8>);
+
+impl io::Read for Script {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for Script {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn file(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn test_crc() {
+    assert_eq!(crc16(0, b"123456789"), 0x31C3);
+    assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+    assert_eq!(crc16(crc16(0, b"1234"), b"56789"), 0x31C3);
+}
+
+#[test]
+fn test_headers() {
+    let headers = [Header::with_position(ZRPOS, 0x1812_1311), Header::new(ZRINIT, [0, 4, 0, CANFC32])];
+    for &crc32 in &[false, true] {
+        let mut port = Port::new(Script(Cursor::new(vec![]), vec![]));
+        port.crc32 = crc32;
+        for header in headers.iter() {
+            port.write_binary_header(header).expect("write okay");
+            port.write_hex_header(header).expect("write okay");
+        }
+
+        // Flow control bytes and `ZDLE` never go out unescaped, except for
+        // the `XON` after hex headers.
+        let written = port.inner.1.clone();
+        let mut port = Port::new(Script(Cursor::new(written), vec![]));
+        for header in headers.iter() {
+            assert_eq!(port.read_header().expect("binary header"), *header);
+            assert_eq!(port.read_header().expect("hex header"), *header);
+        }
+    }
+
+    let mut port = Port::new(Script(Cursor::new(vec![]), vec![]));
+    port.write_hex_header(&Header::with_position(ZRPOS, 0x0102_0304)).expect("write okay");
+    assert_eq!(&port.inner.1[..], &b"**\x18B09040302014d9e\r\x8a\x11"[..]);
+}
+
+#[test]
+fn test_escaping() {
+    let data: Vec<u8> = (0..=255).collect();
+    for &crc32 in &[false, true] {
+        let mut port = Port::new(Script(Cursor::new(vec![]), vec![]));
+        port.crc32 = crc32;
+        port.write_binary_header(&Header::with_position(ZDATA, 0)).expect("write okay");
+        port.write_subpacket(&data, FrameEnd::Go).expect("write okay");
+        port.write_subpacket(&data[..10], FrameEnd::End).expect("write okay");
+
+        let written = port.inner.1.clone();
+        assert!(!written.iter().any(|&b| b == 0x11 || b == 0x13 || b == 0x91 || b == 0x93));
+        let mut port = Port::new(Script(Cursor::new(written), vec![]));
+        port.read_header().expect("header");
+        let mut buf = [0u8; SUBPACKET_LEN];
+        assert_eq!(port.read_subpacket(&mut buf).expect("subpacket"), (256, FrameEnd::Go));
+        assert_eq!(&buf[..256], &data[..]);
+        assert_eq!(port.read_subpacket(&mut buf).expect("subpacket"), (10, FrameEnd::End));
+    }
+}
+
+#[test]
+fn test_bad_crc() {
+    let mut port = Port::new(Script(Cursor::new(vec![]), vec![]));
+    port.crc32 = true;
+    port.write_binary_header(&Header::with_position(ZDATA, 0)).expect("write okay");
+    port.write_subpacket(b"hello", FrameEnd::End).expect("write okay");
+
+    let mut written = port.inner.1.clone();
+    let last = written.len() - 1;
+    written[last] ^= 0x01;
+    let mut port = Port::new(Script(Cursor::new(written), vec![]));
+    port.read_header().expect("header");
+    let e = port.read_subpacket(&mut [0u8; 16]).expect_err("bad CRC");
+    assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn test_batch() {
+    let files = vec![("kernel8.img", file(20 * 1024 + 3)), ("empty", vec![]), ("small", file(100))];
+    let sent = files.clone();
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(rx);
+        for (name, data) in sent.iter() {
+            let info = FileInfo::new(name, data.len() as u64, 0o14000000000).expect("valid info");
+            assert_eq!(zmodem.send(&info, Cursor::new(data)).expect("send okay"), data.len() as u64);
+        }
+        zmodem.finish().expect("finish okay");
+    });
+
+    let mut zmodem = Zmodem::new(tx);
+    for (name, data) in files.iter() {
+        let mut output = Vec::new();
+        let info = zmodem.receive(&mut output).expect("receive okay").expect("a file");
+        assert_eq!((info.name(), info.length, info.mtime), (*name, data.len() as u64, 0o14000000000));
+        assert_eq!(&output, data);
+    }
+    assert_eq!(zmodem.receive(&mut Vec::new()).expect("receive okay"), None);
+    tx_thread.join().expect("tx join okay");
+}
+
+#[test]
+fn test_streaming() {
+    // Without a window, a file is streamed whole: the sender reads nothing
+    // between `ZDATA` and `ZEOF`.
+    let data = file(5000);
+    let mut script = Script(Cursor::new(vec![]), vec![]);
+    {
+        let mut receiver = Port::new(&mut script);
+        receiver.write_hex_header(&Header::new(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])).unwrap();
+        receiver.write_hex_header(&Header::with_position(ZRPOS, 0)).unwrap();
+        receiver.write_hex_header(&Header::new(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])).unwrap();
+    }
+    let script = Script(Cursor::new(script.1), vec![]);
+
+    let mut zmodem = Zmodem::new(script);
+    zmodem.set_window(0);
+    let info = FileInfo::new("data", data.len() as u64, 0).unwrap();
+    assert_eq!(zmodem.send(&info, Cursor::new(&data)).expect("send okay"), 5000);
+
+    let mut port = Port::new(Script(Cursor::new(zmodem.port.inner.1.clone()), vec![]));
+    assert_eq!(port.read_header().unwrap().kind, ZRQINIT);
+    assert_eq!(port.read_header().unwrap().kind, ZFILE);
+    port.read_subpacket(&mut [0u8; SUBPACKET_LEN]).unwrap();
+    assert_eq!(port.read_header().unwrap(), Header::with_position(ZDATA, 0));
+    let mut received = Vec::new();
+    let mut buf = [0u8; SUBPACKET_LEN];
+    loop {
+        let (n, end) = port.read_subpacket(&mut buf).expect("subpacket");
+        received.extend_from_slice(&buf[..n]);
+        match end {
+            FrameEnd::Go => continue,
+            FrameEnd::End => break,
+            end => panic!("unexpected {:?}", end),
+        }
+    }
+    assert_eq!(received, data);
+    assert_eq!(port.read_header().unwrap(), Header::with_position(ZEOF, 5000));
+}
+
+static REWOUND: AtomicBool = AtomicBool::new(false);
+
+fn progress(progress: Progress) {
+    if let Progress::Rewind(_) = progress {
+        REWOUND.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_recovery() {
+    let data = file(40 * 1024);
+    let sent = data.clone();
+
+    // Corrupt a byte of the data, past the headers and the file's
+    // description.
+    let (tx, mut rx) = pipe();
+    rx.corrupt = Some(3000);
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new_with_progress(rx, progress);
+        let info = FileInfo::new("data", sent.len() as u64, 0).unwrap();
+        let n = zmodem.send(&info, Cursor::new(&sent)).expect("send okay");
+        zmodem.finish().expect("finish okay");
+        n
+    });
+
+    let mut zmodem = Zmodem::new(tx);
+    let mut output = Vec::new();
+    let info = zmodem.receive(&mut output).expect("receive okay").expect("a file");
+    assert_eq!(zmodem.receive(&mut Vec::new()).expect("receive okay"), None);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 40 * 1024);
+    assert_eq!(info.length, 40 * 1024);
+    assert!(output == data);
+    assert!(REWOUND.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_resume() {
+    let data = file(10 * 1024 + 5);
+    let sent = data.clone();
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(rx);
+        let info = FileInfo::new("data", sent.len() as u64, 0).unwrap();
+        let n = zmodem.send(&info, Cursor::new(&sent)).expect("send okay");
+        zmodem.finish().expect("finish okay");
+        n
+    });
+
+    // The receiver already has the first 4000 bytes.
+    let mut zmodem = Zmodem::new(tx);
+    let mut output = data[..4000].to_vec();
+    let info = zmodem.receive_from(&mut output, 4000).expect("receive okay").expect("a file");
+    assert_eq!(zmodem.receive(&mut Vec::new()).expect("receive okay"), None);
+    assert_eq!(tx_thread.join().expect("tx join okay"), data.len() as u64 - 4000);
+    assert_eq!(info.length, data.len() as u64);
+    assert!(output == data);
+}
+
+#[test]
+fn test_skip() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(rx);
+        let short = FileInfo::new("short", 10, 0).unwrap();
+        let n = zmodem.send(&short, Cursor::new(vec![0; 10])).expect("send okay");
+        let next = FileInfo::new("next", 3, 0).unwrap();
+        zmodem.send(&next, Cursor::new(b"abc")).expect("send okay");
+        zmodem.finish().expect("finish okay");
+        n
+    });
+
+    // A file shorter than what the receiver has is skipped.
+    let mut zmodem = Zmodem::new(tx);
+    let e = zmodem.receive_from(&mut Vec::new(), 100).expect_err("file too short");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let mut output = Vec::new();
+    let info = zmodem.receive(&mut output).expect("receive okay").expect("a file");
+    assert_eq!((info.name(), &output[..]), ("next", &b"abc"[..]));
+    assert_eq!(zmodem.receive(&mut Vec::new()).expect("receive okay"), None);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 0);
+}
+
+#[test]
+fn test_receive_with() {
+    let data = file(3000);
+    let sent = data.clone();
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(rx);
+        let refused = FileInfo::new("refused", 10, 0).unwrap();
+        zmodem.send(&refused, Cursor::new(vec![0; 10])).expect("send okay");
+        let info = FileInfo::new("data", sent.len() as u64, 0).unwrap();
+        let n = zmodem.send(&info, Cursor::new(&sent)).expect("send okay");
+        zmodem.finish().expect("finish okay");
+        n
+    });
+
+    // A file that can't be opened is skipped, and the next one resumed from
+    // what the receiver has.
+    let mut zmodem = Zmodem::new(tx);
+    let e = zmodem
+        .receive_with(|info| -> io::Result<(Vec<u8>, u64)> {
+            assert_eq!(info.name(), "refused");
+            ioerr!(PermissionDenied, "refused")
+        })
+        .expect_err("open fails");
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    let mut output = data[..1000].to_vec();
+    let info = zmodem
+        .receive_with(|info| {
+            assert_eq!((info.name(), info.length), ("data", 3000));
+            let offset = output.len() as u64;
+            Ok((&mut output, offset))
+        })
+        .expect("receive okay")
+        .expect("a file");
+    assert_eq!(zmodem.receive_with(|_| -> io::Result<(Vec<u8>, u64)> { panic!("no file") }).expect("receive okay"), None);
+    assert_eq!(tx_thread.join().expect("tx join okay"), 2000);
+    assert_eq!(info.length, 3000);
+    assert!(output == data);
+}
+
+#[test]
+fn test_short_data() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(rx);
+        let info = FileInfo::new("data", 2000, 0).unwrap();
+        let e = zmodem.send(&info, Cursor::new(file(1500))).expect_err("short data");
+        zmodem.finish().expect("finish okay");
+        e
+    });
+
+    // The batch goes on after the short file.
+    let mut zmodem = Zmodem::new(tx);
+    let e = zmodem.receive(&mut Vec::new()).expect_err("short file");
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(zmodem.receive(&mut Vec::new()).expect("receive okay"), None);
+    let e = tx_thread.join().expect("tx join okay");
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_cancel() {
+    let mut port = Port::new(Script(Cursor::new(vec![]), vec![]));
+    port.write_hex_header(&Header::new(ZRQINIT, [0; 4])).unwrap();
+    port.cancel().unwrap();
+    let mut zmodem = Zmodem::new(Script(Cursor::new(port.inner.1), vec![]));
+    let e = zmodem.receive(&mut Vec::new()).expect_err("cancelled");
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+}
+
+#[test]
+fn test_timeout() {
+    // A sender that never hears from the receiver gives up after `RETRIES`
+    // attempts.
+    let (_tx, rx) = pipe();
+    let mut zmodem = Zmodem::new(rx);
+    let info = FileInfo::new("data", 1, 0).unwrap();
+    let e = zmodem.send(&info, Cursor::new(vec![0])).expect_err("timed out");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+}
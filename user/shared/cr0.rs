@@ -26,5 +26,5 @@ unsafe fn zeros_bss() {
 pub unsafe extern "C" fn _start() -> ! {
     zeros_bss();
     crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(0);
 }
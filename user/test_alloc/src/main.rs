@@ -0,0 +1,64 @@
+#![feature(alloc_error_handler)]
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::Write;
+
+use kernel_api::allocator::Allocator;
+use kernel_api::println;
+use kernel_api::syscall::{exit, getpid, sbrk};
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
+#[alloc_error_handler]
+fn oom(layout: Layout) -> ! {
+    println!("[{:02}] out of memory allocating {} bytes", getpid(), layout.size());
+    exit(1)
+}
+
+/// Returns the current program break.
+fn heap_end() -> usize {
+    sbrk(0).expect("sbrk") as usize
+}
+
+fn main() {
+    let start = heap_end();
+
+    // Small blocks are carved out of the heap, which grows with `sbrk`.
+    let squares: Vec<u64> = (0..1000).map(|i| i * i).collect();
+    let mut text = String::new();
+    for i in 0..10 {
+        let _ = write!(text, "{} ", squares[i]);
+    }
+    println!("[{:02}] squares: {}", getpid(), text.trim_end());
+
+    // A freed block is reused for the next request of its size class.
+    let first = Box::new([1u8; 100]);
+    let addr = &*first as *const [u8; 100] as usize;
+    drop(first);
+    let second = Box::new([2u8; 100]);
+    let reused = &*second as *const [u8; 100] as usize == addr;
+    println!("[{:02}] freed block reused: {}", getpid(), reused);
+
+    // Blocks over 32KiB get their own mapping and leave the heap alone.
+    let grown = heap_end();
+    let big = vec![0xa5u8; 256 * 1024];
+    let sum: u64 = big.iter().map(|&b| b as u64).sum();
+    println!("[{:02}] mapped {} bytes summing to {}, heap unchanged: {}",
+             getpid(), big.len(), sum, heap_end() == grown);
+    drop(big);
+
+    println!("[{:02}] heap grew by {} bytes", getpid(), heap_end() - start);
+    exit(if reused { 0 } else { 1 });
+}
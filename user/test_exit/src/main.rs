@@ -8,5 +8,5 @@ use kernel_api::println;
 use kernel_api::syscall::{getpid, time, exit};
 
 fn main() {
-    exit();
+    exit(0);
 }
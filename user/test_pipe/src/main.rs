@@ -0,0 +1,76 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use kernel_api::println;
+use kernel_api::syscall::{close, exit, fork, pipe, read, wait, write_all};
+
+/// Number of Fibonacci numbers sent through the pipe.
+const COUNT: u64 = 20;
+
+/// Writes the first `COUNT` Fibonacci numbers to `fd`, one per line.
+fn produce(fd: u64) {
+    let (mut a, mut b) = (1u64, 1u64);
+    for _ in 0..COUNT {
+        let mut line = [0u8; 21];
+        let mut i = line.len() - 1;
+        line[i] = b'\n';
+        let mut n = a;
+        loop {
+            i -= 1;
+            line[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        write_all(fd, &line[i..]).expect("write to pipe");
+
+        let next = a + b;
+        a = b;
+        b = next;
+    }
+}
+
+/// Reads lines from `fd` until end of file and prints each with its index.
+fn consume(fd: u64) {
+    let mut buf = [0u8; 64];
+    let mut index = 0;
+    let mut value = 0u64;
+    loop {
+        let n = read(fd, &mut buf).expect("read from pipe");
+        if n == 0 {
+            break;
+        }
+
+        for &byte in &buf[..n] {
+            if byte == b'\n' {
+                println!("fib({:>2}) = {}", index, value);
+                index += 1;
+                value = 0;
+            } else {
+                value = value * 10 + (byte - b'0') as u64;
+            }
+        }
+    }
+}
+
+fn main() {
+    let (read_fd, write_fd) = pipe().expect("pipe");
+    match fork().expect("fork") {
+        0 => {
+            let _ = close(read_fd);
+            produce(write_fd);
+            exit(0);
+        }
+        _ => {
+            let _ = close(write_fd);
+            consume(read_fd);
+            let _ = wait();
+            println!("producer finished");
+        }
+    }
+    exit(0);
+}
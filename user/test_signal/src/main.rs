@@ -0,0 +1,39 @@
+#![feature(asm)]
+#![no_std]
+#![no_main]
+
+mod cr0;
+
+use kernel_api::println;
+use kernel_api::syscall::{exit, fork, getpid, kill, sigaction, sleep, waitpid};
+use kernel_api::{SIGKILL, SIGUSR1};
+use core::time::Duration;
+
+static mut RECEIVED: u64 = 0;
+
+extern "C" fn on_usr1(sig: u64) {
+    unsafe {
+        RECEIVED += 1;
+    }
+    println!("[{:02}] caught signal {}", getpid(), sig);
+}
+
+fn main() {
+    sigaction(SIGUSR1, on_usr1 as u64).expect("sigaction");
+    kill(getpid(), SIGUSR1).expect("kill self");
+    println!("[{:02}] handled {} signal(s)", getpid(), unsafe { RECEIVED });
+
+    let child = fork().expect("fork");
+    if child == 0 {
+        loop {
+            let _ = sleep(Duration::from_millis(1000));
+        }
+    }
+
+    let _ = kill(child, SIGKILL);
+    match waitpid(child) {
+        Ok((id, status)) => println!("[{:02}] child {} exited with {}", getpid(), id, status),
+        Err(e) => println!("[{:02}] waitpid failed: {:?}", getpid(), e),
+    }
+    exit(0);
+}